@@ -34,6 +34,26 @@ impl AstExpr {
         matches!(self, AstExpr::Agg(_, _))
     }
 
+    /// The expressions joined by AND in self, left to right. An expression without AND is its
+    /// own only conjunct.
+    pub fn conjuncts(&self) -> Vec<&AstExpr> {
+        match self {
+            AstExpr::Boolean(BooleanOp::And, l, r) => {
+                let mut res = l.conjuncts();
+                res.extend(r.conjuncts());
+                res
+            }
+            other => vec![other],
+        }
+    }
+
+    /// Join exprs with AND, the inverse of conjuncts. None if there are no exprs.
+    pub fn conjunction(exprs: impl IntoIterator<Item = AstExpr>) -> Option<AstExpr> {
+        exprs
+            .into_iter()
+            .reduce(|acc, e| AstExpr::Boolean(BooleanOp::And, Box::new(acc), Box::new(e)))
+    }
+
     pub fn to_attr(&self, schema: &TableSchema) -> Attribute {
         use AstExpr::*;
         match self {
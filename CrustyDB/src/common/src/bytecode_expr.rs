@@ -1,4 +1,6 @@
-use crate::{CrustyError, Field, Tuple};
+use crate::ast_expr::AstExpr;
+use crate::error::c_err;
+use crate::{BooleanOp, CrustyError, Field, MathOp, Tuple};
 use std::ops::{Add, Div, Mul, Sub};
 
 pub trait FromBool {
@@ -107,6 +109,66 @@ impl ByteCodeExpr {
         }
         stack.pop().unwrap()
     }
+
+    /// Compile an expression bound to a schema with `bind_expr`.
+    pub fn from_ast(expr: &AstExpr) -> Result<Self, CrustyError> {
+        let mut bytecode_expr = ByteCodeExpr::new();
+        from_ast_inner(expr, &mut bytecode_expr)?;
+        Ok(bytecode_expr)
+    }
+}
+
+fn from_ast_inner(expr: &AstExpr, bytecode_expr: &mut ByteCodeExpr) -> Result<(), CrustyError> {
+    match expr {
+        AstExpr::Ident(_) | AstExpr::Alias(_, _) | AstExpr::Agg(_, _) => {
+            return Err(c_err(
+                "Ident, Alias, and Agg should have been handled in conversion to logical plan or bound expression",
+            ));
+        }
+        AstExpr::Literal(l) => {
+            let i = bytecode_expr.add_literal(l.clone());
+            bytecode_expr.add_code(ByteCodes::PushLit as usize);
+            bytecode_expr.add_code(i);
+        }
+        AstExpr::Math(op, l, r) => {
+            // (a+b)-(c+d) Bytecode will be [a][b][+][c][d][+][-]
+            // i, Stack
+            // 0, [a]
+            // 1, [a][b]
+            // 2, [a+b]
+            // 3, [a+b][c]
+            // 4, [a+b][c][d]
+            // 5, [a+b][c+d]
+            // 6, [a+b-c-d]
+            from_ast_inner(l, bytecode_expr)?;
+            from_ast_inner(r, bytecode_expr)?;
+            match op {
+                MathOp::Add => bytecode_expr.add_code(ByteCodes::Add as usize),
+                MathOp::Sub => bytecode_expr.add_code(ByteCodes::Sub as usize),
+                MathOp::Mul => bytecode_expr.add_code(ByteCodes::Mul as usize),
+                MathOp::Div => bytecode_expr.add_code(ByteCodes::Div as usize),
+            }
+        }
+        AstExpr::Boolean(op, l, r) => {
+            from_ast_inner(l, bytecode_expr)?;
+            from_ast_inner(r, bytecode_expr)?;
+            match op {
+                BooleanOp::Eq => bytecode_expr.add_code(ByteCodes::Eq as usize),
+                BooleanOp::Neq => bytecode_expr.add_code(ByteCodes::Neq as usize),
+                BooleanOp::Gt => bytecode_expr.add_code(ByteCodes::Gt as usize),
+                BooleanOp::Gte => bytecode_expr.add_code(ByteCodes::Gte as usize),
+                BooleanOp::Lt => bytecode_expr.add_code(ByteCodes::Lt as usize),
+                BooleanOp::Lte => bytecode_expr.add_code(ByteCodes::Lte as usize),
+                BooleanOp::And => bytecode_expr.add_code(ByteCodes::And as usize),
+                BooleanOp::Or => bytecode_expr.add_code(ByteCodes::Or as usize),
+            }
+        }
+        AstExpr::ColIdx(i) => {
+            bytecode_expr.add_code(ByteCodes::PushField as usize);
+            bytecode_expr.add_code(*i);
+        }
+    }
+    Ok(())
 }
 
 type DispatchFn<T> = fn(&[usize], &mut usize, &mut Vec<T>, &[T], &[T]);
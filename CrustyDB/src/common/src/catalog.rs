@@ -1,11 +1,11 @@
 use crate::ids::ContainerId;
-use crate::table::TableInfo;
+use crate::table::{IndexInfo, TableInfo};
 use crate::TableSchema;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex, RwLock};
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct ContainerIdGenerator {
     next_id: ContainerId,
     table_to_id: HashMap<String, ContainerId>,
@@ -31,12 +31,24 @@ impl ContainerIdGenerator {
             }
         }
     }
+
+    /// A container id not tied to a name, for containers named apart from tables.
+    fn next_container_id(&mut self) -> ContainerId {
+        let c_id = self.next_id;
+        self.next_id += 1;
+        c_id
+    }
 }
 
-#[derive(Serialize)]
+/// The tables and indexes of a database. Serialized with the database state so it survives a
+/// restart.
+#[derive(Serialize, Deserialize)]
 pub struct Catalog {
     container_id_generator: Mutex<ContainerIdGenerator>,
     tables: RwLock<HashMap<ContainerId, TableInfo>>,
+    /// Indexes by the container holding them
+    #[serde(default)]
+    indexes: RwLock<HashMap<ContainerId, IndexInfo>>,
 }
 
 impl Catalog {
@@ -44,6 +56,7 @@ impl Catalog {
         Arc::new(Catalog {
             container_id_generator: Mutex::new(ContainerIdGenerator::new()),
             tables: RwLock::new(HashMap::new()),
+            indexes: RwLock::new(HashMap::new()),
         })
     }
 
@@ -52,6 +65,12 @@ impl Catalog {
         generator.get_table_id(name)
     }
 
+    /// A new container id for an index
+    pub fn get_new_container_id(&self) -> ContainerId {
+        let mut generator = self.container_id_generator.lock().unwrap();
+        generator.next_container_id()
+    }
+
     pub fn add_table(&self, table_info: TableInfo) -> Option<()> {
         let mut tables = self.tables.write().unwrap();
         match tables.get(&table_info.c_id) {
@@ -68,6 +87,17 @@ impl Catalog {
         tables.get(&c_id).cloned()
     }
 
+    /// The table with the given name, without assigning an id to unknown names
+    pub fn get_table_by_name(&self, name: &str) -> Option<TableInfo> {
+        let tables = self.tables.read().unwrap();
+        tables.values().find(|info| info.name == name).cloned()
+    }
+
+    pub fn get_table_ids(&self) -> Vec<ContainerId> {
+        let tables = self.tables.read().unwrap();
+        tables.keys().cloned().collect()
+    }
+
     pub fn get_table_names(&self) -> Vec<String> {
         let tables = self.tables.read().unwrap();
         tables.values().map(|info| info.name.clone()).collect()
@@ -91,6 +121,50 @@ impl Catalog {
             false
         }
     }
+
+    /// Add an index. Returns None if an index with the same name exists.
+    pub fn add_index(&self, index_info: IndexInfo) -> Option<()> {
+        let mut indexes = self.indexes.write().unwrap();
+        if indexes.values().any(|i| i.name == index_info.name) {
+            return None;
+        }
+        indexes.insert(index_info.c_id, index_info);
+        Some(())
+    }
+
+    pub fn get_index(&self, c_id: ContainerId) -> Option<IndexInfo> {
+        let indexes = self.indexes.read().unwrap();
+        indexes.get(&c_id).cloned()
+    }
+
+    pub fn get_index_by_name(&self, name: &str) -> Option<IndexInfo> {
+        let indexes = self.indexes.read().unwrap();
+        indexes.values().find(|i| i.name == name).cloned()
+    }
+
+    /// Remove the index with the given name, returning it.
+    pub fn remove_index(&self, name: &str) -> Option<IndexInfo> {
+        let mut indexes = self.indexes.write().unwrap();
+        let c_id = indexes.values().find(|i| i.name == name)?.c_id;
+        indexes.remove(&c_id)
+    }
+
+    /// The indexes of a table, ordered by name
+    pub fn get_table_indexes(&self, table_id: ContainerId) -> Vec<IndexInfo> {
+        let indexes = self.indexes.read().unwrap();
+        let mut table_indexes: Vec<IndexInfo> = indexes
+            .values()
+            .filter(|i| i.table_id == table_id)
+            .cloned()
+            .collect();
+        table_indexes.sort_by(|a, b| a.name.cmp(&b.name));
+        table_indexes
+    }
+
+    pub fn get_indexes(&self) -> Vec<IndexInfo> {
+        let indexes = self.indexes.read().unwrap();
+        indexes.values().cloned().collect()
+    }
 }
 
 pub type CatalogRef = Arc<Catalog>;
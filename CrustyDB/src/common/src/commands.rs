@@ -3,8 +3,8 @@ use std::path::PathBuf;
 /// Enum for system commands related to server state.
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 pub enum SystemCommand {
-    /// Create a new database.
-    Create(String),
+    /// Create a new database, with pages of the given number of bytes or the server's default.
+    Create(String, Option<usize>),
     /// Connect to an existing database.
     Connect(String),
     /// Resets the server state
@@ -24,7 +24,8 @@ pub enum SystemCommand {
 impl std::fmt::Display for SystemCommand {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            SystemCommand::Create(s) => write!(f, "Create({})", s),
+            SystemCommand::Create(s, None) => write!(f, "Create({})", s),
+            SystemCommand::Create(s, Some(page_size)) => write!(f, "Create({}, {})", s, page_size),
             SystemCommand::Connect(s) => write!(f, "Connect({})", s),
             SystemCommand::Reset => write!(f, "Reset"),
             SystemCommand::Shutdown => write!(f, "Shutdown"),
@@ -53,10 +54,14 @@ pub enum DBCommand {
     ShowTables,
     /// Show all registered queries in the current database.
     ShowQueries,
+    /// Show the columns and indexes of a table.
+    Describe(String),
     /// Generates a CSV file from a specified source.
     Generate(String),
     /// Import a CSV file into a specified table.
     Import(String, PathBuf),
+    /// Check the stored pages of a table, or of every table.
+    Check(Option<String>),
 }
 
 impl std::fmt::Display for DBCommand {
@@ -69,8 +74,10 @@ impl std::fmt::Display for DBCommand {
             DBCommand::ConvertQuery(s) => write!(f, "ConvertQuery({})", s),
             DBCommand::ShowTables => write!(f, "ShowTables"),
             DBCommand::ShowQueries => write!(f, "ShowQueries"),
+            DBCommand::Describe(s) => write!(f, "Describe({})", s),
             DBCommand::Generate(s) => write!(f, "Generate({})", s),
             DBCommand::Import(s, p) => write!(f, "Import({}, {:?})", s, p),
+            DBCommand::Check(s) => write!(f, "Check({:?})", s),
         }
     }
 }
@@ -140,15 +147,33 @@ pub fn parse_command(mut cmd: String) -> Option<Command> {
         "\\shutdown" => Some(Command::System(SystemCommand::Shutdown)),
         "\\quiet" => Some(Command::System(SystemCommand::QuietMode)),
         "\\t" => Some(Command::System(SystemCommand::Test)),
+        "\\check" => Some(Command::DB(DBCommand::Check(None))),
         _ => {
             if let Some(clean_cmd) = cmd.strip_prefix("\\r ") {
-                Some(Command::System(SystemCommand::Create(
-                    clean_cmd.to_string(),
-                )))
+                let mut split = clean_cmd.split_whitespace();
+                let name = split.next().map(str::to_string);
+                let page_size = split.next().map(str::parse::<usize>).transpose();
+                match (name, page_size, split.next()) {
+                    (Some(name), Ok(page_size), None) => {
+                        Some(Command::System(SystemCommand::Create(name, page_size)))
+                    }
+                    _ => {
+                        info!("Invalid command received {}", cmd);
+                        None
+                    }
+                }
             } else if let Some(clean_cmd) = cmd.strip_prefix("\\c ") {
                 Some(Command::System(SystemCommand::Connect(
                     clean_cmd.to_string(),
                 )))
+            } else if let Some(clean_cmd) = cmd.strip_prefix("\\d ") {
+                Some(Command::DB(DBCommand::Describe(
+                    clean_cmd.trim().to_string(),
+                )))
+            } else if let Some(clean_cmd) = cmd.strip_prefix("\\check ") {
+                Some(Command::DB(DBCommand::Check(Some(
+                    clean_cmd.trim().to_string(),
+                ))))
             } else if let Some(clean_cmd) = cmd.strip_prefix("\\i ") {
                 let mut split = clean_cmd.split(' ');
                 let path = split.next().unwrap().to_string();
@@ -1,4 +1,4 @@
-use crate::ids::TransactionId;
+use crate::ids::{TransactionId, ValueId};
 use regex::Error as RegexError;
 use std::error::Error;
 use std::fmt;
@@ -27,6 +27,9 @@ pub enum CrustyError {
     InvalidMutationError(String),
     /// Transaction Rollback
     TransactionRollback(TransactionId),
+    /// A page read from disk failed its checksum or does not hold what its place says it should.
+    /// The value id names the page.
+    CorruptPage(ValueId, String),
 }
 
 impl fmt::Display for CrustyError {
@@ -44,6 +47,7 @@ impl fmt::Display for CrustyError {
                 CrustyError::InvalidMutationError(s) => format!("InvalidMutationError {}", s),
                 CrustyError::TransactionRollback(tid) =>
                     format!("Transaction Rolledback {:?}", tid),
+                CrustyError::CorruptPage(page, s) => format!("Corrupt page {:?}: {}", page, s),
             }
         )
     }
@@ -7,6 +7,7 @@ static TXN_COUNTER: AtomicU64 = AtomicU64::new(0);
 pub type TidType = u64;
 
 /// Permissions for locks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Permissions {
     ReadOnly,
     ReadWrite,
@@ -31,6 +32,12 @@ impl TransactionId {
     pub fn id(&self) -> u64 {
         self.id
     }
+
+    /// Make every transaction id created from now on larger than id, e.g. than ids stored in
+    /// records before a restart.
+    pub fn skip_past(id: TidType) {
+        TXN_COUNTER.fetch_max(id + 1, Ordering::SeqCst);
+    }
 }
 
 impl Default for TransactionId {
@@ -49,13 +56,17 @@ pub type SlotId = u16;
 /// For field changes
 pub type TupleAssignments = Vec<(usize, Field)>;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[allow(dead_code)]
 /// The things that can be saved and maintained in the database
 pub enum StateType {
     HashTable,
     BaseTable,
     MatView,
+    /// B+tree index over a base table
+    TreeIndex,
+    /// Tuples an operator spilled while running a query, removed when the operator is done
+    Temporary,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -139,11 +150,35 @@ impl fmt::Debug for ValueId {
     }
 }
 
+/// Log sequence number. Identifies a write-ahead log record by the log page it starts on and
+/// its byte offset within that page. LSNs grow with the log, so they also order the records.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub struct Lsn {
-    pub page_id: PageId,
+    pub page_id: u32,
     pub slot_id: SlotId,
 }
 
+impl Lsn {
+    pub fn new(page_id: u32, slot_id: SlotId) -> Self {
+        Lsn { page_id, slot_id }
+    }
+
+    /// Create the LSN for a byte offset in the log.
+    pub fn from_offset(offset: u64) -> Self {
+        Lsn {
+            page_id: (offset / crate::PAGE_SIZE as u64) as u32,
+            slot_id: (offset % crate::PAGE_SIZE as u64) as SlotId,
+        }
+    }
+
+    /// The byte offset in the log this LSN points to.
+    pub fn offset(&self) -> u64 {
+        self.page_id as u64 * crate::PAGE_SIZE as u64 + self.slot_id as u64
+    }
+}
+
 /// Stuff delta storage manager
 pub type LogicalTimeStamp = u32;
 pub type AtomicTimeStamp = AtomicU32;
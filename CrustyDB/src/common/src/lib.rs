@@ -18,6 +18,7 @@ pub mod ids;
 pub mod logical_plan;
 pub mod physical_plan;
 pub mod query_result;
+pub mod row;
 pub mod storage_trait;
 pub mod table;
 pub mod testutil;
@@ -27,7 +28,8 @@ pub mod ast_expr;
 pub mod bytecode_expr;
 pub mod operation;
 
-/// Page size in bytes
+/// Default page size in bytes. A storage manager may be created with another, see
+/// StorageTrait::open_with_page_size.
 pub const PAGE_SIZE: usize = 4096;
 // How many pages a buffer pool can hold
 pub const PAGE_SLOTS: usize = 50;
@@ -40,12 +42,12 @@ pub mod prelude {
         ContainerId, LogicalTimeStamp, Lsn, PageId, SlotId, StateType, TidType, TransactionId,
         ValueId,
     };
-    pub use crate::table::TableInfo;
+    pub use crate::table::{IndexInfo, IndexKind, TableInfo};
     pub use crate::{TableSchema, Tuple};
 }
 pub use crate::datatypes::{DataType, Field};
 pub use crate::error::{ConversionError, CrustyError};
-pub use crate::operation::{AggOp, BooleanOp, MathOp};
+pub use crate::operation::{AggOp, BooleanOp, JoinType, MathOp};
 pub use query_result::QueryResult;
 
 /// Handle schemas.
@@ -254,6 +256,60 @@ impl Attribute {
     }
 }
 
+/// The end_ts of a version that has not been replaced. It serializes to as many bytes as a
+/// transaction id, so a version can be ended in place.
+#[cfg(feature = "mvcc")]
+pub const LIVE_TS: TidType = TidType::MAX;
+
+#[cfg(feature = "mvcc")]
+fn live_ts() -> TidType {
+    LIVE_TS
+}
+
+/// A missing version pointer packed with `pack_version_pointer`
+pub const NO_VERSION_POINTER: u64 = u64::MAX;
+
+/// Pack a pointer to a version, the id of a heap record, into a u64 that always takes as many
+/// bytes to serialize, so a version can be linked to the next one in place.
+pub fn pack_version_pointer(pointer: Option<ValueId>) -> u64 {
+    match pointer {
+        None => NO_VERSION_POINTER,
+        Some(id) => {
+            1 << 56
+                | (id.segment_id.unwrap_or(0) as u64) << 48
+                | (id.container_id as u64) << 32
+                | (id.page_id.unwrap_or(0) as u64) << 16
+                | id.slot_id.unwrap_or(0) as u64
+        }
+    }
+}
+
+/// Unpack a pointer packed with `pack_version_pointer`
+pub fn unpack_version_pointer(packed: u64) -> Option<ValueId> {
+    if packed == NO_VERSION_POINTER {
+        return None;
+    }
+    let segment = (packed >> 48) as u8;
+    let mut id = ValueId::new_slot((packed >> 32) as u16, (packed >> 16) as u16, packed as u16);
+    id.segment_id = (segment > 0).then_some(segment);
+    Some(id)
+}
+
+#[cfg(feature = "mvcc")]
+fn serialize_version_pointer<S: Serializer>(
+    pointer: &Option<ValueId>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    pack_version_pointer(*pointer).serialize(serializer)
+}
+
+#[cfg(feature = "mvcc")]
+fn deserialize_version_pointer<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ValueId>, D::Error> {
+    u64::deserialize(deserializer).map(unpack_version_pointer)
+}
+
 /// Tuple type.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Tuple {
@@ -265,18 +321,23 @@ pub struct Tuple {
     /// Optionally used for read lock or read-ts
     pub read: TidType,
 
-    #[serde(skip_serializing)]
+    #[serde(default)]
     #[cfg(feature = "mvcc")]
-    /// Used for multi-version systems
+    /// Used for multi-version systems, the transaction that created this version
     pub begin_ts: TidType,
 
-    #[serde(skip_serializing)]
+    #[serde(default = "live_ts")]
     #[cfg(feature = "mvcc")]
-    /// Used for multi-version systems
+    /// Used for multi-version systems, the transaction that replaced this version or `LIVE_TS`
     pub end_ts: TidType,
 
-    /// Used for multi-version systems, points to next version (older or newer)
+    #[serde(
+        default,
+        serialize_with = "serialize_version_pointer",
+        deserialize_with = "deserialize_version_pointer"
+    )]
     #[cfg(feature = "mvcc")]
+    /// Used for multi-version systems, points to the next newer version once this one is ended
     pub tuple_pointer: Option<ValueId>,
 
     #[serde(skip_serializing)]
@@ -296,6 +357,12 @@ impl Tuple {
     pub fn new(field_vals: Vec<Field>) -> Self {
         Self {
             tid: 0,
+            #[cfg(feature = "mvcc")]
+            begin_ts: 0,
+            #[cfg(feature = "mvcc")]
+            end_ts: LIVE_TS,
+            #[cfg(feature = "mvcc")]
+            tuple_pointer: None,
             value_id: None,
             field_vals,
         }
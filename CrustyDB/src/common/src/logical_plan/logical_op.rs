@@ -4,7 +4,7 @@ use std::fmt::Debug;
 
 use crate::ast_expr::AstExpr;
 use crate::ids::ContainerId;
-use crate::Field;
+use crate::{Field, JoinType};
 
 /// Scan node.
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -39,6 +39,10 @@ pub struct JoinNode {
     pub eqs: Vec<(AstExpr, AstExpr)>, // (left, right)
     /// Other predicates (e.g. t0.a > t1.b, t0.c < 5)
     pub filter: Option<AstExpr>,
+    /// Inputs whose tuples without a match are padded with nulls. The left input is the first
+    /// child returned by the edges of the join.
+    #[serde(default)]
+    pub join_type: JoinType,
 }
 
 /// CrossProduct node.
@@ -58,7 +62,7 @@ pub struct FilterNode {
 /// Orderby node.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SortNode {
-    pub fields: Vec<(AstExpr, bool)>, // (field, asc)
+    pub fields: Vec<(AstExpr, bool, bool)>, // (field, asc, nulls_first)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
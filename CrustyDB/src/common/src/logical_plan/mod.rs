@@ -12,6 +12,7 @@ pub use logical_op::*;
 
 use crate::ast_expr::AstExpr;
 use crate::prelude::ContainerId;
+use crate::JoinType;
 
 mod delta_op;
 mod logical_op;
@@ -336,7 +337,11 @@ impl LogicalPlan {
         left_idx: OpIndex,
         right_idx: OpIndex,
     ) -> OpIndex {
-        let join = LogicalOp::Join(JoinNode { eqs, filter });
+        let join = LogicalOp::Join(JoinNode {
+            eqs,
+            filter,
+            join_type: JoinType::Inner,
+        });
         let join_idx = self.dataflow.add_node(join);
         self.dataflow.add_edge(join_idx, left_idx);
         self.dataflow.add_edge(join_idx, right_idx);
@@ -48,6 +48,50 @@ impl std::fmt::Display for BooleanOp {
     }
 }
 
+/// Which inputs of a join also output their tuples without a match, padded with nulls for the
+/// fields of the other input.
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum JoinType {
+    #[default]
+    Inner,
+    Left,
+    Right,
+    Full,
+}
+
+impl JoinType {
+    /// Whether left tuples without a match are output.
+    pub fn preserves_left(&self) -> bool {
+        matches!(self, JoinType::Left | JoinType::Full)
+    }
+
+    /// Whether right tuples without a match are output.
+    pub fn preserves_right(&self) -> bool {
+        matches!(self, JoinType::Right | JoinType::Full)
+    }
+
+    /// The same join with its inputs swapped.
+    pub fn swap(&self) -> Self {
+        match self {
+            JoinType::Left => JoinType::Right,
+            JoinType::Right => JoinType::Left,
+            other => *other,
+        }
+    }
+}
+
+impl std::fmt::Display for JoinType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use JoinType::*;
+        match self {
+            Inner => write!(f, "INNER"),
+            Left => write!(f, "LEFT OUTER"),
+            Right => write!(f, "RIGHT OUTER"),
+            Full => write!(f, "FULL OUTER"),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum AggOp {
     Avg,
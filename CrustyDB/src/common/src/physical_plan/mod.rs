@@ -16,11 +16,13 @@ mod physical_op;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum PhysicalOp {
     Scan(PhysicalScanNode),
+    IndexScan(PhysicalIndexScanNode),
     Project(PhysicalProjectNode),
     HashAggregate(PhysicalHashAggregateNode),
     CrossProduct(PhysicalCrossProductNode),
     NestedLoopJoin(PhysicalNestedLoopJoinNode),
     HashJoin(PhysicalHashJoinNode),
+    IndexNestedLoopJoin(PhysicalIndexNestedLoopJoinNode),
     Filter(PhysicalFilterNode),
     MaterializedView(MaterializedViewNode),
     Update(PhysicalUpdateNode),
@@ -281,6 +283,9 @@ impl PhysicalPlan {
                 materialized_view_state_id,
             })) => Some(*materialized_view_state_id),
             Some(PhysicalOp::Scan(PhysicalScanNode { container_id, .. })) => Some(*container_id),
+            Some(PhysicalOp::IndexScan(PhysicalIndexScanNode { container_id, .. })) => {
+                Some(*container_id)
+            }
             _ => None,
         }
     }
@@ -376,6 +381,14 @@ impl PhysicalPlan {
         if let Some(container_id) = self.get_container_id(op_index) {
             res.insert(container_id);
         }
+        // the inner table of an index join is read without a child scan
+        if let Some(PhysicalOp::IndexNestedLoopJoin(PhysicalIndexNestedLoopJoinNode {
+            container_id,
+            ..
+        })) = self.get_operator(op_index)
+        {
+            res.insert(*container_id);
+        }
 
         for child in self.edges(op_index) {
             let child_containers = self.get_containers_in_children(child);
@@ -1,5 +1,5 @@
 use crate::ast_expr::AstExpr;
-use crate::operation::BooleanOp;
+use crate::operation::{BooleanOp, JoinType};
 use crate::prelude::*;
 
 /// Physical Scan Operator
@@ -11,6 +11,22 @@ pub struct PhysicalScanNode {
     pub projection: Option<Vec<AstExpr>>,
 }
 
+/// Physical Index Scan Operator
+/// Reads the records of a table whose key in one of its indexes lies between the bounds.
+/// The bounds are on a prefix of the index key and hold whether they are inclusive.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PhysicalIndexScanNode {
+    /// Table to read.
+    pub container_id: ContainerId,
+    /// Index of the table to look the records up in.
+    pub index_id: ContainerId,
+    pub lower: Option<(Vec<Field>, bool)>,
+    pub upper: Option<(Vec<Field>, bool)>,
+    /// Predicate checked on every record read, the scan filter the bounds came from.
+    pub filter: Option<AstExpr>,
+    pub projection: Option<Vec<AstExpr>>,
+}
+
 /// Physical Project Operator
 /// Same as Logical
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -39,23 +55,62 @@ pub struct PhysicalHashAggregateNode {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct PhysicalCrossProductNode {}
+pub struct PhysicalCrossProductNode {
+    /// Predicate checked on the joined tuple.
+    pub filter: Option<AstExpr>,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PhysicalNestedLoopJoinNode {
-    pub left: AstExpr,
-    pub right: AstExpr,
+    /// Left sides of the key comparisons.
+    pub left: Vec<AstExpr>,
+    /// Right sides of the key comparisons.
+    pub right: Vec<AstExpr>,
+    /// Operator every left and right pair is compared with.
     pub op: BooleanOp,
+    /// Predicate checked on the joined tuple.
+    pub filter: Option<AstExpr>,
+    /// Children whose tuples without a match are padded with nulls.
+    #[serde(default)]
+    pub join_type: JoinType,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PhysicalHashJoinNode {
-    /// Left side of the operator.
-    pub left: AstExpr,
-    /// Right side of the operator.
-    pub right: AstExpr,
+    /// Left sides of the key equalities, computed by the build side.
+    pub left: Vec<AstExpr>,
+    /// Right sides of the key equalities.
+    pub right: Vec<AstExpr>,
     /// Predicate operator.
     pub op: BooleanOp,
+    /// Persisted hash index of the build side table, probed instead of building a hash table.
+    #[serde(default)]
+    pub hash_table: Option<ContainerId>,
+    /// Predicate checked on the joined tuple.
+    pub filter: Option<AstExpr>,
+    /// Children whose tuples without a match are padded with nulls.
+    #[serde(default)]
+    pub join_type: JoinType,
+}
+
+/// Physical Index Nested Loop Join Operator
+/// For every tuple of its single (outer) child, looks up the matching records of the inner table
+/// in an index of that table.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PhysicalIndexNestedLoopJoinNode {
+    /// Expressions on the outer tuple giving a prefix of the index key.
+    pub outer_exprs: Vec<AstExpr>,
+    /// Inner table.
+    pub container_id: ContainerId,
+    /// Index of the inner table.
+    pub index_id: ContainerId,
+    /// Filter and projection of the inner table scan the join replaced.
+    pub inner_filter: Option<AstExpr>,
+    pub inner_projection: Option<Vec<AstExpr>>,
+    /// Join predicate not answered by the index, checked on the joined tuple.
+    pub filter: Option<AstExpr>,
+    /// Whether the outer tuple comes first in the joined tuple.
+    pub outer_first: bool,
 }
 
 /// Physical Filter Operator
@@ -77,7 +132,7 @@ pub struct MaterializedViewNode {
 /// Physical Sort Node
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PhysicalSortNode {
-    pub fields: Vec<(AstExpr, bool)>, // (field, asc)
+    pub fields: Vec<(AstExpr, bool, bool)>, // (field, asc, nulls_first)
 }
 
 /// Physical Sort Merge Join Node
@@ -87,4 +142,11 @@ pub struct PhysicalSortMergeJoinNode {
     pub left_expr: Vec<(AstExpr, bool)>,
     /// Right side of the operator.
     pub right_expr: Vec<(AstExpr, bool)>,
+    /// Whether each child already returns its tuples in key order, so the join does not sort it.
+    #[serde(default)]
+    pub left_sorted: bool,
+    #[serde(default)]
+    pub right_sorted: bool,
+    /// Predicate checked on the joined tuple.
+    pub filter: Option<AstExpr>,
 }
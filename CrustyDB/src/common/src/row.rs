@@ -0,0 +1,401 @@
+use crate::ids::TidType;
+#[cfg(not(feature = "mvcc"))]
+use crate::NO_VERSION_POINTER;
+#[cfg(feature = "mvcc")]
+use crate::{pack_version_pointer, unpack_version_pointer};
+use crate::{CrustyError, DataType, Field, TableSchema, Tuple};
+
+/// First byte of a record in the row format. A record written with `Tuple::to_bytes` is a CBOR
+/// map, whose first byte is in 0xa0..=0xbf, so the two formats can share a table.
+pub const ROW_FORMAT: u8 = 0x01;
+
+/// Offsets of the header fields. The header has the same size whether or not the multi-version
+/// fields are used, so rows can be read by either build.
+const TID_OFFSET: usize = 1;
+const BEGIN_TS_OFFSET: usize = TID_OFFSET + 8;
+const END_TS_OFFSET: usize = BEGIN_TS_OFFSET + 8;
+const POINTER_OFFSET: usize = END_TS_OFFSET + 8;
+const HEADER_SIZE: usize = POINTER_OFFSET + 8;
+
+/// Where a column's value is found in a row
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    /// Offset into the fixed-width area
+    Fixed(usize),
+    /// Index into the string offset table
+    Var(usize),
+}
+
+/// The layout of the rows of a table, computed from its schema.
+///
+/// A row is the format byte, a fixed header with the tuple's tid, begin_ts, end_ts and
+/// tuple_pointer, packed with `pack_version_pointer`, a null
+/// bitmap with a bit per column, a fixed-width slot for every Int, Date,
+/// Decimal and Bool column in schema order, a table of u32 end offsets for the String columns,
+/// and the string bytes. Any column can be read without decoding the rest of the row.
+/// Records not in the row format are decoded with `Tuple::from_bytes`.
+#[derive(Debug, Clone)]
+pub struct RowLayout {
+    dtypes: Vec<DataType>,
+    slots: Vec<Slot>,
+    bitmap_size: usize,
+    fixed_size: usize,
+    num_strings: usize,
+}
+
+impl RowLayout {
+    pub fn new(schema: &TableSchema) -> Self {
+        let dtypes: Vec<DataType> = schema.attributes().map(|a| a.dtype().clone()).collect();
+        let mut slots = Vec::with_capacity(dtypes.len());
+        let mut fixed_size = 0;
+        let mut num_strings = 0;
+        for dtype in &dtypes {
+            match dtype.size() {
+                Some(size) => {
+                    slots.push(Slot::Fixed(fixed_size));
+                    fixed_size += size;
+                }
+                None => {
+                    slots.push(Slot::Var(num_strings));
+                    num_strings += 1;
+                }
+            }
+        }
+        RowLayout {
+            bitmap_size: dtypes.len().div_ceil(8),
+            dtypes,
+            slots,
+            fixed_size,
+            num_strings,
+        }
+    }
+
+    /// Number of columns
+    pub fn len(&self) -> usize {
+        self.dtypes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dtypes.is_empty()
+    }
+
+    /// Whether bytes hold a row in the row format rather than a CBOR encoded tuple
+    pub fn is_row(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&ROW_FORMAT)
+    }
+
+    fn fixed_start(&self) -> usize {
+        HEADER_SIZE + self.bitmap_size
+    }
+
+    fn strings_start(&self) -> usize {
+        self.fixed_start() + self.fixed_size + 4 * self.num_strings
+    }
+
+    /// Encode tuple as a row. Every field must be null or of its column's type.
+    pub fn encode(&self, tuple: &Tuple) -> Result<Vec<u8>, CrustyError> {
+        if tuple.len() != self.len() {
+            return Err(CrustyError::ValidationError(format!(
+                "Tuple has {} fields, table has {} columns",
+                tuple.len(),
+                self.len()
+            )));
+        }
+        let strings_len: usize = tuple
+            .field_vals()
+            .map(|f| match f {
+                Field::String(s) => s.len(),
+                _ => 0,
+            })
+            .sum();
+        let mut row = vec![0; self.strings_start() + strings_len];
+        row[0] = ROW_FORMAT;
+        write_header(tuple, &mut row);
+
+        let fixed_start = self.fixed_start();
+        let offsets_start = fixed_start + self.fixed_size;
+        let mut end = 0;
+        for (i, (field, (dtype, slot))) in tuple
+            .field_vals()
+            .zip(self.dtypes.iter().zip(&self.slots))
+            .enumerate()
+        {
+            let fixed = |off: usize| fixed_start + off;
+            match (field, dtype, *slot) {
+                (Field::Null, _, _) => row[HEADER_SIZE + i / 8] |= 1 << (i % 8),
+                (Field::Int(v), DataType::Int, Slot::Fixed(off))
+                | (Field::Date(v), DataType::Date, Slot::Fixed(off)) => {
+                    row[fixed(off)..fixed(off) + 8].copy_from_slice(&v.to_le_bytes());
+                }
+                (Field::Decimal(v, s), DataType::Decimal(_, _), Slot::Fixed(off)) => {
+                    row[fixed(off)..fixed(off) + 8].copy_from_slice(&v.to_le_bytes());
+                    row[fixed(off) + 8..fixed(off) + 12].copy_from_slice(&s.to_le_bytes());
+                }
+                (Field::Bool(b), DataType::Bool, Slot::Fixed(off)) => row[fixed(off)] = *b as u8,
+                (Field::String(s), DataType::String, Slot::Var(_)) => {
+                    let start = self.strings_start() + end;
+                    row[start..start + s.len()].copy_from_slice(s.as_bytes());
+                    end += s.len();
+                }
+                _ => {
+                    return Err(CrustyError::ValidationError(format!(
+                        "Field {:?} cannot be stored in column {} of type {}",
+                        field, i, dtype
+                    )))
+                }
+            }
+            if let Slot::Var(k) = slot {
+                let pos = offsets_start + 4 * k;
+                row[pos..pos + 4].copy_from_slice(&(end as u32).to_le_bytes());
+            }
+        }
+        Ok(row)
+    }
+
+    /// Decode a row, or a tuple encoded with `Tuple::to_bytes`.
+    pub fn decode(&self, bytes: &[u8]) -> Result<Tuple, CrustyError> {
+        if !Self::is_row(bytes) {
+            return Ok(Tuple::from_bytes(bytes));
+        }
+        self.check_size(bytes)?;
+        let field_vals = (0..self.len())
+            .map(|i| self.field_at(bytes, i))
+            .collect::<Result<Vec<Field>, CrustyError>>()?;
+        let mut tuple = Tuple::new(field_vals);
+        read_header(bytes, &mut tuple);
+        Ok(tuple)
+    }
+
+    /// Read the field of column i without decoding the rest of the row.
+    pub fn read_field(&self, bytes: &[u8], i: usize) -> Result<Field, CrustyError> {
+        let out_of_range = |len: usize| {
+            CrustyError::ValidationError(format!(
+                "Column {} out of range for a row of {} columns",
+                i, len
+            ))
+        };
+        if !Self::is_row(bytes) {
+            let tuple = Tuple::from_bytes(bytes);
+            return tuple
+                .get_field(i)
+                .cloned()
+                .ok_or_else(|| out_of_range(tuple.len()));
+        }
+        if i >= self.len() {
+            return Err(out_of_range(self.len()));
+        }
+        self.check_size(bytes)?;
+        self.field_at(bytes, i)
+    }
+
+    fn check_size(&self, bytes: &[u8]) -> Result<(), CrustyError> {
+        if bytes.len() < self.strings_start() {
+            return Err(CrustyError::SerializationError(format!(
+                "Row of {} bytes is shorter than its layout of {} bytes",
+                bytes.len(),
+                self.strings_start()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Read column i of a row in the row format at least strings_start bytes long
+    fn field_at(&self, bytes: &[u8], i: usize) -> Result<Field, CrustyError> {
+        if bytes[HEADER_SIZE + i / 8] & (1 << (i % 8)) != 0 {
+            return Ok(Field::Null);
+        }
+        let field = match (&self.dtypes[i], self.slots[i]) {
+            (DataType::Int, Slot::Fixed(off)) => {
+                Field::Int(read_i64(bytes, self.fixed_start() + off))
+            }
+            (DataType::Date, Slot::Fixed(off)) => {
+                Field::Date(read_i64(bytes, self.fixed_start() + off))
+            }
+            (DataType::Decimal(_, _), Slot::Fixed(off)) => {
+                let pos = self.fixed_start() + off;
+                Field::Decimal(read_i64(bytes, pos), read_u32(bytes, pos + 8))
+            }
+            (DataType::Bool, Slot::Fixed(off)) => Field::Bool(bytes[self.fixed_start() + off] != 0),
+            (DataType::String, Slot::Var(k)) => {
+                let offsets_start = self.fixed_start() + self.fixed_size;
+                let start = match k {
+                    0 => 0,
+                    _ => read_u32(bytes, offsets_start + 4 * (k - 1)) as usize,
+                };
+                let end = read_u32(bytes, offsets_start + 4 * k) as usize;
+                let strings = &bytes[self.strings_start()..];
+                let s = strings.get(start..end).ok_or_else(|| {
+                    CrustyError::SerializationError(format!(
+                        "String {}..{} of column {} is outside the row",
+                        start, end, i
+                    ))
+                })?;
+                Field::String(
+                    String::from_utf8(s.to_vec()).map_err(|e| {
+                        CrustyError::SerializationError(format!("Column {}: {}", i, e))
+                    })?,
+                )
+            }
+            (dtype, _) => {
+                return Err(CrustyError::SerializationError(format!(
+                    "Column {} of type {} has no value in the row layout",
+                    i, dtype
+                )))
+            }
+        };
+        Ok(field)
+    }
+}
+
+/// Encode tuple with layout, or with `Tuple::to_bytes` for a table without one.
+pub fn encode_row(layout: Option<&RowLayout>, tuple: &Tuple) -> Result<Vec<u8>, CrustyError> {
+    match layout {
+        Some(layout) => layout.encode(tuple),
+        None => Ok(tuple.to_bytes()),
+    }
+}
+
+/// Decode a record with layout, or with `Tuple::from_bytes` for a table without one.
+pub fn decode_row(layout: Option<&RowLayout>, bytes: &[u8]) -> Result<Tuple, CrustyError> {
+    match layout {
+        Some(layout) => layout.decode(bytes),
+        None => Ok(Tuple::from_bytes(bytes)),
+    }
+}
+
+fn read_i64(bytes: &[u8], pos: usize) -> i64 {
+    i64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap())
+}
+
+#[cfg(feature = "mvcc")]
+fn header_fields(tuple: &Tuple) -> (TidType, TidType, u64) {
+    (
+        tuple.begin_ts,
+        tuple.end_ts,
+        pack_version_pointer(tuple.tuple_pointer),
+    )
+}
+
+#[cfg(not(feature = "mvcc"))]
+fn header_fields(_tuple: &Tuple) -> (TidType, TidType, u64) {
+    (0, TidType::MAX, NO_VERSION_POINTER)
+}
+
+fn write_header(tuple: &Tuple, row: &mut [u8]) {
+    let (begin_ts, end_ts, pointer) = header_fields(tuple);
+    row[TID_OFFSET..TID_OFFSET + 8].copy_from_slice(&tuple.tid.to_le_bytes());
+    row[BEGIN_TS_OFFSET..BEGIN_TS_OFFSET + 8].copy_from_slice(&begin_ts.to_le_bytes());
+    row[END_TS_OFFSET..END_TS_OFFSET + 8].copy_from_slice(&end_ts.to_le_bytes());
+    row[POINTER_OFFSET..POINTER_OFFSET + 8].copy_from_slice(&pointer.to_le_bytes());
+}
+
+fn read_header(bytes: &[u8], tuple: &mut Tuple) {
+    tuple.tid = read_u64(bytes, TID_OFFSET);
+    #[cfg(feature = "mvcc")]
+    {
+        tuple.begin_ts = read_u64(bytes, BEGIN_TS_OFFSET);
+        tuple.end_ts = read_u64(bytes, END_TS_OFFSET);
+        tuple.tuple_pointer = unpack_version_pointer(read_u64(bytes, POINTER_OFFSET));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn schema() -> TableSchema {
+        TableSchema::from_vecs(
+            vec!["a", "b", "c", "d", "e", "f", "g", "h", "i"],
+            vec![
+                DataType::Int,
+                DataType::String,
+                DataType::Decimal(10, 2),
+                DataType::Date,
+                DataType::String,
+                DataType::Bool,
+                DataType::Int,
+                DataType::String,
+                DataType::Int,
+            ],
+        )
+    }
+
+    fn tuple() -> Tuple {
+        let mut tuple = Tuple::new(vec![
+            Field::Int(-7),
+            Field::String("hello".to_string()),
+            Field::Decimal(12345, 2),
+            Field::Date(19000),
+            Field::Null,
+            Field::Bool(true),
+            Field::Null,
+            Field::String("".to_string()),
+            Field::Int(i64::MAX),
+        ]);
+        tuple.tid = 42;
+        tuple
+    }
+
+    #[test]
+    fn test_row_roundtrip() {
+        let layout = RowLayout::new(&schema());
+        let mut tuple = tuple();
+        #[cfg(feature = "mvcc")]
+        {
+            tuple.begin_ts = 3;
+            tuple.end_ts = 9;
+            tuple.tuple_pointer = Some(crate::ids::ValueId::new_slot(1, 2, 3));
+        }
+        let bytes = layout.encode(&tuple).unwrap();
+        assert!(RowLayout::is_row(&bytes));
+        assert!(bytes.len() < tuple.to_bytes().len());
+        assert_eq!(tuple, layout.decode(&bytes).unwrap());
+        tuple.field_vals = vec![Field::Null; 9];
+        let bytes = layout.encode(&tuple).unwrap();
+        assert_eq!(tuple, layout.decode(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_row_read_field() {
+        let layout = RowLayout::new(&schema());
+        let tuple = tuple();
+        let bytes = layout.encode(&tuple).unwrap();
+        for (i, field) in tuple.field_vals().enumerate() {
+            assert_eq!(field, &layout.read_field(&bytes, i).unwrap());
+        }
+        assert!(layout.read_field(&bytes, 9).is_err());
+        assert!(layout.read_field(&bytes[..20], 0).is_err());
+    }
+
+    #[test]
+    fn test_row_reads_cbor() {
+        let layout = RowLayout::new(&schema());
+        let tuple = tuple();
+        let bytes = tuple.to_bytes();
+        assert!(!RowLayout::is_row(&bytes));
+        assert_eq!(tuple, layout.decode(&bytes).unwrap());
+        assert_eq!(
+            Field::Decimal(12345, 2),
+            layout.read_field(&bytes, 2).unwrap()
+        );
+        assert!(layout.read_field(&bytes, 9).is_err());
+    }
+
+    #[test]
+    fn test_row_type_mismatch() {
+        let layout = RowLayout::new(&schema());
+        let mut tuple = tuple();
+        tuple.set_field(0, Field::String("x".to_string()));
+        assert!(layout.encode(&tuple).is_err());
+        tuple.field_vals.pop();
+        assert!(layout.encode(&tuple).is_err());
+    }
+}
@@ -13,13 +13,28 @@ use crate::prelude::*;
 /// shared across threads.
 pub trait StorageTrait {
     /// The associated type of the iterator that will need to be written and defined for the storage manager
-    /// This iterator will be used to scan records of a container
-    type ValIterator: Iterator<Item = (Vec<u8>, ValueId)>;
+    /// This iterator will be used to scan records of a container. A record or page that cannot be
+    /// read is returned as an error and ends the scan.
+    type ValIterator: Iterator<Item = Result<(Vec<u8>, ValueId), CrustyError>>;
 
     /// Create a new storage manager that will use storage_dir as the location to persist data
     /// (if the storage manager persists records on disk)
     fn new(storage_dir: &Path) -> Self;
 
+    /// Open the storage manager persisted in storage_dir, or create one whose pages are
+    /// page_size bytes, the default size if None. An existing storage manager keeps the page size
+    /// it was created with, and asking it for another is an error. Storage managers without
+    /// pages, or with a fixed page size, ignore page_size.
+    fn open_with_page_size(
+        storage_dir: &Path,
+        _page_size: Option<usize>,
+    ) -> Result<Self, CrustyError>
+    where
+        Self: Sized,
+    {
+        Ok(Self::new(storage_dir))
+    }
+
     /// Create a new storage manager for testing. If this creates a temporary directory it should be cleaned up
     /// when it leaves scope.
     fn new_test_sm() -> Self;
@@ -57,6 +72,60 @@ pub trait StorageTrait {
         tid: TransactionId,
     ) -> Result<ValueId, CrustyError>;
 
+    /// Overwrite a value keeping its ValueId, for structures that link values by id (e.g. index
+    /// nodes). Error if the value is not found or the new value does not fit where the old one is.
+    fn replace_value(
+        &self,
+        value: Vec<u8>,
+        id: ValueId,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError>;
+
+    /// Reclaim the space of deleted values in a container and return the old and new id of every
+    /// value that moved. Values only move if move_values is set, and the caller must then point
+    /// whatever refers to them (e.g. index entries) at their new ids. Storage managers without
+    /// pages have nothing to reclaim.
+    fn vacuum(
+        &self,
+        _container_id: ContainerId,
+        _move_values: bool,
+        _tid: TransactionId,
+    ) -> Result<Vec<(ValueId, ValueId)>, CrustyError> {
+        Ok(Vec::new())
+    }
+
+    /// Check the stored pages of a container for corruption and inconsistent bookkeeping, and
+    /// return a description of every problem found. Storage managers without pages have nothing
+    /// to check.
+    fn check(&self, _container_id: ContainerId) -> Result<Vec<String>, CrustyError> {
+        Ok(Vec::new())
+    }
+
+    /// Called when tid commits. A storage manager that logs changes makes every change of tid
+    /// durable, as until then recovery undoes the changes of tid after a crash.
+    fn commit(&self, _tid: TransactionId) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
+    /// Called when tid rolls back, once the caller undid the changes of tid. Recovery has
+    /// nothing left to undo for tid.
+    fn abort(&self, _tid: TransactionId) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
+    /// Whether opening the storage manager recovered from a crash. Anything kept in sync with
+    /// the stored values by separate writes, such as indexes, may then disagree with them.
+    fn recovered(&self) -> bool {
+        false
+    }
+
+    /// Make the stored values durable without waiting for shutdown, so a log of changes kept for
+    /// recovery can be cut short while transactions run. Storage managers that keep no log have
+    /// nothing to do.
+    fn checkpoint(&self) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
     /// Create a new container to be stored.
     /// fn create_container(&self, name: String) -> ContainerId;
     /// Creates a new container object.
@@ -109,6 +178,12 @@ pub trait StorageTrait {
     /// Get the base storage path for the storage manager
     fn get_storage_path(&self) -> &Path;
 
+    /// Bytes in every page, for callers that size what they store to fit in a page. Storage
+    /// managers without pages return the default page size.
+    fn page_size(&self) -> usize {
+        crate::PAGE_SIZE
+    }
+
     /// Reset all state associated the storage manager.
     /// Deletes all tables and stored items
     fn reset(&self) -> Result<(), CrustyError>;
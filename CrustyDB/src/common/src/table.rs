@@ -1,5 +1,5 @@
 use crate::ids::ContainerId;
-use crate::TableSchema;
+use crate::{Attribute, Field, TableSchema, Tuple};
 
 /// Table implementation.
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -16,3 +16,54 @@ impl TableInfo {
         TableInfo { c_id, name, schema }
     }
 }
+
+/// How an index is organized.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum IndexKind {
+    /// B+tree, answers equality and range lookups on a prefix of the key.
+    #[default]
+    BTree,
+    /// Hash table, answers equality lookups on the whole key.
+    Hash,
+}
+
+impl std::fmt::Display for IndexKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexKind::BTree => write!(f, "btree"),
+            IndexKind::Hash => write!(f, "hash"),
+        }
+    }
+}
+
+/// Index implementation.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct IndexInfo {
+    /// Container holding the index.
+    pub c_id: ContainerId,
+    /// Index name.
+    pub name: String,
+    /// Table the index is on.
+    pub table_id: ContainerId,
+    /// Positions of the key columns in the table schema, in key order.
+    pub key_columns: Vec<usize>,
+    /// Attributes of the key columns.
+    pub attributes: Vec<Attribute>,
+    /// No two records may have the same key.
+    pub is_unique: bool,
+    /// The index enforces the primary key, so keys may not be null either.
+    pub is_primary: bool,
+    /// How the index is organized.
+    #[serde(default)]
+    pub kind: IndexKind,
+}
+
+impl IndexInfo {
+    /// The key of a tuple of the indexed table
+    pub fn key_of(&self, tuple: &Tuple) -> Vec<Field> {
+        self.key_columns
+            .iter()
+            .map(|i| tuple.get_field(*i).cloned().unwrap_or(Field::Null))
+            .collect()
+    }
+}
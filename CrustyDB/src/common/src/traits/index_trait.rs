@@ -1,15 +1,38 @@
 use crate::prelude::*;
 
+/// An index maps keys, the values of one or more columns of a table, to the ValueIds of the
+/// records holding them. Keys given to lookups may be a prefix of the index key.
 pub trait IndexTrait {
-    /// get a list of values that are equal to a field
-    fn equality_get_value_ids(field: Field) -> Vec<ValueId>;
+    /// get a list of values whose key starts with key
+    fn equality_get_value_ids(
+        &self,
+        key: &[Field],
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError>;
+
     /// Option set to none means min or max. inclusive set to false means exclusive. Calling on non-range idx will give empty vec
     fn range_get_value_ids(
-        min_field: Option<Field>,
-        max_field: Option<Field>,
+        &self,
+        min_key: Option<&[Field]>,
+        max_key: Option<&[Field]>,
         min_inclusive: bool,
         max_inclusive: bool,
-    ) -> Vec<ValueId>;
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError>;
+
+    /// Add an entry for each value id and its key
+    fn add_values(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError>;
 
-    fn add_values(value_ids: Vec<ValueId>, fields: Vec<Field>) -> Result<(), CrustyError>;
+    /// Remove the entries for each value id and its key
+    fn remove_values(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError>;
 }
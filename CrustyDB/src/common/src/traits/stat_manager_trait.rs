@@ -35,5 +35,13 @@ pub trait StatManagerTrait {
         filter: Option<AstExpr>,
     ) -> Result<(usize, f64), CrustyError>;
 
+    /// Estimate the bytes of the records of a container that satisfy the predicate, or of all
+    /// its records without one.
+    fn estimate_size(
+        &self,
+        c_id: ContainerId,
+        predicate: Option<AstExpr>,
+    ) -> Result<usize, CrustyError>;
+
     fn get_container_record_count(&self, c_id: ContainerId) -> Result<usize, CrustyError>;
 }
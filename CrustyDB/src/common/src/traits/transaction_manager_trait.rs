@@ -1,25 +1,87 @@
 use crate::{ast_expr::AstExpr, ids::TupleAssignments, prelude::*};
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::Duration;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum IsolationLevel {
     ReadCommitted,
+    /// Every transaction reads the versions committed before it started
+    SnapshotIsolation,
+    /// Transactions behave as if they ran one after the other. A transaction is rolled back at
+    /// commit when a transaction that committed while it ran wrote a record matching one of its
+    /// predicates.
+    Serializable,
+}
+
+/// A change a transaction made to a table, kept so it can be undone if the transaction rolls back
+#[derive(Clone, Debug, PartialEq)]
+pub enum TxnWrite {
+    /// tuple was inserted at id
+    Insert { id: ValueId, tuple: Tuple },
+    /// The record old at old_id was changed to new, which may have moved to new_id
+    Update {
+        old_id: ValueId,
+        old: Tuple,
+        new_id: ValueId,
+        new: Tuple,
+    },
+    /// new was inserted at new_id as the next version of old, the record at old_id, which stays
+    /// for older snapshots
+    Version {
+        old_id: ValueId,
+        old: Tuple,
+        new_id: ValueId,
+        new: Tuple,
+    },
+    /// A bulk load inserted records at ids, oldest first, logged without their values, which
+    /// are read back from the table to undo the inserts
+    Load { ids: Vec<ValueId> },
 }
 
 pub trait TransactionManagerTrait {
+    /// Whether an update inserts a new version of the record and keeps the old one instead of
+    /// changing the record in place.
+    const MULTI_VERSION: bool = false;
+
+    /// Whether read_record locks the record, so a copy of the record read before the call may
+    /// be stale once tid holds the lock.
+    const LOCKS_RECORDS: bool = false;
+
     fn new(storage_path: &Path) -> Self;
 
     fn shutdown(&mut self) -> Result<(), CrustyError>;
 
     fn set_isolation_level(&self, lvl: IsolationLevel) -> Result<(), CrustyError>;
 
+    /// How long a transaction may wait for a lock before it is rolled back, None to wait
+    /// forever. Managers that do not lock ignore it.
+    fn set_lock_timeout(&self, _timeout: Option<Duration>) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
+    /// Abort the youngest transaction of every cycle of transactions waiting for each other's
+    /// locks and return the aborted transactions. Managers that do not lock have no deadlocks.
+    fn detect_deadlocks(&self) -> Result<Vec<TransactionId>, CrustyError> {
+        Ok(Vec::new())
+    }
+
     fn start_transaction(&self, tid: TransactionId) -> Result<(), CrustyError>;
 
-    fn read_record(
+    /// Called before tid reads the record at value_id. Managers that lock records block until
+    /// tid holds a shared lock on it.
+    fn read_record(&self, value_id: &ValueId, tid: &TransactionId) -> Result<(), CrustyError>;
+
+    /// Whether tid may see tuple, the record at value_id. Managers that keep a single version
+    /// of each record show every record.
+    fn is_visible(
         &self,
-        tuple: &Tuple,
-        value_id: &ValueId,
-        tid: &TransactionId,
-    ) -> Result<(), CrustyError>;
+        _tuple: &Tuple,
+        _value_id: &ValueId,
+        _tid: &TransactionId,
+    ) -> Result<bool, CrustyError> {
+        Ok(true)
+    }
 
     fn pre_update_record(
         &self,
@@ -47,10 +109,78 @@ pub trait TransactionManagerTrait {
         tid: TransactionId,
     ) -> Result<(), CrustyError>;
 
-    fn read_predicate(&self, predicate: AstExpr, tid: TransactionId) -> Result<(), CrustyError>;
+    /// Tell the transaction manager tid reads the records of the table in container_id that
+    /// match predicate, an expression bound to the table's schema.
+    fn read_predicate(
+        &self,
+        container_id: ContainerId,
+        predicate: AstExpr,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError>;
 
     fn validate_txn(&self, tid: TransactionId) -> Result<(), CrustyError>;
 
+    /// Mark old, a version tid replaced with the version at new_id, as ended by tid and link it
+    /// to the new version once tid is validated. Error with TransactionRollback if a transaction
+    /// that committed first already ended old. The caller writes old back in place, with the
+    /// commits of other transactions that end versions held off until tid commits.
+    fn end_version(
+        &self,
+        _old: &mut Tuple,
+        _new_id: ValueId,
+        _tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
+    /// Whether no running or later transaction can see tuple, a version at value_id replaced by
+    /// a committed transaction, so it can be removed. Managers that keep a single version of
+    /// each record never leave dead versions behind.
+    fn is_dead(&self, _tuple: &Tuple, _value_id: &ValueId) -> Result<bool, CrustyError> {
+        Ok(false)
+    }
+
+    /// Point tuple, a version, at the new id of the newer version it links to if that version
+    /// moved, e.g. when a vacuum moves records. Returns whether tuple changed. Managers that keep
+    /// a single version of each record link no versions.
+    fn version_moved(&self, _tuple: &mut Tuple, _moved: &HashMap<ValueId, ValueId>) -> bool {
+        false
+    }
+
+    /// Whether tuple, the version at value_id, still holds its key in the unique indexes tid
+    /// writes to, as it was not replaced by tid or by a committed transaction. Managers that
+    /// keep a single version of each record count every record.
+    fn holds_key(
+        &self,
+        _tuple: &Tuple,
+        _value_id: &ValueId,
+        _tid: &TransactionId,
+    ) -> Result<bool, CrustyError> {
+        Ok(true)
+    }
+
+    /// Whether a transaction other than tid is running, i.e. started and not ended, or has
+    /// writes it could still undo. Running transactions find records by id, e.g. to read them
+    /// again or undo their writes, so records must not move while this is true.
+    fn others_running(&self, tid: TransactionId) -> Result<bool, CrustyError>;
+
+    /// Keep only the ids of the records tid inserts from now on, so a bulk load does not hold
+    /// them all in memory. The inserts are still undone when tid rolls back, see TxnWrite::Load.
+    fn log_insert_ids(&self, tid: TransactionId) -> Result<(), CrustyError>;
+
+    /// A mark of the writes tid has made so far, to pass to `take_writes` later.
+    fn savepoint(&self, tid: TransactionId) -> Result<usize, CrustyError>;
+
+    /// Forget the writes tid made after savepoint and return them newest first, for the caller
+    /// to undo in that order.
+    fn take_writes(
+        &self,
+        tid: TransactionId,
+        savepoint: usize,
+    ) -> Result<Vec<TxnWrite>, CrustyError>;
+
+    /// End tid, forgetting the writes it did not take back. Writes must be undone with
+    /// `take_writes` before rolling back.
     fn rollback_txn(&self, tid: TransactionId) -> Result<(), CrustyError>;
 
     fn commit_txn(&self, tid: TransactionId) -> Result<(), CrustyError>;
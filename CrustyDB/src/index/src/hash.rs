@@ -0,0 +1,619 @@
+use crate::node::{self, Entry, HoldsKey, NODE_SIZE};
+use crate::{StorageManager, StorageTrait};
+use common::ids::SegmentId;
+use common::prelude::*;
+use common::traits::index_trait::IndexTrait;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+/// Buckets of a new index. The number of buckets doubles every round of splits.
+const INITIAL_BUCKETS: usize = 4;
+/// Bucket locations held by one directory record
+const DIRECTORY_FANOUT: usize = 400;
+/// Room kept in a bucket record for everything but its entries
+const BUCKET_OVERHEAD: usize = 128;
+
+/// Where a record of the index container is. Shorter than a ValueId so directories hold more.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+struct Loc(Option<SegmentId>, Option<PageId>, Option<SlotId>);
+
+impl Loc {
+    /// The location taking the most bytes, used to keep room for an overflow link
+    const LONGEST: Loc = Loc(Some(SegmentId::MAX), Some(PageId::MAX), Some(SlotId::MAX));
+
+    fn of(id: ValueId) -> Self {
+        Loc(id.segment_id, id.page_id, id.slot_id)
+    }
+
+    fn id(self, c_id: ContainerId) -> ValueId {
+        ValueId {
+            container_id: c_id,
+            segment_id: self.0,
+            page_id: self.1,
+            slot_id: self.2,
+        }
+    }
+}
+
+/// A record of the index container. The meta record is the first record of the container and
+/// lists the directories, which list the first record of every bucket in bucket order.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+enum Record {
+    Meta {
+        info: IndexInfo,
+        /// Splits done so far, as the round of splits and the next bucket to split in it
+        level: u32,
+        next_split: usize,
+        num_entries: usize,
+        directories: Vec<Loc>,
+    },
+    Directory {
+        buckets: Vec<Loc>,
+    },
+    /// Entries of a bucket and the next record of the bucket when they do not fit in one
+    Bucket {
+        entries: Vec<Entry>,
+        overflow: Option<Loc>,
+    },
+}
+
+impl Record {
+    fn fits(&self) -> bool {
+        node::fits(self)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, CrustyError> {
+        node::to_node_bytes(self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, CrustyError> {
+        node::from_node_bytes(bytes)
+    }
+}
+
+/// FNV-1a hash of the encoded key. It does not depend on the build or the process, so the
+/// buckets of a stored index stay valid.
+fn hash_key(key: &[Field]) -> u64 {
+    serde_cbor::to_vec(&key)
+        .unwrap()
+        .iter()
+        .fold(0xcbf29ce484222325, |h, b| {
+            (h ^ *b as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// Split state of the index, with the buckets cached from the directories
+#[derive(Default)]
+struct State {
+    level: u32,
+    next_split: usize,
+    num_entries: usize,
+    directories: Vec<ValueId>,
+    buckets: Vec<ValueId>,
+}
+
+impl State {
+    /// The bucket holding key. Buckets before next_split were already split in this round and
+    /// use the hash of the next round.
+    fn bucket_of(&self, key: &[Field]) -> usize {
+        let hash = hash_key(key);
+        let round = INITIAL_BUCKETS << self.level;
+        let bucket = (hash % round as u64) as usize;
+        if bucket < self.next_split {
+            (hash % (2 * round) as u64) as usize
+        } else {
+            bucket
+        }
+    }
+}
+
+/// A linear hash index stored in its own container of the storage manager. It answers
+/// equality lookups on the whole key only.
+///
+/// Like the tree, every record is padded to NODE_SIZE and rewritten in place. A bucket is a
+/// chain of records. When the index holds more entries than its buckets are meant to, the
+/// next bucket in split order is split in two, so the index grows one bucket at a time. Writers
+/// hold the state lock exclusively, readers share it.
+pub struct HashIndex {
+    info: IndexInfo,
+    meta_id: ValueId,
+    state: RwLock<State>,
+    sm: &'static StorageManager,
+}
+
+impl HashIndex {
+    /// Create the container for a new, empty index described by info.
+    pub fn create(
+        info: IndexInfo,
+        sm: &'static StorageManager,
+        tid: TransactionId,
+    ) -> Result<Self, CrustyError> {
+        sm.create_container(
+            info.c_id,
+            Some(info.name.clone()),
+            StateType::HashTable,
+            Some(vec![info.table_id]),
+        )?;
+        let mut index = HashIndex {
+            meta_id: ValueId::new(info.c_id),
+            state: RwLock::new(State::default()),
+            info,
+            sm,
+        };
+        // The meta record goes first so open finds it without reading the buckets
+        let mut state = State::default();
+        index.meta_id = index.alloc(&index.meta(&state), tid)?;
+        for _ in 0..INITIAL_BUCKETS {
+            index.add_bucket(&mut state, Vec::new(), tid)?;
+        }
+        index.write(index.meta_id, &index.meta(&state), tid)?;
+        *index.state.get_mut()? = state;
+        Ok(index)
+    }
+
+    /// Open an index created earlier in container c_id.
+    pub fn open(
+        c_id: ContainerId,
+        sm: &'static StorageManager,
+        tid: TransactionId,
+    ) -> Result<Self, CrustyError> {
+        let meta = sm
+            .get_iterator(c_id, tid, Permissions::ReadOnly)
+            .find_map(|scanned| match scanned {
+                Ok((bytes, id)) => match Record::from_bytes(&bytes) {
+                    Ok(Record::Meta {
+                        info,
+                        level,
+                        next_split,
+                        num_entries,
+                        directories,
+                    }) => Some(Ok((info, level, next_split, num_entries, directories, id))),
+                    _ => None,
+                },
+                Err(e) => Some(Err(e)),
+            })
+            .transpose()?;
+        let (info, level, next_split, num_entries, directories, meta_id) =
+            meta.ok_or_else(|| {
+                CrustyError::CrustyError(format!("No index found in container {}", c_id))
+            })?;
+        let directories: Vec<ValueId> = directories.iter().map(|d| d.id(c_id)).collect();
+        let mut buckets = Vec::new();
+        for directory in &directories {
+            match Record::from_bytes(&sm.get_value(*directory, tid, Permissions::ReadOnly)?)? {
+                Record::Directory { buckets: b } => buckets.extend(b.iter().map(|l| l.id(c_id))),
+                _ => return Err(Self::corrupt(*directory)),
+            }
+        }
+        Ok(HashIndex {
+            info,
+            meta_id,
+            state: RwLock::new(State {
+                level,
+                next_split,
+                num_entries,
+                directories,
+                buckets,
+            }),
+            sm,
+        })
+    }
+
+    pub fn info(&self) -> &IndexInfo {
+        &self.info
+    }
+
+    pub fn container_id(&self) -> ContainerId {
+        self.info.c_id
+    }
+
+    /// Check that key may be added for the record id. See [`TreeIndex::check_key`].
+    ///
+    /// [`TreeIndex::check_key`]: crate::TreeIndex::check_key
+    pub fn check_key(
+        &self,
+        key: &[Field],
+        id: Option<ValueId>,
+        holds_key: HoldsKey,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        node::check_key(self, &self.info, key, id, holds_key, tid)
+    }
+
+    /// Add entries like add_values, where a key only conflicts with the records that hold it.
+    pub fn add_values_checked(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        holds_key: HoldsKey,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let entries = node::entries(&self.info, value_ids, keys)?;
+        node::check_batch(&self.info, &entries, holds_key)?;
+        for entry in &entries {
+            self.check_key(&entry.key, Some(entry.id), holds_key, tid)?;
+        }
+        let mut state = self.state.write()?;
+        for entry in entries {
+            // Split until the buckets are about three quarters full on average
+            let per_bucket = ((NODE_SIZE - BUCKET_OVERHEAD) / entry.encoded_len() * 3 / 4).max(1);
+            if self.insert_entry(&state, entry, tid)? {
+                state.num_entries += 1;
+            }
+            while state.num_entries > state.buckets.len() * per_bucket {
+                self.split(&mut state, tid)?;
+            }
+        }
+        self.write(self.meta_id, &self.meta(&state), tid)
+    }
+
+    fn meta(&self, state: &State) -> Record {
+        Record::Meta {
+            info: self.info.clone(),
+            level: state.level,
+            next_split: state.next_split,
+            num_entries: state.num_entries,
+            directories: state.directories.iter().map(|d| Loc::of(*d)).collect(),
+        }
+    }
+
+    fn read(&self, id: ValueId, tid: TransactionId) -> Result<Record, CrustyError> {
+        Record::from_bytes(&self.sm.get_value(id, tid, Permissions::ReadOnly)?)
+    }
+
+    fn write(&self, id: ValueId, record: &Record, tid: TransactionId) -> Result<(), CrustyError> {
+        self.sm.replace_value(record.to_bytes()?, id, tid)
+    }
+
+    fn alloc(&self, record: &Record, tid: TransactionId) -> Result<ValueId, CrustyError> {
+        Ok(self
+            .sm
+            .insert_value(self.info.c_id, record.to_bytes()?, tid))
+    }
+
+    fn corrupt(id: ValueId) -> CrustyError {
+        CrustyError::CrustyError(format!("Unexpected index record at {:?}", id))
+    }
+
+    /// The records of a bucket chain with their entries
+    fn read_chain(
+        &self,
+        head: ValueId,
+        tid: TransactionId,
+    ) -> Result<Vec<(ValueId, Vec<Entry>)>, CrustyError> {
+        let mut chain = Vec::new();
+        let mut next = Some(head);
+        while let Some(id) = next {
+            match self.read(id, tid)? {
+                Record::Bucket { entries, overflow } => {
+                    chain.push((id, entries));
+                    next = overflow.map(|l| l.id(self.info.c_id));
+                }
+                _ => return Err(Self::corrupt(id)),
+            }
+        }
+        Ok(chain)
+    }
+
+    /// Store entries in the chain of records, reusing them in order, allocating more records
+    /// if the entries need them and deleting the records they do not need. Returns the head.
+    fn write_chain(
+        &self,
+        mut records: Vec<ValueId>,
+        entries: Vec<Entry>,
+        tid: TransactionId,
+    ) -> Result<ValueId, CrustyError> {
+        let mut groups = vec![Vec::new()];
+        let mut size = 0;
+        for entry in entries {
+            let len = entry.encoded_len();
+            if size + len > NODE_SIZE - BUCKET_OVERHEAD && !groups.last().unwrap().is_empty() {
+                groups.push(Vec::new());
+                size = 0;
+            }
+            size += len;
+            groups.last_mut().unwrap().push(entry);
+        }
+        // Write from the tail so every record points to one that exists
+        let mut overflow = None;
+        let mut unused = records.split_off(groups.len().min(records.len()));
+        let mut head = None;
+        for (i, group) in groups.into_iter().enumerate().rev() {
+            let record = Record::Bucket {
+                entries: group,
+                overflow,
+            };
+            let id = match records.get(i) {
+                Some(id) => {
+                    self.write(*id, &record, tid)?;
+                    *id
+                }
+                None => self.alloc(&record, tid)?,
+            };
+            overflow = Some(Loc::of(id));
+            head = Some(id);
+        }
+        for id in unused.drain(..) {
+            self.sm.delete_value(id, tid)?;
+        }
+        Ok(head.unwrap())
+    }
+
+    /// Append a bucket holding entries, adding a directory when the last one is full.
+    fn add_bucket(
+        &self,
+        state: &mut State,
+        entries: Vec<Entry>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let head = self.write_chain(Vec::new(), entries, tid)?;
+        state.buckets.push(head);
+        let first = (state.buckets.len() - 1) / DIRECTORY_FANOUT * DIRECTORY_FANOUT;
+        let directory = Record::Directory {
+            buckets: state.buckets[first..].iter().map(|b| Loc::of(*b)).collect(),
+        };
+        if first == state.buckets.len() - 1 {
+            let id = self.alloc(&directory, tid)?;
+            state.directories.push(id);
+            // A new directory is only found through the meta record
+            self.write(self.meta_id, &self.meta(state), tid)
+        } else {
+            self.write(*state.directories.last().unwrap(), &directory, tid)
+        }
+    }
+
+    /// Split the next bucket in split order, moving the entries that hash to the new bucket.
+    fn split(&self, state: &mut State, tid: TransactionId) -> Result<(), CrustyError> {
+        let round = INITIAL_BUCKETS << state.level;
+        let old = state.next_split;
+        let chain = self.read_chain(state.buckets[old], tid)?;
+        let (records, entries): (Vec<ValueId>, Vec<Vec<Entry>>) = chain.into_iter().unzip();
+        let (stay, moved): (Vec<Entry>, Vec<Entry>) = entries
+            .into_iter()
+            .flatten()
+            .partition(|e| (hash_key(&e.key) % (2 * round) as u64) as usize == old);
+        self.add_bucket(state, moved, tid)?;
+        self.write_chain(records, stay, tid)?;
+        state.next_split += 1;
+        if state.next_split == round {
+            state.level += 1;
+            state.next_split = 0;
+        }
+        Ok(())
+    }
+
+    /// Add entry to its bucket, in the first record of the chain with room for it.
+    fn insert_entry(
+        &self,
+        state: &State,
+        entry: Entry,
+        tid: TransactionId,
+    ) -> Result<bool, CrustyError> {
+        let chain = self.read_chain(state.buckets[state.bucket_of(&entry.key)], tid)?;
+        if chain.iter().any(|(_, entries)| entries.contains(&entry)) {
+            return Ok(false);
+        }
+        for (i, (id, entries)) in chain.iter().enumerate() {
+            let mut entries = entries.clone();
+            entries.push(entry.clone());
+            let overflow = chain.get(i + 1).map(|(next, _)| Loc::of(*next));
+            // The last record keeps room to link an overflow record later
+            let widest = Record::Bucket {
+                entries: entries.clone(),
+                overflow: Some(overflow.unwrap_or(Loc::LONGEST)),
+            };
+            if widest.fits() {
+                self.write(*id, &Record::Bucket { entries, overflow }, tid)?;
+                return Ok(true);
+            }
+        }
+        let (last, entries) = chain.last().unwrap();
+        let new = self.alloc(
+            &Record::Bucket {
+                entries: vec![entry],
+                overflow: None,
+            },
+            tid,
+        )?;
+        self.write(
+            *last,
+            &Record::Bucket {
+                entries: entries.clone(),
+                overflow: Some(Loc::of(new)),
+            },
+            tid,
+        )?;
+        Ok(true)
+    }
+
+    fn remove_entry(
+        &self,
+        state: &State,
+        entry: &Entry,
+        tid: TransactionId,
+    ) -> Result<bool, CrustyError> {
+        let chain = self.read_chain(state.buckets[state.bucket_of(&entry.key)], tid)?;
+        for (i, (id, entries)) in chain.iter().enumerate() {
+            if let Some(pos) = entries.iter().position(|e| e == entry) {
+                let mut entries = entries.clone();
+                entries.remove(pos);
+                let record = Record::Bucket {
+                    entries,
+                    overflow: chain.get(i + 1).map(|(next, _)| Loc::of(*next)),
+                };
+                self.write(*id, &record, tid)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl IndexTrait for HashIndex {
+    fn equality_get_value_ids(
+        &self,
+        key: &[Field],
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        if key.len() != self.info.key_columns.len() {
+            return Err(CrustyError::ExecutionError(format!(
+                "Hash index {} needs all {} key columns, lookup uses {}",
+                self.info.name,
+                self.info.key_columns.len(),
+                key.len()
+            )));
+        }
+        if key.contains(&Field::Null) {
+            return Ok(Vec::new());
+        }
+        let state = self.state.read()?;
+        Ok(self
+            .read_chain(state.buckets[state.bucket_of(key)], tid)?
+            .into_iter()
+            .flat_map(|(_, entries)| entries)
+            .filter(|e| e.key == key)
+            .map(|e| e.id)
+            .collect())
+    }
+
+    /// A hash index has no key order, so range lookups find nothing.
+    fn range_get_value_ids(
+        &self,
+        _min_key: Option<&[Field]>,
+        _max_key: Option<&[Field]>,
+        _min_inclusive: bool,
+        _max_inclusive: bool,
+        _tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        Ok(Vec::new())
+    }
+
+    fn add_values(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        self.add_values_checked(value_ids, keys, &node::always_holds, tid)
+    }
+
+    fn remove_values(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let entries = node::entries(&self.info, value_ids, keys)?;
+        let mut state = self.state.write()?;
+        for entry in &entries {
+            if self.remove_entry(&state, entry, tid)? {
+                state.num_entries -= 1;
+            }
+        }
+        self.write(self.meta_id, &self.meta(&state), tid)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::*;
+    use common::Attribute;
+
+    fn managers() -> &'static StorageManager {
+        Box::leak(Box::new(StorageManager::new_test_sm()))
+    }
+
+    fn info(c_id: ContainerId, columns: usize, is_unique: bool) -> IndexInfo {
+        IndexInfo {
+            c_id,
+            name: format!("idx{}", c_id),
+            table_id: 0,
+            key_columns: (0..columns).collect(),
+            attributes: (0..columns)
+                .map(|i| Attribute::new(format!("a{}", i), DataType::Int))
+                .collect(),
+            is_unique,
+            is_primary: false,
+            kind: IndexKind::Hash,
+        }
+    }
+
+    fn row(table: ContainerId, i: u16) -> ValueId {
+        ValueId::new_slot(table, i / 100, i % 100)
+    }
+
+    #[test]
+    fn hash_insert_split_and_reopen() {
+        init();
+        let sm = managers();
+        let tid = TransactionId::new();
+        let idx = HashIndex::create(info(1, 2, false), sm, tid).unwrap();
+        // Enough entries for many splits and a duplicate of every key
+        let n = 4000;
+        let keys: Vec<Vec<Field>> = (0..n)
+            .map(|i| vec![Field::Int(i % (n / 2)), Field::Int(i % 3)])
+            .collect();
+        let ids: Vec<ValueId> = (0..n as u16).map(|i| row(0, i)).collect();
+        idx.add_values(ids.clone(), keys.clone(), tid).unwrap();
+        assert!(idx.state.read().unwrap().buckets.len() > INITIAL_BUCKETS * 4);
+
+        for i in [0, 1, 17, n / 2 - 1] {
+            let mut found = idx.equality_get_value_ids(&keys[i as usize], tid).unwrap();
+            found.sort_by_key(|id| (id.page_id, id.slot_id));
+            let expected: Vec<ValueId> = (0..n as usize)
+                .filter(|j| keys[*j] == keys[i as usize])
+                .map(|j| ids[j])
+                .collect();
+            assert_eq!(expected, found);
+        }
+        // Whole keys only, and no ranges
+        assert!(idx.equality_get_value_ids(&[Field::Int(0)], tid).is_err());
+        assert!(idx
+            .range_get_value_ids(None, None, true, true, tid)
+            .unwrap()
+            .is_empty());
+
+        idx.remove_values(vec![ids[5]], vec![keys[5].clone()], tid)
+            .unwrap();
+        let reopened = HashIndex::open(1, sm, tid).unwrap();
+        assert_eq!(idx.info(), reopened.info());
+        assert_eq!(
+            idx.state.read().unwrap().buckets,
+            reopened.state.read().unwrap().buckets
+        );
+        let remaining = reopened.equality_get_value_ids(&keys[5], tid).unwrap();
+        assert!(!remaining.contains(&ids[5]));
+        assert_eq!(
+            n as usize - 1,
+            keys.iter()
+                .collect::<std::collections::HashSet<_>>()
+                .into_iter()
+                .map(|k| reopened.equality_get_value_ids(k, tid).unwrap().len())
+                .sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn hash_unique() {
+        init();
+        let sm = managers();
+        let tid = TransactionId::new();
+        let idx = HashIndex::create(info(2, 1, true), sm, tid).unwrap();
+        idx.add_values(
+            vec![row(0, 1), row(0, 2)],
+            vec![vec![Field::Int(1)], vec![Field::Null]],
+            tid,
+        )
+        .unwrap();
+        assert!(idx
+            .add_values(vec![row(0, 3)], vec![vec![Field::Int(1)]], tid)
+            .is_err());
+        idx.add_values(vec![row(0, 3)], vec![vec![Field::Null]], tid)
+            .unwrap();
+        assert!(idx
+            .equality_get_value_ids(&[Field::Null], tid)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,13 +1,476 @@
-use crate::{StorageManager, TransactionManager};
+use crate::hash::HashIndex;
+use crate::node::HoldsKey;
+use crate::tree::TreeIndex;
+use crate::{StorageManager, StorageTrait};
+use common::prelude::*;
+use common::row::RowLayout;
+use common::traits::index_trait::IndexTrait;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
-#[allow(dead_code)] //TODO: remove this
+/// An open index of any kind
+pub enum Index {
+    Tree(TreeIndex),
+    Hash(HashIndex),
+}
+
+impl Index {
+    /// Create the container for a new, empty index of the kind given by info.
+    fn create(
+        info: IndexInfo,
+        sm: &'static StorageManager,
+        tid: TransactionId,
+    ) -> Result<Self, CrustyError> {
+        Ok(match info.kind {
+            IndexKind::BTree => Index::Tree(TreeIndex::create(info, sm, tid)?),
+            IndexKind::Hash => Index::Hash(HashIndex::create(info, sm, tid)?),
+        })
+    }
+
+    fn open(
+        info: &IndexInfo,
+        sm: &'static StorageManager,
+        tid: TransactionId,
+    ) -> Result<Self, CrustyError> {
+        Ok(match info.kind {
+            IndexKind::BTree => Index::Tree(TreeIndex::open(info.c_id, sm, tid)?),
+            IndexKind::Hash => Index::Hash(HashIndex::open(info.c_id, sm, tid)?),
+        })
+    }
+
+    pub fn info(&self) -> &IndexInfo {
+        match self {
+            Index::Tree(index) => index.info(),
+            Index::Hash(index) => index.info(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.info().name
+    }
+
+    pub fn container_id(&self) -> ContainerId {
+        self.info().c_id
+    }
+
+    pub fn table_id(&self) -> ContainerId {
+        self.info().table_id
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        self.info().kind
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.info().is_unique
+    }
+
+    /// The key of a tuple of the indexed table
+    pub fn key_of(&self, tuple: &Tuple) -> Vec<Field> {
+        self.info().key_of(tuple)
+    }
+
+    /// Check that key may be added for the record id.
+    pub fn check_key(
+        &self,
+        key: &[Field],
+        id: Option<ValueId>,
+        holds_key: HoldsKey,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        match self {
+            Index::Tree(index) => index.check_key(key, id, holds_key, tid),
+            Index::Hash(index) => index.check_key(key, id, holds_key, tid),
+        }
+    }
+
+    /// Add entries, where a key only conflicts with the records that hold it.
+    pub fn add_values_checked(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        holds_key: HoldsKey,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        match self {
+            Index::Tree(index) => index.add_values_checked(value_ids, keys, holds_key, tid),
+            Index::Hash(index) => index.add_values_checked(value_ids, keys, holds_key, tid),
+        }
+    }
+}
+
+impl IndexTrait for Index {
+    fn equality_get_value_ids(
+        &self,
+        key: &[Field],
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        match self {
+            Index::Tree(index) => index.equality_get_value_ids(key, tid),
+            Index::Hash(index) => index.equality_get_value_ids(key, tid),
+        }
+    }
+
+    fn range_get_value_ids(
+        &self,
+        min_key: Option<&[Field]>,
+        max_key: Option<&[Field]>,
+        min_inclusive: bool,
+        max_inclusive: bool,
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        match self {
+            Index::Tree(index) => {
+                index.range_get_value_ids(min_key, max_key, min_inclusive, max_inclusive, tid)
+            }
+            Index::Hash(index) => {
+                index.range_get_value_ids(min_key, max_key, min_inclusive, max_inclusive, tid)
+            }
+        }
+    }
+
+    fn add_values(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        match self {
+            Index::Tree(index) => index.add_values(value_ids, keys, tid),
+            Index::Hash(index) => index.add_values(value_ids, keys, tid),
+        }
+    }
+
+    fn remove_values(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        match self {
+            Index::Tree(index) => index.remove_values(value_ids, keys, tid),
+            Index::Hash(index) => index.remove_values(value_ids, keys, tid),
+        }
+    }
+}
+
+/// Keeps the open indexes of each table and applies table changes to them.
 pub struct IndexManager {
     sm: &'static StorageManager,
-    tm: &'static TransactionManager,
+    /// The indexes of each table, by table id
+    indexes: RwLock<HashMap<ContainerId, Vec<Arc<Index>>>>,
 }
 
 impl IndexManager {
-    pub fn new(sm: &'static StorageManager, tm: &'static TransactionManager) -> Self {
-        Self { sm, tm }
+    pub fn new(sm: &'static StorageManager) -> Self {
+        Self {
+            sm,
+            indexes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn register(&self, index: Arc<Index>) {
+        self.indexes
+            .write()
+            .unwrap()
+            .entry(index.table_id())
+            .or_default()
+            .push(index);
+    }
+
+    /// Create an index of the kind given by info and fill it with the records already in its
+    /// table, which are read with layout if the table has one. If the records that hold their
+    /// keys break a unique index the index is removed and the error returned.
+    pub fn create_index(
+        &self,
+        info: IndexInfo,
+        layout: Option<&RowLayout>,
+        holds_key: HoldsKey,
+        tid: TransactionId,
+    ) -> Result<Arc<Index>, CrustyError> {
+        let index = Index::create(info, self.sm, tid)?;
+        let entries = self
+            .sm
+            .get_iterator(index.table_id(), tid, Permissions::ReadOnly)
+            .map(|scanned| {
+                let (bytes, id) = scanned?;
+                let key = match layout {
+                    // Only the key columns are read
+                    Some(layout) => index
+                        .info()
+                        .key_columns
+                        .iter()
+                        .map(|i| layout.read_field(&bytes, *i))
+                        .collect::<Result<Vec<Field>, CrustyError>>()?,
+                    None => index.key_of(&Tuple::from_bytes(&bytes)),
+                };
+                Ok((id, key))
+            })
+            .collect::<Result<Vec<(ValueId, Vec<Field>)>, CrustyError>>();
+        if let Err(e) = entries.and_then(|entries| {
+            let (ids, keys) = entries.into_iter().unzip();
+            index.add_values_checked(ids, keys, holds_key, tid)
+        }) {
+            self.sm.remove_container(index.container_id())?;
+            return Err(e);
+        }
+        let index = Arc::new(index);
+        self.register(Arc::clone(&index));
+        Ok(index)
+    }
+
+    /// Open the index described by info, for example after a restart.
+    pub fn open_index(
+        &self,
+        info: &IndexInfo,
+        tid: TransactionId,
+    ) -> Result<Arc<Index>, CrustyError> {
+        if let Some(index) = self.get_index(info.c_id) {
+            return Ok(index);
+        }
+        let index = Arc::new(Index::open(info, self.sm, tid)?);
+        self.register(Arc::clone(&index));
+        Ok(index)
+    }
+
+    /// Forget the index in container c_id and remove its container.
+    pub fn drop_index(&self, c_id: ContainerId) -> Result<(), CrustyError> {
+        let mut indexes = self.indexes.write().unwrap();
+        for table_indexes in indexes.values_mut() {
+            table_indexes.retain(|i| i.container_id() != c_id);
+        }
+        self.sm.remove_container(c_id)
+    }
+
+    /// The index in container c_id if it is open
+    pub fn get_index(&self, c_id: ContainerId) -> Option<Arc<Index>> {
+        self.indexes
+            .read()
+            .unwrap()
+            .values()
+            .flatten()
+            .find(|i| i.container_id() == c_id)
+            .cloned()
+    }
+
+    /// The open hash index of a table on exactly key_columns, if there is one
+    pub fn get_hash_index(
+        &self,
+        table_id: ContainerId,
+        key_columns: &[usize],
+    ) -> Option<Arc<Index>> {
+        self.table_indexes(table_id)
+            .into_iter()
+            .find(|i| i.kind() == IndexKind::Hash && i.info().key_columns == key_columns)
+    }
+
+    /// The open indexes of a table
+    pub fn table_indexes(&self, table_id: ContainerId) -> Vec<Arc<Index>> {
+        self.indexes
+            .read()
+            .unwrap()
+            .get(&table_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Forget all open indexes, for when the storage manager is reset.
+    pub fn reset(&self) {
+        self.indexes.write().unwrap().clear();
+    }
+
+    /// Check that tuples can be inserted into the table without breaking an index, including
+    /// against each other. Stored keys only conflict if their records hold them.
+    pub fn check_insert(
+        &self,
+        table_id: ContainerId,
+        tuples: &[Tuple],
+        holds_key: HoldsKey,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        for index in self.table_indexes(table_id) {
+            let mut keys: Vec<Vec<Field>> = tuples.iter().map(|t| index.key_of(t)).collect();
+            for key in &keys {
+                index.check_key(key, None, holds_key, tid)?;
+            }
+            if index.is_unique() {
+                keys.retain(|k| !k.contains(&Field::Null));
+                keys.sort();
+                if keys.windows(2).any(|pair| pair[0] == pair[1]) {
+                    return Err(CrustyError::InvalidMutationError(format!(
+                        "Duplicate key for unique index {}",
+                        index.name()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add inserted tuples stored at ids to the indexes of the table. Stored keys only conflict
+    /// if their records hold them.
+    pub fn insert_tuples(
+        &self,
+        table_id: ContainerId,
+        tuples: &[Tuple],
+        ids: &[ValueId],
+        holds_key: HoldsKey,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        for index in self.table_indexes(table_id) {
+            let keys = tuples.iter().map(|t| index.key_of(t)).collect();
+            index.add_values_checked(ids.to_vec(), keys, holds_key, tid)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the record at id can be changed from old to new without breaking an index.
+    /// Stored keys only conflict if their records hold them.
+    pub fn check_update(
+        &self,
+        table_id: ContainerId,
+        old: &Tuple,
+        new: &Tuple,
+        id: ValueId,
+        holds_key: HoldsKey,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        for index in self.table_indexes(table_id) {
+            let key = index.key_of(new);
+            if key != index.key_of(old) {
+                index.check_key(&key, Some(id), holds_key, tid)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the entries of an updated record, which may have moved from old_id to new_id.
+    pub fn update_tuple(
+        &self,
+        table_id: ContainerId,
+        old: &Tuple,
+        old_id: ValueId,
+        new: &Tuple,
+        new_id: ValueId,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        for index in self.table_indexes(table_id) {
+            let (old_key, new_key) = (index.key_of(old), index.key_of(new));
+            if old_key == new_key && old_id == new_id {
+                continue;
+            }
+            index.remove_values(vec![old_id], vec![old_key], tid)?;
+            index.add_values(vec![new_id], vec![new_key], tid)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the entries of a deleted record.
+    pub fn delete_tuple(
+        &self,
+        table_id: ContainerId,
+        tuple: &Tuple,
+        id: ValueId,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        for index in self.table_indexes(table_id) {
+            index.remove_values(vec![id], vec![index.key_of(tuple)], tid)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::node::always_holds;
+    use common::testutil::*;
+
+    #[test]
+    fn index_manager_follows_table() {
+        init();
+        let sm: &'static StorageManager = Box::leak(Box::new(StorageManager::new_test_sm()));
+        let im = IndexManager::new(sm);
+        let tid = TransactionId::new();
+        let table = 1;
+        sm.create_table(table).unwrap();
+        let tuples = create_tuple_list(vec![vec![1, 10], vec![2, 20], vec![3, 20]]);
+        let layout = RowLayout::new(&get_int_table_schema(2));
+        let bytes = tuples.iter().map(|t| layout.encode(t).unwrap()).collect();
+        let ids = sm.insert_values(table, bytes, tid);
+
+        let info = |c_id, column, is_unique| IndexInfo {
+            c_id,
+            name: format!("idx{}", c_id),
+            table_id: table,
+            key_columns: vec![column],
+            attributes: vec![get_int_table_schema(2)
+                .get_attribute(column)
+                .unwrap()
+                .clone()],
+            is_unique,
+            is_primary: false,
+            kind: if column == 1 {
+                IndexKind::Hash
+            } else {
+                IndexKind::BTree
+            },
+        };
+        // Existing duplicates keep a unique index from being created
+        assert!(im
+            .create_index(info(2, 1, true), Some(&layout), &always_holds, tid)
+            .is_err());
+        assert!(im.get_index(2).is_none());
+        let by_a = im
+            .create_index(info(3, 0, true), Some(&layout), &always_holds, tid)
+            .unwrap();
+        let by_b = im
+            .create_index(info(4, 1, false), Some(&layout), &always_holds, tid)
+            .unwrap();
+        assert_eq!(2, im.table_indexes(table).len());
+        assert_eq!(
+            2,
+            by_b.equality_get_value_ids(&[Field::Int(20)], tid)
+                .unwrap()
+                .len()
+        );
+
+        let new = create_tuple_list(vec![vec![4, 40], vec![4, 41]]);
+        assert!(im.check_insert(table, &new, &always_holds, tid).is_err());
+        assert!(im
+            .check_insert(table, &new[..1], &always_holds, tid)
+            .is_ok());
+        let new_id = sm.insert_value(table, layout.encode(&new[0]).unwrap(), tid);
+        im.insert_tuples(table, &new[..1], &[new_id], &always_holds, tid)
+            .unwrap();
+        assert_eq!(
+            vec![new_id],
+            by_a.equality_get_value_ids(&[Field::Int(4)], tid).unwrap()
+        );
+
+        // Changing a to 1 would duplicate the first row
+        let updated = int_vec_to_tuple(vec![1, 50]);
+        assert!(im
+            .check_update(table, &tuples[1], &updated, ids[1], &always_holds, tid)
+            .is_err());
+        let updated = int_vec_to_tuple(vec![2, 50]);
+        im.check_update(table, &tuples[1], &updated, ids[1], &always_holds, tid)
+            .unwrap();
+        im.update_tuple(table, &tuples[1], ids[1], &updated, ids[1], tid)
+            .unwrap();
+        assert_eq!(
+            vec![ids[1]],
+            by_b.equality_get_value_ids(&[Field::Int(50)], tid).unwrap()
+        );
+
+        im.delete_tuple(table, &updated, ids[1], tid).unwrap();
+        assert!(by_a
+            .equality_get_value_ids(&[Field::Int(2)], tid)
+            .unwrap()
+            .is_empty());
+
+        im.drop_index(3).unwrap();
+        assert_eq!(1, im.table_indexes(table).len());
+        assert!(im.get_index(3).is_none());
     }
 }
@@ -1,11 +1,16 @@
 /// Re-export Storage manager here for this crate to use. This allows us to change
 /// the storage manager by changing one use statement.
 pub use common::storage_trait::StorageTrait;
+pub use common::traits::index_trait::IndexTrait;
 pub use storage::StorageManager;
-pub use txn_manager::mock_tm::MockTransactionManager as TransactionManager;
+pub use txn_manager::TransactionManager;
 
-pub use index_manager::IndexManager;
+pub use hash::HashIndex;
+pub use index_manager::{Index, IndexManager};
+pub use node::HoldsKey;
 pub use tree::TreeIndex;
 
+mod hash;
 mod index_manager;
+mod node;
 mod tree;
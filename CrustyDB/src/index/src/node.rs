@@ -0,0 +1,192 @@
+use common::ids::SegmentId;
+use common::prelude::*;
+use common::traits::index_trait::IndexTrait;
+use common::PAGE_SIZE;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// Bytes every node is padded to. All nodes have the same size so a node can be rewritten in
+/// place, and a node fills most of a page so the storage manager keeps one node per page.
+pub(crate) const NODE_SIZE: usize = PAGE_SIZE - 64;
+/// Bytes in front of an encoded node holding its length
+const LEN_SIZE: usize = 4;
+/// Largest encoded key. Keeps a node from holding fewer than four entries so splits balance.
+pub(crate) const MAX_KEY_SIZE: usize = NODE_SIZE / 4;
+
+/// A key and the id of the record it was taken from. Entries are ordered by key and then by
+/// id, so duplicate keys of a non unique index still have a place in the tree.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub key: Vec<Field>,
+    pub id: ValueId,
+}
+
+impl Entry {
+    fn id_order(
+        &self,
+    ) -> (
+        ContainerId,
+        Option<SegmentId>,
+        Option<PageId>,
+        Option<SlotId>,
+    ) {
+        (
+            self.id.container_id,
+            self.id.segment_id,
+            self.id.page_id,
+            self.id.slot_id,
+        )
+    }
+
+    /// Bytes of the encoded entry
+    pub fn encoded_len(&self) -> usize {
+        serde_cbor::to_vec(self).unwrap().len()
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            .then_with(|| self.id_order().cmp(&other.id_order()))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Whether node fits in NODE_SIZE once encoded
+pub(crate) fn fits<T: Serialize>(node: &T) -> bool {
+    serde_cbor::to_vec(node).unwrap().len() + LEN_SIZE <= NODE_SIZE
+}
+
+/// The bytes stored for a node: its length, the encoded node and padding up to NODE_SIZE.
+pub(crate) fn to_node_bytes<T: Serialize>(node: &T) -> Result<Vec<u8>, CrustyError> {
+    let encoded = serde_cbor::to_vec(node).unwrap();
+    if encoded.len() + LEN_SIZE > NODE_SIZE {
+        return Err(CrustyError::CrustyError(format!(
+            "Index record of {} bytes does not fit in a node",
+            encoded.len()
+        )));
+    }
+    let mut bytes = Vec::with_capacity(NODE_SIZE);
+    bytes.extend((encoded.len() as u32).to_le_bytes());
+    bytes.extend(encoded);
+    bytes.resize(NODE_SIZE, 0);
+    Ok(bytes)
+}
+
+pub(crate) fn from_node_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CrustyError> {
+    let corrupt = || CrustyError::SerializationError(String::from("Corrupt index record"));
+    let len = bytes
+        .get(..LEN_SIZE)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+        .ok_or_else(corrupt)?;
+    let encoded = bytes.get(LEN_SIZE..LEN_SIZE + len).ok_or_else(corrupt)?;
+    serde_cbor::from_slice(encoded).map_err(|_| corrupt())
+}
+
+/// Pair value ids with their keys, checking every key fits the index described by info.
+pub(crate) fn entries(
+    info: &IndexInfo,
+    value_ids: Vec<ValueId>,
+    keys: Vec<Vec<Field>>,
+) -> Result<Vec<Entry>, CrustyError> {
+    if value_ids.len() != keys.len() {
+        return Err(CrustyError::ExecutionError(format!(
+            "Got {} value ids for {} keys",
+            value_ids.len(),
+            keys.len()
+        )));
+    }
+    let mut entries = Vec::with_capacity(keys.len());
+    for (key, id) in keys.into_iter().zip(value_ids) {
+        if key.len() != info.key_columns.len() {
+            return Err(CrustyError::ExecutionError(format!(
+                "Key {:?} does not match the columns of index {}",
+                key, info.name
+            )));
+        }
+        if serde_cbor::to_vec(&key).unwrap().len() > MAX_KEY_SIZE {
+            return Err(CrustyError::InvalidMutationError(format!(
+                "Key too large for index {}",
+                info.name
+            )));
+        }
+        entries.push(Entry { key, id });
+    }
+    // Sorted entries touch each node once in a row, so it stays in the buffer pool
+    entries.sort();
+    Ok(entries)
+}
+
+/// Tells whether the record at an id still holds its key, so that no other record may take the
+/// key in a unique index. A table keeping several versions of a record leaves the versions that
+/// were replaced in its indexes until they are vacuumed, and those give up their keys.
+pub type HoldsKey<'a> = &'a dyn Fn(ValueId) -> Result<bool, CrustyError>;
+
+/// Every record holds its key, for tables with a single version of each record
+pub(crate) fn always_holds(_id: ValueId) -> Result<bool, CrustyError> {
+    Ok(true)
+}
+
+/// Check that key may be added for the record id to the index described by info. Primary keys
+/// may not be null, and a unique index may not hold the key for another record that holds it.
+/// Keys with a null are never duplicates.
+pub(crate) fn check_key<I: IndexTrait>(
+    index: &I,
+    info: &IndexInfo,
+    key: &[Field],
+    id: Option<ValueId>,
+    holds_key: HoldsKey,
+    tid: TransactionId,
+) -> Result<(), CrustyError> {
+    let has_null = key.contains(&Field::Null);
+    if info.is_primary && has_null {
+        return Err(CrustyError::InvalidMutationError(format!(
+            "Null key not allowed in primary index {}",
+            info.name
+        )));
+    }
+    if info.is_unique && !has_null {
+        for existing in index.equality_get_value_ids(key, tid)? {
+            if Some(existing) != id && holds_key(existing)? {
+                return Err(CrustyError::InvalidMutationError(format!(
+                    "Duplicate key {:?} for unique index {}",
+                    key, info.name
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that sorted entries hold no duplicate key of a unique index among the records that
+/// hold their keys.
+pub(crate) fn check_batch(
+    info: &IndexInfo,
+    entries: &[Entry],
+    holds_key: HoldsKey,
+) -> Result<(), CrustyError> {
+    if !info.is_unique {
+        return Ok(());
+    }
+    let mut last_held: Option<&Entry> = None;
+    for entry in entries {
+        if entry.key.contains(&Field::Null) || !holds_key(entry.id)? {
+            continue;
+        }
+        if last_held.is_some_and(|last| last.key == entry.key) {
+            return Err(CrustyError::InvalidMutationError(format!(
+                "Duplicate key {:?} for unique index {}",
+                entry.key, info.name
+            )));
+        }
+        last_held = Some(entry);
+    }
+    Ok(())
+}
@@ -1,33 +1,709 @@
-use crate::StorageManager;
-use crate::TransactionManager;
+use crate::node::{self, Entry, HoldsKey};
+use crate::{StorageManager, StorageTrait};
+use common::prelude::*;
+use common::traits::index_trait::IndexTrait;
 use common::Attribute;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::sync::RwLock;
 
-#[allow(dead_code)] //TODO: remove this
+/// A record of the index container. The meta record is the first record of the container and
+/// points to the root node.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+enum Record {
+    Meta {
+        info: IndexInfo,
+        root: ValueId,
+    },
+    /// Sorted entries and the next leaf to the right
+    Leaf {
+        entries: Vec<Entry>,
+        next: Option<ValueId>,
+    },
+    /// children[i] holds the entries below separators[i], and children[i + 1] the entries at or
+    /// above it.
+    Internal {
+        separators: Vec<Entry>,
+        children: Vec<ValueId>,
+    },
+}
+
+impl Record {
+    fn fits(&self) -> bool {
+        node::fits(self)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, CrustyError> {
+        node::to_node_bytes(self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, CrustyError> {
+        node::from_node_bytes(bytes)
+    }
+}
+
+/// Compare the leading columns of key with a bound that may be shorter than the key
+fn cmp_prefix(key: &[Field], bound: &[Field]) -> Ordering {
+    key.iter()
+        .zip(bound)
+        .map(|(k, b)| k.cmp(b))
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Where to split an overfull node so both halves hold about the same number of bytes
+fn split_point(entries: &[Entry]) -> usize {
+    let sizes: Vec<usize> = entries.iter().map(|e| e.encoded_len()).collect();
+    let half = sizes.iter().sum::<usize>() / 2;
+    let mut acc = 0;
+    let mid = sizes
+        .iter()
+        .position(|s| {
+            acc += s;
+            acc >= half
+        })
+        .unwrap_or(0);
+    mid.clamp(1, entries.len() - 1)
+}
+
+/// A B+tree index stored in its own container of the storage manager.
+///
+/// Every node is one record padded to NODE_SIZE, so nodes are rewritten in place with
+/// replace_value and the ValueIds linking them never change. Splits write the new right node
+/// first, then the left node pointing to it, then the parent, so readers following leaf links
+/// never see a missing node. Deleted entries are removed from their leaf and nodes are never
+/// merged. Writers hold the root lock exclusively, readers share it.
 pub struct TreeIndex {
-    is_primary: bool,
-    is_unique: bool,
-    name: String,
-    attributes: Vec<Attribute>,
+    info: IndexInfo,
+    meta_id: ValueId,
+    root: RwLock<ValueId>,
     sm: &'static StorageManager,
-    tm: &'static TransactionManager,
 }
 
 impl TreeIndex {
-    pub fn new(
-        is_primary: bool,
-        is_unique: bool,
-        name: String,
-        attributes: Vec<Attribute>,
+    /// Create the container for a new, empty index described by info.
+    pub fn create(
+        info: IndexInfo,
         sm: &'static StorageManager,
-        tm: &'static TransactionManager,
-    ) -> Self {
-        Self {
-            is_primary,
-            is_unique,
-            name,
-            attributes,
+        tid: TransactionId,
+    ) -> Result<Self, CrustyError> {
+        sm.create_container(
+            info.c_id,
+            Some(info.name.clone()),
+            StateType::TreeIndex,
+            Some(vec![info.table_id]),
+        )?;
+        // The meta record goes first so open finds it without reading the nodes
+        let mut meta = Record::Meta {
+            info: info.clone(),
+            root: ValueId::new(info.c_id),
+        };
+        let meta_id = sm.insert_value(info.c_id, meta.to_bytes()?, tid);
+        let root_node = Record::Leaf {
+            entries: Vec::new(),
+            next: None,
+        };
+        let root = sm.insert_value(info.c_id, root_node.to_bytes()?, tid);
+        if let Record::Meta { root: r, .. } = &mut meta {
+            *r = root;
+        }
+        sm.replace_value(meta.to_bytes()?, meta_id, tid)?;
+        Ok(TreeIndex {
+            info,
+            meta_id,
+            root: RwLock::new(root),
             sm,
-            tm,
+        })
+    }
+
+    /// Open an index created earlier in container c_id.
+    pub fn open(
+        c_id: ContainerId,
+        sm: &'static StorageManager,
+        tid: TransactionId,
+    ) -> Result<Self, CrustyError> {
+        let meta = sm
+            .get_iterator(c_id, tid, Permissions::ReadOnly)
+            .find_map(|scanned| match scanned {
+                Ok((bytes, id)) => match Record::from_bytes(&bytes) {
+                    Ok(Record::Meta { info, root }) => Some(Ok((info, root, id))),
+                    _ => None,
+                },
+                Err(e) => Some(Err(e)),
+            })
+            .transpose()?;
+        match meta {
+            Some((info, root, meta_id)) => Ok(TreeIndex {
+                info,
+                meta_id,
+                root: RwLock::new(root),
+                sm,
+            }),
+            None => Err(CrustyError::CrustyError(format!(
+                "No index found in container {}",
+                c_id
+            ))),
+        }
+    }
+
+    pub fn info(&self) -> &IndexInfo {
+        &self.info
+    }
+
+    pub fn name(&self) -> &str {
+        &self.info.name
+    }
+
+    pub fn container_id(&self) -> ContainerId {
+        self.info.c_id
+    }
+
+    pub fn table_id(&self) -> ContainerId {
+        self.info.table_id
+    }
+
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.info.attributes
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.info.is_unique
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.info.is_primary
+    }
+
+    /// The key of a tuple of the indexed table
+    pub fn key_of(&self, tuple: &Tuple) -> Vec<Field> {
+        self.info.key_of(tuple)
+    }
+
+    /// Check that key may be added for the record id. Primary keys may not be null, and a
+    /// unique index may not hold the key for another record that holds it. Keys with a null are
+    /// never duplicates.
+    pub fn check_key(
+        &self,
+        key: &[Field],
+        id: Option<ValueId>,
+        holds_key: HoldsKey,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        node::check_key(self, &self.info, key, id, holds_key, tid)
+    }
+
+    /// Add entries like add_values, where a key only conflicts with the records that hold it.
+    pub fn add_values_checked(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        holds_key: HoldsKey,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let entries = node::entries(&self.info, value_ids, keys)?;
+        node::check_batch(&self.info, &entries, holds_key)?;
+        for entry in &entries {
+            self.check_key(&entry.key, Some(entry.id), holds_key, tid)?;
+        }
+        let mut root = self.root.write()?;
+        for entry in entries {
+            self.insert_entry(&mut root, entry, tid)?;
+        }
+        Ok(())
+    }
+
+    fn read(&self, id: ValueId, tid: TransactionId) -> Result<Record, CrustyError> {
+        Record::from_bytes(&self.sm.get_value(id, tid, Permissions::ReadOnly)?)
+    }
+
+    fn write(&self, id: ValueId, record: &Record, tid: TransactionId) -> Result<(), CrustyError> {
+        self.sm.replace_value(record.to_bytes()?, id, tid)
+    }
+
+    fn alloc(&self, record: &Record, tid: TransactionId) -> Result<ValueId, CrustyError> {
+        Ok(self
+            .sm
+            .insert_value(self.info.c_id, record.to_bytes()?, tid))
+    }
+
+    fn corrupt(id: ValueId) -> CrustyError {
+        CrustyError::CrustyError(format!("Unexpected index record at {:?}", id))
+    }
+
+    /// Descend from the root to the leaf where entry belongs. Returns the internal nodes on the
+    /// way down and the leaf.
+    #[allow(clippy::type_complexity)]
+    fn find_leaf(
+        &self,
+        root: ValueId,
+        entry: &Entry,
+        tid: TransactionId,
+    ) -> Result<
+        (
+            Vec<(ValueId, Vec<Entry>, Vec<ValueId>)>,
+            ValueId,
+            Vec<Entry>,
+            Option<ValueId>,
+        ),
+        CrustyError,
+    > {
+        let mut path = Vec::new();
+        let mut id = root;
+        loop {
+            match self.read(id, tid)? {
+                Record::Internal {
+                    separators,
+                    children,
+                } => {
+                    let child = children[separators.partition_point(|s| s <= entry)];
+                    path.push((id, separators, children));
+                    id = child;
+                }
+                Record::Leaf { entries, next } => return Ok((path, id, entries, next)),
+                Record::Meta { .. } => return Err(Self::corrupt(id)),
+            }
         }
     }
+
+    fn insert_entry(
+        &self,
+        root: &mut ValueId,
+        entry: Entry,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let (path, leaf_id, mut entries, next) = self.find_leaf(*root, &entry, tid)?;
+        match entries.binary_search(&entry) {
+            Ok(_) => return Ok(()),
+            Err(pos) => entries.insert(pos, entry),
+        }
+        let leaf = Record::Leaf { entries, next };
+        if leaf.fits() {
+            return self.write(leaf_id, &leaf, tid);
+        }
+        let (mut entries, next) = match leaf {
+            Record::Leaf { entries, next } => (entries, next),
+            _ => unreachable!(),
+        };
+        let right_entries = entries.split_off(split_point(&entries));
+        let separator = right_entries[0].clone();
+        let right = self.alloc(
+            &Record::Leaf {
+                entries: right_entries,
+                next,
+            },
+            tid,
+        )?;
+        self.write(
+            leaf_id,
+            &Record::Leaf {
+                entries,
+                next: Some(right),
+            },
+            tid,
+        )?;
+        self.insert_separator(root, path, separator, right, tid)
+    }
+
+    /// Add the separator and the new right child from a split to the parents on path, splitting
+    /// them as needed. A split of the root adds a level to the tree.
+    fn insert_separator(
+        &self,
+        root: &mut ValueId,
+        mut path: Vec<(ValueId, Vec<Entry>, Vec<ValueId>)>,
+        mut separator: Entry,
+        mut right: ValueId,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        while let Some((id, mut separators, mut children)) = path.pop() {
+            let pos = separators.partition_point(|s| *s <= separator);
+            separators.insert(pos, separator);
+            children.insert(pos + 1, right);
+            let node = Record::Internal {
+                separators,
+                children,
+            };
+            if node.fits() {
+                return self.write(id, &node, tid);
+            }
+            let (mut separators, mut children) = match node {
+                Record::Internal {
+                    separators,
+                    children,
+                } => (separators, children),
+                _ => unreachable!(),
+            };
+            let mid = split_point(&separators);
+            let right_separators = separators.split_off(mid + 1);
+            let right_children = children.split_off(mid + 1);
+            separator = separators.pop().unwrap();
+            right = self.alloc(
+                &Record::Internal {
+                    separators: right_separators,
+                    children: right_children,
+                },
+                tid,
+            )?;
+            self.write(
+                id,
+                &Record::Internal {
+                    separators,
+                    children,
+                },
+                tid,
+            )?;
+        }
+        let new_root = self.alloc(
+            &Record::Internal {
+                separators: vec![separator],
+                children: vec![*root, right],
+            },
+            tid,
+        )?;
+        self.write(
+            self.meta_id,
+            &Record::Meta {
+                info: self.info.clone(),
+                root: new_root,
+            },
+            tid,
+        )?;
+        *root = new_root;
+        Ok(())
+    }
+
+    fn remove_entry(
+        &self,
+        root: ValueId,
+        entry: Entry,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let (_, leaf_id, mut entries, next) = self.find_leaf(root, &entry, tid)?;
+        if let Ok(pos) = entries.binary_search(&entry) {
+            entries.remove(pos);
+            self.write(leaf_id, &Record::Leaf { entries, next }, tid)?;
+        }
+        Ok(())
+    }
+
+    /// Ids of the entries between the bounds, in key order. Bounds compare with the leading
+    /// columns of the key. Entries with a null in a bounded column are skipped.
+    fn scan(
+        &self,
+        min: Option<(&[Field], bool)>,
+        max: Option<(&[Field], bool)>,
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        let bounded = min
+            .map_or(0, |(b, _)| b.len())
+            .max(max.map_or(0, |(b, _)| b.len()));
+        if bounded > self.info.key_columns.len() {
+            return Err(CrustyError::ExecutionError(format!(
+                "Index {} has {} key columns, lookup uses {}",
+                self.info.name,
+                self.info.key_columns.len(),
+                bounded
+            )));
+        }
+        let root = self.root.read()?;
+        let mut id = *root;
+        let mut leaf = loop {
+            match self.read(id, tid)? {
+                Record::Internal {
+                    separators,
+                    children,
+                } => {
+                    let pos = match min {
+                        Some((bound, _)) => {
+                            separators.partition_point(|s| cmp_prefix(&s.key, bound).is_lt())
+                        }
+                        None => 0,
+                    };
+                    id = children[pos];
+                }
+                Record::Leaf { entries, next } => break (entries, next),
+                Record::Meta { .. } => return Err(Self::corrupt(id)),
+            }
+        };
+        let mut ids = Vec::new();
+        loop {
+            for entry in leaf.0 {
+                if let Some((bound, inclusive)) = min {
+                    match cmp_prefix(&entry.key, bound) {
+                        Ordering::Less => continue,
+                        Ordering::Equal if !inclusive => continue,
+                        _ => {}
+                    }
+                }
+                if let Some((bound, inclusive)) = max {
+                    match cmp_prefix(&entry.key, bound) {
+                        Ordering::Greater => return Ok(ids),
+                        Ordering::Equal if !inclusive => return Ok(ids),
+                        _ => {}
+                    }
+                }
+                if entry.key[..bounded].contains(&Field::Null) {
+                    continue;
+                }
+                ids.push(entry.id);
+            }
+            leaf = match leaf.1 {
+                Some(next) => match self.read(next, tid)? {
+                    Record::Leaf { entries, next } => (entries, next),
+                    _ => return Err(Self::corrupt(next)),
+                },
+                None => return Ok(ids),
+            };
+        }
+    }
+}
+
+impl IndexTrait for TreeIndex {
+    fn equality_get_value_ids(
+        &self,
+        key: &[Field],
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        if key.contains(&Field::Null) {
+            return Ok(Vec::new());
+        }
+        self.scan(Some((key, true)), Some((key, true)), tid)
+    }
+
+    fn range_get_value_ids(
+        &self,
+        min_key: Option<&[Field]>,
+        max_key: Option<&[Field]>,
+        min_inclusive: bool,
+        max_inclusive: bool,
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        self.scan(
+            min_key.map(|k| (k, min_inclusive)),
+            max_key.map(|k| (k, max_inclusive)),
+            tid,
+        )
+    }
+
+    fn add_values(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        self.add_values_checked(value_ids, keys, &node::always_holds, tid)
+    }
+
+    fn remove_values(
+        &self,
+        value_ids: Vec<ValueId>,
+        keys: Vec<Vec<Field>>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let entries = node::entries(&self.info, value_ids, keys)?;
+        let root = self.root.write()?;
+        for entry in entries {
+            self.remove_entry(*root, entry, tid)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::*;
+    use common::Constraint;
+
+    fn managers() -> &'static StorageManager {
+        Box::leak(Box::new(StorageManager::new_test_sm()))
+    }
+
+    fn info(c_id: ContainerId, columns: usize, is_unique: bool) -> IndexInfo {
+        IndexInfo {
+            c_id,
+            name: format!("idx{}", c_id),
+            table_id: 0,
+            key_columns: (0..columns).collect(),
+            attributes: (0..columns)
+                .map(|i| {
+                    Attribute::new_with_constraint(
+                        format!("a{}", i),
+                        DataType::Int,
+                        Constraint::None,
+                    )
+                })
+                .collect(),
+            is_unique,
+            is_primary: false,
+            kind: IndexKind::BTree,
+        }
+    }
+
+    fn row(table: ContainerId, i: u16) -> ValueId {
+        ValueId::new_slot(table, i / 100, i % 100)
+    }
+
+    fn int_keys(keys: &[i64]) -> Vec<Vec<Field>> {
+        keys.iter().map(|k| vec![Field::Int(*k)]).collect()
+    }
+
+    #[test]
+    fn tree_insert_and_lookup() {
+        init();
+        let sm = managers();
+        let tid = TransactionId::new();
+        let idx = TreeIndex::create(info(1, 1, false), sm, tid).unwrap();
+        // Enough keys to split leaves and internal nodes, with a duplicate for every key
+        let n = 3000;
+        let keys: Vec<i64> = (0..n).map(|i| (i * 7919) % (n / 2)).collect();
+        let ids: Vec<ValueId> = (0..n as u16).map(|i| row(0, i)).collect();
+        idx.add_values(ids.clone(), int_keys(&keys), tid).unwrap();
+
+        for k in [0, 1, 17, n / 2 - 1] {
+            let mut found = idx.equality_get_value_ids(&[Field::Int(k)], tid).unwrap();
+            let mut expected: Vec<ValueId> = keys
+                .iter()
+                .zip(&ids)
+                .filter(|(key, _)| **key == k)
+                .map(|(_, id)| *id)
+                .collect();
+            found.sort_by_key(|id| (id.page_id, id.slot_id));
+            expected.sort_by_key(|id| (id.page_id, id.slot_id));
+            assert_eq!(expected, found);
+        }
+        assert!(idx
+            .equality_get_value_ids(&[Field::Int(n)], tid)
+            .unwrap()
+            .is_empty());
+
+        let range = idx
+            .range_get_value_ids(
+                Some(&[Field::Int(10)]),
+                Some(&[Field::Int(20)]),
+                false,
+                true,
+                tid,
+            )
+            .unwrap();
+        assert_eq!(20, range.len());
+        let all = idx
+            .range_get_value_ids(None, None, true, true, tid)
+            .unwrap();
+        assert_eq!(n as usize, all.len());
+    }
+
+    #[test]
+    fn tree_prefix_remove_and_reopen() {
+        init();
+        let sm = managers();
+        let tid = TransactionId::new();
+        let idx = TreeIndex::create(info(2, 2, false), sm, tid).unwrap();
+        let keys: Vec<Vec<Field>> = (0..500)
+            .map(|i| vec![Field::Int(i % 10), Field::Int(i)])
+            .collect();
+        let ids: Vec<ValueId> = (0..500).map(|i| row(0, i)).collect();
+        idx.add_values(ids.clone(), keys.clone(), tid).unwrap();
+        assert_eq!(
+            50,
+            idx.equality_get_value_ids(&[Field::Int(3)], tid)
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            vec![ids[13]],
+            idx.equality_get_value_ids(&[Field::Int(3), Field::Int(13)], tid)
+                .unwrap()
+        );
+
+        idx.remove_values(vec![ids[13]], vec![keys[13].clone()], tid)
+            .unwrap();
+        assert!(idx
+            .equality_get_value_ids(&[Field::Int(3), Field::Int(13)], tid)
+            .unwrap()
+            .is_empty());
+
+        let reopened = TreeIndex::open(2, sm, tid).unwrap();
+        assert_eq!(idx.info(), reopened.info());
+        assert_eq!(
+            49,
+            reopened
+                .equality_get_value_ids(&[Field::Int(3)], tid)
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn tree_unique_and_nulls() {
+        init();
+        let sm = managers();
+        let tid = TransactionId::new();
+        let idx = TreeIndex::create(info(3, 1, true), sm, tid).unwrap();
+        idx.add_values(vec![row(0, 0), row(0, 1)], int_keys(&[1, 2]), tid)
+            .unwrap();
+        // Duplicate of a stored key, and duplicates within a batch
+        assert!(idx
+            .add_values(vec![row(0, 2)], int_keys(&[1]), tid)
+            .is_err());
+        assert!(idx
+            .add_values(vec![row(0, 3), row(0, 4)], int_keys(&[5, 5]), tid)
+            .is_err());
+        // Nulls are never duplicates and never match
+        idx.add_values(
+            vec![row(0, 5), row(0, 6)],
+            vec![vec![Field::Null], vec![Field::Null]],
+            tid,
+        )
+        .unwrap();
+        assert!(idx
+            .equality_get_value_ids(&[Field::Null], tid)
+            .unwrap()
+            .is_empty());
+        let above = idx
+            .range_get_value_ids(Some(&[Field::Int(1)]), None, false, true, tid)
+            .unwrap();
+        assert_eq!(vec![row(0, 1)], above);
+
+        let mut primary = info(4, 1, true);
+        primary.is_primary = true;
+        let pk = TreeIndex::create(primary, sm, tid).unwrap();
+        assert!(pk
+            .add_values(vec![row(0, 0)], vec![vec![Field::Null]], tid)
+            .is_err());
+    }
+
+    #[test]
+    fn tree_unique_replaced_versions() {
+        init();
+        let sm = managers();
+        let tid = TransactionId::new();
+        let idx = TreeIndex::create(info(3, 1, true), sm, tid).unwrap();
+        idx.add_values(vec![row(0, 0)], int_keys(&[1]), tid)
+            .unwrap();
+        // A new version of row 0 may take its key once the old version gives it up
+        let replaced = |id: ValueId| Ok(id != row(0, 0));
+        assert!(idx
+            .check_key(&[Field::Int(1)], None, &node::always_holds, tid)
+            .is_err());
+        idx.check_key(&[Field::Int(1)], None, &replaced, tid)
+            .unwrap();
+        idx.add_values_checked(vec![row(0, 1)], int_keys(&[1]), &replaced, tid)
+            .unwrap();
+        assert_eq!(
+            vec![row(0, 0), row(0, 1)],
+            idx.equality_get_value_ids(&[Field::Int(1)], tid).unwrap()
+        );
+        // The versions still conflict with other records
+        assert!(idx
+            .add_values_checked(vec![row(0, 2)], int_keys(&[1]), &replaced, tid)
+            .is_err());
+        assert!(idx
+            .add_values_checked(
+                vec![row(0, 3), row(0, 4)],
+                int_keys(&[7, 7]),
+                &replaced,
+                tid
+            )
+            .is_err());
+    }
 }
@@ -5,8 +5,8 @@ use common::catalog::CatalogRef;
 use common::logical_plan::{
     AggregateNode, CrossProductNode, JoinNode, LogicalOp, LogicalPlan, OpIndex, ScanNode,
 };
-use common::BooleanOp;
 use common::CrustyError;
+use common::{BooleanOp, JoinType};
 
 use crate::optimizer::{extract_columns, extract_columns_vec, OptimizerRule};
 
@@ -30,7 +30,7 @@ impl PredicatePushdown {
             ),
             LogicalOp::Filter(f) => {
                 // Separate the predicates in the Filter node by 'AND' and append to 'predicates'.
-                Self::separate_predicates_by_and(&f.predicate, predicates);
+                predicates.extend(f.predicate.conjuncts().into_iter().cloned());
 
                 // Since Filter node can have only one child,
                 // we can directly push down the predicates to the child and delete the Filter node.
@@ -44,6 +44,10 @@ impl PredicatePushdown {
                     panic!("Join should have exactly two children");
                 }
 
+                if j.join_type != JoinType::Inner {
+                    return Self::pushdown_outer_join(lp, start, j, predicates, catalog);
+                }
+
                 let (left_child, right_child) = (children[0], children[1]);
 
                 let mut left_predicates: Vec<AstExpr> = Vec::new();
@@ -52,7 +56,7 @@ impl PredicatePushdown {
                 let mut remaining_predicates: Vec<AstExpr> = Vec::new(); // remaining predicates after pushdown but not eqs
 
                 if let Some(filter) = &j.filter {
-                    Self::separate_predicates_by_and(filter, predicates);
+                    predicates.extend(filter.conjuncts().into_iter().cloned());
                 }
 
                 for predicate in predicates.drain(..) {
@@ -87,11 +91,8 @@ impl PredicatePushdown {
                 // Reconstruct the JoinNode with any remaining predicates
                 let new_join_node = LogicalOp::Join(JoinNode {
                     eqs: new_eqs,
-                    filter: if !remaining_predicates.is_empty() {
-                        Some(Self::combine_predicates_with_and(&remaining_predicates).unwrap())
-                    } else {
-                        None
-                    },
+                    filter: AstExpr::conjunction(remaining_predicates),
+                    join_type: JoinType::Inner,
                 });
                 new_left_lp.merge(new_join_node, new_right_lp);
                 new_left_lp
@@ -106,7 +107,7 @@ impl PredicatePushdown {
                 let (left_child, right_child) = (children[0], children[1]);
 
                 if let Some(filter) = &cp.filter {
-                    Self::separate_predicates_by_and(filter, predicates);
+                    predicates.extend(filter.conjuncts().into_iter().cloned());
                 }
 
                 // All of predicates are separated into below 4 categories
@@ -148,11 +149,12 @@ impl PredicatePushdown {
                 let new_node = if !new_eqs.is_empty() {
                     LogicalOp::Join(JoinNode {
                         eqs: new_eqs,
-                        filter: Self::combine_predicates_with_and(&remaining_predicates),
+                        filter: AstExpr::conjunction(remaining_predicates.iter().cloned()),
+                        join_type: JoinType::Inner,
                     })
                 } else {
                     LogicalOp::CrossProduct(CrossProductNode {
-                        filter: Self::combine_predicates_with_and(&remaining_predicates),
+                        filter: AstExpr::conjunction(remaining_predicates.iter().cloned()),
                     })
                 };
                 new_left_lp.merge(new_node, new_right_lp);
@@ -160,9 +162,9 @@ impl PredicatePushdown {
             }
             LogicalOp::Scan(s) => {
                 if let Some(existing_filter) = &s.filter {
-                    Self::separate_predicates_by_and(existing_filter, predicates);
+                    predicates.extend(existing_filter.conjuncts().into_iter().cloned());
                 }
-                let new_filter_condition = Self::combine_predicates_with_and(predicates);
+                let new_filter_condition = AstExpr::conjunction(predicates.iter().cloned());
 
                 let scan_node = LogicalOp::Scan(ScanNode {
                     container_id: s.container_id,
@@ -178,7 +180,7 @@ impl PredicatePushdown {
                 let child = lp.edges(start).next().unwrap();
 
                 if let Some(having) = &a.having {
-                    Self::separate_predicates_by_and(having, predicates);
+                    predicates.extend(having.conjuncts().into_iter().cloned());
                 }
 
                 print!("\n{:?}\n", predicates);
@@ -197,7 +199,7 @@ impl PredicatePushdown {
                 let new_aggregate_node = LogicalOp::Aggregate(AggregateNode {
                     fields: a.fields.clone(),
                     group_by: a.group_by.clone(),
-                    having: Self::combine_predicates_with_and(&having_predicates),
+                    having: AstExpr::conjunction(having_predicates.iter().cloned()),
                 });
                 let old_root = new_lp.root().unwrap();
                 let new_agg_idx = new_lp.add_node(new_aggregate_node);
@@ -220,30 +222,6 @@ impl PredicatePushdown {
 
 // Utility functions for PredicatePushdown
 impl PredicatePushdown {
-    fn combine_predicates_with_and(predicates: &[AstExpr]) -> Option<AstExpr> {
-        if predicates.is_empty() {
-            None
-        } else {
-            Some(
-                predicates
-                    .iter()
-                    .cloned()
-                    .reduce(|a, b| AstExpr::Boolean(BooleanOp::And, Box::new(a), Box::new(b)))
-                    .unwrap(),
-            )
-        }
-    }
-
-    fn separate_predicates_by_and(predicate: &AstExpr, separated_predicates: &mut Vec<AstExpr>) {
-        match predicate {
-            AstExpr::Boolean(BooleanOp::And, left, right) => {
-                Self::separate_predicates_by_and(left, separated_predicates);
-                Self::separate_predicates_by_and(right, separated_predicates);
-            }
-            _ => separated_predicates.push(predicate.clone()),
-        }
-    }
-
     fn can_be_pushed_down(
         predicate: &AstExpr,
         lp: &LogicalPlan,
@@ -327,6 +305,46 @@ impl PredicatePushdown {
         columns
     }
 
+    /// Pushes predicates through an outer join. A predicate on the null-supplying input must
+    /// also see the padded tuples of the join, so only predicates on an input that keeps all
+    /// its tuples are pushed down, and the rest are kept in a filter above the join. The
+    /// conditions of the join itself stay in the join.
+    fn pushdown_outer_join(
+        lp: &LogicalPlan,
+        start: usize,
+        join: &JoinNode,
+        predicates: &mut Vec<AstExpr>,
+        catalog: &CatalogRef,
+    ) -> LogicalPlan {
+        let children: Vec<_> = lp.edges(start).collect();
+        let (left_child, right_child) = (children[0], children[1]);
+
+        let mut left_predicates: Vec<AstExpr> = Vec::new();
+        let mut right_predicates: Vec<AstExpr> = Vec::new();
+        let mut remaining_predicates: Vec<AstExpr> = Vec::new();
+        for predicate in predicates.drain(..) {
+            if !join.join_type.preserves_right()
+                && Self::can_be_pushed_down(&predicate, lp, left_child, catalog)
+            {
+                left_predicates.push(predicate);
+            } else if !join.join_type.preserves_left()
+                && Self::can_be_pushed_down(&predicate, lp, right_child, catalog)
+            {
+                right_predicates.push(predicate);
+            } else {
+                remaining_predicates.push(predicate);
+            }
+        }
+
+        let mut new_left_lp = Self::pushdown(lp, left_child, &mut left_predicates, catalog);
+        let new_right_lp = Self::pushdown(lp, right_child, &mut right_predicates, catalog);
+        new_left_lp.merge(LogicalOp::Join(join.clone()), new_right_lp);
+        if let Some(predicate) = AstExpr::conjunction(remaining_predicates.iter().cloned()) {
+            new_left_lp.add_filter_node(predicate, None);
+        }
+        new_left_lp
+    }
+
     fn pushdown_through_node(
         lp: &LogicalPlan,
         start: usize,
@@ -535,6 +553,98 @@ mod test {
         assert_eq!(optimized_lp.to_json(), expected_lp.to_json());
     }
 
+    /// Tests the predicate pushdown optimizer for a left outer join.
+    ///
+    /// ## Original SQL Query
+    /// ```
+    /// SELECT table0.a, table1.c
+    /// FROM table0
+    /// LEFT JOIN table1 ON table0.a = table1.a
+    /// WHERE table0.b > 10 AND table1.c < 20;
+    /// ```
+    ///
+    /// ### Original Plan Tree
+    /// ```
+    /// Project Node (Identifiers: ["table0.a", "table1.c"])
+    /// └───Filter Node (Predicate: table1.c < 20)
+    ///     └───Filter Node (Predicate: table0.b > 10)
+    ///         └───Join Node (Eq Conditions: ["table0.a", "table1.a"], Filter: None, Type: LEFT OUTER)
+    ///             ├───Scan Node (Container ID: [ID for table0], Filter: None, Projection: None)
+    ///             └───Scan Node (Container ID: [ID for table1], Filter: None, Projection: None)
+    /// ```
+    ///
+    /// ## Expected SQL Query After Predicate Pushdown
+    /// The filter on table0 is pushed to its scan. The filter on table1 must also see the tuples of
+    /// table0 padded with nulls, so it stays above the join.
+    /// ```
+    /// SELECT subtable0.a, table1.c
+    /// FROM (
+    ///     SELECT *
+    ///     FROM table0
+    ///     WHERE table0.b > 10
+    /// ) AS subtable0
+    /// LEFT JOIN table1 ON subtable0.a = table1.a
+    /// WHERE table1.c < 20;
+    /// ```
+    ///
+    /// ### Expected Plan Tree After Predicate Pushdown
+    /// ```
+    /// Project Node (Identifiers: ["table0.a", "table1.c"])
+    /// └───Filter Node (Predicate: table1.c < 20)
+    ///     └───Join Node (Eq Conditions: ["table0.a", "table1.a"], Filter: None, Type: LEFT OUTER)
+    ///         ├───Scan Node (Container ID: [ID for table0], Filter: table0.b > 10, Projection: None)
+    ///         └───Scan Node (Container ID: [ID for table1], Filter: None, Projection: None)
+    /// ```
+    #[test]
+    fn test_predicate_pushdown_with_outer_join() {
+        let catalog = TestSetup::new_with_content().catalog;
+        let c_id0 = catalog.get_table_id("table0");
+        let c_id1 = catalog.get_table_id("table1");
+        let add_left_join = |lp: &mut LogicalPlan, left_idx, right_idx| {
+            let join = LogicalOp::Join(JoinNode {
+                eqs: vec![(
+                    AstExpr::Ident("table0.a".to_string()),
+                    AstExpr::Ident("table1.a".to_string()),
+                )],
+                filter: None,
+                join_type: JoinType::Left,
+            });
+            let join_idx = lp.add_node(join);
+            // Right table is always the first child to add
+            lp.add_edge(join_idx, right_idx);
+            lp.add_edge(join_idx, left_idx);
+        };
+
+        // Construct the logical plan
+        let mut lp = LogicalPlan::new();
+        let scan_idx0 = lp.add_scan_node(c_id0, None, None);
+        let scan_idx1 = lp.add_scan_node(c_id1, None, None);
+        add_left_join(&mut lp, scan_idx0, scan_idx1);
+        add_simple_filter_node(&mut lp, "table0.b", ">", "10", None);
+        add_simple_filter_node(&mut lp, "table1.c", "<", "20", None);
+        add_simple_projection_node(&mut lp, vec!["table0.a", "table1.c"], None);
+
+        // Apply the predicate pushdown optimizer
+        let optimized_lp =
+            PredicatePushdown::pushdown(&lp, lp.root().unwrap(), &mut Vec::new(), &catalog);
+
+        // Construct the expected logical plan after applying predicate pushdown
+        let mut expected_lp = LogicalPlan::new();
+        let escan_idx0 = add_simple_scan_node(
+            &mut expected_lp,
+            c_id0,
+            Some(vec![("table0.b", ">", "10")]),
+            None,
+        );
+        let escan_idx1 = add_simple_scan_node(&mut expected_lp, c_id1, None, None);
+        add_left_join(&mut expected_lp, escan_idx0, escan_idx1);
+        add_simple_filter_node(&mut expected_lp, "table1.c", "<", "20", None);
+        add_simple_projection_node(&mut expected_lp, vec!["table0.a", "table1.c"], None);
+
+        // Compare the expected and actual logical plans
+        assert_eq!(optimized_lp.to_json(), expected_lp.to_json());
+    }
+
     /// Tests the predicate pushdown optimizer for a scenario with multiple cross products and filters.
     ///
     /// ## Original SQL Query
@@ -79,7 +79,7 @@ impl ProjectionPushdown {
             }
             LogicalOp::Sort(s) => {
                 extract_columns_vec(
-                    &s.fields.iter().map(|(f, _asc)| f.clone()).collect(),
+                    &s.fields.iter().map(|(f, _, _)| f.clone()).collect(),
                     col_names,
                 );
                 let child = lp.edges(start).next().unwrap();
@@ -132,7 +132,7 @@ mod test {
     use super::*;
     use common::{
         logical_plan::{FilterNode, JoinNode, ProjectNode},
-        Field,
+        Field, JoinType,
     };
     use queryexe::testutil::TestSetup;
 
@@ -231,6 +231,7 @@ mod test {
                 AstExpr::Ident("table1.a".to_string()),
             )],
             filter: None,
+            join_type: JoinType::Inner,
         });
         let join_idx = lp.add_node(join);
         // Right table is always the first child to add
@@ -278,6 +279,7 @@ mod test {
                 AstExpr::Ident("table1.a".to_string()),
             )],
             filter: None,
+            join_type: JoinType::Inner,
         });
         let join_idx = expected.add_node(join);
         // Right table is always the first child to add
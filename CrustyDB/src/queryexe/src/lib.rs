@@ -7,11 +7,17 @@ pub mod query;
 pub mod stats;
 pub mod testutil;
 
+use common::ids::Permissions;
 use common::prelude::*;
+use common::row::{self, RowLayout};
+use common::traits::transaction_manager_trait::{TransactionManagerTrait, TxnWrite};
+use index::Index;
 pub use index::IndexManager;
 use index::StorageTrait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
 pub use storage::{StorageManager, STORAGE_DIR};
-pub use txn_manager::mock_tm::MockTransactionManager as TransactionManager;
+pub use txn_manager::TransactionManager;
 
 /// This is a wrapper for the managers, which are components responsible
 /// for various parts of the system (e.g. storage, indices, etc).
@@ -23,6 +29,13 @@ pub struct Managers {
     pub tm: &'static TransactionManager,
     pub im: &'static IndexManager,
     pub stats: &'static stats::ReservoirStatManager,
+    /// Row layouts of the tables whose records are stored in the row format
+    rows: RwLock<HashMap<ContainerId, Arc<RowLayout>>>,
+    /// Ids of temporary containers: the next id never handed out and the ids given back. They
+    /// count down from ContainerId::MAX so they do not meet the ids of a catalog.
+    temp_ids: Mutex<(ContainerId, Vec<ContainerId>)>,
+    /// Held by a committing transaction while it ends the versions it replaced
+    commits: Mutex<()>,
 }
 
 impl Managers {
@@ -32,7 +45,39 @@ impl Managers {
         im: &'static IndexManager,
         stats: &'static stats::ReservoirStatManager,
     ) -> Self {
-        Self { sm, tm, im, stats }
+        Self {
+            sm,
+            tm,
+            im,
+            stats,
+            rows: RwLock::new(HashMap::new()),
+            temp_ids: Mutex::new((ContainerId::MAX, Vec::new())),
+            commits: Mutex::new(()),
+        }
+    }
+
+    /// Store the records of table_id in the row format laid out by schema. Records of tables
+    /// that are not registered, and records written before, are CBOR encoded tuples.
+    pub fn register_row_layout(&self, table_id: ContainerId, schema: &TableSchema) {
+        self.rows
+            .write()
+            .unwrap()
+            .insert(table_id, Arc::new(RowLayout::new(schema)));
+    }
+
+    /// The row layout of table_id, if it has one
+    pub fn row_layout(&self, table_id: ContainerId) -> Option<Arc<RowLayout>> {
+        self.rows.read().unwrap().get(&table_id).cloned()
+    }
+
+    /// Encode a tuple of table_id for storage
+    pub fn encode_row(&self, table_id: ContainerId, tuple: &Tuple) -> Result<Vec<u8>, CrustyError> {
+        row::encode_row(self.row_layout(table_id).as_deref(), tuple)
+    }
+
+    /// Decode a stored record of table_id
+    pub fn decode_row(&self, table_id: ContainerId, bytes: &[u8]) -> Result<Tuple, CrustyError> {
+        row::decode_row(self.row_layout(table_id).as_deref(), bytes)
     }
 
     pub fn shutdown(&self) {
@@ -43,6 +88,231 @@ impl Managers {
 
     pub fn reset(&self) -> Result<(), CrustyError> {
         info!("TODO Storage manager reset -- add reset for other managers");
+        self.im.reset();
         self.sm.reset()
     }
+
+    /// Create a container for the temporary state of an operator. It must be removed with
+    /// remove_temp_container. The storage manager does not log temporary containers or keep
+    /// them across a restart, so their ids can be handed out again by the next process.
+    pub fn create_temp_container(&self) -> Result<ContainerId, CrustyError> {
+        let c_id = {
+            let mut ids = self.temp_ids.lock()?;
+            match ids.1.pop() {
+                Some(c_id) => c_id,
+                None => {
+                    let c_id = ids.0;
+                    ids.0 -= 1;
+                    c_id
+                }
+            }
+        };
+        if let Err(e) = self
+            .sm
+            .create_container(c_id, None, StateType::Temporary, None)
+        {
+            self.temp_ids.lock()?.1.push(c_id);
+            return Err(e);
+        }
+        Ok(c_id)
+    }
+
+    /// Remove a container made by create_temp_container, so its id can be used again.
+    pub fn remove_temp_container(&self, c_id: ContainerId) -> Result<(), CrustyError> {
+        self.sm.remove_container(c_id)?;
+        self.temp_ids.lock()?.1.push(c_id);
+        Ok(())
+    }
+
+    /// Create an index and fill it with the records already in its table, see
+    /// IndexManager::create_index.
+    pub fn create_index(
+        &self,
+        info: IndexInfo,
+        tid: TransactionId,
+    ) -> Result<Arc<Index>, CrustyError> {
+        let layout = self.row_layout(info.table_id);
+        self.im
+            .create_index(info, layout.as_deref(), &|id| self.holds_key(id, tid), tid)
+    }
+
+    /// Replace the stored index described by info with one built from the records of its table,
+    /// e.g. after a crash, when the stored index may not match the recovered table.
+    pub fn rebuild_index(
+        &self,
+        info: IndexInfo,
+        tid: TransactionId,
+    ) -> Result<Arc<Index>, CrustyError> {
+        self.im.drop_index(info.c_id)?;
+        self.create_index(info, tid)
+    }
+
+    /// Whether the record at id still holds its key in the unique indexes tid writes to, see
+    /// HoldsKey. Only the versions a multi-version transaction manager keeps can give it up.
+    pub fn holds_key(&self, id: ValueId, tid: TransactionId) -> Result<bool, CrustyError> {
+        if !TransactionManager::MULTI_VERSION {
+            return Ok(true);
+        }
+        let bytes = self.sm.get_value(id, tid, Permissions::ReadOnly)?;
+        let tuple = self.decode_row(id.container_id, &bytes)?;
+        self.tm.holds_key(&tuple, &id, &tid)
+    }
+
+    /// Commit tid, keeping its writes. If the transaction manager finds tid conflicts with
+    /// another transaction, tid is rolled back instead and the error is returned. The writes are
+    /// durable once the storage manager commits tid, before the transaction manager does.
+    pub fn commit_txn(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        // A version must not be ended by two transactions checking it at once
+        let _commit = self.commits.lock()?;
+        if let Err(e) = self.tm.validate_txn(tid) {
+            self.rollback_txn(tid)?;
+            return Err(e);
+        }
+        // The versions tid replaced end where its new versions begin, unless one already ended
+        let writes = self.tm.take_writes(tid, 0)?;
+        let mut ended = Vec::new();
+        for write in &writes {
+            if let TxnWrite::Version { old_id, new_id, .. } = write {
+                let bytes = self.sm.get_value(*old_id, tid, Permissions::ReadOnly)?;
+                let mut old = self.decode_row(old_id.container_id, &bytes)?;
+                if let Err(e) = self.tm.end_version(&mut old, *new_id, tid) {
+                    self.undo_writes(writes, tid)?;
+                    self.sm.abort(tid)?;
+                    self.tm.rollback_txn(tid)?;
+                    return Err(e);
+                }
+                ended.push((*old_id, old));
+            }
+        }
+        for (old_id, old) in ended {
+            let bytes = self.encode_row(old_id.container_id, &old)?;
+            self.sm.replace_value(bytes, old_id, tid)?;
+        }
+        self.sm.commit(tid)?;
+        self.tm.commit_txn(tid)
+    }
+
+    /// Reclaim the space of a table: remove the versions no transaction can see anymore with
+    /// their index entries, then vacuum the table's container. Records only move to fill sparse
+    /// pages if move_records is set and no other transaction is running; the index entries of
+    /// moved records are rewritten in tid, so the moves and the rewrites commit together. Index
+    /// containers are never vacuumed, as index nodes link to each other by id.
+    /// Returns the number of versions removed and of records moved.
+    pub fn vacuum(
+        &self,
+        table_id: ContainerId,
+        move_records: bool,
+        tid: TransactionId,
+    ) -> Result<(usize, usize), CrustyError> {
+        let mut dead = Vec::new();
+        if TransactionManager::MULTI_VERSION {
+            let layout = self.row_layout(table_id);
+            for scanned in self.sm.get_iterator(table_id, tid, Permissions::ReadOnly) {
+                let (bytes, id) = scanned?;
+                let tuple = row::decode_row(layout.as_deref(), &bytes)?;
+                if self.tm.is_dead(&tuple, &id)? {
+                    dead.push((tuple, id));
+                }
+            }
+        }
+        for (tuple, id) in &dead {
+            self.im.delete_tuple(table_id, tuple, *id, tid)?;
+            self.sm.delete_value(*id, tid)?;
+        }
+
+        let move_records = move_records && !self.tm.others_running(tid)?;
+        let moved = self.sm.vacuum(table_id, move_records, tid)?;
+        for (old_id, new_id) in &moved {
+            let bytes = self.sm.get_value(*new_id, tid, Permissions::ReadOnly)?;
+            let tuple = self.decode_row(table_id, &bytes)?;
+            self.im
+                .update_tuple(table_id, &tuple, *old_id, &tuple, *new_id, tid)?;
+        }
+        // Versions still linked to a newer version that moved point to where it went
+        if TransactionManager::MULTI_VERSION && !moved.is_empty() {
+            let moved: HashMap<ValueId, ValueId> = moved.iter().copied().collect();
+            let mut relinked = Vec::new();
+            for scanned in self.sm.get_iterator(table_id, tid, Permissions::ReadOnly) {
+                let (bytes, id) = scanned?;
+                let mut tuple = self.decode_row(table_id, &bytes)?;
+                if self.tm.version_moved(&mut tuple, &moved) {
+                    relinked.push((id, tuple));
+                }
+            }
+            for (id, tuple) in relinked {
+                let bytes = self.encode_row(table_id, &tuple)?;
+                self.sm.replace_value(bytes, id, tid)?;
+            }
+        }
+        Ok((dead.len(), moved.len()))
+    }
+
+    /// Undo every write of tid and roll it back.
+    pub fn rollback_txn(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.rollback_to_savepoint(tid, 0)?;
+        self.sm.abort(tid)?;
+        self.tm.rollback_txn(tid)
+    }
+
+    /// Undo the writes tid made after savepoint, newest first, in the tables and their indexes.
+    /// tid stays active and keeps its locks.
+    pub fn rollback_to_savepoint(
+        &self,
+        tid: TransactionId,
+        savepoint: usize,
+    ) -> Result<(), CrustyError> {
+        let writes = self.tm.take_writes(tid, savepoint)?;
+        self.undo_writes(writes, tid)
+    }
+
+    /// Undo writes of tid, given newest first, in the tables and their indexes.
+    fn undo_writes(&self, writes: Vec<TxnWrite>, tid: TransactionId) -> Result<(), CrustyError> {
+        // Restoring an updated record may move it, and older writes still name the old place
+        let mut moved: HashMap<ValueId, ValueId> = HashMap::new();
+        for write in writes {
+            match write {
+                TxnWrite::Insert { id, tuple }
+                | TxnWrite::Version {
+                    new_id: id,
+                    new: tuple,
+                    ..
+                } => {
+                    let id = moved.get(&id).copied().unwrap_or(id);
+                    self.im.delete_tuple(id.container_id, &tuple, id, tid)?;
+                    self.sm.delete_value(id, tid)?;
+                }
+                TxnWrite::Load { ids } => {
+                    for id in ids.into_iter().rev() {
+                        let id = moved.get(&id).copied().unwrap_or(id);
+                        let bytes = self.sm.get_value(id, tid, Permissions::ReadOnly)?;
+                        let tuple = self.decode_row(id.container_id, &bytes)?;
+                        self.im.delete_tuple(id.container_id, &tuple, id, tid)?;
+                        self.sm.delete_value(id, tid)?;
+                    }
+                }
+                TxnWrite::Update {
+                    old_id,
+                    old,
+                    new_id,
+                    new,
+                } => {
+                    let new_id = moved.get(&new_id).copied().unwrap_or(new_id);
+                    let bytes = self.encode_row(new_id.container_id, &old)?;
+                    let restored_id = self.sm.update_value(bytes, new_id, tid)?;
+                    self.im.update_tuple(
+                        new_id.container_id,
+                        &new,
+                        new_id,
+                        &old,
+                        restored_id,
+                        tid,
+                    )?;
+                    if restored_id != old_id {
+                        moved.insert(old_id, restored_id);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
 }
@@ -3,25 +3,41 @@ use crate::Managers;
 use common::{
     datatypes::{default_decimal_precision, default_decimal_scale},
     prelude::*,
+    row,
     storage_trait::StorageTrait,
     traits::stat_manager_trait::StatManagerTrait,
+    traits::transaction_manager_trait::TransactionManagerTrait,
     ConversionError, ConvertedResult,
 };
 use sqlparser::ast::{Value, Values};
 
 pub(crate) fn insert_validated_tuples(
     table_id: ContainerId,
-    tuples: &Vec<Tuple>,
+    tuples: &[Tuple],
     txn_id: TransactionId,
     managers: &'static Managers,
 ) -> Result<usize, CrustyError> {
+    let holds_key = |id| managers.holds_key(id, txn_id);
+    managers
+        .im
+        .check_insert(table_id, tuples, &holds_key, txn_id)?;
+    let mut tuples = tuples.to_vec();
     let mut tuples_bytes = Vec::new();
-    for t in tuples {
-        tuples_bytes.push(t.to_bytes());
+    let layout = managers.row_layout(table_id);
+    for t in tuples.iter_mut() {
+        managers.tm.pre_insert_record(t, txn_id)?;
+        tuples_bytes.push(row::encode_row(layout.as_deref(), t)?);
     }
     let inserted = managers.sm.insert_values(table_id, tuples_bytes, txn_id);
     let insert_count = inserted.len();
+    // Records inserted before a failure are logged too, so a rollback removes them
+    for (t, v) in tuples.iter_mut().zip(inserted.iter()) {
+        managers.tm.post_insert_record(t, *v, txn_id)?;
+    }
     if insert_count == tuples.len() {
+        managers
+            .im
+            .insert_tuples(table_id, &tuples, &inserted, &holds_key, txn_id)?;
         for (t, v) in tuples.iter().zip(inserted.iter()) {
             managers.stats.new_record(t, *v)?;
         }
@@ -35,7 +51,10 @@ pub(crate) fn insert_validated_tuples(
     }
 }
 
-/// Check new or updated records to ensure that they do not break any constraints
+/// Check that new records fit the schema of the table: a value of the column's type, or null
+/// where the column allows it. Records that do not fit are moved to the unconverted records with
+/// their errors. Primary keys and other unique keys are checked by the table's indexes when the
+/// records are added, foreign keys are not checked.
 pub(crate) fn validate_tuples(
     _table_id: &ContainerId,
     schema: &TableSchema,
@@ -48,9 +67,9 @@ pub(crate) fn validate_tuples(
             "Col ordering not supported",
         )));
     }
-    let mut values_to_remove: Vec<(usize, Vec<ConversionError>)> = Vec::new();
-    warn!("PK, FK, Unique constaints not checked");
+    let mut invalid: Vec<(usize, Vec<ConversionError>)> = Vec::new();
     for (i, rec) in values.converted.iter().enumerate() {
+        let mut errors = Vec::new();
         for (j, (field, attr)) in (rec.field_vals()).zip(schema.attributes()).enumerate() {
             if let Field::Null = field {
                 match attr.constraint {
@@ -58,10 +77,11 @@ pub(crate) fn validate_tuples(
                     | common::Constraint::UniqueNotNull
                     | common::Constraint::PrimaryKey
                     | common::Constraint::NotNullFKey(_) => {
-                        values_to_remove.push((i, vec![ConversionError::NullFieldNotAllowed(j)]));
+                        errors.push(ConversionError::NullFieldNotAllowed(j));
                     }
-                    _ => continue, // Null value so nothing to check
+                    _ => {}
                 }
+                continue; // Null value so nothing else to check
             }
             match (&attr.dtype, field) {
                 (DataType::Int, Field::Int(_v)) => {
@@ -81,14 +101,18 @@ pub(crate) fn validate_tuples(
                 }
                 _ => {
                     debug!("Wrong field: {} for attr type: {}", field, &attr.dtype);
-                    values_to_remove.push((i, vec![ConversionError::WrongType]));
+                    errors.push(ConversionError::WrongType);
                 }
             }
         }
+        if !errors.is_empty() {
+            invalid.push((i, errors));
+        }
     }
     // Remove in reverse order records that were invalid
-    for i in values_to_remove.iter().rev() {
-        values.converted.remove(i.0);
+    for (i, errors) in invalid.into_iter().rev() {
+        values.converted.remove(i);
+        values.unconverted.push((i, errors));
     }
     Ok(values)
 }
@@ -1,9 +1,11 @@
-use super::OpIterator;
+use super::{passes_filter, OpIterator};
+use common::bytecode_expr::ByteCodeExpr;
 use common::{CrustyError, TableSchema, Tuple};
 
 pub struct CrossJoin {
     // Parameters (No need to reset on close)
     schema: TableSchema,
+    filter: Option<ByteCodeExpr>,
     left_child: Box<dyn OpIterator>,
     right_child: Box<dyn OpIterator>,
     // States (Need to reset on close)
@@ -12,13 +14,23 @@ pub struct CrossJoin {
 }
 
 impl CrossJoin {
+    /// CrossJoin constructor. Joins every left tuple with every right tuple.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the joined tuples.
+    /// * `filter` - Predicate on the joined tuple, bound to schema.
+    /// * `left_child` - Left child of join operator.
+    /// * `right_child` - Right child of join operator.
     pub fn new(
         schema: TableSchema,
+        filter: Option<ByteCodeExpr>,
         left_child: Box<dyn OpIterator>,
         right_child: Box<dyn OpIterator>,
     ) -> Self {
         Self {
             schema,
+            filter,
             left_child,
             right_child,
             open: false,
@@ -50,7 +62,10 @@ impl OpIterator for CrossJoin {
         while let Some(left_tuple) = &self.current_tuple {
             if let Some(right_tuple) = self.right_child.next()? {
                 let t = left_tuple.merge(&right_tuple);
-                return Ok(Some(t));
+                if passes_filter(self.filter.as_ref(), &t) {
+                    return Ok(Some(t));
+                }
+                continue;
             }
             self.right_child.rewind()?;
             self.current_tuple = self.left_child.next()?;
@@ -83,11 +98,17 @@ mod test {
     use super::*;
     use crate::testutil::execute_iter;
     use crate::testutil::TestTuples;
+    use common::bytecode_expr::ByteCodes;
 
     fn get_iter() -> Box<dyn OpIterator> {
+        get_filtered_iter(None)
+    }
+
+    fn get_filtered_iter(filter: Option<ByteCodeExpr>) -> Box<dyn OpIterator> {
         let setup = TestTuples::new("");
         let mut iter = Box::new(CrossJoin::new(
             setup.schema.clone(),
+            filter,
             Box::new(TupleIterator::new(
                 setup.tuples.clone(),
                 setup.schema.clone(),
@@ -137,6 +158,23 @@ mod test {
                 assert_eq!(t.field_vals, e.field_vals);
             }
         }
+
+        #[test]
+        fn test_filtered_join() {
+            // left(col(0)) < right(col(0)), on column 4 of the joined tuple
+            let mut filter = ByteCodeExpr::new();
+            filter.add_code(ByteCodes::PushField as usize);
+            filter.add_code(0);
+            filter.add_code(ByteCodes::PushField as usize);
+            filter.add_code(4);
+            filter.add_code(ByteCodes::Lt as usize);
+            let mut iter = get_filtered_iter(Some(filter));
+            let tuples = execute_iter(&mut *iter, true).unwrap();
+            assert_eq!(5 + 4 + 3 + 2 + 1, tuples.len());
+            for t in &tuples {
+                assert!(t.get_field(0) < t.get_field(4));
+            }
+        }
     }
 
     mod opiterator_test {
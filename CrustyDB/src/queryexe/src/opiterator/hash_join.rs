@@ -1,9 +1,151 @@
-use super::OpIterator;
+use super::index_scan::{get_index, read_record};
+use super::spill::{block_size, SpillCursor, SpillFile};
+use super::{null_tuple, passes_filter, OpIterator, MEMORY_BUDGET};
 use crate::Managers;
 
 use common::bytecode_expr::ByteCodeExpr;
-use common::{CrustyError, Field, TableSchema, Tuple};
+use common::ids::{ContainerId, TransactionId};
+use common::traits::index_trait::IndexTrait;
+use common::{CrustyError, Field, JoinType, TableSchema, Tuple};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Most partitions an input is split into at once
+const MAX_FAN_OUT: usize = 32;
+/// Times a partition too large for memory is split again before it is joined in memory anyway
+const MAX_LEVEL: usize = 4;
+
+/// Left tuples by key, each with whether it has been joined
+type HashTable = HashMap<Vec<Field>, Vec<(Tuple, bool)>>;
+
+/// Where the left tuples matching a key are found
+enum BuildSide {
+    /// Hash table built from the left child when the join is opened
+    Memory {
+        left_exprs: Vec<ByteCodeExpr>,
+        left_child: Box<dyn OpIterator>,
+        hash_table: HashTable,
+        /// Partitions of both inputs when the left one does not fit in the memory budget
+        spilled: Option<Spilled>,
+    },
+    /// Persisted hash index of the left table, which is kept in sync with the table
+    Persisted {
+        index_id: ContainerId,
+        transaction_id: TransactionId,
+    },
+}
+
+/// The key of tuple
+fn key_of(exprs: &[ByteCodeExpr], tuple: &Tuple) -> Vec<Field> {
+    exprs.iter().map(|expr| expr.eval(tuple)).collect()
+}
+
+/// Tuples of both inputs partitioned by the hash of their keys. Matching tuples are in the same
+/// pair of partitions, which are joined one at a time with the left one in the hash table.
+struct Spilled {
+    pairs: Vec<(SpillFile, SpillFile)>,
+    /// Pair whose left partition is in the hash table
+    current: usize,
+    right: SpillCursor,
+}
+
+impl Spilled {
+    /// Put the left partition of pair i in the hash table and probe it with its right partition
+    fn load(
+        &mut self,
+        i: usize,
+        left_exprs: &[ByteCodeExpr],
+        hash_table: &mut HashTable,
+    ) -> Result<(), CrustyError> {
+        hash_table.clear();
+        self.current = i;
+        self.right = SpillCursor::default();
+        if let Some((left, _)) = self.pairs.get(i) {
+            let mut cursor = SpillCursor::default();
+            while let Some(tuple) = cursor.next(left)? {
+                hash_table
+                    .entry(key_of(left_exprs, &tuple))
+                    .or_default()
+                    .push((tuple, false));
+            }
+        }
+        Ok(())
+    }
+
+    /// The next right tuple of the current pair
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match self.pairs.get(self.current) {
+            Some((_, right)) => self.right.next(right),
+            None => Ok(None),
+        }
+    }
+
+    /// Load the pair after the current one, if there is one
+    fn advance(
+        &mut self,
+        left_exprs: &[ByteCodeExpr],
+        hash_table: &mut HashTable,
+    ) -> Result<bool, CrustyError> {
+        if self.current + 1 >= self.pairs.len() {
+            return Ok(false);
+        }
+        self.load(self.current + 1, left_exprs, hash_table)?;
+        Ok(true)
+    }
+}
+
+/// Tuples split into spill files by the hash of their keys
+struct Partitioner {
+    level: usize,
+    /// Whether tuples whose key has a null are kept, as they are when an outer join returns them
+    keep_nulls: bool,
+    files: Vec<SpillFile>,
+    /// Bytes of tuples in each file
+    sizes: Vec<usize>,
+}
+
+impl Partitioner {
+    fn new(
+        managers: &'static Managers,
+        schema: &TableSchema,
+        tid: TransactionId,
+        fan_out: usize,
+        level: usize,
+        keep_nulls: bool,
+    ) -> Result<Self, CrustyError> {
+        Ok(Partitioner {
+            level,
+            keep_nulls,
+            files: (0..fan_out)
+                .map(|_| SpillFile::new(managers, schema, tid))
+                .collect::<Result<_, _>>()?,
+            sizes: vec![0; fan_out],
+        })
+    }
+
+    /// Add tuple with key to its partition. A key with a null never matches, so the tuple is
+    /// dropped unless nulls are kept.
+    fn push(&mut self, key: &[Field], tuple: &Tuple) -> Result<(), CrustyError> {
+        if !self.keep_nulls && key.contains(&Field::Null) {
+            return Ok(());
+        }
+        // Each level hashes differently, so a partition split again spreads over all files
+        let mut hasher = DefaultHasher::new();
+        self.level.hash(&mut hasher);
+        key.hash(&mut hasher);
+        let i = hasher.finish() as usize % self.files.len();
+        self.sizes[i] += tuple.size();
+        self.files[i].push(tuple)
+    }
+
+    fn finish(mut self) -> Result<(Vec<SpillFile>, Vec<usize>), CrustyError> {
+        for file in &mut self.files {
+            file.flush()?;
+        }
+        Ok((self.files, self.sizes))
+    }
+}
 
 /// Hash equi-join implementation. (You can add any other fields that you think are neccessary)
 pub struct HashEqJoin {
@@ -12,85 +154,427 @@ pub struct HashEqJoin {
 
     // Parameters (No need to reset on close)
     schema: TableSchema,
-    left_expr: ByteCodeExpr,
-    right_expr: ByteCodeExpr,
-    left_child: Box<dyn OpIterator>,
+    right_exprs: Vec<ByteCodeExpr>,
+    filter: Option<ByteCodeExpr>,
     right_child: Box<dyn OpIterator>,
+    build: BuildSide,
+    mem_budget: usize,
+    transaction_id: TransactionId,
+    join_type: JoinType,
     // States (Need to reset on close)
-    hash_table: HashMap<Field, Vec<Tuple>>,
+    open: bool,
+    /// Joined tuples of the current right tuple not returned yet, in reverse order
+    pending: Vec<Tuple>,
+    /// Whether the unmatched left tuples of the hash table have been returned
+    drained: bool,
 }
 
 impl HashEqJoin {
-    /// NestedLoopJoin constructor. Creates a new node for a nested-loop join.
+    /// HashEqJoin constructor. Creates a hash join building its hash table from the left child.
+    /// When the left tuples exceed the memory budget, both children are partitioned into
+    /// temporary containers and the partitions are joined one at a time.
     ///
     /// # Arguments
     ///
-    /// * `left_expr` - ByteCodeExpr for the left field in join condition.
-    /// * `right_expr` - ByteCodeExpr for the right field in join condition.
+    /// * `keys` - ByteCodeExprs for the left and right fields of each equality in join condition.
+    /// * `filter` - Predicate on the joined tuple, bound to schema.
+    /// * `tid` - Transaction writing and reading the spilled partitions.
     /// * `left_child` - Left child of join operator.
     /// * `right_child` - Left child of join operator.
     pub fn new(
         managers: &'static Managers,
         schema: TableSchema,
-        left_expr: ByteCodeExpr,
-        right_expr: ByteCodeExpr,
+        keys: Vec<(ByteCodeExpr, ByteCodeExpr)>,
+        filter: Option<ByteCodeExpr>,
+        tid: TransactionId,
         left_child: Box<dyn OpIterator>,
         right_child: Box<dyn OpIterator>,
     ) -> Self {
+        let (left_exprs, right_exprs) = keys.into_iter().unzip();
         HashEqJoin {
             managers,
             schema,
-            left_expr,
-            right_expr,
-            left_child,
+            right_exprs,
+            filter,
+            right_child,
+            build: BuildSide::Memory {
+                left_exprs,
+                left_child,
+                hash_table: HashMap::new(),
+                spilled: None,
+            },
+            mem_budget: MEMORY_BUDGET,
+            transaction_id: tid,
+            join_type: JoinType::Inner,
+            open: false,
+            pending: Vec::new(),
+            drained: false,
+        }
+    }
+
+    /// Creates a hash join probing a persisted hash index of the left table instead of building
+    /// a hash table from a scan of it.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_id` - Hash index of the left table on the left fields of the join condition.
+    /// * `tid` - Transaction used to read the left table.
+    /// * `right_exprs` - ByteCodeExprs for the right fields in join condition, in key order.
+    /// * `filter` - Predicate on the joined tuple, bound to schema.
+    /// * `right_child` - Right child of join operator.
+    pub fn persisted(
+        managers: &'static Managers,
+        schema: TableSchema,
+        index_id: ContainerId,
+        tid: TransactionId,
+        right_exprs: Vec<ByteCodeExpr>,
+        filter: Option<ByteCodeExpr>,
+        right_child: Box<dyn OpIterator>,
+    ) -> Self {
+        HashEqJoin {
+            managers,
+            schema,
+            right_exprs,
+            filter,
             right_child,
-            hash_table: HashMap::new(),
+            build: BuildSide::Persisted {
+                index_id,
+                transaction_id: tid,
+            },
+            mem_budget: MEMORY_BUDGET,
+            transaction_id: tid,
+            join_type: JoinType::Inner,
+            open: false,
+            pending: Vec::new(),
+            drained: false,
+        }
+    }
+
+    /// Set the bytes of left tuples the hash table holds before the inputs are partitioned.
+    pub fn with_memory_budget(mut self, mem_budget: usize) -> Self {
+        self.mem_budget = mem_budget;
+        self
+    }
+
+    /// Set the children whose tuples without a match are returned padded with nulls. A join
+    /// probing a persisted hash index cannot return the left tuples without a match.
+    pub fn with_join_type(mut self, join_type: JoinType) -> Self {
+        self.join_type = join_type;
+        self
+    }
+
+    /// The joined tuples of the left tuples matching right_tuple, which are marked as joined
+    fn probe(&mut self, right_tuple: &Tuple) -> Result<Vec<Tuple>, CrustyError> {
+        let key = key_of(&self.right_exprs, right_tuple);
+        // Null never equals a key
+        if key.contains(&Field::Null) {
+            return Ok(Vec::new());
+        }
+        let mut joined = Vec::new();
+        match &mut self.build {
+            BuildSide::Memory { hash_table, .. } => {
+                if let Some(bucket) = hash_table.get_mut(&key) {
+                    for (left_tuple, matched) in bucket {
+                        let tuple = left_tuple.merge(right_tuple);
+                        if passes_filter(self.filter.as_ref(), &tuple) {
+                            *matched = true;
+                            joined.push(tuple);
+                        }
+                    }
+                }
+            }
+            BuildSide::Persisted {
+                index_id,
+                transaction_id,
+            } => {
+                let index = get_index(self.managers, *index_id)?;
+                for id in index.equality_get_value_ids(&key, *transaction_id)? {
+                    if let Some(left_tuple) =
+                        read_record(self.managers, id, *transaction_id, None, None)?
+                    {
+                        let tuple = left_tuple.merge(right_tuple);
+                        if passes_filter(self.filter.as_ref(), &tuple) {
+                            joined.push(tuple);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(joined)
+    }
+
+    /// The left tuples in the hash table not joined with any right tuple, padded with nulls
+    fn unmatched_left(&self) -> Vec<Tuple> {
+        match &self.build {
+            BuildSide::Memory { hash_table, .. } => {
+                let nulls = null_tuple(self.right_child.get_schema());
+                hash_table
+                    .values()
+                    .flatten()
+                    .filter(|(_, matched)| !matched)
+                    .map(|(left_tuple, _)| left_tuple.merge(&nulls))
+                    .collect()
+            }
+            BuildSide::Persisted { .. } => Vec::new(),
+        }
+    }
+
+    /// A left tuple of nulls padding a right tuple without a match
+    fn left_nulls(&self) -> Tuple {
+        // The schema starts with the fields of the left tuples
+        let size = self.schema.size() - self.right_child.get_schema().size();
+        Tuple::new(vec![Field::Null; size])
+    }
+
+    /// Builds the hash table from the left child. Once the left tuples exceed the memory
+    /// budget, the rest of the left child and the whole right child are partitioned instead.
+    fn build(&mut self) -> Result<(), CrustyError> {
+        let (left_exprs, left_child, hash_table, spilled) = match &mut self.build {
+            BuildSide::Memory {
+                left_exprs,
+                left_child,
+                hash_table,
+                spilled,
+            } => (left_exprs, left_child, hash_table, spilled),
+            BuildSide::Persisted { .. } => return Ok(()),
+        };
+        left_child.open()?;
+        hash_table.clear();
+        *spilled = None;
+        let mut used = 0;
+        let keep_left_nulls = self.join_type.preserves_left();
+        let keep_right_nulls = self.join_type.preserves_right();
+        while let Some(tuple) = left_child.next()? {
+            let key = key_of(left_exprs, &tuple);
+            // A key with a null is never probed, so its tuple is only kept to be padded
+            if !keep_left_nulls && key.contains(&Field::Null) {
+                continue;
+            }
+            used += tuple.size();
+            hash_table.entry(key).or_default().push((tuple, false));
+            if used > self.mem_budget {
+                break;
+            }
+        }
+        if used <= self.mem_budget {
+            return Ok(());
+        }
+
+        let fan_out = (self.mem_budget / block_size(self.managers)).clamp(2, MAX_FAN_OUT);
+        let tid = self.transaction_id;
+        let mut left = Partitioner::new(
+            self.managers,
+            left_child.get_schema(),
+            tid,
+            fan_out,
+            0,
+            keep_left_nulls,
+        )?;
+        for (key, tuples) in hash_table.drain() {
+            for (tuple, _) in tuples {
+                left.push(&key, &tuple)?;
+            }
+        }
+        while let Some(tuple) = left_child.next()? {
+            left.push(&key_of(left_exprs, &tuple), &tuple)?;
+        }
+        let mut right = Partitioner::new(
+            self.managers,
+            self.right_child.get_schema(),
+            tid,
+            fan_out,
+            0,
+            keep_right_nulls,
+        )?;
+        while let Some(tuple) = self.right_child.next()? {
+            right.push(&key_of(&self.right_exprs, &tuple), &tuple)?;
+        }
+        let (left, sizes) = left.finish()?;
+        let (right, _) = right.finish()?;
+
+        // Partitions still too large are split again with another hash
+        let mut work: Vec<(usize, SpillFile, usize, SpillFile)> = left
+            .into_iter()
+            .zip(sizes)
+            .zip(right)
+            .map(|((left, size), right)| (0, left, size, right))
+            .collect();
+        let mut pairs = Vec::new();
+        while let Some((level, left, size, right)) = work.pop() {
+            if size <= self.mem_budget || level == MAX_LEVEL {
+                pairs.push((left, right));
+                continue;
+            }
+            let mut left_parts = Partitioner::new(
+                self.managers,
+                left_child.get_schema(),
+                tid,
+                fan_out,
+                level + 1,
+                keep_left_nulls,
+            )?;
+            let mut cursor = SpillCursor::default();
+            while let Some(tuple) = cursor.next(&left)? {
+                left_parts.push(&key_of(left_exprs, &tuple), &tuple)?;
+            }
+            let (left_parts, sizes) = left_parts.finish()?;
+            if sizes.contains(&size) {
+                // Nothing was split off, as when every tuple has the same key
+                pairs.push((left, right));
+                continue;
+            }
+            let mut right_parts = Partitioner::new(
+                self.managers,
+                self.right_child.get_schema(),
+                tid,
+                fan_out,
+                level + 1,
+                keep_right_nulls,
+            )?;
+            let mut cursor = SpillCursor::default();
+            while let Some(tuple) = cursor.next(&right)? {
+                right_parts.push(&key_of(&self.right_exprs, &tuple), &tuple)?;
+            }
+            let (right_parts, _) = right_parts.finish()?;
+            work.extend(
+                left_parts
+                    .into_iter()
+                    .zip(sizes)
+                    .zip(right_parts)
+                    .map(|((left, size), right)| (level + 1, left, size, right)),
+            );
+        }
+        debug!("Hash join spilled {} pairs of partitions", pairs.len());
+
+        let mut partitions = Spilled {
+            pairs,
+            current: 0,
+            right: SpillCursor::default(),
+        };
+        partitions.load(0, left_exprs, hash_table)?;
+        *spilled = Some(partitions);
+        Ok(())
+    }
+
+    /// The next right tuple to probe the hash table with
+    fn next_right(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match &mut self.build {
+            BuildSide::Memory {
+                spilled: Some(spilled),
+                ..
+            } => spilled.next(),
+            _ => self.right_child.next(),
+        }
+    }
+
+    /// Put the next pair of spilled partitions in the hash table, if there is one
+    fn next_pair(&mut self) -> Result<bool, CrustyError> {
+        match &mut self.build {
+            BuildSide::Memory {
+                left_exprs,
+                hash_table,
+                spilled: Some(spilled),
+                ..
+            } => spilled.advance(left_exprs, hash_table),
+            _ => Ok(false),
         }
     }
 }
 
 impl OpIterator for HashEqJoin {
     fn configure(&mut self, will_rewind: bool) {
-        self.left_child.configure(false); // left child will never be rewound by HJ
+        if let BuildSide::Memory { left_child, .. } = &mut self.build {
+            left_child.configure(false); // left child will never be rewound by HJ
+        }
         self.right_child.configure(will_rewind);
     }
 
     fn open(&mut self) -> Result<(), CrustyError> {
-        self.left_child.open()?;
-        self.right_child.open()?;
-
-        while let Some(tuple) = self.left_child.next()? {
-            let key = self.left_expr.eval(&tuple);
-            self.hash_table.entry(key).or_default().push(tuple);
+        if !self.open {
+            if matches!(self.build, BuildSide::Persisted { .. }) && self.join_type.preserves_left()
+            {
+                return Err(CrustyError::ExecutionError(String::from(
+                    "A persisted hash table cannot return its unmatched tuples",
+                )));
+            }
+            self.right_child.open()?;
+            self.build()?;
+            self.pending.clear();
+            self.drained = false;
+            self.open = true;
         }
-
         Ok(())
     }
 
     fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
-        while let Some(right_tuple) = self.right_child.next()? {
-            let right_key = self.right_expr.eval(&right_tuple);
-            if let Some(matching_tuples) = self.hash_table.get(&right_key) {
-                if let Some(left_tuple) = matching_tuples.iter().next() {
-                    let mut joined_tuple = left_tuple.clone();
-                    joined_tuple
-                        .field_vals
-                        .extend(right_tuple.field_vals.clone());
-                    return Ok(Some(joined_tuple));
+        loop {
+            if let Some(joined) = self.pending.pop() {
+                return Ok(Some(joined));
+            }
+            match self.next_right()? {
+                Some(right_tuple) => {
+                    let mut joined = self.probe(&right_tuple)?;
+                    if joined.is_empty() && self.join_type.preserves_right() {
+                        joined.push(self.left_nulls().merge(&right_tuple));
+                    }
+                    joined.reverse();
+                    self.pending = joined;
+                }
+                // Every right tuple of the hash table has probed it
+                None if !self.drained => {
+                    self.drained = true;
+                    if self.join_type.preserves_left() {
+                        self.pending = self.unmatched_left();
+                    }
+                }
+                None => {
+                    if !self.next_pair()? {
+                        return Ok(None);
+                    }
+                    self.drained = false;
                 }
             }
         }
-        Ok(None)
     }
 
     fn close(&mut self) -> Result<(), CrustyError> {
-        self.left_child.close()?;
+        if let BuildSide::Memory {
+            left_child,
+            hash_table,
+            spilled,
+            ..
+        } = &mut self.build
+        {
+            left_child.close()?;
+            hash_table.clear();
+            // Dropping the partitions removes their containers
+            *spilled = None;
+        }
         self.right_child.close()?;
+        self.pending.clear();
+        self.drained = false;
+        self.open = false;
         Ok(())
     }
 
     fn rewind(&mut self) -> Result<(), CrustyError> {
-        self.right_child.rewind()?;
+        match &mut self.build {
+            BuildSide::Memory {
+                left_exprs,
+                hash_table,
+                spilled: Some(spilled),
+                ..
+            } => spilled.load(0, left_exprs, hash_table)?,
+            BuildSide::Memory { hash_table, .. } => {
+                for (_, matched) in hash_table.values_mut().flatten() {
+                    *matched = false;
+                }
+                self.right_child.rewind()?
+            }
+            BuildSide::Persisted { .. } => self.right_child.rewind()?,
+        }
+        self.pending.clear();
+        self.drained = false;
         Ok(())
     }
 
@@ -101,12 +585,14 @@ impl OpIterator for HashEqJoin {
 
 #[cfg(test)]
 mod test {
+    use super::super::index_scan::test::indexed_table;
     use super::super::TupleIterator;
     use super::*;
     use crate::testutil::execute_iter;
     use crate::testutil::new_test_managers;
     use crate::testutil::TestTuples;
-    use common::bytecode_expr::{ByteCodeExpr, ByteCodes};
+    use common::bytecode_expr::{colidx_expr, ByteCodeExpr, ByteCodes};
+    use common::prelude::IndexKind;
 
     fn get_join_predicate() -> (ByteCodeExpr, ByteCodeExpr) {
         // Joining two tables each containing the following tuples:
@@ -138,8 +624,9 @@ mod test {
         let mut iter = Box::new(HashEqJoin::new(
             managers,
             setup.schema.clone(),
-            left_expr,
-            right_expr,
+            vec![(left_expr, right_expr)],
+            None,
+            TransactionId::new(),
             Box::new(TupleIterator::new(
                 setup.tuples.clone(),
                 setup.schema.clone(),
@@ -169,6 +656,184 @@ mod test {
             let _ = run_hash_eq_join(left_expr, right_expr);
         }
 
+        #[test]
+        fn test_join_all_matches() {
+            // left(col(1)) == right(col(1)), every left tuple with the same b matches
+            let mut left_expr = ByteCodeExpr::new();
+            left_expr.add_code(ByteCodes::PushField as usize);
+            left_expr.add_code(1);
+            let t = run_hash_eq_join(left_expr.clone(), left_expr);
+            assert_eq!(3 * 3 + 3 * 3, t.len());
+        }
+
+        #[test]
+        fn test_spilled_join() {
+            // Many copies of the test tuples on the left, with a null key in some
+            let managers = new_test_managers();
+            let setup = TestTuples::new("");
+            let mut left: Vec<Tuple> = (0..200).flat_map(|_| setup.tuples.clone()).collect();
+            for tuple in left.iter_mut().step_by(7) {
+                tuple.set_field(2, Field::Null);
+            }
+            let join = |col: usize, mem_budget: usize| {
+                let mut left_expr = ByteCodeExpr::new();
+                left_expr.add_code(ByteCodes::PushField as usize);
+                left_expr.add_code(col);
+                let right_expr = left_expr.clone();
+                let mut iter = HashEqJoin::new(
+                    managers,
+                    setup.schema.merge(&setup.schema),
+                    vec![(left_expr, right_expr)],
+                    None,
+                    TransactionId::new(),
+                    Box::new(TupleIterator::new(left.clone(), setup.schema.clone())),
+                    Box::new(TupleIterator::new(
+                        setup.tuples.clone(),
+                        setup.schema.clone(),
+                    )),
+                )
+                .with_memory_budget(mem_budget);
+                iter.configure(true);
+                iter
+            };
+            // Keys on c are split over the partitions, while a partition of one key on b is
+            // not split again
+            for col in [2, 1] {
+                let expected = execute_iter(&mut join(col, usize::MAX), true).unwrap();
+                assert!(!expected.is_empty());
+                let mut iter = join(col, 1024);
+                let t = execute_iter(&mut iter, true).unwrap();
+                assert_eq!(expected, t);
+                iter.rewind().unwrap();
+                assert_eq!(expected, execute_iter(&mut iter, true).unwrap());
+            }
+        }
+
+        #[test]
+        fn test_outer_joins() {
+            // left(col(1)) == right(col(0)) with a null key on both sides
+            let managers = new_test_managers();
+            let setup = TestTuples::new("");
+            let mut left = setup.tuples.clone();
+            left[0].set_field(1, Field::Null);
+            let mut right = setup.tuples.clone();
+            right[5].set_field(0, Field::Null);
+            // Left tuples 2 to 6 are joined with right tuple 1 or 2. Left tuple 1 and right
+            // tuples 3 to 6 are not joined.
+            let cases = [
+                (JoinType::Inner, 5),
+                (JoinType::Left, 6),
+                (JoinType::Right, 9),
+                (JoinType::Full, 10),
+            ];
+            for (join_type, expected) in cases {
+                for mem_budget in [MEMORY_BUDGET, 0] {
+                    let mut iter = HashEqJoin::new(
+                        managers,
+                        setup.schema.merge(&setup.schema),
+                        vec![(colidx_expr(1), colidx_expr(0))],
+                        None,
+                        TransactionId::new(),
+                        Box::new(TupleIterator::new(left.clone(), setup.schema.clone())),
+                        Box::new(TupleIterator::new(right.clone(), setup.schema.clone())),
+                    )
+                    .with_memory_budget(mem_budget)
+                    .with_join_type(join_type);
+                    iter.configure(true);
+                    let t = execute_iter(&mut iter, true).unwrap();
+                    assert_eq!(expected, t.len());
+                    // Field a of a left tuple and field b of a right tuple are never null
+                    let left_padded = t.iter().filter(|t| t.get_field(5) == Some(&Field::Null));
+                    let right_padded = t.iter().filter(|t| t.get_field(0) == Some(&Field::Null));
+                    let left_expected = if join_type.preserves_left() { 1 } else { 0 };
+                    let right_expected = if join_type.preserves_right() { 4 } else { 0 };
+                    assert_eq!(left_expected, left_padded.count());
+                    assert_eq!(right_expected, right_padded.count());
+                    iter.rewind().unwrap();
+                    assert_eq!(t, execute_iter(&mut iter, true).unwrap());
+                }
+            }
+        }
+
+        #[test]
+        fn test_composite_key_and_filter() {
+            // left(col(1)) == right(col(1)) and left(col(2)) == right(col(2)) and
+            // left(col(0)) < right(col(0))
+            let managers = new_test_managers();
+            let setup = TestTuples::new("");
+            let mut filter = ByteCodeExpr::new();
+            filter.add_code(ByteCodes::PushField as usize);
+            filter.add_code(0);
+            filter.add_code(ByteCodes::PushField as usize);
+            filter.add_code(4);
+            filter.add_code(ByteCodes::Lt as usize);
+            for mem_budget in [MEMORY_BUDGET, 0] {
+                let mut iter = HashEqJoin::new(
+                    managers,
+                    setup.schema.merge(&setup.schema),
+                    vec![
+                        (colidx_expr(1), colidx_expr(1)),
+                        (colidx_expr(2), colidx_expr(2)),
+                    ],
+                    Some(filter.clone()),
+                    TransactionId::new(),
+                    Box::new(TupleIterator::new(
+                        setup.tuples.clone(),
+                        setup.schema.clone(),
+                    )),
+                    Box::new(TupleIterator::new(
+                        setup.tuples.clone(),
+                        setup.schema.clone(),
+                    )),
+                )
+                .with_memory_budget(mem_budget);
+                iter.configure(false);
+                // Only 1 1 3 E and 2 1 3 G, and 5 2 5 G and 6 2 5 G share b and c
+                let t = execute_iter(&mut iter, true).unwrap();
+                let ids: Vec<_> = t
+                    .iter()
+                    .map(|t| (t.get_field(0).unwrap(), t.get_field(4).unwrap()))
+                    .collect();
+                assert_eq!(
+                    vec![
+                        (&Field::Int(1), &Field::Int(2)),
+                        (&Field::Int(5), &Field::Int(6))
+                    ],
+                    ids
+                );
+            }
+        }
+
+        #[test]
+        fn test_persisted_join() {
+            // Probing a hash index of the left table on b with right(col(1))
+            let managers = new_test_managers();
+            let (setup, _, index_id) = indexed_table(managers, vec![1], IndexKind::Hash);
+            let mut right_expr = ByteCodeExpr::new();
+            right_expr.add_code(ByteCodes::PushField as usize);
+            right_expr.add_code(1);
+            let mut iter = HashEqJoin::persisted(
+                managers,
+                setup.schema.merge(&setup.schema),
+                index_id,
+                TransactionId::new(),
+                vec![right_expr],
+                None,
+                Box::new(TupleIterator::new(
+                    setup.tuples.clone(),
+                    setup.schema.clone(),
+                )),
+            );
+            iter.configure(true);
+            let t = execute_iter(&mut iter, false).unwrap();
+            assert_eq!(3 * 3 + 3 * 3, t.len());
+            for tuple in &t {
+                assert_eq!(tuple.get_field(1), tuple.get_field(5));
+            }
+            iter.rewind().unwrap();
+            assert_eq!(t.len(), execute_iter(&mut iter, true).unwrap().len());
+        }
+
         #[test]
         fn test_join() {
             // Joining two tables each containing the following tuples:
@@ -0,0 +1,246 @@
+use super::index_scan::{get_index, read_record};
+use super::OpIterator;
+use crate::Managers;
+use common::bytecode_expr::ByteCodeExpr;
+use common::ids::{ContainerId, TransactionId};
+use common::prelude::ValueId;
+use common::traits::index_trait::IndexTrait;
+use common::{CrustyError, Field, TableSchema, Tuple};
+
+/// Index nested loop join implementation. For every tuple of the outer child, the inner
+/// records are looked up in an index of the inner table instead of scanning it.
+pub struct IndexNestedLoopJoin {
+    // Parameters (No need to reset on close)
+    managers: &'static Managers,
+    schema: TableSchema,
+    outer_exprs: Vec<ByteCodeExpr>,
+    index_id: ContainerId,
+    transaction_id: TransactionId,
+    inner_filter: Option<ByteCodeExpr>,
+    inner_projection: Option<Vec<ByteCodeExpr>>,
+    filter: Option<ByteCodeExpr>,
+    outer_first: bool,
+    outer_child: Box<dyn OpIterator>,
+
+    // States (Need to reset on close)
+    current_outer: Option<Tuple>,
+    matches: Vec<ValueId>,
+    position: usize,
+}
+
+impl IndexNestedLoopJoin {
+    /// IndexNestedLoopJoin constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the joined tuples.
+    /// * `outer_exprs` - Expressions on the outer tuple giving a prefix of the index key.
+    /// * `index_id` - Index of the inner table.
+    /// * `inner_filter` - Filter of the inner records, bound to the inner table.
+    /// * `inner_projection` - Projection of the inner records, bound to the inner table.
+    /// * `filter` - Predicate on the joined tuple not answered by the index.
+    /// * `outer_first` - Whether the outer tuple comes first in the joined tuple.
+    /// * `outer_child` - Outer child of join operator.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        managers: &'static Managers,
+        schema: TableSchema,
+        outer_exprs: Vec<ByteCodeExpr>,
+        index_id: ContainerId,
+        tid: TransactionId,
+        inner_filter: Option<ByteCodeExpr>,
+        inner_projection: Option<Vec<ByteCodeExpr>>,
+        filter: Option<ByteCodeExpr>,
+        outer_first: bool,
+        outer_child: Box<dyn OpIterator>,
+    ) -> Self {
+        IndexNestedLoopJoin {
+            managers,
+            schema,
+            outer_exprs,
+            index_id,
+            transaction_id: tid,
+            inner_filter,
+            inner_projection,
+            filter,
+            outer_first,
+            outer_child,
+            current_outer: None,
+            matches: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn join(&self, outer: &Tuple, inner: &Tuple) -> Tuple {
+        if self.outer_first {
+            outer.merge(inner)
+        } else {
+            inner.merge(outer)
+        }
+    }
+
+    fn reset(&mut self) {
+        self.current_outer = None;
+        self.matches.clear();
+        self.position = 0;
+    }
+}
+
+impl OpIterator for IndexNestedLoopJoin {
+    fn configure(&mut self, will_rewind: bool) {
+        self.outer_child.configure(will_rewind);
+    }
+
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.outer_child.open()?;
+        self.reset();
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let index = get_index(self.managers, self.index_id)?;
+        loop {
+            if self.current_outer.is_none() {
+                match self.outer_child.next()? {
+                    Some(outer) => {
+                        let key: Vec<Field> =
+                            self.outer_exprs.iter().map(|e| e.eval(&outer)).collect();
+                        // Null never equals a key
+                        self.matches = if key.contains(&Field::Null) {
+                            Vec::new()
+                        } else {
+                            index.equality_get_value_ids(&key, self.transaction_id)?
+                        };
+                        self.position = 0;
+                        self.current_outer = Some(outer);
+                    }
+                    None => return Ok(None),
+                }
+            }
+
+            while let Some(id) = self.matches.get(self.position).copied() {
+                self.position += 1;
+                let inner = match read_record(
+                    self.managers,
+                    id,
+                    self.transaction_id,
+                    self.inner_filter.as_ref(),
+                    self.inner_projection.as_ref(),
+                )? {
+                    Some(inner) => inner,
+                    None => continue,
+                };
+                let joined = self.join(self.current_outer.as_ref().unwrap(), &inner);
+                if let Some(filter) = &self.filter {
+                    match filter.eval(&joined) {
+                        Field::Bool(true) => {}
+                        Field::Bool(false) => continue,
+                        _ => panic!("Filter must evaluate to a boolean"),
+                    }
+                }
+                return Ok(Some(joined));
+            }
+
+            // matches exhausted, move to next outer
+            self.current_outer = None;
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.outer_child.close()?;
+        self.reset();
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.outer_child.rewind()?;
+        self.reset();
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::index_scan::test::indexed_table;
+    use super::super::TupleIterator;
+    use super::*;
+    use crate::testutil::{execute_iter, new_test_managers, TestTuples};
+    use common::bytecode_expr::ByteCodes;
+    use common::prelude::IndexKind;
+
+    fn push_field(i: usize) -> ByteCodeExpr {
+        let mut expr = ByteCodeExpr::new();
+        expr.add_code(ByteCodes::PushField as usize);
+        expr.add_code(i);
+        expr
+    }
+
+    fn get_iter(
+        outer_exprs: Vec<ByteCodeExpr>,
+        index_columns: Vec<usize>,
+        outer_first: bool,
+    ) -> Box<dyn OpIterator> {
+        let managers = new_test_managers();
+        let (setup, _, index_id) = indexed_table(managers, index_columns, IndexKind::BTree);
+        let outer = TestTuples::new("");
+        let schema = if outer_first {
+            outer.schema.merge(&setup.schema)
+        } else {
+            setup.schema.merge(&outer.schema)
+        };
+        let mut iter = Box::new(IndexNestedLoopJoin::new(
+            managers,
+            schema,
+            outer_exprs,
+            index_id,
+            TransactionId::new(),
+            None,
+            None,
+            None,
+            outer_first,
+            Box::new(TupleIterator::new(outer.tuples, outer.schema)),
+        ));
+        iter.configure(false);
+        iter
+    }
+
+    #[test]
+    fn test_inlj_eq() {
+        // outer.a = inner.b
+        let mut iter = get_iter(vec![push_field(0)], vec![1], true);
+        let tuples = execute_iter(&mut *iter, true).unwrap();
+        // outer a = 1 and a = 2 each match three inner records
+        assert_eq!(6, tuples.len());
+        for t in &tuples {
+            assert_eq!(t.get_field(0), t.get_field(5));
+            assert_eq!(8, t.len());
+        }
+    }
+
+    #[test]
+    fn test_inlj_key_prefix_and_order() {
+        // (outer.b, outer.c) = (inner.b, inner.c)
+        let mut iter = get_iter(vec![push_field(1), push_field(2)], vec![1, 2], false);
+        let tuples = execute_iter(&mut *iter, true).unwrap();
+        // two records with (1, 3), one with (1, 4), one with (2, 4), two with (2, 5)
+        assert_eq!(4 + 1 + 1 + 4, tuples.len());
+        for t in &tuples {
+            assert_eq!(t.get_field(1), t.get_field(5));
+            assert_eq!(t.get_field(2), t.get_field(6));
+        }
+    }
+
+    #[test]
+    fn test_inlj_rewind() {
+        let mut iter = get_iter(vec![push_field(0)], vec![0], true);
+        iter.configure(true);
+        let first = execute_iter(&mut *iter, true).unwrap();
+        assert_eq!(6, first.len());
+        iter.rewind().unwrap();
+        assert_eq!(first, execute_iter(&mut *iter, true).unwrap());
+    }
+}
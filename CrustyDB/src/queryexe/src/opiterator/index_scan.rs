@@ -0,0 +1,360 @@
+use super::OpIterator;
+use crate::{Managers, TransactionManager};
+use common::bytecode_expr::ByteCodeExpr;
+use common::ids::Permissions;
+use common::ids::{ContainerId, TransactionId};
+use common::prelude::ValueId;
+use common::row::{self, RowLayout};
+use common::storage_trait::StorageTrait;
+use common::traits::index_trait::IndexTrait;
+use common::traits::transaction_manager_trait::TransactionManagerTrait;
+use common::{CrustyError, Field, TableSchema, Tuple};
+use index::Index;
+use std::sync::Arc;
+
+/// The open index in container index_id
+pub(crate) fn get_index(
+    managers: &'static Managers,
+    index_id: ContainerId,
+) -> Result<Arc<Index>, CrustyError> {
+    managers
+        .im
+        .get_index(index_id)
+        .ok_or_else(|| CrustyError::ExecutionError(format!("Index {} is not open", index_id)))
+}
+
+/// Lock the record at id for tid, which may wait for a lock, and then read it. Returns None if
+/// the record is gone or is a version tid may not see.
+pub(crate) fn lock_record(
+    managers: &'static Managers,
+    id: ValueId,
+    tid: TransactionId,
+) -> Result<Option<Tuple>, CrustyError> {
+    managers.tm.read_record(&id, &tid)?;
+    match managers.sm.get_value(id, tid, Permissions::ReadOnly) {
+        Ok(bytes) => {
+            let tuple = managers.decode_row(id.container_id, &bytes)?;
+            visible_record(managers, tuple, id, tid)
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// Lock a record a scan read before tid locked it, see lock_record. If the transaction manager
+/// locks records, a writer may have changed or deleted the record before tid got the lock, so
+/// it is read again; otherwise the scanned bytes are decoded with the table's layout.
+pub(crate) fn lock_scanned_record(
+    managers: &'static Managers,
+    layout: Option<&RowLayout>,
+    bytes: &[u8],
+    id: ValueId,
+    tid: TransactionId,
+) -> Result<Option<Tuple>, CrustyError> {
+    if TransactionManager::LOCKS_RECORDS {
+        return lock_record(managers, id, tid);
+    }
+    managers.tm.read_record(&id, &tid)?;
+    let tuple = row::decode_row(layout, bytes)?;
+    visible_record(managers, tuple, id, tid)
+}
+
+/// The record tuple at id, if tid may see it
+fn visible_record(
+    managers: &'static Managers,
+    mut tuple: Tuple,
+    id: ValueId,
+    tid: TransactionId,
+) -> Result<Option<Tuple>, CrustyError> {
+    if !managers.tm.is_visible(&tuple, &id, &tid)? {
+        return Ok(None);
+    }
+    tuple.value_id = Some(id);
+    Ok(Some(tuple))
+}
+
+/// Read the record at id and apply a scan's filter and projection to it. Returns None if the
+/// record does not pass the filter.
+pub(crate) fn read_record(
+    managers: &'static Managers,
+    id: ValueId,
+    tid: TransactionId,
+    filter: Option<&ByteCodeExpr>,
+    projection: Option<&Vec<ByteCodeExpr>>,
+) -> Result<Option<Tuple>, CrustyError> {
+    let tuple = match lock_record(managers, id, tid)? {
+        Some(tuple) => tuple,
+        None => return Ok(None),
+    };
+
+    if let Some(filter) = filter {
+        match filter.eval(&tuple) {
+            Field::Bool(true) => {}
+            Field::Bool(false) => return Ok(None),
+            _ => panic!("Filter must evaluate to a boolean"),
+        }
+    }
+
+    if let Some(projection) = projection {
+        Ok(Some(Tuple::new(
+            projection.iter().map(|expr| expr.eval(&tuple)).collect(),
+        )))
+    } else {
+        Ok(Some(tuple))
+    }
+}
+
+/// Index scan operator. Reads the records of a table whose index key lies between a lower
+/// and an upper bound, in key order. Equal bounds make a point lookup.
+pub struct IndexScan {
+    // Parameters (No need to reset on close)
+    schema: TableSchema,
+    managers: &'static Managers,
+    index_id: ContainerId,
+    transaction_id: TransactionId,
+    lower: Option<(Vec<Field>, bool)>,
+    upper: Option<(Vec<Field>, bool)>,
+    filter: Option<ByteCodeExpr>,
+    projection: Option<Vec<ByteCodeExpr>>,
+
+    // States (Need to reset on close)
+    open: bool,
+    value_ids: Vec<ValueId>,
+    position: usize,
+}
+
+impl IndexScan {
+    /// Constructor for the index scan operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the output tuples.
+    /// * `index_id` - Index of the table to scan.
+    /// * `tid` - Transaction used to read the table.
+    /// * `lower` - Lower bound on a prefix of the index key and whether it is inclusive.
+    /// * `upper` - Upper bound on a prefix of the index key and whether it is inclusive.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        managers: &'static Managers,
+        schema: &TableSchema,
+        index_id: ContainerId,
+        tid: TransactionId,
+        lower: Option<(Vec<Field>, bool)>,
+        upper: Option<(Vec<Field>, bool)>,
+        filter: Option<ByteCodeExpr>,
+        projection: Option<Vec<ByteCodeExpr>>,
+    ) -> Self {
+        Self {
+            schema: schema.clone(),
+            managers,
+            index_id,
+            transaction_id: tid,
+            lower,
+            upper,
+            filter,
+            projection,
+            open: false,
+            value_ids: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn lookup(&self) -> Result<Vec<ValueId>, CrustyError> {
+        let index = get_index(self.managers, self.index_id)?;
+        match (&self.lower, &self.upper) {
+            (Some((lower, true)), Some((upper, true))) if lower == upper => {
+                index.equality_get_value_ids(lower, self.transaction_id)
+            }
+            (lower, upper) => index.range_get_value_ids(
+                lower.as_ref().map(|(key, _)| key.as_slice()),
+                upper.as_ref().map(|(key, _)| key.as_slice()),
+                lower.as_ref().is_none_or(|(_, inclusive)| *inclusive),
+                upper.as_ref().is_none_or(|(_, inclusive)| *inclusive),
+                self.transaction_id,
+            ),
+        }
+    }
+}
+
+impl OpIterator for IndexScan {
+    fn configure(&mut self, _will_rewind: bool) {
+        // do nothing
+    }
+
+    fn open(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            self.value_ids = self.lookup()?;
+            self.position = 0;
+        }
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        while let Some(id) = self.value_ids.get(self.position).copied() {
+            self.position += 1;
+            if let Some(tuple) = read_record(
+                self.managers,
+                id,
+                self.transaction_id,
+                self.filter.as_ref(),
+                self.projection.as_ref(),
+            )? {
+                return Ok(Some(tuple));
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.open = false;
+        self.value_ids.clear();
+        self.position = 0;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.position = 0;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::testutil::{execute_iter, new_test_managers, TestTuples};
+    use common::prelude::{IndexInfo, IndexKind};
+
+    /// Create a table holding the test tuples and an index of the given kind on columns
+    pub(crate) fn indexed_table(
+        managers: &'static Managers,
+        columns: Vec<usize>,
+        kind: IndexKind,
+    ) -> (TestTuples, ContainerId, ContainerId) {
+        let setup = TestTuples::new("");
+        let tid = TransactionId::new();
+        let table_id = 1;
+        managers.sm.create_table(table_id).unwrap();
+        managers.register_row_layout(table_id, &setup.schema);
+        let bytes = setup
+            .tuples
+            .iter()
+            .map(|t| managers.encode_row(table_id, t).unwrap())
+            .collect();
+        managers.sm.insert_values(table_id, bytes, tid);
+        let index_id = 2;
+        let attributes = columns
+            .iter()
+            .map(|c| setup.schema.get_attribute(*c).unwrap().clone())
+            .collect();
+        managers
+            .create_index(
+                IndexInfo {
+                    c_id: index_id,
+                    name: "idx".to_string(),
+                    table_id,
+                    key_columns: columns,
+                    attributes,
+                    is_unique: false,
+                    is_primary: false,
+                    kind,
+                },
+                tid,
+            )
+            .unwrap();
+        (setup, table_id, index_id)
+    }
+
+    fn scan(lower: Option<(Vec<Field>, bool)>, upper: Option<(Vec<Field>, bool)>) -> Vec<Tuple> {
+        let managers = new_test_managers();
+        let (setup, _, index_id) = indexed_table(managers, vec![1, 2], IndexKind::BTree);
+        let mut iter = IndexScan::new(
+            managers,
+            &setup.schema,
+            index_id,
+            TransactionId::new(),
+            lower,
+            upper,
+            None,
+            None,
+        );
+        iter.configure(false);
+        execute_iter(&mut iter, true).unwrap()
+    }
+
+    fn column(tuples: &[Tuple], i: usize) -> Vec<Field> {
+        tuples
+            .iter()
+            .map(|t| t.get_field(i).unwrap().clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_index_scan_point() {
+        // (b, c) = (1, 3)
+        let key = vec![Field::Int(1), Field::Int(3)];
+        let tuples = scan(Some((key.clone(), true)), Some((key, true)));
+        assert_eq!(vec![Field::Int(1), Field::Int(2)], column(&tuples, 0));
+        // b = 2, a key prefix
+        let key = vec![Field::Int(2)];
+        assert_eq!(3, scan(Some((key.clone(), true)), Some((key, true))).len());
+    }
+
+    #[test]
+    fn test_index_scan_range() {
+        // b = 1 and c > 3
+        let tuples = scan(
+            Some((vec![Field::Int(1), Field::Int(3)], false)),
+            Some((vec![Field::Int(1)], true)),
+        );
+        assert_eq!(vec![Field::Int(3)], column(&tuples, 0));
+        // b < 2, in key order
+        let tuples = scan(None, Some((vec![Field::Int(2)], false)));
+        assert_eq!(
+            vec![Field::Int(1), Field::Int(2), Field::Int(3)],
+            column(&tuples, 0)
+        );
+        assert_eq!(6, scan(None, None).len());
+    }
+
+    #[test]
+    fn test_index_scan_filter_and_rewind() {
+        let managers = new_test_managers();
+        let (setup, _, index_id) = indexed_table(managers, vec![1], IndexKind::BTree);
+        let mut filter = ByteCodeExpr::new();
+        filter.add_literal(Field::String("G".to_string()));
+        for code in [
+            common::bytecode_expr::ByteCodes::PushField as usize,
+            3,
+            common::bytecode_expr::ByteCodes::PushLit as usize,
+            0,
+            common::bytecode_expr::ByteCodes::Eq as usize,
+        ] {
+            filter.add_code(code);
+        }
+        let key = vec![Field::Int(2)];
+        let mut iter = IndexScan::new(
+            managers,
+            &setup.schema,
+            index_id,
+            TransactionId::new(),
+            Some((key.clone(), true)),
+            Some((key, true)),
+            Some(filter),
+            None,
+        );
+        iter.configure(true);
+        assert_eq!(3, execute_iter(&mut iter, false).unwrap().len());
+        iter.rewind().unwrap();
+        assert_eq!(3, execute_iter(&mut iter, true).unwrap().len());
+    }
+}
@@ -2,23 +2,50 @@ pub use self::aggregate::Aggregate;
 pub use self::cross_join::CrossJoin;
 pub use self::filter::Filter;
 pub use self::hash_join::HashEqJoin;
+pub use self::index_nested_loop_join::IndexNestedLoopJoin;
+pub use self::index_scan::IndexScan;
 pub use self::nested_loop_join::NestedLoopJoin;
 pub use self::project::Project;
 pub use self::seqscan::SeqScan;
+pub use self::sort::{Sort, SortKey};
+pub use self::sort_merge_join::SortMergeJoin;
 pub use self::tuple_iterator::TupleIterator;
 pub use self::update::Update;
-use common::{CrustyError, TableSchema, Tuple};
+use common::bytecode_expr::ByteCodeExpr;
+use common::{CrustyError, Field, TableSchema, Tuple};
 
 mod aggregate;
 mod cross_join;
 mod filter;
 mod hash_join;
+mod index_nested_loop_join;
+mod index_scan;
 mod nested_loop_join;
 mod project;
 mod seqscan;
+mod sort;
+mod sort_merge_join;
+mod spill;
 mod tuple_iterator;
 mod update;
 
+/// Bytes of tuples an operator holds in memory before spilling them to temporary containers
+pub const MEMORY_BUDGET: usize = 16 * 1024 * 1024;
+
+/// Whether a joined tuple passes the residual predicate of a join, if the join has one
+pub(crate) fn passes_filter(filter: Option<&ByteCodeExpr>, tuple: &Tuple) -> bool {
+    match filter.map(|f| f.eval(tuple)) {
+        None | Some(Field::Bool(true)) => true,
+        Some(Field::Bool(false)) => false,
+        _ => panic!("Filter must evaluate to a boolean"),
+    }
+}
+
+/// A tuple of schema with every field null, padding the input of an outer join without a match
+pub(crate) fn null_tuple(schema: &TableSchema) -> Tuple {
+    Tuple::new(vec![Field::Null; schema.size()])
+}
+
 pub trait OpIterator {
     /// conifgure the opiterator
     ///
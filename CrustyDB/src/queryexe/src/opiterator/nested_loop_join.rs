@@ -1,20 +1,30 @@
-use super::OpIterator;
+use super::{null_tuple, passes_filter, OpIterator};
 
 use common::bytecode_expr::ByteCodeExpr;
 use common::datatypes::compare_fields;
-use common::{BooleanOp, CrustyError, TableSchema, Tuple};
+use common::{BooleanOp, CrustyError, JoinType, TableSchema, Tuple};
 
 /// Nested loop join implementation. (You can add any other fields that you think are neccessary)
 pub struct NestedLoopJoin {
     // Parameters (No need to reset on close)
     schema: TableSchema,
     op: BooleanOp,
-    left_expr: ByteCodeExpr,
-    right_expr: ByteCodeExpr,
+    left_exprs: Vec<ByteCodeExpr>,
+    right_exprs: Vec<ByteCodeExpr>,
+    filter: Option<ByteCodeExpr>,
+    join_type: JoinType,
     left_child: Box<dyn OpIterator>,
     right_child: Box<dyn OpIterator>,
     // maintain operator state here
     current_left: Option<Tuple>,
+    /// Whether the current left tuple has been joined
+    left_matched: bool,
+    /// Whether each right tuple, by its position in the right child, has been joined
+    right_matched: Vec<bool>,
+    /// Position of the next right tuple
+    right_pos: usize,
+    /// Whether the left child is exhausted, and the unmatched right tuples are being returned
+    left_done: bool,
 }
 
 impl NestedLoopJoin {
@@ -22,15 +32,17 @@ impl NestedLoopJoin {
     ///
     /// # Arguments
     ///
-    /// * `op` - Operation in join condition.
-    /// * `left_expr` - ByteCodeExpr for the left field in join condition.
-    /// * `right_expr` - ByteCodeExpr for the right field in join condition.
+    /// * `op` - Operation in join condition, which every pair of key fields must satisfy.
+    /// * `left_exprs` - ByteCodeExprs for the left fields in join condition.
+    /// * `right_exprs` - ByteCodeExprs for the right fields in join condition.
+    /// * `filter` - Predicate on the joined tuple, bound to schema.
     /// * `left_child` - Left child of join operator.
     /// * `right_child` - Left child of join operator.
     pub fn new(
         op: BooleanOp,
-        left_expr: ByteCodeExpr,
-        right_expr: ByteCodeExpr,
+        left_exprs: Vec<ByteCodeExpr>,
+        right_exprs: Vec<ByteCodeExpr>,
+        filter: Option<ByteCodeExpr>,
         left_child: Box<dyn OpIterator>,
         right_child: Box<dyn OpIterator>,
         schema: TableSchema,
@@ -38,11 +50,43 @@ impl NestedLoopJoin {
         NestedLoopJoin {
             schema,
             op,
-            left_expr,
-            right_expr,
+            left_exprs,
+            right_exprs,
+            filter,
+            join_type: JoinType::Inner,
             left_child,
             right_child,
             current_left: None,
+            left_matched: false,
+            right_matched: Vec::new(),
+            right_pos: 0,
+            left_done: false,
+        }
+    }
+
+    /// Set the children whose tuples without a match are returned padded with nulls.
+    pub fn with_join_type(mut self, join_type: JoinType) -> Self {
+        self.join_type = join_type;
+        self
+    }
+
+    fn reset(&mut self) {
+        self.current_left = None;
+        self.left_matched = false;
+        self.right_matched.clear();
+        self.right_pos = 0;
+        self.left_done = false;
+    }
+
+    /// The next right tuple with its position in the right child
+    fn next_right(&mut self) -> Result<Option<(usize, Tuple)>, CrustyError> {
+        let pos = self.right_pos;
+        match self.right_child.next()? {
+            Some(right) => {
+                self.right_pos += 1;
+                Ok(Some((pos, right)))
+            }
+            None => Ok(None),
         }
     }
 }
@@ -56,54 +100,82 @@ impl OpIterator for NestedLoopJoin {
     fn open(&mut self) -> Result<(), CrustyError> {
         self.left_child.open()?;
         self.right_child.open()?;
-        self.current_left = None;
+        self.reset();
         Ok(())
     }
 
     fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
         loop {
+            if self.left_done {
+                // Right tuples no left tuple was joined with
+                while let Some((pos, right)) = self.next_right()? {
+                    if !self.right_matched.get(pos).copied().unwrap_or(false) {
+                        return Ok(Some(null_tuple(self.left_child.get_schema()).merge(&right)));
+                    }
+                }
+                return Ok(None);
+            }
+
             // Fetch new left tuple if needed
             if self.current_left.is_none() {
                 match self.left_child.next()? {
                     Some(left) => {
                         self.current_left = Some(left);
-                        // reset right side for new left
-                        self.right_child.rewind()?;
+                        self.left_matched = false;
                     }
+                    None if self.join_type.preserves_right() => self.left_done = true,
                     None => return Ok(None),
                 }
+                // reset right side for new left, or for the unmatched right tuples
+                self.right_child.rewind()?;
+                self.right_pos = 0;
+                continue;
             }
 
             // At this point we have a left tuple
-            let left = self.current_left.as_ref().unwrap();
             // Iterate over right child
-            while let Some(right) = self.right_child.next()? {
-                let lval = self.left_expr.eval(left);
-                let rval = self.right_expr.eval(&right);
-                if compare_fields(self.op, &lval, &rval) {
+            while let Some((pos, right)) = self.next_right()? {
+                let left = self.current_left.as_ref().unwrap();
+                let matches = self
+                    .left_exprs
+                    .iter()
+                    .zip(&self.right_exprs)
+                    .all(|(l, r)| compare_fields(self.op, &l.eval(left), &r.eval(&right)));
+                if matches {
                     // combine matching tuples
-                    let mut fields = left.field_vals.clone();
-                    fields.extend(right.field_vals.clone());
-                    return Ok(Some(Tuple::new(fields)));
+                    let joined = left.merge(&right);
+                    if passes_filter(self.filter.as_ref(), &joined) {
+                        self.left_matched = true;
+                        if self.join_type.preserves_right() {
+                            if self.right_matched.len() <= pos {
+                                self.right_matched.resize(pos + 1, false);
+                            }
+                            self.right_matched[pos] = true;
+                        }
+                        return Ok(Some(joined));
+                    }
                 }
             }
 
             // right exhausted, move to next left
-            self.current_left = None;
+            let left = self.current_left.take().unwrap();
+            if self.join_type.preserves_left() && !self.left_matched {
+                return Ok(Some(left.merge(&null_tuple(self.right_child.get_schema()))));
+            }
         }
     }
 
     fn close(&mut self) -> Result<(), CrustyError> {
         self.left_child.close()?;
         self.right_child.close()?;
-        self.current_left = None;
+        self.reset();
         Ok(())
     }
 
     fn rewind(&mut self) -> Result<(), CrustyError> {
         self.left_child.rewind()?;
         self.right_child.rewind()?;
-        self.current_left = None;
+        self.reset();
         Ok(())
     }
 
@@ -119,8 +191,8 @@ mod test {
     use super::*;
     use crate::testutil::execute_iter;
     use crate::testutil::TestTuples;
-    use common::bytecode_expr::{ByteCodeExpr, ByteCodes};
-    use common::Field;
+    use common::bytecode_expr::{colidx_expr, ByteCodeExpr, ByteCodes};
+    use common::{Field, JoinType};
 
     fn get_join_predicate() -> (ByteCodeExpr, ByteCodeExpr) {
         // Joining two tables each containing the following tuples:
@@ -154,8 +226,9 @@ mod test {
         let setup = TestTuples::new("");
         let mut iter = Box::new(NestedLoopJoin::new(
             op,
-            left_expr,
-            right_expr,
+            vec![left_expr],
+            vec![right_expr],
+            None,
             Box::new(TupleIterator::new(
                 setup.tuples.clone(),
                 setup.schema.clone(),
@@ -190,6 +263,93 @@ mod test {
             let _ = run_nested_loop_join(BooleanOp::Eq, left_expr, right_expr);
         }
 
+        #[test]
+        fn test_composite_key_and_filter() {
+            // left(col(1)) == right(col(1)) and left(col(2)) == right(col(2)) and
+            // left(col(0)) < right(col(0))
+            let setup = TestTuples::new("");
+            let mut filter = ByteCodeExpr::new();
+            filter.add_code(ByteCodes::PushField as usize);
+            filter.add_code(0);
+            filter.add_code(ByteCodes::PushField as usize);
+            filter.add_code(4);
+            filter.add_code(ByteCodes::Lt as usize);
+            let mut iter = NestedLoopJoin::new(
+                BooleanOp::Eq,
+                vec![colidx_expr(1), colidx_expr(2)],
+                vec![colidx_expr(1), colidx_expr(2)],
+                Some(filter),
+                Box::new(TupleIterator::new(
+                    setup.tuples.clone(),
+                    setup.schema.clone(),
+                )),
+                Box::new(TupleIterator::new(
+                    setup.tuples.clone(),
+                    setup.schema.clone(),
+                )),
+                setup.schema.merge(&setup.schema),
+            );
+            iter.configure(false);
+            // Only 1 1 3 E and 2 1 3 G, and 5 2 5 G and 6 2 5 G share b and c
+            let t = execute_iter(&mut iter, true).unwrap();
+            let ids: Vec<_> = t
+                .iter()
+                .map(|t| (t.get_field(0).unwrap(), t.get_field(4).unwrap()))
+                .collect();
+            assert_eq!(
+                vec![
+                    (&Field::Int(1), &Field::Int(2)),
+                    (&Field::Int(5), &Field::Int(6))
+                ],
+                ids
+            );
+        }
+
+        #[test]
+        fn test_outer_joins() {
+            // left(col(1)) == right(col(0)), every left tuple is joined with right tuple 1 or 2
+            let setup = TestTuples::new("");
+            let cases = [
+                (JoinType::Inner, 6, 0),
+                (JoinType::Left, 6, 0),
+                (JoinType::Right, 10, 4),
+                (JoinType::Full, 10, 4),
+            ];
+            for (join_type, expected, padded) in cases {
+                let mut iter = NestedLoopJoin::new(
+                    BooleanOp::Eq,
+                    vec![colidx_expr(1)],
+                    vec![colidx_expr(0)],
+                    None,
+                    Box::new(TupleIterator::new(
+                        setup.tuples.clone(),
+                        setup.schema.clone(),
+                    )),
+                    Box::new(TupleIterator::new(
+                        setup.tuples.clone(),
+                        setup.schema.clone(),
+                    )),
+                    setup.schema.merge(&setup.schema),
+                )
+                .with_join_type(join_type);
+                iter.configure(true);
+                let t = execute_iter(&mut iter, true).unwrap();
+                assert_eq!(expected, t.len());
+                // Right tuples 3 to 6 are padded with a left tuple of nulls
+                let nulls: Vec<_> = t
+                    .iter()
+                    .filter(|t| t.get_field(0) == Some(&Field::Null))
+                    .collect();
+                assert_eq!(padded, nulls.len());
+                for t in nulls {
+                    assert!(t.field_vals[..4].iter().all(|f| *f == Field::Null));
+                    assert!(t.get_field(4).unwrap() > &Field::Int(2));
+                }
+                iter.rewind().unwrap();
+                assert_eq!(t, execute_iter(&mut iter, true).unwrap());
+            }
+        }
+
         #[test]
         fn test_eq_join() {
             // Joining two tables each containing the following tuples:
@@ -1,11 +1,14 @@
+use super::index_scan::lock_scanned_record;
 use super::OpIterator;
 use crate::{Managers, StorageManager};
 use common::bytecode_expr::ByteCodeExpr;
 use common::ids::Permissions;
 use common::ids::{ContainerId, TransactionId};
 use common::prelude::ValueId;
+use common::row::RowLayout;
 use common::storage_trait::StorageTrait;
 use common::{CrustyError, Field, TableSchema, Tuple};
+use std::sync::Arc;
 
 /// Sequential scan operator
 pub struct SeqScan {
@@ -13,6 +16,7 @@ pub struct SeqScan {
     schema: TableSchema,
     managers: &'static Managers,
     container_id: ContainerId,
+    layout: Option<Arc<RowLayout>>,
     transaction_id: TransactionId,
     filter: Option<ByteCodeExpr>,
     projection: Option<Vec<ByteCodeExpr>>,
@@ -44,6 +48,7 @@ impl SeqScan {
             schema: schema.clone(),
             managers,
             container_id: *container_id,
+            layout: managers.row_layout(*container_id),
             transaction_id: tid,
             index: None,
             file_iter: None,
@@ -88,11 +93,19 @@ impl OpIterator for SeqScan {
             .as_mut()
             .expect("File iterator should be set on open");
 
-        for (bytes, id) in file_iter.by_ref() {
-            // Create the tuple
-            let mut tuple = Tuple::from_bytes(&bytes);
-            tuple.value_id = Some(id);
+        for scanned in file_iter.by_ref() {
+            let (bytes, id) = scanned?;
             self.index = Some(id);
+            let tuple = match lock_scanned_record(
+                self.managers,
+                self.layout.as_deref(),
+                &bytes,
+                id,
+                self.transaction_id,
+            )? {
+                Some(tuple) => tuple,
+                None => continue,
+            };
 
             if let Some(filter) = &self.filter {
                 match filter.eval(&tuple) {
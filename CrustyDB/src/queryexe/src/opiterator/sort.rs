@@ -0,0 +1,453 @@
+use super::spill::{block_size, SpillCursor, SpillFile};
+use super::OpIterator;
+use crate::Managers;
+use common::bytecode_expr::ByteCodeExpr;
+use common::ids::TransactionId;
+use common::{CrustyError, Field, TableSchema, Tuple};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// A sort key: the expression, whether it is ascending and whether nulls come first
+pub type SortKey = (ByteCodeExpr, bool, bool);
+
+/// The value of a sort key of a tuple, ordered by the key's direction and null placement
+#[derive(PartialEq, Eq)]
+pub(crate) struct KeyField {
+    field: Field,
+    asc: bool,
+    nulls_first: bool,
+}
+
+impl KeyField {
+    pub(crate) fn is_null(&self) -> bool {
+        self.field == Field::Null
+    }
+}
+
+impl Ord for KeyField {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let null_order = if self.nulls_first {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        };
+        match (&self.field, &other.field) {
+            (Field::Null, Field::Null) => Ordering::Equal,
+            (Field::Null, _) => null_order,
+            (_, Field::Null) => null_order.reverse(),
+            (a, b) if self.asc => a.cmp(b),
+            (a, b) => b.cmp(a),
+        }
+    }
+}
+
+impl PartialOrd for KeyField {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The sort key of tuple
+pub(crate) fn key_of(keys: &[SortKey], tuple: &Tuple) -> Vec<KeyField> {
+    keys.iter()
+        .map(|(expr, asc, nulls_first)| KeyField {
+            field: expr.eval(tuple),
+            asc: *asc,
+            nulls_first: *nulls_first,
+        })
+        .collect()
+}
+
+/// The next tuple of a run in a merge. The heap pops the smallest key first and, among equal
+/// keys, the tuple of the earliest run, which keeps the sort stable.
+struct Head {
+    key: Vec<KeyField>,
+    run: usize,
+    tuple: Tuple,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .cmp(&self.key)
+            .then_with(|| other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// A k-way merge of sorted runs, holding one block of each run in memory
+pub(crate) struct Merge {
+    runs: Vec<SpillFile>,
+    cursors: Vec<SpillCursor>,
+    heap: BinaryHeap<Head>,
+}
+
+impl Merge {
+    pub(crate) fn new(keys: &[SortKey], runs: Vec<SpillFile>) -> Result<Self, CrustyError> {
+        let mut merge = Merge {
+            runs,
+            cursors: Vec::new(),
+            heap: BinaryHeap::new(),
+        };
+        merge.restart(keys)?;
+        Ok(merge)
+    }
+
+    /// Start over from the first tuple of every run
+    pub(crate) fn restart(&mut self, keys: &[SortKey]) -> Result<(), CrustyError> {
+        self.cursors = self.runs.iter().map(|_| SpillCursor::default()).collect();
+        self.heap.clear();
+        for run in 0..self.runs.len() {
+            self.advance(keys, run)?;
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self, keys: &[SortKey], run: usize) -> Result<(), CrustyError> {
+        if let Some(tuple) = self.cursors[run].next(&self.runs[run])? {
+            self.heap.push(Head {
+                key: key_of(keys, &tuple),
+                run,
+                tuple,
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn next(&mut self, keys: &[SortKey]) -> Result<Option<Tuple>, CrustyError> {
+        match self.heap.pop() {
+            Some(head) => {
+                self.advance(keys, head.run)?;
+                Ok(Some(head.tuple))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Sort tuples of child with the memory budget given, spilling sorted runs to temporary
+/// containers when they do not fit. With more runs than the budget can hold a block of, runs
+/// are merged into longer runs before the final merge.
+pub(crate) fn sort_tuples(
+    managers: &'static Managers,
+    schema: &TableSchema,
+    keys: &[SortKey],
+    mem_budget: usize,
+    tid: TransactionId,
+    child: &mut dyn OpIterator,
+) -> Result<SortedTuples, CrustyError> {
+    let mut buffer = Vec::new();
+    let mut used = 0;
+    let mut runs = Vec::new();
+    while let Some(tuple) = child.next()? {
+        used += tuple.size();
+        buffer.push((key_of(keys, &tuple), tuple));
+        if used > mem_budget {
+            runs.push(write_run(managers, schema, tid, &mut buffer)?);
+            used = 0;
+        }
+    }
+    if runs.is_empty() {
+        buffer.sort_by(|a, b| a.0.cmp(&b.0));
+        return Ok(SortedTuples::Memory {
+            tuples: buffer.into_iter().map(|(_, tuple)| tuple).collect(),
+            position: 0,
+        });
+    }
+    if !buffer.is_empty() {
+        runs.push(write_run(managers, schema, tid, &mut buffer)?);
+    }
+    debug!("Sort spilled {} runs", runs.len());
+
+    let fan_in = (mem_budget / block_size(managers)).max(2);
+    while runs.len() > fan_in {
+        // The earliest runs are merged and stay in front, so equal keys keep their order
+        let rest = runs.split_off(fan_in);
+        let mut merge = Merge::new(keys, runs)?;
+        let mut run = SpillFile::new(managers, schema, tid)?;
+        while let Some(tuple) = merge.next(keys)? {
+            run.push(&tuple)?;
+        }
+        run.flush()?;
+        runs = std::iter::once(run).chain(rest).collect();
+    }
+    Ok(SortedTuples::Merge(Merge::new(keys, runs)?))
+}
+
+/// Sort buffer and write it to a new spill file
+fn write_run(
+    managers: &'static Managers,
+    schema: &TableSchema,
+    tid: TransactionId,
+    buffer: &mut Vec<(Vec<KeyField>, Tuple)>,
+) -> Result<SpillFile, CrustyError> {
+    buffer.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut run = SpillFile::new(managers, schema, tid)?;
+    for (_, tuple) in buffer.drain(..) {
+        run.push(&tuple)?;
+    }
+    run.flush()?;
+    Ok(run)
+}
+
+/// Sorted tuples, in memory if they fit or else as a merge of spilled runs
+pub(crate) enum SortedTuples {
+    Memory { tuples: Vec<Tuple>, position: usize },
+    Merge(Merge),
+}
+
+impl SortedTuples {
+    pub(crate) fn next(&mut self, keys: &[SortKey]) -> Result<Option<Tuple>, CrustyError> {
+        match self {
+            SortedTuples::Memory { tuples, position } => {
+                let tuple = tuples.get(*position).cloned();
+                *position += 1;
+                Ok(tuple)
+            }
+            SortedTuples::Merge(merge) => merge.next(keys),
+        }
+    }
+
+    pub(crate) fn rewind(&mut self, keys: &[SortKey]) -> Result<(), CrustyError> {
+        match self {
+            SortedTuples::Memory { position, .. } => {
+                *position = 0;
+                Ok(())
+            }
+            SortedTuples::Merge(merge) => merge.restart(keys),
+        }
+    }
+}
+
+/// Sort operator. Sorts the tuples of its child by a list of keys, in memory while they fit in
+/// the memory budget and with an external merge sort otherwise. The sort is stable.
+pub struct Sort {
+    // Static objects (No need to reset on close)
+    managers: &'static Managers,
+
+    // Parameters (No need to reset on close)
+    schema: TableSchema,
+    keys: Vec<SortKey>,
+    mem_budget: usize,
+    transaction_id: TransactionId,
+    child: Box<dyn OpIterator>,
+
+    // States (Need to reset on close)
+    open: bool,
+    sorted: Option<SortedTuples>,
+}
+
+impl Sort {
+    /// Sort constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - Sort keys, most significant first, with whether they are ascending and
+    ///   whether nulls come first.
+    /// * `mem_budget` - Bytes of tuples to hold in memory before spilling a sorted run.
+    /// * `tid` - Transaction writing and reading the spilled runs.
+    /// * `child` - Child operator of the sort.
+    pub fn new(
+        managers: &'static Managers,
+        keys: Vec<SortKey>,
+        mem_budget: usize,
+        tid: TransactionId,
+        child: Box<dyn OpIterator>,
+    ) -> Self {
+        Sort {
+            managers,
+            schema: child.get_schema().clone(),
+            keys,
+            mem_budget,
+            transaction_id: tid,
+            child,
+            open: false,
+            sorted: None,
+        }
+    }
+}
+
+impl OpIterator for Sort {
+    fn configure(&mut self, _will_rewind: bool) {
+        self.child.configure(false); // the child is read once, rewinding replays the sorted tuples
+    }
+
+    fn open(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            self.child.open()?;
+            self.sorted = Some(sort_tuples(
+                self.managers,
+                &self.schema,
+                &self.keys,
+                self.mem_budget,
+                self.transaction_id,
+                &mut *self.child,
+            )?);
+            self.open = true;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match &mut self.sorted {
+            Some(sorted) => sorted.next(&self.keys),
+            None => panic!("Operator has not been opened"),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        // Dropping the runs removes their containers
+        self.sorted = None;
+        self.child.close()?;
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        match &mut self.sorted {
+            Some(sorted) => sorted.rewind(&self.keys),
+            None => panic!("Operator has not been opened"),
+        }
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::opiterator::TupleIterator;
+    use crate::testutil::{execute_iter, new_test_managers, TestTuples};
+    use common::bytecode_expr::colidx_expr;
+
+    fn sort(
+        managers: &'static Managers,
+        tuples: Vec<Tuple>,
+        schema: &TableSchema,
+        keys: &[(usize, bool, bool)],
+        mem_budget: usize,
+    ) -> Sort {
+        let keys = keys
+            .iter()
+            .map(|(i, asc, nulls_first)| (colidx_expr(*i), *asc, *nulls_first))
+            .collect();
+        let child = Box::new(TupleIterator::new(tuples, schema.clone()));
+        let mut sort = Sort::new(managers, keys, mem_budget, TransactionId::new(), child);
+        sort.configure(false);
+        sort
+    }
+
+    fn column(tuples: &[Tuple], i: usize) -> Vec<Field> {
+        tuples
+            .iter()
+            .map(|t| t.get_field(i).unwrap().clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_multiple_keys() {
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        let mut iter = sort(
+            managers,
+            setup.tuples.clone(),
+            &setup.schema,
+            &[(3, true, false), (0, false, false)],
+            usize::MAX,
+        );
+        let tuples = execute_iter(&mut iter, false).unwrap();
+        let ids = column(&tuples, 0);
+        assert_eq!(
+            vec![3, 1, 6, 5, 4, 2],
+            ids.iter()
+                .map(|f| match f {
+                    Field::Int(i) => *i,
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        );
+        iter.rewind().unwrap();
+        assert_eq!(tuples, execute_iter(&mut iter, false).unwrap());
+    }
+
+    #[test]
+    fn test_sort_nulls() {
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        let mut tuples = setup.tuples.clone();
+        tuples[1].set_field(2, Field::Null);
+        tuples[4].set_field(2, Field::Null);
+        let run = |asc, nulls_first| {
+            let mut iter = sort(
+                managers,
+                tuples.clone(),
+                &setup.schema,
+                &[(2, asc, nulls_first)],
+                usize::MAX,
+            );
+            column(&execute_iter(&mut iter, false).unwrap(), 2)
+        };
+        let (n, i) = (Field::Null, Field::Int);
+        assert_eq!(
+            vec![i(3), i(4), i(4), i(5), n.clone(), n.clone()],
+            run(true, false)
+        );
+        assert_eq!(
+            vec![n.clone(), n.clone(), i(3), i(4), i(4), i(5)],
+            run(true, true)
+        );
+        assert_eq!(
+            vec![i(5), i(4), i(4), i(3), n.clone(), n.clone()],
+            run(false, false)
+        );
+        assert_eq!(vec![n.clone(), n, i(5), i(4), i(4), i(3)], run(false, true));
+    }
+
+    #[test]
+    fn test_sort_spills_runs() {
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        // Many copies of the test tuples, with a null key in some
+        let mut tuples = Vec::new();
+        for copy in 0..400 {
+            for tuple in &setup.tuples {
+                let mut tuple = tuple.clone();
+                tuple.set_field(1, Field::Int(copy));
+                if copy % 7 == 0 {
+                    tuple.set_field(2, Field::Null);
+                }
+                tuples.push(tuple);
+            }
+        }
+        let keys = [(2, false, false), (3, true, true)];
+        let mut in_memory = sort(managers, tuples.clone(), &setup.schema, &keys, usize::MAX);
+        let expected = execute_iter(&mut in_memory, false).unwrap();
+        assert_eq!(tuples.len(), expected.len());
+
+        // Small budgets spill many runs and merge them in several passes. Copies with equal
+        // keys keep their order.
+        for mem_budget in [1000, 3 * block_size(managers)] {
+            let mut iter = sort(managers, tuples.clone(), &setup.schema, &keys, mem_budget);
+            assert_eq!(expected, execute_iter(&mut iter, false).unwrap());
+            iter.rewind().unwrap();
+            assert_eq!(expected, execute_iter(&mut iter, false).unwrap());
+            iter.close().unwrap();
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use super::sort::{key_of, sort_tuples, KeyField, SortKey, SortedTuples};
+use super::{passes_filter, OpIterator, MEMORY_BUDGET};
+use crate::Managers;
+
+use common::bytecode_expr::ByteCodeExpr;
+use common::ids::TransactionId;
+use common::{CrustyError, TableSchema, Tuple};
+use std::cmp::Ordering;
+
+/// One input of a merge join, read in key order
+struct Side {
+    keys: Vec<SortKey>,
+    /// Whether the child already returns its tuples in key order
+    presorted: bool,
+    child: Box<dyn OpIterator>,
+    /// Tuples of the child sorted by the join, when it is not presorted
+    sorted: Option<SortedTuples>,
+    /// Next tuple with a key that can match, and its key
+    head: Option<(Vec<KeyField>, Tuple)>,
+}
+
+impl Side {
+    fn new(keys: Vec<SortKey>, child: Box<dyn OpIterator>) -> Self {
+        Side {
+            keys,
+            presorted: false,
+            child,
+            sorted: None,
+            head: None,
+        }
+    }
+
+    fn open(
+        &mut self,
+        managers: &'static Managers,
+        mem_budget: usize,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        self.child.open()?;
+        if !self.presorted {
+            let schema = self.child.get_schema().clone();
+            self.sorted = Some(sort_tuples(
+                managers,
+                &schema,
+                &self.keys,
+                mem_budget,
+                tid,
+                &mut *self.child,
+            )?);
+        }
+        self.advance()
+    }
+
+    /// Move the head to the next tuple. Tuples with a null key never match and are skipped.
+    fn advance(&mut self) -> Result<(), CrustyError> {
+        loop {
+            let tuple = match &mut self.sorted {
+                Some(sorted) => sorted.next(&self.keys)?,
+                None => self.child.next()?,
+            };
+            self.head = match tuple {
+                Some(tuple) => {
+                    let key = key_of(&self.keys, &tuple);
+                    if key.iter().any(KeyField::is_null) {
+                        continue;
+                    }
+                    Some((key, tuple))
+                }
+                None => None,
+            };
+            return Ok(());
+        }
+    }
+
+    /// The head tuple and the following tuples with the same key
+    fn take_group(&mut self) -> Result<Vec<Tuple>, CrustyError> {
+        let (key, tuple) = match self.head.take() {
+            Some(head) => head,
+            None => return Ok(Vec::new()),
+        };
+        let mut group = vec![tuple];
+        self.advance()?;
+        while let Some((next_key, _)) = &self.head {
+            if *next_key != key {
+                break;
+            }
+            group.push(self.head.take().unwrap().1);
+            self.advance()?;
+        }
+        Ok(group)
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        match &mut self.sorted {
+            Some(sorted) => sorted.rewind(&self.keys)?,
+            None => self.child.rewind()?,
+        }
+        self.advance()
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        // Dropping the sorted tuples removes their spilled runs
+        self.sorted = None;
+        self.head = None;
+        self.child.close()
+    }
+}
+
+/// Sort-merge equi-join. Both inputs are read in key order, sorting them with an external sort
+/// unless the child already returns them in that order, and tuples with equal keys are joined.
+/// The tuples of one key are held in memory while they are joined.
+pub struct SortMergeJoin {
+    // Static objects (No need to reset on close)
+    managers: &'static Managers,
+
+    // Parameters (No need to reset on close)
+    schema: TableSchema,
+    filter: Option<ByteCodeExpr>,
+    mem_budget: usize,
+    transaction_id: TransactionId,
+    left: Side,
+    right: Side,
+
+    // States (Need to reset on close)
+    open: bool,
+    /// Joined tuples of the current key not returned yet, in reverse order
+    pending: Vec<Tuple>,
+}
+
+impl SortMergeJoin {
+    /// SortMergeJoin constructor. Creates a join that sorts both children.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - ByteCodeExprs of the left and right fields of each equality of the join
+    ///   condition, with whether the inputs are in ascending order of them.
+    /// * `filter` - Predicate on the joined tuple, bound to schema.
+    /// * `tid` - Transaction writing and reading the spilled runs.
+    /// * `left_child` - Left child of join operator.
+    /// * `right_child` - Right child of join operator.
+    pub fn new(
+        managers: &'static Managers,
+        schema: TableSchema,
+        keys: Vec<(ByteCodeExpr, ByteCodeExpr, bool)>,
+        filter: Option<ByteCodeExpr>,
+        tid: TransactionId,
+        left_child: Box<dyn OpIterator>,
+        right_child: Box<dyn OpIterator>,
+    ) -> Self {
+        // Null keys are skipped, so where they sort does not matter
+        let (left_keys, right_keys) = keys
+            .into_iter()
+            .map(|(left, right, asc)| ((left, asc, false), (right, asc, false)))
+            .unzip();
+        SortMergeJoin {
+            managers,
+            schema,
+            filter,
+            mem_budget: MEMORY_BUDGET,
+            transaction_id: tid,
+            left: Side::new(left_keys, left_child),
+            right: Side::new(right_keys, right_child),
+            open: false,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Mark the children that already return their tuples in key order, such as an index scan
+    /// on the join fields, so they are not sorted again.
+    pub fn presorted(mut self, left: bool, right: bool) -> Self {
+        self.left.presorted = left;
+        self.right.presorted = right;
+        self
+    }
+
+    /// Set the bytes of tuples each sort holds in memory before spilling a sorted run.
+    pub fn with_memory_budget(mut self, mem_budget: usize) -> Self {
+        self.mem_budget = mem_budget;
+        self
+    }
+}
+
+impl OpIterator for SortMergeJoin {
+    fn configure(&mut self, will_rewind: bool) {
+        // A sorted child is read once, rewinding replays its sorted tuples
+        self.left
+            .child
+            .configure(will_rewind && self.left.presorted);
+        self.right
+            .child
+            .configure(will_rewind && self.right.presorted);
+    }
+
+    fn open(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            self.left
+                .open(self.managers, self.mem_budget, self.transaction_id)?;
+            self.right
+                .open(self.managers, self.mem_budget, self.transaction_id)?;
+            self.pending.clear();
+            self.open = true;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        loop {
+            if let Some(joined) = self.pending.pop() {
+                return Ok(Some(joined));
+            }
+            let order = match (&self.left.head, &self.right.head) {
+                (Some((left_key, _)), Some((right_key, _))) => left_key.cmp(right_key),
+                _ => return Ok(None),
+            };
+            match order {
+                Ordering::Less => self.left.advance()?,
+                Ordering::Greater => self.right.advance()?,
+                Ordering::Equal => {
+                    let left_group = self.left.take_group()?;
+                    let right_group = self.right.take_group()?;
+                    self.pending = left_group
+                        .iter()
+                        .flat_map(|l| right_group.iter().map(move |r| l.merge(r)))
+                        .filter(|joined| passes_filter(self.filter.as_ref(), joined))
+                        .rev()
+                        .collect();
+                }
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.left.close()?;
+        self.right.close()?;
+        self.pending.clear();
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.left.rewind()?;
+        self.right.rewind()?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::opiterator::{Sort, TupleIterator};
+    use crate::testutil::{execute_iter, new_test_managers, TestTuples};
+    use common::bytecode_expr::{colidx_expr, ByteCodes};
+    use common::Field;
+
+    fn join(
+        managers: &'static Managers,
+        left: Vec<Tuple>,
+        right: Vec<Tuple>,
+        schema: &TableSchema,
+        cols: &[usize],
+        mem_budget: usize,
+    ) -> SortMergeJoin {
+        let keys = cols
+            .iter()
+            .map(|i| (colidx_expr(*i), colidx_expr(*i), true))
+            .collect();
+        let mut iter = SortMergeJoin::new(
+            managers,
+            schema.merge(schema),
+            keys,
+            None,
+            TransactionId::new(),
+            Box::new(TupleIterator::new(left, schema.clone())),
+            Box::new(TupleIterator::new(right, schema.clone())),
+        )
+        .with_memory_budget(mem_budget);
+        iter.configure(false);
+        iter
+    }
+
+    /// The joined tuples of a nested loop over left and right on the columns given
+    fn expected(left: &[Tuple], right: &[Tuple], cols: &[usize]) -> Vec<Tuple> {
+        let mut joined: Vec<Tuple> = left
+            .iter()
+            .flat_map(|l| right.iter().map(move |r| (l, r)))
+            .filter(|(l, r)| {
+                cols.iter().all(|i| {
+                    let f = l.get_field(*i).unwrap();
+                    *f != Field::Null && Some(f) == r.get_field(*i)
+                })
+            })
+            .map(|(l, r)| l.merge(r))
+            .collect();
+        joined.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+        joined
+    }
+
+    #[test]
+    fn test_join_duplicates_and_nulls() {
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        let mut left = setup.tuples.clone();
+        left[0].set_field(2, Field::Null);
+        let mut right = setup.tuples.clone();
+        right.reverse();
+        right[3].set_field(1, Field::Null);
+        for cols in [vec![1], vec![1, 2], vec![2, 1]] {
+            let mut iter = join(
+                managers,
+                left.clone(),
+                right.clone(),
+                &setup.schema,
+                &cols,
+                usize::MAX,
+            );
+            let t = execute_iter(&mut iter, true).unwrap();
+            assert_eq!(expected(&left, &right, &cols), t);
+            iter.rewind().unwrap();
+            assert_eq!(t, execute_iter(&mut iter, true).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_join_spills_runs() {
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        let tuples: Vec<Tuple> = (0..300).flat_map(|_| setup.tuples.clone()).collect();
+        let mut iter = join(
+            managers,
+            tuples.clone(),
+            setup.tuples.clone(),
+            &setup.schema,
+            &[2],
+            1024,
+        );
+        let t = execute_iter(&mut iter, true).unwrap();
+        assert_eq!(expected(&tuples, &setup.tuples, &[2]), t);
+    }
+
+    #[test]
+    fn test_join_filter() {
+        // left(col(1)) == right(col(1)) and left(col(0)) < right(col(0))
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        let mut filter = ByteCodeExpr::new();
+        filter.add_code(ByteCodes::PushField as usize);
+        filter.add_code(0);
+        filter.add_code(ByteCodes::PushField as usize);
+        filter.add_code(4);
+        filter.add_code(ByteCodes::Lt as usize);
+        let mut iter = SortMergeJoin::new(
+            managers,
+            setup.schema.merge(&setup.schema),
+            vec![(colidx_expr(1), colidx_expr(1), true)],
+            Some(filter),
+            TransactionId::new(),
+            Box::new(TupleIterator::new(
+                setup.tuples.clone(),
+                setup.schema.clone(),
+            )),
+            Box::new(TupleIterator::new(
+                setup.tuples.clone(),
+                setup.schema.clone(),
+            )),
+        );
+        iter.configure(false);
+        let t = execute_iter(&mut iter, true).unwrap();
+        let mut expected: Vec<Tuple> = expected(&setup.tuples, &setup.tuples, &[1])
+            .into_iter()
+            .filter(|t| t.get_field(0) < t.get_field(4))
+            .collect();
+        expected.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+        assert_eq!(3 + 3, t.len());
+        assert_eq!(expected, t);
+    }
+
+    #[test]
+    fn test_presorted_children() {
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        let sorted = |tuples: Vec<Tuple>| {
+            Box::new(Sort::new(
+                managers,
+                vec![(colidx_expr(2), false, false)],
+                usize::MAX,
+                TransactionId::new(),
+                Box::new(TupleIterator::new(tuples, setup.schema.clone())),
+            ))
+        };
+        let mut iter = SortMergeJoin::new(
+            managers,
+            setup.schema.merge(&setup.schema),
+            vec![(colidx_expr(2), colidx_expr(2), false)],
+            None,
+            TransactionId::new(),
+            sorted(setup.tuples.clone()),
+            sorted(setup.tuples.clone()),
+        )
+        .presorted(true, true);
+        iter.configure(true);
+        let t = execute_iter(&mut iter, false).unwrap();
+        // Keys come out in the descending order of the children
+        let keys: Vec<_> = t.iter().map(|t| t.get_field(2).unwrap().clone()).collect();
+        let mut descending = keys.clone();
+        descending.sort_by(|a, b| b.cmp(a));
+        assert_eq!(descending, keys);
+        assert_eq!(2 * 2 + 2 * 2 + 2 * 2, t.len());
+        iter.rewind().unwrap();
+        assert_eq!(t, execute_iter(&mut iter, false).unwrap());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        let mut iter = join(
+            managers,
+            setup.tuples.clone(),
+            setup.tuples.clone(),
+            &setup.schema,
+            &[1],
+            usize::MAX,
+        );
+        let _ = iter.next();
+    }
+}
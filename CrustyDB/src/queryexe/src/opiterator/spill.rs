@@ -0,0 +1,206 @@
+use crate::Managers;
+use common::ids::Permissions;
+use common::prelude::*;
+use common::row::RowLayout;
+use common::storage_trait::StorageTrait;
+
+/// Bytes of rows packed into one value of a spill file, so that a block fits in a page of the
+/// storage manager
+pub(crate) fn block_size(managers: &Managers) -> usize {
+    managers.sm.page_size() / 2
+}
+
+/// Tuples an operator spilled to a temporary container, read back in the order they were
+/// written. Rows are packed into blocks of length-prefixed rows, so the order does not depend on
+/// where the storage manager places values. The container is removed when the file is dropped.
+pub(crate) struct SpillFile {
+    managers: &'static Managers,
+    container_id: ContainerId,
+    tid: TransactionId,
+    layout: RowLayout,
+    block_size: usize,
+    blocks: Vec<ValueId>,
+    /// Rows not written yet
+    block: Vec<u8>,
+}
+
+impl SpillFile {
+    /// Create an empty spill file for tuples of schema, written and read by tid.
+    pub(crate) fn new(
+        managers: &'static Managers,
+        schema: &TableSchema,
+        tid: TransactionId,
+    ) -> Result<Self, CrustyError> {
+        let block_size = block_size(managers);
+        Ok(SpillFile {
+            container_id: managers.create_temp_container()?,
+            managers,
+            tid,
+            layout: RowLayout::new(schema),
+            block_size,
+            blocks: Vec::new(),
+            block: Vec::with_capacity(block_size),
+        })
+    }
+
+    /// Add a tuple, which must fit the schema of the file.
+    pub(crate) fn push(&mut self, tuple: &Tuple) -> Result<(), CrustyError> {
+        let row = self.layout.encode(tuple)?;
+        if !self.block.is_empty() && self.block.len() + 4 + row.len() > self.block_size {
+            self.flush()?;
+        }
+        self.block
+            .extend_from_slice(&(row.len() as u32).to_le_bytes());
+        self.block.extend_from_slice(&row);
+        Ok(())
+    }
+
+    /// Write the rows pushed since the last block, so they can be read.
+    pub(crate) fn flush(&mut self) -> Result<(), CrustyError> {
+        if !self.block.is_empty() {
+            let block = std::mem::replace(&mut self.block, Vec::with_capacity(self.block_size));
+            let id = self
+                .managers
+                .sm
+                .insert_value(self.container_id, block, self.tid);
+            self.blocks.push(id);
+        }
+        Ok(())
+    }
+
+    /// The tuples of block i
+    fn read_block(&self, i: usize) -> Result<Vec<Tuple>, CrustyError> {
+        let block = self
+            .managers
+            .sm
+            .get_value(self.blocks[i], self.tid, Permissions::ReadOnly)?;
+        let cut_off = |pos: usize| {
+            CrustyError::SerializationError(format!(
+                "Block {} of spill container {} is cut off at byte {}",
+                i, self.container_id, pos
+            ))
+        };
+        let mut tuples = Vec::new();
+        let mut pos = 0;
+        while pos < block.len() {
+            let len = block.get(pos..pos + 4).ok_or_else(|| cut_off(pos))?;
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            pos += 4;
+            let row = block.get(pos..pos + len).ok_or_else(|| cut_off(pos))?;
+            tuples.push(self.layout.decode(row)?);
+            pos += len;
+        }
+        Ok(tuples)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.managers.remove_temp_container(self.container_id) {
+            warn!(
+                "Unable to remove spill container {}: {}",
+                self.container_id, e
+            );
+        }
+    }
+}
+
+/// Position of a reader of a spill file, which holds one block of the file in memory
+#[derive(Default)]
+pub(crate) struct SpillCursor {
+    next_block: usize,
+    tuples: std::vec::IntoIter<Tuple>,
+}
+
+impl SpillCursor {
+    /// The next tuple of file, which must be flushed
+    pub(crate) fn next(&mut self, file: &SpillFile) -> Result<Option<Tuple>, CrustyError> {
+        loop {
+            if let Some(tuple) = self.tuples.next() {
+                return Ok(Some(tuple));
+            }
+            if self.next_block == file.blocks.len() {
+                return Ok(None);
+            }
+            self.tuples = file.read_block(self.next_block)?.into_iter();
+            self.next_block += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::{new_test_managers, TestTuples};
+
+    fn read_all(file: &SpillFile) -> Result<Vec<Tuple>, CrustyError> {
+        let mut cursor = SpillCursor::default();
+        let mut tuples = Vec::new();
+        while let Some(tuple) = cursor.next(file)? {
+            tuples.push(tuple);
+        }
+        Ok(tuples)
+    }
+
+    #[test]
+    fn test_spill_round_trip() {
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        let mut tuples = setup.tuples.clone();
+        tuples[2].set_field(3, Field::Null);
+        let mut file = SpillFile::new(managers, &setup.schema, TransactionId::new()).unwrap();
+        for tuple in &tuples {
+            file.push(tuple).unwrap();
+        }
+        file.flush().unwrap();
+        assert_eq!(1, file.blocks.len());
+        assert_eq!(tuples, read_all(&file).unwrap());
+
+        // A tuple that does not fit the schema is refused
+        let wide = Tuple::new(vec![Field::String("x".to_string()); 4]);
+        assert!(file.push(&wide).is_err());
+    }
+
+    #[test]
+    fn test_spill_multiple_blocks() {
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        let tuples: Vec<Tuple> = (0..2000)
+            .map(|i| {
+                let mut tuple = setup.tuples[i % setup.tuples.len()].clone();
+                tuple.set_field(0, Field::Int(i as i64));
+                tuple
+            })
+            .collect();
+        let mut file = SpillFile::new(managers, &setup.schema, TransactionId::new()).unwrap();
+        for tuple in &tuples {
+            file.push(tuple).unwrap();
+        }
+        file.flush().unwrap();
+        assert!(file.blocks.len() > 1);
+        assert_eq!(tuples, read_all(&file).unwrap());
+
+        // Two readers of the same file do not share their position
+        let mut first = SpillCursor::default();
+        let mut second = SpillCursor::default();
+        assert_eq!(Some(tuples[0].clone()), first.next(&file).unwrap());
+        assert_eq!(Some(tuples[1].clone()), first.next(&file).unwrap());
+        assert_eq!(Some(tuples[0].clone()), second.next(&file).unwrap());
+    }
+
+    #[test]
+    fn test_spill_cut_off_block() {
+        let managers = new_test_managers();
+        let setup = TestTuples::new("");
+        let tid = TransactionId::new();
+        let mut file = SpillFile::new(managers, &setup.schema, tid).unwrap();
+        for bad in [vec![1, 0], vec![100, 0, 0, 0, 1, 2]] {
+            let id = managers.sm.insert_value(file.container_id, bad, tid);
+            file.blocks = vec![id];
+            assert!(matches!(
+                read_all(&file),
+                Err(CrustyError::SerializationError(_))
+            ));
+        }
+    }
+}
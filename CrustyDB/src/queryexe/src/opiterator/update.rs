@@ -1,21 +1,24 @@
 use super::OpIterator;
-use crate::{StorageManager, TransactionManager};
+use crate::{Managers, TransactionManager};
 use common::ids::TupleAssignments;
 use common::prelude::*;
 use common::storage_trait::StorageTrait;
 use common::traits::transaction_manager_trait::TransactionManagerTrait;
+use std::collections::HashSet;
 
 /// Sequential scan operator
 pub struct Update {
     schema: TableSchema,
     open: bool,
-    storage_manager: &'static StorageManager,
-    transaction_manager: &'static TransactionManager,
-    _container_id: ContainerId,
+    managers: &'static Managers,
+    container_id: ContainerId,
     tid: TransactionId,
     assignments: TupleAssignments,
     child: Box<dyn OpIterator>,
     count: usize,
+    /// Where the updated records were written. A child scanning the table reaches the new
+    /// versions, or the records that moved, again and must not update them twice.
+    written: HashSet<ValueId>,
 }
 
 impl Update {
@@ -27,8 +30,7 @@ impl Update {
     /// * `table_alias` - Table alias given by the user.
     /// * `tid` - Transaction used to read the table.
     pub fn new(
-        storage_manager: &'static StorageManager,
-        transaction_manager: &'static TransactionManager,
+        managers: &'static Managers,
         container_id: &ContainerId,
         tid: TransactionId,
         assignments: TupleAssignments,
@@ -37,13 +39,13 @@ impl Update {
         Self {
             schema: child.get_schema().clone(),
             open: false,
-            storage_manager,
-            transaction_manager,
-            _container_id: *container_id,
+            managers,
+            container_id: *container_id,
             tid,
             assignments,
             child,
             count: 0,
+            written: HashSet::new(),
         }
     }
 }
@@ -62,7 +64,14 @@ impl OpIterator for Update {
         if !self.open {
             panic!("Operator has not been opened")
         }
-        let next = self.child.next()?;
+        let mut next = self.child.next()?;
+        while next
+            .as_ref()
+            .and_then(|t| t.value_id)
+            .is_some_and(|id| self.written.contains(&id))
+        {
+            next = self.child.next()?;
+        }
         if let Some(mut tuple) = next {
             let id = match tuple.value_id {
                 Some(id) => id,
@@ -73,27 +82,38 @@ impl OpIterator for Update {
                 }
             };
 
-            //TODO determine should check for constaints and maintain indexes
-
             // Update values
-            self.transaction_manager.pre_update_record(
-                &mut tuple,
-                &id,
-                &self.tid,
-                &self.assignments,
-            )?;
+            let old = tuple.clone();
+            self.managers
+                .tm
+                .pre_update_record(&mut tuple, &id, &self.tid, &self.assignments)?;
             for (field_idx, new_value) in &self.assignments {
                 tuple.set_field(*field_idx, new_value.clone());
             }
-            // Persist change
-            let res = self
-                .storage_manager
-                .update_value(tuple.to_bytes(), id, self.tid);
+            let holds_key = |id| self.managers.holds_key(id, self.tid);
+            self.managers.im.check_update(
+                self.container_id,
+                &old,
+                &tuple,
+                id,
+                &holds_key,
+                self.tid,
+            )?;
+            // Persist change. A multi-version manager keeps the old version for older snapshots.
+            let bytes = self.managers.encode_row(self.container_id, &tuple)?;
+            let res = if TransactionManager::MULTI_VERSION {
+                Ok(self
+                    .managers
+                    .sm
+                    .insert_value(self.container_id, bytes, self.tid))
+            } else {
+                self.managers.sm.update_value(bytes, id, self.tid)
+            };
             //Check result
             match res {
                 Ok(new_value_id) => {
                     // notify txn manager
-                    self.transaction_manager.post_update_record(
+                    self.managers.tm.post_update_record(
                         &mut tuple,
                         &new_value_id,
                         &id,
@@ -101,10 +121,28 @@ impl OpIterator for Update {
                         &self.assignments,
                     )?;
                     if new_value_id != id {
-                        // The record moved. Update index if not using PK
                         debug!("record moved on update");
+                        self.written.insert(new_value_id);
+                    }
+                    // update indexes for values that changed or records that moved
+                    if TransactionManager::MULTI_VERSION {
+                        self.managers.im.insert_tuples(
+                            self.container_id,
+                            std::slice::from_ref(&tuple),
+                            &[new_value_id],
+                            &holds_key,
+                            self.tid,
+                        )?;
+                    } else {
+                        self.managers.im.update_tuple(
+                            self.container_id,
+                            &old,
+                            id,
+                            &tuple,
+                            new_value_id,
+                            self.tid,
+                        )?;
                     }
-                    // update indexes for values that changed
                     self.count += 1;
                 }
                 Err(e) => {
@@ -118,6 +156,7 @@ impl OpIterator for Update {
 
     fn close(&mut self) -> Result<(), CrustyError> {
         self.child.close()?;
+        self.written.clear();
         self.open = false;
         Ok(())
     }
@@ -134,6 +173,268 @@ impl OpIterator for Update {
 #[cfg(test)]
 #[allow(unused_must_use)]
 mod test {
-    //use super::*;
-    //use common::ids::TransactionId;
+    use super::*;
+    use crate::mutator::insert_validated_tuples;
+    use crate::opiterator::index_scan::test::indexed_table;
+    use crate::opiterator::{SeqScan, TupleIterator};
+    use crate::testutil::{execute_iter, new_test_managers};
+    use common::traits::index_trait::IndexTrait;
+    use common::traits::stat_manager_trait::StatManagerTrait;
+
+    #[test]
+    fn test_update_rollback() {
+        let managers = new_test_managers();
+        let (setup, table_id, index_id) = indexed_table(managers, vec![1], IndexKind::BTree);
+        let table = || {
+            let tid = TransactionId::new();
+            let mut scan = SeqScan::new(managers, &setup.schema, &table_id, tid, None, None);
+            scan.configure(false);
+            let tuples = execute_iter(&mut scan, true).unwrap();
+            managers.commit_txn(tid).unwrap();
+            tuples
+        };
+        let before = table();
+
+        let tid = TransactionId::new();
+        let child = TupleIterator::new(before.clone(), setup.schema.clone());
+        let mut update = Update::new(
+            managers,
+            &table_id,
+            tid,
+            vec![(1, Field::Int(7))],
+            Box::new(child),
+        );
+        update.open().unwrap();
+        while update.next().unwrap().is_some() {}
+        let index = managers.im.get_index(index_id).unwrap();
+        assert_eq!(
+            6,
+            index
+                .equality_get_value_ids(&[Field::Int(7)], tid)
+                .unwrap()
+                .len()
+        );
+
+        // Rolling back restores the records and their index entries
+        managers.rollback_txn(tid).unwrap();
+        assert_eq!(
+            before.iter().map(|t| &t.field_vals).collect::<Vec<_>>(),
+            table().iter().map(|t| &t.field_vals).collect::<Vec<_>>()
+        );
+        let tid = TransactionId::new();
+        assert!(index
+            .equality_get_value_ids(&[Field::Int(7)], tid)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            3,
+            index
+                .equality_get_value_ids(&[Field::Int(1)], tid)
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn test_load_rollback() {
+        let managers = new_test_managers();
+        let (setup, table_id, index_id) = indexed_table(managers, vec![1], IndexKind::BTree);
+        let count = || {
+            let tid = TransactionId::new();
+            let mut scan = SeqScan::new(managers, &setup.schema, &table_id, tid, None, None);
+            scan.configure(false);
+            let count = execute_iter(&mut scan, false).unwrap().len();
+            managers.commit_txn(tid).unwrap();
+            count
+        };
+        let before = count();
+        managers
+            .stats
+            .register_container(table_id, setup.schema.clone())
+            .unwrap();
+
+        // A load keeps only the ids of its inserts, which still roll back with their entries
+        let tid = TransactionId::new();
+        managers.tm.log_insert_ids(tid).unwrap();
+        let mut loaded = setup.tuples.clone();
+        for t in loaded.iter_mut() {
+            t.field_vals[1] = Field::Int(7);
+        }
+        insert_validated_tuples(table_id, &loaded, tid, managers).unwrap();
+        insert_validated_tuples(table_id, &loaded, tid, managers).unwrap();
+        managers.rollback_txn(tid).unwrap();
+        assert_eq!(before, count());
+        let index = managers.im.get_index(index_id).unwrap();
+        assert!(index
+            .equality_get_value_ids(&[Field::Int(7)], TransactionId::new())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_update_scanned_table() {
+        let managers = new_test_managers();
+        let (setup, table_id, index_id) = indexed_table(managers, vec![1], IndexKind::BTree);
+        managers
+            .stats
+            .register_container(table_id, setup.schema.clone())
+            .unwrap();
+        // Enough records for the scan to reach pages the update writes to
+        let tid = TransactionId::new();
+        let more = (0..500)
+            .map(|i| {
+                let mut tuple = setup.tuples[i % setup.tuples.len()].clone();
+                tuple.set_field(0, Field::Int(100 + i as i64));
+                tuple
+            })
+            .collect::<Vec<_>>();
+        insert_validated_tuples(table_id, &more, tid, managers).unwrap();
+        managers.commit_txn(tid).unwrap();
+        let len = setup.tuples.len() + more.len();
+        let scan = |tid| {
+            let mut scan = SeqScan::new(managers, &setup.schema, &table_id, tid, None, None);
+            scan.configure(false);
+            scan
+        };
+
+        // The records the update writes to the table it scans are not updated again
+        let tid = TransactionId::new();
+        let assignments = vec![(1, Field::Int(7))];
+        let mut update = Update::new(managers, &table_id, tid, assignments, Box::new(scan(tid)));
+        update.open().unwrap();
+        let mut count = 0;
+        while update.next().unwrap().is_some() {
+            count += 1;
+            assert!(count <= len);
+        }
+        update.close().unwrap();
+        managers.commit_txn(tid).unwrap();
+        assert_eq!(len, count);
+
+        let tid = TransactionId::new();
+        let tuples = execute_iter(&mut scan(tid), true).unwrap();
+        assert_eq!(len, tuples.len());
+        assert!(tuples
+            .iter()
+            .all(|t| t.get_field(1) == Some(&Field::Int(7))));
+        let index = managers.im.get_index(index_id).unwrap();
+        assert_eq!(
+            len,
+            index
+                .equality_get_value_ids(&[Field::Int(7)], tid)
+                .unwrap()
+                .len()
+        );
+        managers.commit_txn(tid).unwrap();
+    }
+
+    #[test]
+    fn test_update_conflict() {
+        if !TransactionManager::MULTI_VERSION {
+            return;
+        }
+        let managers = new_test_managers();
+        let (setup, table_id, _) = indexed_table(managers, vec![1], IndexKind::BTree);
+        let scan = |tid| {
+            let mut scan = SeqScan::new(managers, &setup.schema, &table_id, tid, None, None);
+            scan.configure(false);
+            scan
+        };
+        let update = |tid, value| {
+            let assignments = vec![(1, Field::Int(value))];
+            let mut update =
+                Update::new(managers, &table_id, tid, assignments, Box::new(scan(tid)));
+            update.open().unwrap();
+            while update.next().unwrap().is_some() {}
+            update.close().unwrap();
+        };
+
+        // Both replace every version, the one committing last finds them ended
+        let (first, second) = (TransactionId::new(), TransactionId::new());
+        managers.tm.start_transaction(first).unwrap();
+        managers.tm.start_transaction(second).unwrap();
+        update(first, 7);
+        update(second, 8);
+        managers.commit_txn(first).unwrap();
+        assert_eq!(
+            Err(CrustyError::TransactionRollback(second)),
+            managers.commit_txn(second)
+        );
+
+        let tid = TransactionId::new();
+        managers.tm.start_transaction(tid).unwrap();
+        let tuples = execute_iter(&mut scan(tid), true).unwrap();
+        assert_eq!(setup.tuples.len(), tuples.len());
+        assert!(tuples
+            .iter()
+            .all(|t| t.get_field(1) == Some(&Field::Int(7))));
+        managers.commit_txn(tid).unwrap();
+    }
+
+    #[test]
+    fn test_update_primary_key() {
+        let managers = new_test_managers();
+        let (setup, table_id, _) = indexed_table(managers, vec![1], IndexKind::BTree);
+        let pk = IndexInfo {
+            c_id: 3,
+            name: "pk".to_string(),
+            table_id,
+            key_columns: vec![0],
+            attributes: vec![setup.schema.get_attribute(0).unwrap().clone()],
+            is_unique: true,
+            is_primary: true,
+            kind: IndexKind::BTree,
+        };
+        managers.create_index(pk, TransactionId::new()).unwrap();
+        managers
+            .stats
+            .register_container(table_id, setup.schema.clone())
+            .unwrap();
+        let rows = |key: Option<i64>, tid| {
+            let mut scan = SeqScan::new(managers, &setup.schema, &table_id, tid, None, None);
+            scan.configure(false);
+            let tuples = execute_iter(&mut scan, true).unwrap();
+            tuples
+                .into_iter()
+                .filter(|t| key.is_none_or(|k| t.get_field(0) == Some(&Field::Int(k))))
+                .collect::<Vec<_>>()
+        };
+        let update = |key: i64, assignments: Vec<(usize, Field)>| {
+            let tid = TransactionId::new();
+            let child = TupleIterator::new(rows(Some(key), tid), setup.schema.clone());
+            let mut update = Update::new(managers, &table_id, tid, assignments, Box::new(child));
+            update.open().unwrap();
+            let res = (|| {
+                while update.next()?.is_some() {}
+                Ok(())
+            })();
+            match res {
+                Ok(()) => managers.commit_txn(tid),
+                Err(e) => {
+                    managers.rollback_txn(tid).unwrap();
+                    Err(e)
+                }
+            }
+        };
+
+        // A key may not be taken from another record or cleared
+        assert!(update(2, vec![(0, Field::Int(1))]).is_err());
+        assert!(update(2, vec![(0, Field::Null)]).is_err());
+        // Other columns change freely, also when a new version keeps the key
+        update(2, vec![(1, Field::Int(9))]).unwrap();
+        update(2, vec![(0, Field::Int(10))]).unwrap();
+
+        // The key given up by the update can be inserted again, the new one not
+        let tid = TransactionId::new();
+        let mut tuple = setup.tuples[1].clone();
+        insert_validated_tuples(table_id, &[tuple.clone()], tid, managers).unwrap();
+        tuple.set_field(0, Field::Int(10));
+        assert!(insert_validated_tuples(table_id, &[tuple], tid, managers).is_err());
+        managers.commit_txn(tid).unwrap();
+        let tid = TransactionId::new();
+        assert_eq!(1, rows(Some(2), tid).len());
+        assert_eq!(1, rows(Some(10), tid).len());
+        assert_eq!(setup.tuples.len() + 1, rows(None, tid).len());
+        managers.commit_txn(tid).unwrap();
+    }
 }
@@ -1,17 +1,21 @@
 use crate::opiterator::{
-    Aggregate, CrossJoin, Filter, HashEqJoin, NestedLoopJoin, OpIterator, Project, SeqScan,
+    Aggregate, CrossJoin, Filter, HashEqJoin, IndexNestedLoopJoin, IndexScan, NestedLoopJoin,
+    OpIterator, Project, SeqScan, Sort, SortKey, SortMergeJoin, MEMORY_BUDGET,
 };
+use crate::stats::ReservoirStatManager;
 use crate::Managers;
 use common::ast_expr::bind_expr;
-use common::bytecode_expr::ByteCodes;
 use common::catalog::CatalogRef;
 use common::error::c_err;
 use common::logical_plan::*;
 use common::physical_plan::*;
 use common::prelude::*;
+use common::traits::stat_manager_trait::StatManagerTrait;
+use common::traits::transaction_manager_trait::TransactionManagerTrait;
 use common::Attribute;
 use common::{ast_expr::AstExpr, bytecode_expr::ByteCodeExpr};
-use common::{BooleanOp, MathOp};
+use common::{BooleanOp, JoinType};
+use std::collections::{HashMap, HashSet};
 
 /// Converts a logical operator into a physical operator
 ///
@@ -23,7 +27,7 @@ use common::{BooleanOp, MathOp};
 fn logical_op_to_physical_op(
     logical_op: LogicalOp,
     physical_plan: &mut PhysicalPlan,
-    _catalog: &CatalogRef,
+    catalog: &CatalogRef,
 ) -> Result<PhysicalOp, CrustyError> {
     match logical_op {
         LogicalOp::ReadDeltas(_) | LogicalOp::WriteDeltas(_) | LogicalOp::Update(_) => {
@@ -35,6 +39,9 @@ fn logical_op_to_physical_op(
             projection,
         }) => {
             physical_plan.add_base_table(container_id);
+            if let Some(index_scan) = plan_index_scan(container_id, &filter, &projection, catalog) {
+                return Ok(PhysicalOp::IndexScan(index_scan));
+            }
             Ok(PhysicalOp::Scan(PhysicalScanNode {
                 container_id,
                 filter,
@@ -60,33 +67,46 @@ fn logical_op_to_physical_op(
                 group_by,
             }))
         }
-        LogicalOp::CrossProduct(CrossProductNode { filter: _ }) => {
-            // TODO: (Jun) Add filter support
-            Ok(PhysicalOp::CrossProduct(PhysicalCrossProductNode {}))
+        LogicalOp::CrossProduct(CrossProductNode { filter }) => {
+            Ok(PhysicalOp::CrossProduct(PhysicalCrossProductNode {
+                filter,
+            }))
         }
-        LogicalOp::Join(JoinNode { eqs, filter: _ }) => {
-            // TODO: (Jun) Add filter support
-            let left = eqs[0].0.clone();
-            let right = eqs[0].1.clone();
+        LogicalOp::Join(JoinNode {
+            eqs,
+            filter,
+            join_type,
+        }) => {
             let op = BooleanOp::Eq;
-
-            if matches!(left, AstExpr::Ident(_))
-                && matches!(right, AstExpr::Ident(_))
-                && matches!(op, BooleanOp::Eq)
-            {
-                // If it is a simple join, we assume we can use a hash join
-                Ok(PhysicalOp::HashJoin(PhysicalHashJoinNode {
-                    left,
-                    right,
-                    op,
-                }))
-            } else {
-                Ok(PhysicalOp::NestedLoopJoin(PhysicalNestedLoopJoinNode {
+            // Equalities of columns are hash keys, other equalities are checked with the filter
+            let (keys, others): (Vec<_>, Vec<_>) = eqs.into_iter().partition(|(l, r)| {
+                matches!(l, AstExpr::Ident(_)) && matches!(r, AstExpr::Ident(_))
+            });
+            if keys.is_empty() {
+                let (left, right) = others.into_iter().unzip();
+                return Ok(PhysicalOp::NestedLoopJoin(PhysicalNestedLoopJoinNode {
                     left,
                     right,
                     op,
-                }))
+                    filter,
+                    join_type,
+                }));
             }
+            let filter = AstExpr::conjunction(
+                others
+                    .into_iter()
+                    .map(|(l, r)| AstExpr::Boolean(op, Box::new(l), Box::new(r)))
+                    .chain(filter),
+            );
+            let (left, right) = keys.into_iter().unzip();
+            Ok(PhysicalOp::HashJoin(PhysicalHashJoinNode {
+                left,
+                right,
+                op,
+                hash_table: None,
+                filter,
+                join_type,
+            }))
         }
         LogicalOp::Filter(FilterNode { predicate }) => {
             Ok(PhysicalOp::Filter(PhysicalFilterNode { predicate }))
@@ -101,25 +121,520 @@ fn logical_op_to_physical_op(
 ///
 /// * `logical_plan` - the logical plan to convert to a physical plan
 /// * `catalog` - the catalog in which containers can be created during this conversion  
+/// * `stats` - the statistics used to estimate the sizes of join inputs
 pub fn logical_plan_to_physical_plan(
     logical_plan: LogicalPlan,
     catalog: &CatalogRef,
+    stats: &ReservoirStatManager,
 ) -> Result<PhysicalPlan, CrustyError> {
     let mut physical_plan = PhysicalPlan::new();
-    for (_, node) in logical_plan.node_references() {
-        let logical_op = node.data();
-        let physical_op =
-            logical_op_to_physical_op(logical_op.clone(), &mut physical_plan, catalog)?;
-        physical_plan.add_node(physical_op);
+
+    // An index join reads its inner table itself, so the scan of that table is left out. A
+    // merge join of two indexed tables scans them through the indexes.
+    let mut index_joins = HashMap::new();
+    let mut merge_joins = HashMap::new();
+    let mut ordered_scans = HashMap::new();
+    for (i, node) in logical_plan.node_references() {
+        if let LogicalOp::Join(join) = node.data() {
+            // Index and merge joins only output tuples with a match
+            if join.join_type != JoinType::Inner {
+                continue;
+            }
+            if let Some((scans, merge_join)) = plan_merge_join(&logical_plan, i, join, catalog) {
+                ordered_scans.extend(scans);
+                merge_joins.insert(i, merge_join);
+            } else if let Some(index_join) = plan_index_join(&logical_plan, i, join, catalog) {
+                index_joins.insert(i, index_join);
+            }
+        }
+    }
+    let inner_scans: HashSet<OpIndex> = index_joins.values().map(|(inner, _)| *inner).collect();
+
+    let mut node_map = HashMap::new();
+    for (i, node) in logical_plan.node_references() {
+        if inner_scans.contains(&i) {
+            continue;
+        }
+        let mut physical_op = if let Some((_, index_join)) = index_joins.remove(&i) {
+            physical_plan.add_base_table(index_join.container_id);
+            PhysicalOp::IndexNestedLoopJoin(index_join)
+        } else if let Some(merge_join) = merge_joins.remove(&i) {
+            PhysicalOp::SortMergeJoin(merge_join)
+        } else if let Some(index_scan) = ordered_scans.remove(&i) {
+            physical_plan.add_base_table(index_scan.container_id);
+            PhysicalOp::IndexScan(index_scan)
+        } else {
+            logical_op_to_physical_op(node.data().clone(), &mut physical_plan, catalog)?
+        };
+        if let PhysicalOp::HashJoin(join) = &mut physical_op {
+            orient_hash_keys(&logical_plan, i, join, catalog);
+            // A persisted hash table or a merge join only outputs tuples with a match
+            if join.join_type == JoinType::Inner {
+                join.hash_table = plan_hash_table(&logical_plan, i, join, catalog);
+                if join.hash_table.is_none() {
+                    if let Some(merge_join) =
+                        plan_large_join(&logical_plan, i, join, catalog, stats)
+                    {
+                        physical_op = PhysicalOp::SortMergeJoin(merge_join);
+                    }
+                }
+            }
+        }
+        node_map.insert(i, physical_plan.add_node(physical_op));
     }
 
     for edge in logical_plan.edge_references() {
-        physical_plan.add_edge(edge.source(), edge.target())
+        if let (Some(source), Some(target)) =
+            (node_map.get(&edge.source()), node_map.get(&edge.target()))
+        {
+            physical_plan.add_edge(*source, *target)
+        }
     }
 
     Ok(physical_plan)
 }
 
+/// The operator comparing `right op left` the same way `left op right` does.
+fn flip_comparison(op: BooleanOp) -> Option<BooleanOp> {
+    match op {
+        BooleanOp::Eq => Some(BooleanOp::Eq),
+        BooleanOp::Gt => Some(BooleanOp::Lt),
+        BooleanOp::Gte => Some(BooleanOp::Lte),
+        BooleanOp::Lt => Some(BooleanOp::Gt),
+        BooleanOp::Lte => Some(BooleanOp::Gte),
+        _ => None,
+    }
+}
+
+/// Whether expr reads any column of schema.
+fn mentions_any(expr: &AstExpr, schema: &TableSchema) -> bool {
+    match expr {
+        AstExpr::Ident(name) => schema.contains(name),
+        AstExpr::Alias(_, e) | AstExpr::Agg(_, e) => mentions_any(e, schema),
+        AstExpr::Math(_, l, r) | AstExpr::Boolean(_, l, r) => {
+            mentions_any(l, schema) || mentions_any(r, schema)
+        }
+        AstExpr::Literal(_) | AstExpr::ColIdx(_) => false,
+    }
+}
+
+/// Plans an index scan for a table scan whose filter compares a prefix of an index key with
+/// literals: equalities on the leading key columns, optionally followed by a range on the next
+/// one. A hash index is only used when every column of its key is compared for equality. The
+/// index answering the most of the filter is used. The whole filter is still checked on every
+/// record read.
+fn plan_index_scan(
+    container_id: ContainerId,
+    filter: &Option<AstExpr>,
+    projection: &Option<Vec<AstExpr>>,
+    catalog: &CatalogRef,
+) -> Option<PhysicalIndexScanNode> {
+    let filter = filter.as_ref()?;
+    // (column, op, literal) with the column on the left. Comparisons with null never use an index.
+    let comparisons: Vec<(&str, BooleanOp, &Field)> = filter
+        .conjuncts()
+        .into_iter()
+        .filter_map(|c| match c {
+            AstExpr::Boolean(op, l, r) => match (l.as_ref(), r.as_ref()) {
+                (AstExpr::Ident(col), AstExpr::Literal(f)) => Some((col.as_str(), *op, f)),
+                (AstExpr::Literal(f), AstExpr::Ident(col)) => {
+                    Some((col.as_str(), flip_comparison(*op)?, f))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|(_, _, f)| **f != Field::Null)
+        .collect();
+    let find = |col: &str, ops: &[BooleanOp]| {
+        comparisons
+            .iter()
+            .find(|(c, op, _)| *c == col && ops.contains(op))
+            .map(|(_, op, f)| ((*f).clone(), *op))
+    };
+
+    let mut best: Option<(usize, PhysicalIndexScanNode)> = None;
+    for info in catalog.get_table_indexes(container_id) {
+        let mut prefix = Vec::new();
+        let (mut lower, mut upper) = (None, None);
+        for attr in &info.attributes {
+            if let Some((f, _)) = find(attr.name(), &[BooleanOp::Eq]) {
+                prefix.push(f);
+                continue;
+            }
+            lower = find(attr.name(), &[BooleanOp::Gt, BooleanOp::Gte])
+                .map(|(f, op)| (f, op == BooleanOp::Gte));
+            upper = find(attr.name(), &[BooleanOp::Lt, BooleanOp::Lte])
+                .map(|(f, op)| (f, op == BooleanOp::Lte));
+            break;
+        }
+        if info.kind == IndexKind::Hash && prefix.len() < info.attributes.len() {
+            continue;
+        }
+        let score = 2 * prefix.len() + lower.is_some() as usize + upper.is_some() as usize;
+        if score == 0 || best.as_ref().is_some_and(|(s, _)| *s >= score) {
+            continue;
+        }
+        let bound = |range: Option<(Field, bool)>| match range {
+            Some((f, inclusive)) => {
+                let mut key = prefix.clone();
+                key.push(f);
+                Some((key, inclusive))
+            }
+            None if !prefix.is_empty() => Some((prefix.clone(), true)),
+            None => None,
+        };
+        let node = PhysicalIndexScanNode {
+            container_id,
+            index_id: info.c_id,
+            lower: bound(lower),
+            upper: bound(upper),
+            filter: Some(filter.clone()),
+            projection: projection.clone(),
+        };
+        best = Some((score, node));
+    }
+    best.map(|(_, node)| node)
+}
+
+/// Plans an index nested loop join for a join with a table scan child whose table has an index
+/// with a key prefix matched by the join's equalities. Hash indexes are left to hash joins.
+/// Returns the scan the join replaces and the join. Equalities not answered by the index and the join filter are checked on joined tuples.
+fn plan_index_join(
+    logical_plan: &LogicalPlan,
+    join_idx: OpIndex,
+    join: &JoinNode,
+    catalog: &CatalogRef,
+) -> Option<(OpIndex, PhysicalIndexNestedLoopJoinNode)> {
+    let children: Vec<OpIndex> = logical_plan.edges(join_idx).collect();
+    if join.eqs.is_empty() || children.len() != 2 {
+        return None;
+    }
+    for (inner_pos, inner) in children.iter().enumerate().rev() {
+        let scan = match logical_plan.get_operator(*inner) {
+            Some(LogicalOp::Scan(scan)) => scan,
+            _ => continue,
+        };
+        let schema = match catalog.get_table_schema(scan.container_id) {
+            Some(schema) => schema,
+            None => continue,
+        };
+        // (inner column, outer expression) of each equality that can be looked up
+        let lookups: Vec<Option<(&str, &AstExpr)>> = join
+            .eqs
+            .iter()
+            .map(|(l, r)| match (l, r) {
+                (AstExpr::Ident(col), other) | (other, AstExpr::Ident(col))
+                    if schema.contains(col) && !mentions_any(other, &schema) =>
+                {
+                    Some((col.as_str(), other))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut best: Option<(Vec<usize>, IndexInfo)> = None;
+        for info in catalog.get_table_indexes(scan.container_id) {
+            if info.kind == IndexKind::Hash {
+                continue;
+            }
+            let mut used: Vec<usize> = Vec::new();
+            for attr in &info.attributes {
+                let eq = lookups.iter().enumerate().position(|(i, lookup)| {
+                    !used.contains(&i) && lookup.is_some_and(|(col, _)| col == attr.name())
+                });
+                match eq {
+                    Some(i) => used.push(i),
+                    None => break,
+                }
+            }
+            if !used.is_empty() && best.as_ref().is_none_or(|(u, _)| u.len() < used.len()) {
+                best = Some((used, info));
+            }
+        }
+        let (used, info) = match best {
+            Some(best) => best,
+            None => continue,
+        };
+
+        let outer_exprs = used
+            .iter()
+            .map(|i| lookups[*i].unwrap().1.clone())
+            .collect();
+        let filter = AstExpr::conjunction(
+            join.eqs
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !used.contains(i))
+                .map(|(_, (l, r))| {
+                    AstExpr::Boolean(BooleanOp::Eq, Box::new(l.clone()), Box::new(r.clone()))
+                })
+                .chain(join.filter.clone()),
+        );
+        return Some((
+            *inner,
+            PhysicalIndexNestedLoopJoinNode {
+                outer_exprs,
+                container_id: scan.container_id,
+                index_id: info.c_id,
+                inner_filter: scan.filter.clone(),
+                inner_projection: scan.projection.clone(),
+                filter,
+                outer_first: inner_pos == 1,
+            },
+        ));
+    }
+    None
+}
+
+/// Plans a sort-merge join for a join of two table scans whose tables have b+tree indexes keyed
+/// on the join columns in the same order, so scanning the indexes returns both inputs in key
+/// order and neither is sorted. Every equality must compare a column of each table. Returns
+/// the index scans replacing the two scans and the join.
+#[allow(clippy::type_complexity)]
+fn plan_merge_join(
+    logical_plan: &LogicalPlan,
+    join_idx: OpIndex,
+    join: &JoinNode,
+    catalog: &CatalogRef,
+) -> Option<(
+    Vec<(OpIndex, PhysicalIndexScanNode)>,
+    PhysicalSortMergeJoinNode,
+)> {
+    let children: Vec<OpIndex> = logical_plan.edges(join_idx).collect();
+    if join.eqs.is_empty() || children.len() != 2 {
+        return None;
+    }
+    let scans = children
+        .iter()
+        .map(|child| match logical_plan.get_operator(*child) {
+            Some(LogicalOp::Scan(scan)) => Some(scan),
+            _ => None,
+        })
+        .collect::<Option<Vec<&ScanNode>>>()?;
+    let schemas = scans
+        .iter()
+        .map(|scan| catalog.get_table_schema(scan.container_id))
+        .collect::<Option<Vec<TableSchema>>>()?;
+    // (left column, right column) of each equality
+    let cols = join
+        .eqs
+        .iter()
+        .map(|(l, r)| match (l, r) {
+            (AstExpr::Ident(l), AstExpr::Ident(r))
+                if schemas[0].contains(l) && schemas[1].contains(r) =>
+            {
+                Some((l.as_str(), r.as_str()))
+            }
+            (AstExpr::Ident(l), AstExpr::Ident(r))
+                if schemas[0].contains(r) && schemas[1].contains(l) =>
+            {
+                Some((r.as_str(), l.as_str()))
+            }
+            _ => None,
+        })
+        .collect::<Option<Vec<(&str, &str)>>>()?;
+    let btrees = |scan: &ScanNode| {
+        catalog
+            .get_table_indexes(scan.container_id)
+            .into_iter()
+            .filter(|info| info.kind == IndexKind::BTree && info.attributes.len() >= cols.len())
+    };
+
+    for left in btrees(scans[0]) {
+        // The equalities in the order of the left index key
+        let order = left.attributes[..cols.len()]
+            .iter()
+            .map(|attr| cols.iter().position(|(l, _)| *l == attr.name()))
+            .collect::<Option<Vec<usize>>>();
+        let order = match order {
+            Some(order) if (0..cols.len()).all(|i| order.contains(&i)) => order,
+            _ => continue,
+        };
+        let right = btrees(scans[1]).find(|info| {
+            info.attributes
+                .iter()
+                .zip(&order)
+                .all(|(attr, i)| attr.name() == cols[*i].1)
+        });
+        let right = match right {
+            Some(right) => right,
+            None => continue,
+        };
+        let index_scan = |scan: &ScanNode, info: &IndexInfo| PhysicalIndexScanNode {
+            container_id: scan.container_id,
+            index_id: info.c_id,
+            lower: None,
+            upper: None,
+            filter: scan.filter.clone(),
+            projection: scan.projection.clone(),
+        };
+        let (left_expr, right_expr) = order
+            .iter()
+            .map(|i| {
+                let (l, r) = cols[*i];
+                (
+                    (AstExpr::Ident(l.to_string()), true),
+                    (AstExpr::Ident(r.to_string()), true),
+                )
+            })
+            .unzip();
+        let merge_join = PhysicalSortMergeJoinNode {
+            left_expr,
+            right_expr,
+            left_sorted: true,
+            right_sorted: true,
+            filter: join.filter.clone(),
+        };
+        let scans = vec![
+            (children[0], index_scan(scans[0], &left)),
+            (children[1], index_scan(scans[1], &right)),
+        ];
+        return Some((scans, merge_join));
+    }
+    None
+}
+
+/// Plans a sort-merge join, sorting both inputs, in place of a hash join whose hash table would
+/// not fit in memory. The hash table is built from the child computing the left sides of the
+/// join keys, and its size is estimated when that child is a table scan.
+fn plan_large_join(
+    logical_plan: &LogicalPlan,
+    join_idx: OpIndex,
+    join: &PhysicalHashJoinNode,
+    catalog: &CatalogRef,
+    stats: &ReservoirStatManager,
+) -> Option<PhysicalSortMergeJoinNode> {
+    let first = join.left.first()?;
+    let children: Vec<OpIndex> = logical_plan.edges(join_idx).collect();
+    if children.len() != 2 {
+        return None;
+    }
+    let (build_pos, scan, schema) = children.iter().enumerate().find_map(|(pos, child)| {
+        match logical_plan.get_operator(*child) {
+            Some(LogicalOp::Scan(scan)) => {
+                let schema = catalog.get_table_schema(scan.container_id)?;
+                is_computed_from(first, &schema).then_some((pos, scan, schema))
+            }
+            _ => None,
+        }
+    })?;
+    let builds_all = join
+        .left
+        .iter()
+        .zip(&join.right)
+        .all(|(l, r)| is_computed_from(l, &schema) && !mentions_any(r, &schema));
+    if !builds_all {
+        return None;
+    }
+    let size = stats
+        .estimate_size(scan.container_id, scan.filter.clone())
+        .ok()?;
+    if size <= MEMORY_BUDGET {
+        return None;
+    }
+    debug!(
+        "Estimated {} bytes of hash table, planning a merge join",
+        size
+    );
+    let build_keys = join.left.iter().map(|e| (e.clone(), true)).collect();
+    let other_keys = join.right.iter().map(|e| (e.clone(), true)).collect();
+    let (left_expr, right_expr) = if build_pos == 0 {
+        (build_keys, other_keys)
+    } else {
+        (other_keys, build_keys)
+    };
+    Some(PhysicalSortMergeJoinNode {
+        left_expr,
+        right_expr,
+        left_sorted: false,
+        right_sorted: false,
+        filter: join.filter.clone(),
+    })
+}
+
+/// Swaps the sides of the keys of a hash join whose right side, rather than its left, is computed
+/// from the table scanned by the child the hash table is built from, which computes the left side
+/// of the first key.
+fn orient_hash_keys(
+    logical_plan: &LogicalPlan,
+    join_idx: OpIndex,
+    join: &mut PhysicalHashJoinNode,
+    catalog: &CatalogRef,
+) {
+    let first = match join.left.first() {
+        Some(first) => first,
+        None => return,
+    };
+    let schema =
+        logical_plan
+            .edges(join_idx)
+            .find_map(|child| match logical_plan.get_operator(child) {
+                Some(LogicalOp::Scan(scan)) => catalog
+                    .get_table_schema(scan.container_id)
+                    .filter(|schema| is_computed_from(first, schema)),
+                _ => None,
+            });
+    if let Some(schema) = schema {
+        for (l, r) in join.left.iter_mut().zip(join.right.iter_mut()) {
+            if !mentions_any(l, &schema) && is_computed_from(r, &schema) {
+                std::mem::swap(l, r);
+            }
+        }
+    }
+}
+
+/// Picks the persisted hash index a hash join probes instead of building its hash table. The
+/// hash table is built from the child computing the left sides of the join keys, which must be
+/// a plain scan of a table keyed on its columns. Only a hash index created on those columns with
+/// CREATE INDEX is used; planning never adds indexes to the catalog.
+fn plan_hash_table(
+    logical_plan: &LogicalPlan,
+    join_idx: OpIndex,
+    join: &PhysicalHashJoinNode,
+    catalog: &CatalogRef,
+) -> Option<ContainerId> {
+    let cols = join
+        .left
+        .iter()
+        .map(|e| match e {
+            AstExpr::Ident(col) => Some(col),
+            _ => None,
+        })
+        .collect::<Option<Vec<&String>>>()?;
+    let col = cols.first()?;
+    let mut build = None;
+    for child in logical_plan.edges(join_idx) {
+        let schema = match logical_plan.get_operator(child) {
+            Some(LogicalOp::Scan(scan)) => catalog
+                .get_table_schema(scan.container_id)
+                .map(|schema| (scan, schema)),
+            // The key may come from a table below another operator
+            _ => return None,
+        };
+        if let Some((scan, schema)) = schema.filter(|(_, schema)| schema.contains(col)) {
+            if build.is_some() || scan.filter.is_some() || scan.projection.is_some() {
+                return None;
+            }
+            build = Some((scan, schema));
+        }
+    }
+    let (scan, schema) = build?;
+    // Each key must pair a column of the table with an expression on the other child
+    if join.right.iter().any(|e| mentions_any(e, &schema)) {
+        return None;
+    }
+    let columns = cols
+        .iter()
+        .map(|col| schema.get_field_index(col))
+        .collect::<Option<Vec<usize>>>()?;
+    catalog
+        .get_table_indexes(scan.container_id)
+        .into_iter()
+        .find(|i| i.kind == IndexKind::Hash && i.key_columns == columns)
+        .map(|info| info.c_id)
+}
+
 pub fn is_computed_from(expr: &AstExpr, schema: &TableSchema) -> bool {
     match expr {
         AstExpr::Ident(name) => schema.contains(name.as_str()),
@@ -134,71 +649,82 @@ pub fn is_computed_from(expr: &AstExpr, schema: &TableSchema) -> bool {
     }
 }
 
+/// Orients the key pairs of a join so the left of each is computed from the left child and the
+/// right from the right child. A pair that cannot be oriented, such as one comparing two columns
+/// of the same child, is checked with the filter instead and returned in it.
+fn orient_join_keys(
+    left: &[AstExpr],
+    right: &[AstExpr],
+    op: BooleanOp,
+    filter: &Option<AstExpr>,
+    left_schema: &TableSchema,
+    right_schema: &TableSchema,
+) -> (Vec<(AstExpr, AstExpr)>, Option<AstExpr>) {
+    let mut keys = Vec::new();
+    let mut residual = Vec::new();
+    for (l, r) in left.iter().zip(right) {
+        if is_computed_from(l, left_schema) && is_computed_from(r, right_schema) {
+            keys.push((l.clone(), r.clone()));
+        } else if matches!(op, BooleanOp::Eq | BooleanOp::Neq)
+            && is_computed_from(r, left_schema)
+            && is_computed_from(l, right_schema)
+        {
+            keys.push((r.clone(), l.clone()));
+        } else {
+            residual.push(AstExpr::Boolean(
+                op,
+                Box::new(l.clone()),
+                Box::new(r.clone()),
+            ));
+        }
+    }
+    let filter = AstExpr::conjunction(residual.into_iter().chain(filter.clone()));
+    (keys, filter)
+}
+
 /// Function to transform a logical expression (AST) to physical expression (Bytecode)
 pub fn convert_ast_to_bytecode(
     expr: AstExpr,
     schema: &TableSchema,
 ) -> Result<ByteCodeExpr, CrustyError> {
-    let bound_expr = bind_expr(expr, schema)?;
-    let mut bytecode_expr = ByteCodeExpr::new();
-    convert_ast_to_bytecode_inner(&bound_expr, &mut bytecode_expr)?;
-    Ok(bytecode_expr)
+    ByteCodeExpr::from_ast(&bind_expr(expr, schema)?)
 }
 
-fn convert_ast_to_bytecode_inner(
-    expr: &AstExpr,
-    bytecode_expr: &mut ByteCodeExpr,
-) -> Result<(), CrustyError> {
-    match expr {
-        AstExpr::Ident(_) | AstExpr::Alias(_, _) | AstExpr::Agg(_, _) => {
-            return Err(c_err(
-                "Ident, Alias, and Agg should have been handled in conversion to logical plan or bound expression",
-            ));
-        }
-        AstExpr::Literal(l) => {
-            let i = bytecode_expr.add_literal(l.clone());
-            bytecode_expr.add_code(ByteCodes::PushLit as usize);
-            bytecode_expr.add_code(i);
-        }
-        AstExpr::Math(op, l, r) => {
-            // (a+b)-(c+d) Bytecode will be [a][b][+][c][d][+][-]
-            // i, Stack
-            // 0, [a]
-            // 1, [a][b]
-            // 2, [a+b]
-            // 3, [a+b][c]
-            // 4, [a+b][c][d]
-            // 5, [a+b][c+d]
-            // 6, [a+b-c-d]
-            convert_ast_to_bytecode_inner(l, bytecode_expr)?;
-            convert_ast_to_bytecode_inner(r, bytecode_expr)?;
-            match op {
-                MathOp::Add => bytecode_expr.add_code(ByteCodes::Add as usize),
-                MathOp::Sub => bytecode_expr.add_code(ByteCodes::Sub as usize),
-                MathOp::Mul => bytecode_expr.add_code(ByteCodes::Mul as usize),
-                MathOp::Div => bytecode_expr.add_code(ByteCodes::Div as usize),
-            }
-        }
-        AstExpr::Boolean(op, l, r) => {
-            convert_ast_to_bytecode_inner(l, bytecode_expr)?;
-            convert_ast_to_bytecode_inner(r, bytecode_expr)?;
-            match op {
-                BooleanOp::Eq => bytecode_expr.add_code(ByteCodes::Eq as usize),
-                BooleanOp::Neq => bytecode_expr.add_code(ByteCodes::Neq as usize),
-                BooleanOp::Gt => bytecode_expr.add_code(ByteCodes::Gt as usize),
-                BooleanOp::Gte => bytecode_expr.add_code(ByteCodes::Gte as usize),
-                BooleanOp::Lt => bytecode_expr.add_code(ByteCodes::Lt as usize),
-                BooleanOp::Lte => bytecode_expr.add_code(ByteCodes::Lte as usize),
-                BooleanOp::And => bytecode_expr.add_code(ByteCodes::And as usize),
-                BooleanOp::Or => bytecode_expr.add_code(ByteCodes::Or as usize),
-            }
-        }
-        AstExpr::ColIdx(i) => {
-            bytecode_expr.add_code(ByteCodes::PushField as usize);
-            bytecode_expr.add_code(*i);
+/// Binds the filter and projection of a scan of a table to its schema. Returns the schema of
+/// the scanned tuples with the bound filter and projection.
+#[allow(clippy::type_complexity)]
+fn bind_scan(
+    catalog: &CatalogRef,
+    container_id: ContainerId,
+    filter: &Option<AstExpr>,
+    projection: &Option<Vec<AstExpr>>,
+) -> Result<(TableSchema, Option<ByteCodeExpr>, Option<Vec<ByteCodeExpr>>), CrustyError> {
+    let in_schema = catalog
+        .get_table_schema(container_id)
+        .ok_or_else(|| CrustyError::ExecutionError(format!("No table {}", container_id)))?;
+    let out_schema = if let Some(p) = projection {
+        // If projection is specified, we need to create a new schema
+        let mut attrs = Vec::new();
+        for e in p {
+            attrs.push(e.to_attr(&in_schema));
         }
-    }
-    Ok(())
+        TableSchema::new(attrs)
+    } else {
+        in_schema.clone()
+    };
+    let filter = filter
+        .as_ref()
+        .map(|f| convert_ast_to_bytecode(f.clone(), &in_schema))
+        .transpose()?;
+    let projection = projection
+        .as_ref()
+        .map(|p| {
+            p.iter()
+                .map(|e| convert_ast_to_bytecode(e.clone(), &in_schema))
+                .collect::<Result<Vec<ByteCodeExpr>, CrustyError>>()
+        })
+        .transpose()?;
+    Ok((out_schema, filter, projection))
 }
 
 /// Converts a physical_plan to an op_iterator.
@@ -218,9 +744,73 @@ pub fn physical_plan_to_op_iterator(
     let start = physical_plan
         .root()
         .ok_or_else(|| CrustyError::ExecutionError(String::from("No root node")))?;
+    read_predicates(managers, catalog, physical_plan, start, None, tid)?;
     physical_plan_to_op_iterator_helper(managers, catalog, physical_plan, start, tid)
 }
 
+/// Tells the transaction manager which records of each table the plan reads. A scan reads the
+/// records passing its filter, and the filter of a Filter right above it if it keeps every
+/// column. The filters bound to the table are passed on as one conjunction.
+fn read_predicates(
+    managers: &'static Managers,
+    catalog: &CatalogRef,
+    physical_plan: &PhysicalPlan,
+    start: OpIndex,
+    parent_filter: Option<&AstExpr>,
+    tid: TransactionId,
+) -> Result<(), CrustyError> {
+    let op = physical_plan
+        .get_operator(start)
+        .ok_or_else(|| CrustyError::ExecutionError(String::from("Malformed logical plan")))?;
+    let read = |container_id: ContainerId,
+                filter: &Option<AstExpr>,
+                parent_filter: Option<&AstExpr>|
+     -> Result<(), CrustyError> {
+        let schema = catalog
+            .get_table_schema(container_id)
+            .ok_or_else(|| CrustyError::ExecutionError(format!("No table {}", container_id)))?;
+        let mut filters = filter
+            .iter()
+            .map(|f| bind_expr(f.clone(), &schema))
+            .collect::<Result<Vec<_>, _>>()?;
+        filters.extend(parent_filter.and_then(|f| bind_expr(f.clone(), &schema).ok()));
+        let predicate =
+            AstExpr::conjunction(filters).unwrap_or(AstExpr::Literal(Field::Bool(true)));
+        managers.tm.read_predicate(container_id, predicate, tid)
+    };
+    match op {
+        PhysicalOp::Scan(PhysicalScanNode {
+            container_id,
+            filter,
+            projection,
+        })
+        | PhysicalOp::IndexScan(PhysicalIndexScanNode {
+            container_id,
+            filter,
+            projection,
+            ..
+        }) => read(
+            *container_id,
+            filter,
+            parent_filter.filter(|_| projection.is_none()),
+        )?,
+        PhysicalOp::IndexNestedLoopJoin(PhysicalIndexNestedLoopJoinNode {
+            container_id,
+            inner_filter,
+            ..
+        }) => read(*container_id, inner_filter, None)?,
+        _ => {}
+    }
+    let filter = match op {
+        PhysicalOp::Filter(PhysicalFilterNode { predicate, .. }) => Some(predicate),
+        _ => None,
+    };
+    for child in physical_plan.edges(start) {
+        read_predicates(managers, catalog, physical_plan, child, filter, tid)?;
+    }
+    Ok(())
+}
+
 /// Recursive helper function to parse physical plan into opiterator.
 ///
 /// Function first converts all of the current nodes children to an opiterator before converting self to an opiterator.
@@ -254,33 +844,34 @@ fn physical_plan_to_op_iterator_helper(
             filter,
             projection,
         }) => {
-            let in_schema = catalog.get_table_schema(*container_id).unwrap();
-            let out_schema = if let Some(p) = projection {
-                // If projection is specified, we need to create a new schema
-                let mut attrs = Vec::new();
-                for e in p {
-                    attrs.push(e.to_attr(&in_schema));
-                }
-                TableSchema::new(attrs)
-            } else {
-                in_schema.clone()
-            };
-            let filter = filter
-                .as_ref()
-                .map(|f| convert_ast_to_bytecode(f.clone(), &in_schema))
-                .transpose()?;
-            let projection = projection
-                .as_ref()
-                .map(|p| {
-                    p.iter()
-                        .map(|e| convert_ast_to_bytecode(e.clone(), &in_schema))
-                        .collect::<Result<Vec<ByteCodeExpr>, CrustyError>>()
-                })
-                .transpose()?;
+            let (out_schema, filter, projection) =
+                bind_scan(catalog, *container_id, filter, projection)?;
             let scan_iter =
                 SeqScan::new(managers, &out_schema, container_id, tid, filter, projection);
             Ok(Box::new(scan_iter))
         }
+        PhysicalOp::IndexScan(PhysicalIndexScanNode {
+            container_id,
+            index_id,
+            lower,
+            upper,
+            filter,
+            projection,
+        }) => {
+            let (out_schema, filter, projection) =
+                bind_scan(catalog, *container_id, filter, projection)?;
+            let scan_iter = IndexScan::new(
+                managers,
+                &out_schema,
+                *index_id,
+                tid,
+                lower.clone(),
+                upper.clone(),
+                filter,
+                projection,
+            );
+            Ok(Box::new(scan_iter))
+        }
         PhysicalOp::Project(PhysicalProjectNode { identifiers }) => {
             let child = children.next().ok_or_else(|| err.clone())??;
             let input_schema = child.get_schema();
@@ -327,81 +918,174 @@ fn physical_plan_to_op_iterator_helper(
             let agg = Aggregate::new(managers, group_by_expr, agg_expr, ops, schema, child);
             Ok(Box::new(agg))
         }
-        PhysicalOp::CrossProduct(PhysicalCrossProductNode {}) => {
+        PhysicalOp::CrossProduct(PhysicalCrossProductNode { filter }) => {
             let left_child = children.next().ok_or_else(|| err.clone())??;
             let left_schema = left_child.get_schema();
             let right_child = children.next().ok_or_else(|| err.clone())??;
             let right_schema = right_child.get_schema();
             let schema = left_schema.merge(right_schema);
-            let cross_iter = CrossJoin::new(schema, left_child, right_child);
+            let filter = filter
+                .as_ref()
+                .map(|f| convert_ast_to_bytecode(f.clone(), &schema))
+                .transpose()?;
+            let cross_iter = CrossJoin::new(schema, filter, left_child, right_child);
             Ok(Box::new(cross_iter))
         }
-        PhysicalOp::NestedLoopJoin(PhysicalNestedLoopJoinNode { left, right, op }) => {
+        PhysicalOp::NestedLoopJoin(PhysicalNestedLoopJoinNode {
+            left,
+            right,
+            op,
+            filter,
+            join_type,
+        }) => {
             let left_child = children.next().ok_or_else(|| err.clone())??;
             let left_schema = left_child.get_schema();
             let right_child = children.next().ok_or_else(|| err.clone())??;
             let right_schema = right_child.get_schema();
             let schema = left_schema.merge(right_schema);
 
-            let join_iter = if is_computed_from(left, left_schema)
-                && is_computed_from(right, right_schema)
-            {
-                NestedLoopJoin::new(
-                    *op,
-                    convert_ast_to_bytecode(left.clone(), left_schema)?,
-                    convert_ast_to_bytecode(right.clone(), right_schema)?,
-                    left_child,
-                    right_child,
-                    schema,
-                )
-            } else if is_computed_from(right, left_schema) && is_computed_from(left, right_schema) {
-                NestedLoopJoin::new(
-                    *op,
-                    convert_ast_to_bytecode(right.clone(), left_schema)?,
-                    convert_ast_to_bytecode(left.clone(), right_schema)?,
-                    left_child,
-                    right_child,
-                    schema,
-                )
-            } else {
-                Err(c_err("NestedLoopJoin failed to find a joinable expression"))?
-            };
+            let (keys, filter) =
+                orient_join_keys(left, right, *op, filter, left_schema, right_schema);
+            let mut left_exprs = Vec::new();
+            let mut right_exprs = Vec::new();
+            for (l, r) in keys {
+                left_exprs.push(convert_ast_to_bytecode(l, left_schema)?);
+                right_exprs.push(convert_ast_to_bytecode(r, right_schema)?);
+            }
+            let filter = filter
+                .map(|f| convert_ast_to_bytecode(f, &schema))
+                .transpose()?;
+            let join_iter = NestedLoopJoin::new(
+                *op,
+                left_exprs,
+                right_exprs,
+                filter,
+                left_child,
+                right_child,
+                schema,
+            )
+            .with_join_type(*join_type);
             Ok(Box::new(join_iter))
         }
         PhysicalOp::HashJoin(PhysicalHashJoinNode {
-            left, right, op: _, ..
+            left,
+            right,
+            op,
+            hash_table,
+            filter,
+            join_type,
         }) => {
             let left_child = children.next().ok_or_else(|| err.clone())??;
-            let left_schema = left_child.get_schema();
             let right_child = children.next().ok_or_else(|| err.clone())??;
-            let right_schema = right_child.get_schema();
 
-            let join_iter = if is_computed_from(left, left_schema)
-                && is_computed_from(right, right_schema)
-            {
-                let schema = left_schema.merge(right_schema);
-                HashEqJoin::new(
-                    managers,
+            // The hash table is built from the child computing the left side of the first key
+            let first = left.first().ok_or_else(|| c_err("HashJoin without keys"))?;
+            let (build_child, probe_child, join_type) =
+                if is_computed_from(first, left_child.get_schema()) {
+                    (left_child, right_child, *join_type)
+                } else {
+                    (right_child, left_child, join_type.swap())
+                };
+            let build_schema = build_child.get_schema();
+            let probe_schema = probe_child.get_schema();
+            let schema = build_schema.merge(probe_schema);
+            let (keys, filter) =
+                orient_join_keys(left, right, *op, filter, build_schema, probe_schema);
+            let filter = filter
+                .map(|f| convert_ast_to_bytecode(f, &schema))
+                .transpose()?;
+            if keys.is_empty() && hash_table.is_none() {
+                // No key compares the two children, so every pair is checked with the filter
+                let join_iter = NestedLoopJoin::new(
+                    *op,
+                    vec![],
+                    vec![],
+                    filter,
+                    build_child,
+                    probe_child,
                     schema,
-                    convert_ast_to_bytecode(left.clone(), left_schema)?,
-                    convert_ast_to_bytecode(right.clone(), right_schema)?,
-                    left_child,
-                    right_child,
                 )
-            } else if is_computed_from(right, left_schema) && is_computed_from(left, right_schema) {
-                let schema = right_schema.merge(left_schema);
-                HashEqJoin::new(
+                .with_join_type(join_type);
+                return Ok(Box::new(join_iter));
+            }
+            let keys = keys
+                .into_iter()
+                .map(|(l, r)| {
+                    Ok((
+                        convert_ast_to_bytecode(l, build_schema)?,
+                        convert_ast_to_bytecode(r, probe_schema)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, CrustyError>>()?;
+
+            let join_iter = match hash_table {
+                Some(index_id) => {
+                    // The index is keyed on every left side, in order
+                    if keys.len() != left.len() {
+                        Err(c_err("HashJoin keys do not match its hash table"))?
+                    }
+                    let probe_exprs = keys.into_iter().map(|(_, r)| r).collect();
+                    HashEqJoin::persisted(
+                        managers,
+                        schema,
+                        *index_id,
+                        tid,
+                        probe_exprs,
+                        filter,
+                        probe_child,
+                    )
+                }
+                None => HashEqJoin::new(
                     managers,
                     schema,
-                    convert_ast_to_bytecode(right.clone(), left_schema)?,
-                    convert_ast_to_bytecode(left.clone(), right_schema)?,
-                    right_child,
-                    left_child,
-                )
+                    keys,
+                    filter,
+                    tid,
+                    build_child,
+                    probe_child,
+                ),
+            }
+            .with_join_type(join_type);
+            Ok(Box::new(join_iter))
+        }
+        PhysicalOp::IndexNestedLoopJoin(PhysicalIndexNestedLoopJoinNode {
+            outer_exprs,
+            container_id,
+            index_id,
+            inner_filter,
+            inner_projection,
+            filter,
+            outer_first,
+        }) => {
+            let outer_child = children.next().ok_or_else(|| err.clone())??;
+            let outer_schema = outer_child.get_schema();
+            let (inner_schema, inner_filter, inner_projection) =
+                bind_scan(catalog, *container_id, inner_filter, inner_projection)?;
+            let schema = if *outer_first {
+                outer_schema.merge(&inner_schema)
             } else {
-                Err(c_err("HashJoin failed to find a joinable expression"))?
+                inner_schema.merge(outer_schema)
             };
-
+            let outer_exprs = outer_exprs
+                .iter()
+                .map(|e| convert_ast_to_bytecode(e.clone(), outer_schema))
+                .collect::<Result<Vec<ByteCodeExpr>, CrustyError>>()?;
+            let filter = filter
+                .as_ref()
+                .map(|f| convert_ast_to_bytecode(f.clone(), &schema))
+                .transpose()?;
+            let join_iter = IndexNestedLoopJoin::new(
+                managers,
+                schema,
+                outer_exprs,
+                *index_id,
+                tid,
+                inner_filter,
+                inner_projection,
+                filter,
+                *outer_first,
+                outer_child,
+            );
             Ok(Box::new(join_iter))
         }
         PhysicalOp::Filter(PhysicalFilterNode { predicate, .. }) => {
@@ -414,14 +1098,67 @@ fn physical_plan_to_op_iterator_helper(
             );
             Ok(Box::new(filter_iter))
         }
-        PhysicalOp::Sort(PhysicalSortNode { fields: _ }) => {
-            panic!("Sort not implemented")
+        PhysicalOp::Sort(PhysicalSortNode { fields }) => {
+            let child = children.next().ok_or_else(|| err.clone())??;
+            let keys = fields
+                .iter()
+                .map(|(expr, asc, nulls_first)| {
+                    Ok((
+                        convert_ast_to_bytecode(expr.clone(), child.get_schema())?,
+                        *asc,
+                        *nulls_first,
+                    ))
+                })
+                .collect::<Result<Vec<SortKey>, CrustyError>>()?;
+            let sort_iter = Sort::new(managers, keys, MEMORY_BUDGET, tid, child);
+            Ok(Box::new(sort_iter))
         }
         PhysicalOp::SortMergeJoin(PhysicalSortMergeJoinNode {
-            left_expr: _,
-            right_expr: _,
+            left_expr,
+            right_expr,
+            left_sorted,
+            right_sorted,
+            filter,
         }) => {
-            panic!("SortMergeJoin not implemented")
+            let left_child = children.next().ok_or_else(|| err.clone())??;
+            let left_schema = left_child.get_schema();
+            let right_child = children.next().ok_or_else(|| err.clone())??;
+            let right_schema = right_child.get_schema();
+            let schema = left_schema.merge(right_schema);
+
+            if left_expr.len() != right_expr.len() {
+                Err(c_err("SortMergeJoin needs as many left as right keys"))?
+            }
+            let mut keys = Vec::new();
+            for ((left, left_asc), (right, right_asc)) in left_expr.iter().zip(right_expr) {
+                if left_asc != right_asc {
+                    Err(c_err("SortMergeJoin keys must be sorted the same way"))?
+                }
+                let (left, right) = if is_computed_from(left, left_schema)
+                    && is_computed_from(right, right_schema)
+                {
+                    (left, right)
+                } else if is_computed_from(right, left_schema)
+                    && is_computed_from(left, right_schema)
+                {
+                    (right, left)
+                } else {
+                    Err(c_err("SortMergeJoin failed to find a joinable expression"))?
+                };
+                keys.push((
+                    convert_ast_to_bytecode(left.clone(), left_schema)?,
+                    convert_ast_to_bytecode(right.clone(), right_schema)?,
+                    *left_asc,
+                ));
+            }
+            let filter = filter
+                .as_ref()
+                .map(|f| convert_ast_to_bytecode(f.clone(), &schema))
+                .transpose()?;
+            let join_iter =
+                SortMergeJoin::new(managers, schema, keys, filter, tid, left_child, right_child)
+                    .presorted(*left_sorted, *right_sorted);
+            Ok(Box::new(join_iter))
         }
         PhysicalOp::MaterializedView(_) => unimplemented!(),
         PhysicalOp::Update(PhysicalUpdateNode {
@@ -447,7 +1184,6 @@ fn physical_plan_to_op_iterator_helper(
             );
             let update = Update::new(
                 managers,
-                transaction_manager,
                 container_id,
                 tid,
                 indices.into_iter().zip(fields).collect(),
@@ -465,3 +1201,483 @@ fn physical_plan_to_op_iterator_helper(
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::{execute_iter, TestSetup, TestTuples};
+    use common::MathOp;
+
+    /// Index table0 on columns and record the index in the catalog
+    fn add_index(setup: &TestSetup, table: &str, name: &str, columns: Vec<usize>) -> ContainerId {
+        add_index_of_kind(setup, table, name, columns, IndexKind::BTree)
+    }
+
+    fn add_index_of_kind(
+        setup: &TestSetup,
+        table: &str,
+        name: &str,
+        columns: Vec<usize>,
+        kind: IndexKind,
+    ) -> ContainerId {
+        let catalog = setup.get_catalog();
+        let table_id = catalog.get_table_id(table);
+        let schema = catalog.get_table_schema(table_id).unwrap();
+        let info = IndexInfo {
+            c_id: catalog.get_new_container_id(),
+            name: name.to_string(),
+            table_id,
+            attributes: columns
+                .iter()
+                .map(|c| schema.get_attribute(*c).unwrap().clone())
+                .collect(),
+            key_columns: columns,
+            is_unique: false,
+            is_primary: false,
+            kind,
+        };
+        setup
+            .managers
+            .create_index(info.clone(), TransactionId::new())
+            .unwrap();
+        catalog.add_index(info.clone()).unwrap();
+        info.c_id
+    }
+
+    fn comparison(op: BooleanOp, col: &str, value: i64) -> AstExpr {
+        AstExpr::Boolean(
+            op,
+            Box::new(AstExpr::Ident(col.to_string())),
+            Box::new(AstExpr::Literal(Field::Int(value))),
+        )
+    }
+
+    fn and(l: AstExpr, r: AstExpr) -> AstExpr {
+        AstExpr::Boolean(BooleanOp::And, Box::new(l), Box::new(r))
+    }
+
+    fn run(setup: &TestSetup, physical_plan: &PhysicalPlan) -> Vec<Tuple> {
+        let mut iter = physical_plan_to_op_iterator(
+            setup.managers,
+            setup.get_catalog(),
+            physical_plan,
+            TransactionId::new(),
+            0,
+        )
+        .unwrap();
+        iter.configure(false);
+        execute_iter(&mut *iter, true).unwrap()
+    }
+
+    #[test]
+    fn test_plan_index_scan() {
+        let setup = TestSetup::new_with_content();
+        let catalog = setup.get_catalog();
+        let table_id = catalog.get_table_id("table0");
+        add_index(&setup, "table0", "by_a", vec![0]);
+        let by_bc = add_index(&setup, "table0", "by_bc", vec![1, 2]);
+
+        // b = 2 and c >= 5 and d = 'G' answers the most with by_bc
+        let filter = and(
+            and(
+                comparison(BooleanOp::Eq, "table0.b", 2),
+                comparison(BooleanOp::Gte, "table0.c", 5),
+            ),
+            AstExpr::Boolean(
+                BooleanOp::Eq,
+                Box::new(AstExpr::Ident("table0.d".to_string())),
+                Box::new(AstExpr::Literal(Field::String("G".to_string()))),
+            ),
+        );
+        let mut logical_plan = LogicalPlan::new();
+        logical_plan.add_scan_node(table_id, Some(filter), None);
+        let physical_plan =
+            logical_plan_to_physical_plan(logical_plan, catalog, setup.managers.stats).unwrap();
+        match physical_plan.get_operator(physical_plan.root().unwrap()) {
+            Some(PhysicalOp::IndexScan(node)) => {
+                assert_eq!(by_bc, node.index_id);
+                assert_eq!(Some((vec![Field::Int(2), Field::Int(5)], true)), node.lower);
+                assert_eq!(Some((vec![Field::Int(2)], true)), node.upper);
+            }
+            op => panic!("Expected an index scan, got {:?}", op),
+        }
+        let tuples = run(&setup, &physical_plan);
+        assert_eq!(2, tuples.len());
+
+        // a filter on no key prefix keeps the sequential scan
+        let mut logical_plan = LogicalPlan::new();
+        logical_plan.add_scan_node(
+            table_id,
+            Some(comparison(BooleanOp::Eq, "table0.c", 3)),
+            None,
+        );
+        let physical_plan =
+            logical_plan_to_physical_plan(logical_plan, catalog, setup.managers.stats).unwrap();
+        assert!(matches!(
+            physical_plan.get_operator(physical_plan.root().unwrap()),
+            Some(PhysicalOp::Scan(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_index_join() {
+        let setup = TestSetup::new_with_content();
+        let catalog = setup.get_catalog();
+        let table0 = catalog.get_table_id("table0");
+        let table1 = catalog.get_table_id("table1");
+        let by_b = add_index(&setup, "table0", "by_b", vec![1]);
+
+        // table1.a = table0.b and table1.d = table0.d
+        let eqs = vec![
+            (
+                AstExpr::Ident("table1.a".to_string()),
+                AstExpr::Ident("table0.b".to_string()),
+            ),
+            (
+                AstExpr::Ident("table0.d".to_string()),
+                AstExpr::Ident("table1.d".to_string()),
+            ),
+        ];
+        let mut logical_plan = LogicalPlan::new();
+        let left = logical_plan.add_scan_node(table1, None, None);
+        let right = logical_plan.add_scan_node(table0, None, None);
+        logical_plan.add_join_node(eqs, None, left, right);
+        let physical_plan =
+            logical_plan_to_physical_plan(logical_plan, catalog, setup.managers.stats).unwrap();
+
+        assert_eq!(2, physical_plan.node_count());
+        let root = physical_plan.root().unwrap();
+        match physical_plan.get_operator(root) {
+            Some(PhysicalOp::IndexNestedLoopJoin(node)) => {
+                assert_eq!(by_b, node.index_id);
+                assert_eq!(table0, node.container_id);
+                assert!(node.filter.is_some());
+            }
+            op => panic!("Expected an index join, got {:?}", op),
+        }
+        assert_eq!(1, physical_plan.edges(root).count());
+        assert!(physical_plan.base_tables().contains(&table0));
+
+        // a = 1 of table1 joins one table0 record with b = 1, a = 2 joins all three with b = 2
+        let tuples = run(&setup, &physical_plan);
+        assert_eq!(4, tuples.len());
+        for t in &tuples {
+            assert_eq!(8, t.len());
+            assert_eq!(t.get_field(3), t.get_field(7));
+        }
+    }
+
+    #[test]
+    fn test_plan_persisted_hash_join() {
+        let setup = TestSetup::new_with_content();
+        let catalog = setup.get_catalog();
+        let table0 = catalog.get_table_id("table0");
+        let table1 = catalog.get_table_id("table1");
+        let plan = || {
+            // table0.b = table1.a, with no b+tree to look table0 up in
+            let eqs = vec![(
+                AstExpr::Ident("table0.b".to_string()),
+                AstExpr::Ident("table1.a".to_string()),
+            )];
+            let mut logical_plan = LogicalPlan::new();
+            let left = logical_plan.add_scan_node(table0, None, None);
+            let right = logical_plan.add_scan_node(table1, None, None);
+            logical_plan.add_join_node(eqs, None, left, right);
+            logical_plan_to_physical_plan(logical_plan, catalog, setup.managers.stats).unwrap()
+        };
+        let hash_table = |physical_plan: &PhysicalPlan| match physical_plan
+            .get_operator(physical_plan.root().unwrap())
+        {
+            Some(PhysicalOp::HashJoin(node)) => node.hash_table,
+            op => panic!("Expected a hash join, got {:?}", op),
+        };
+
+        // Without a hash index of table0 on b the join builds its hash table and planning leaves
+        // the catalog alone
+        let indexes = catalog.get_indexes().len();
+        let first = plan();
+        assert_eq!(None, hash_table(&first));
+        assert!(first.hash_tables().is_empty());
+        assert_eq!(indexes, catalog.get_indexes().len());
+        let tuples = run(&setup, &first);
+        assert!(!tuples.is_empty());
+        for t in &tuples {
+            assert_eq!(t.get_field(1), t.get_field(4));
+        }
+
+        // A hash index created on b is probed instead
+        let index_id = add_index_of_kind(&setup, "table0", "by_b", vec![1], IndexKind::Hash);
+        let second = plan();
+        assert_eq!(Some(index_id), hash_table(&second));
+        let fields = |tuples: Vec<Tuple>| {
+            let mut fields: Vec<Vec<Field>> = tuples.into_iter().map(|t| t.field_vals).collect();
+            fields.sort();
+            fields
+        };
+        assert_eq!(fields(tuples), fields(run(&setup, &second)));
+    }
+
+    #[test]
+    fn test_plan_merge_join() {
+        let setup = TestSetup::new_with_content();
+        let catalog = setup.get_catalog();
+        let table0 = catalog.get_table_id("table0");
+        let table1 = catalog.get_table_id("table1");
+        let by_bc = add_index(&setup, "table0", "by_bc", vec![1, 2]);
+        add_index(&setup, "table1", "by_cb", vec![2, 1]);
+        let by_bc1 = add_index(&setup, "table1", "by_bc1", vec![1, 2]);
+
+        // table0.c = table1.c and table1.b = table0.b, in the key order of by_bc and by_bc1
+        let eqs = vec![
+            (
+                AstExpr::Ident("table0.c".to_string()),
+                AstExpr::Ident("table1.c".to_string()),
+            ),
+            (
+                AstExpr::Ident("table1.b".to_string()),
+                AstExpr::Ident("table0.b".to_string()),
+            ),
+        ];
+        let mut logical_plan = LogicalPlan::new();
+        let left = logical_plan.add_scan_node(table0, None, None);
+        let right = logical_plan.add_scan_node(table1, None, None);
+        logical_plan.add_join_node(eqs, None, left, right);
+        let physical_plan =
+            logical_plan_to_physical_plan(logical_plan, catalog, setup.managers.stats).unwrap();
+
+        let root = physical_plan.root().unwrap();
+        match physical_plan.get_operator(root) {
+            Some(PhysicalOp::SortMergeJoin(node)) => {
+                assert!(node.left_sorted && node.right_sorted);
+                let names = |keys: &Vec<(AstExpr, bool)>| {
+                    keys.iter()
+                        .map(|(e, _)| match e {
+                            AstExpr::Ident(name) => name.clone(),
+                            e => panic!("Expected a column, got {:?}", e),
+                        })
+                        .collect::<Vec<_>>()
+                };
+                // The first child is the scan of table1
+                assert_eq!(vec!["table1.b", "table1.c"], names(&node.left_expr));
+                assert_eq!(vec!["table0.b", "table0.c"], names(&node.right_expr));
+            }
+            op => panic!("Expected a merge join, got {:?}", op),
+        }
+        let index_ids: Vec<ContainerId> = physical_plan
+            .edges(root)
+            .map(|child| match physical_plan.get_operator(child) {
+                Some(PhysicalOp::IndexScan(node)) => {
+                    assert!(node.lower.is_none() && node.upper.is_none());
+                    node.index_id
+                }
+                op => panic!("Expected an index scan, got {:?}", op),
+            })
+            .collect();
+        assert_eq!(vec![by_bc1, by_bc], index_ids);
+
+        // (b, c) pairs (1, 3) and (2, 5) appear twice and (1, 4) and (2, 4) once in each table
+        let tuples = run(&setup, &physical_plan);
+        assert_eq!(2 * 2 + 2 * 2 + 1 + 1, tuples.len());
+        for t in &tuples {
+            assert_eq!(t.get_field(1), t.get_field(5));
+            assert_eq!(t.get_field(2), t.get_field(6));
+        }
+    }
+
+    #[test]
+    fn test_plan_large_join() {
+        let setup = TestSetup::new_with_content();
+        let catalog = setup.get_catalog();
+        let table0 = catalog.get_table_id("table0");
+        let table1 = catalog.get_table_id("table1");
+        let plan = || {
+            // table0.b = table1.a with a filter on table0, so no persisted hash table is used
+            let eqs = vec![(
+                AstExpr::Ident("table0.b".to_string()),
+                AstExpr::Ident("table1.a".to_string()),
+            )];
+            let mut logical_plan = LogicalPlan::new();
+            let left = logical_plan.add_scan_node(
+                table0,
+                Some(comparison(BooleanOp::Gt, "table0.c", 0)),
+                None,
+            );
+            let right = logical_plan.add_scan_node(table1, None, None);
+            logical_plan.add_join_node(eqs, None, left, right);
+            logical_plan_to_physical_plan(logical_plan, catalog, setup.managers.stats).unwrap()
+        };
+
+        let small = plan();
+        assert!(matches!(
+            small.get_operator(small.root().unwrap()),
+            Some(PhysicalOp::HashJoin(_))
+        ));
+        let expected = run(&setup, &small).len();
+
+        // Records counted by the statistics but never stored make table0 look too large
+        let tuple = TestTuples::new("table0").tuples[0].clone();
+        let records = MEMORY_BUDGET / tuple.size() + 1;
+        for _ in 0..records {
+            setup
+                .managers
+                .stats
+                .new_record(&tuple, ValueId::new(table0))
+                .unwrap();
+        }
+        let large = plan();
+        match large.get_operator(large.root().unwrap()) {
+            Some(PhysicalOp::SortMergeJoin(node)) => {
+                assert!(!node.left_sorted && !node.right_sorted);
+            }
+            op => panic!("Expected a merge join, got {:?}", op),
+        }
+        // The merge join puts the tuple of table1 first
+        let tuples = run(&setup, &large);
+        assert_eq!(expected, tuples.len());
+        for t in &tuples {
+            assert_eq!(t.get_field(0), t.get_field(5));
+        }
+    }
+
+    #[test]
+    fn test_plan_composite_hash_join() {
+        let setup = TestSetup::new_with_content();
+        let catalog = setup.get_catalog();
+        let table0 = catalog.get_table_id("table0");
+        let table1 = catalog.get_table_id("table1");
+        let by_bd = add_index_of_kind(&setup, "table0", "by_bd", vec![1, 3], IndexKind::Hash);
+
+        // table0.b = table1.a and table1.d = table0.d and table0.c > table1.c
+        let eqs = vec![
+            (
+                AstExpr::Ident("table0.b".to_string()),
+                AstExpr::Ident("table1.a".to_string()),
+            ),
+            (
+                AstExpr::Ident("table1.d".to_string()),
+                AstExpr::Ident("table0.d".to_string()),
+            ),
+        ];
+        let filter = AstExpr::Boolean(
+            BooleanOp::Gt,
+            Box::new(AstExpr::Ident("table0.c".to_string())),
+            Box::new(AstExpr::Ident("table1.c".to_string())),
+        );
+        let mut logical_plan = LogicalPlan::new();
+        let left = logical_plan.add_scan_node(table1, None, None);
+        let right = logical_plan.add_scan_node(table0, None, None);
+        logical_plan.add_join_node(eqs, Some(filter), left, right);
+        let physical_plan =
+            logical_plan_to_physical_plan(logical_plan, catalog, setup.managers.stats).unwrap();
+
+        // The hash index of table0 is keyed on both of its columns
+        match physical_plan.get_operator(physical_plan.root().unwrap()) {
+            Some(PhysicalOp::HashJoin(node)) => {
+                assert_eq!(Some(by_bd), node.hash_table);
+                assert!(node.filter.is_some());
+            }
+            op => panic!("Expected a hash join, got {:?}", op),
+        }
+
+        // Records 4, 5 and 6 of table0 join record 2 of table1, record 1 fails the filter
+        let tuples = run(&setup, &physical_plan);
+        assert_eq!(3, tuples.len());
+        for t in &tuples {
+            assert_eq!(t.get_field(1), t.get_field(4));
+            assert_eq!(t.get_field(3), t.get_field(7));
+            assert!(t.get_field(2).unwrap() > t.get_field(6).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_plan_join_without_keys() {
+        let setup = TestSetup::new_with_content();
+        let catalog = setup.get_catalog();
+        let table0 = catalog.get_table_id("table0");
+        let table1 = catalog.get_table_id("table1");
+
+        // table0.a + 1 = table1.a compares no two columns, so it is checked by a nested loop
+        let eqs = vec![(
+            AstExpr::Math(
+                MathOp::Add,
+                Box::new(AstExpr::Ident("table0.a".to_string())),
+                Box::new(AstExpr::Literal(Field::Int(1))),
+            ),
+            AstExpr::Ident("table1.a".to_string()),
+        )];
+        let mut logical_plan = LogicalPlan::new();
+        let left = logical_plan.add_scan_node(table0, None, None);
+        let right = logical_plan.add_scan_node(table1, None, None);
+        logical_plan.add_join_node(eqs, None, left, right);
+        let physical_plan =
+            logical_plan_to_physical_plan(logical_plan, catalog, setup.managers.stats).unwrap();
+        assert!(matches!(
+            physical_plan.get_operator(physical_plan.root().unwrap()),
+            Some(PhysicalOp::NestedLoopJoin(_))
+        ));
+        assert_eq!(5, run(&setup, &physical_plan).len());
+
+        // A cross product keeps its filter
+        let filter = AstExpr::Boolean(
+            BooleanOp::Lt,
+            Box::new(AstExpr::Ident("table0.a".to_string())),
+            Box::new(AstExpr::Ident("table1.a".to_string())),
+        );
+        let mut logical_plan = LogicalPlan::new();
+        let left = logical_plan.add_scan_node(table0, None, None);
+        let right = logical_plan.add_scan_node(table1, None, None);
+        logical_plan.add_cross_product_node(Some(filter), left, right);
+        let physical_plan =
+            logical_plan_to_physical_plan(logical_plan, catalog, setup.managers.stats).unwrap();
+        assert_eq!(15, run(&setup, &physical_plan).len());
+    }
+
+    #[test]
+    fn test_plan_outer_join() {
+        let setup = TestSetup::new_with_content();
+        let catalog = setup.get_catalog();
+        let table0 = catalog.get_table_id("table0");
+        let table1 = catalog.get_table_id("table1");
+        let a = AstExpr::Ident("table0.a".to_string());
+        let b = AstExpr::Ident("table1.b".to_string());
+
+        // table0 joins table1 on table0.a = table1.b, with the hash table built from either
+        let cases = [
+            (JoinType::Left, 10, 4),
+            (JoinType::Right, 6, 0),
+            (JoinType::Full, 10, 4),
+        ];
+        for (join_type, expected, padded) in cases {
+            for eq in [(a.clone(), b.clone()), (b.clone(), a.clone())] {
+                let mut logical_plan = LogicalPlan::new();
+                let left = logical_plan.add_scan_node(table0, None, None);
+                let right = logical_plan.add_scan_node(table1, None, None);
+                let join = logical_plan.add_node(LogicalOp::Join(JoinNode {
+                    eqs: vec![eq],
+                    filter: None,
+                    join_type,
+                }));
+                // Right table is always the first child to add
+                logical_plan.add_edge(join, right);
+                logical_plan.add_edge(join, left);
+                let physical_plan =
+                    logical_plan_to_physical_plan(logical_plan, catalog, setup.managers.stats)
+                        .unwrap();
+
+                // A persisted hash table only finds the tuples with a match
+                match physical_plan.get_operator(physical_plan.root().unwrap()) {
+                    Some(PhysicalOp::HashJoin(node)) => assert!(node.hash_table.is_none()),
+                    op => panic!("Expected a hash join, got {:?}", op),
+                }
+                // Records 3 to 6 of table0 have no record of table1 with b equal to their a
+                let tuples = run(&setup, &physical_plan);
+                assert_eq!(expected, tuples.len());
+                let nulls = tuples
+                    .iter()
+                    .filter(|t| t.field_vals().any(|f| *f == Field::Null))
+                    .count();
+                assert_eq!(padded, nulls);
+            }
+        }
+    }
+}
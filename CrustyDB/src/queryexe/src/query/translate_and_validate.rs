@@ -1,7 +1,7 @@
 use common::ast_expr::AstExpr;
 use common::catalog::CatalogRef;
 use common::datatypes::{default_decimal_precision, default_decimal_scale};
-use common::operation::{AggOp, BooleanOp, MathOp};
+use common::operation::{AggOp, BooleanOp, JoinType, MathOp};
 use common::prelude::{ContainerId, Field};
 use common::{logical_plan::*, Attribute};
 use common::{CrustyError, DataType};
@@ -338,7 +338,9 @@ impl TranslateAndValidate {
                 Some(false) => false,
                 None => true,
             };
-            orderby_fields.push((expr, asc));
+            // Nulls sort as larger than any value unless the query says otherwise
+            let nulls_first = orderby.nulls_first.unwrap_or(!asc);
+            orderby_fields.push((expr, asc, nulls_first));
         }
         if !orderby_fields.is_empty() {
             // Add sort node to the top of the plan
@@ -539,8 +541,11 @@ impl TranslateAndValidate {
         left_node_idx: OpIndex,
     ) -> Result<OpIndex, CrustyError> {
         let right_node_idx = self.process_table_factor(&join.relation)?;
-        let jc = match &join.join_operator {
-            JoinOperator::Inner(jc) => jc,
+        let (jc, join_type) = match &join.join_operator {
+            JoinOperator::Inner(jc) => (jc, JoinType::Inner),
+            JoinOperator::LeftOuter(jc) => (jc, JoinType::Left),
+            JoinOperator::RightOuter(jc) => (jc, JoinType::Right),
+            JoinOperator::FullOuter(jc) => (jc, JoinType::Full),
             _ => {
                 return Err(CrustyError::CrustyError(
                     "Unsupported join type".to_string(),
@@ -550,22 +555,29 @@ impl TranslateAndValidate {
 
         if let JoinConstraint::On(expr) = jc {
             let ast_expr = self.expr_to_astexpr(expr)?;
-            let join_node = match &ast_expr {
-                AstExpr::Boolean(BooleanOp::Eq, l_expr, r_expr) => {
-                    // TODO: (jun) No need this match after apply the predicate pushdown
-                    LogicalOp::Join(JoinNode {
-                        eqs: vec![(*l_expr.clone(), *r_expr.clone())],
-                        filter: None,
-                    })
+            // Every equality in the top-level conjunction becomes a join key;
+            // the remaining conjuncts are evaluated as a residual filter.
+            let mut eqs = Vec::new();
+            let mut rest = Vec::new();
+            for conjunct in ast_expr.conjuncts() {
+                match conjunct {
+                    AstExpr::Boolean(BooleanOp::Eq, l_expr, r_expr) => {
+                        eqs.push((*l_expr.clone(), *r_expr.clone()))
+                    }
+                    other => rest.push(other.clone()),
                 }
-                _ => LogicalOp::Join(JoinNode {
-                    eqs: vec![],
-                    filter: Some(ast_expr),
-                }),
-            };
+            }
+            let filter = AstExpr::conjunction(rest);
+            let join_node = LogicalOp::Join(JoinNode {
+                eqs,
+                filter,
+                join_type,
+            });
             let idx = self.plan.add_node(join_node);
-            self.plan.add_edge(idx, left_node_idx);
+            // Right child is always the first edge to be added, so the edges of the join
+            // start with the left table.
             self.plan.add_edge(idx, right_node_idx);
+            self.plan.add_edge(idx, left_node_idx);
             Ok(idx)
         } else {
             Err(CrustyError::CrustyError(
@@ -138,6 +138,40 @@ impl StatManagerTrait for ReservoirStatManager {
         Ok((estimated_count.round() as usize, selectivity))
     }
 
+    /// Sums the sizes of the sampled tuples satisfying the predicate and scales the sum by the
+    /// total number of records in the container.
+    fn estimate_size(
+        &self,
+        c_id: ContainerId,
+        predicate: Option<AstExpr>,
+    ) -> Result<usize, CrustyError> {
+        let samples = self.samples.read().unwrap();
+        let container_samples = samples
+            .get(&c_id)
+            .ok_or(CrustyError::CrustyError("Container not found".to_string()))?;
+
+        if container_samples.samples.is_empty() {
+            return Ok(0);
+        }
+
+        let predicate = predicate
+            .map(|p| bind_expr(p, &container_samples.schema))
+            .transpose()?;
+        let mut matching_size = 0;
+        for tuple in &container_samples.samples {
+            let matches = match &predicate {
+                Some(p) => self.eval_astexpr(p, tuple)? == Field::Bool(true),
+                None => true,
+            };
+            if matches {
+                matching_size += tuple.size();
+            }
+        }
+
+        let scale = container_samples.record_count as f64 / container_samples.samples.len() as f64;
+        Ok((matching_size as f64 * scale).round() as usize)
+    }
+
     fn estimate_join_count_and_sel(
         &self,
         left_c_id: ContainerId,
@@ -96,9 +96,9 @@ pub fn new_test_managers() -> &'static Managers {
     let sm = StorageManager::new_test_sm();
     let storage_manager_box = Box::new(sm);
     let storage_manager = Box::leak(storage_manager_box);
-    let transaction_manager_box = Box::new(TransactionManager {});
+    let transaction_manager_box = Box::new(TransactionManager::new());
     let transaction_manager = Box::leak(transaction_manager_box);
-    let im = Box::new(IndexManager::new(storage_manager, transaction_manager));
+    let im = Box::new(IndexManager::new(storage_manager));
     let index_manager = Box::leak(im);
     let stats_box = Box::new(crate::stats::ReservoirStatManager::new(
         storage_manager.get_storage_path(),
@@ -131,13 +131,14 @@ impl TestSetup {
             let table = TableInfo::new(c_id, name.clone(), test_tuples.schema.clone());
             catalog.add_table(table.clone()).unwrap();
             managers.sm.create_table(c_id).unwrap();
+            managers.register_row_layout(c_id, &table.schema);
             managers
                 .stats
                 .register_container(c_id, table.schema)
                 .unwrap();
             let mut inserting_values = Vec::with_capacity(test_tuples.tuples.len());
             for tuple in &test_tuples.tuples {
-                inserting_values.push(tuple.to_bytes());
+                inserting_values.push(managers.encode_row(c_id, tuple).unwrap());
             }
             managers
                 .sm
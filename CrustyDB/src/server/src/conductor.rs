@@ -6,11 +6,13 @@ use crate::queryexe::query::TranslateAndValidate;
 use crate::sql_parser::{ParserResponse, SQLParser};
 use crate::Executor;
 
-use common::data_reader::CsvReader;
+use common::data_reader::{CsvReader, DataReader};
 use common::error::c_err;
 
 use common::logical_plan::LogicalPlan;
 use common::physical_plan::PhysicalPlan;
+use common::table::IndexKind;
+use common::traits::transaction_manager_trait::TransactionManagerTrait;
 
 use common::{CrustyError, QueryResult};
 use optimizer::optimizer::Optimizer;
@@ -18,7 +20,7 @@ use optimizer::optimizer::Optimizer;
 use queryexe::query::get_name;
 use queryexe::query::planner::{logical_plan_to_physical_plan, physical_plan_to_op_iterator};
 use queryexe::Managers;
-use sqlparser::ast::{SetExpr, Statement};
+use sqlparser::ast::{Expr, ObjectType, SetExpr, Statement};
 use std::fs::OpenOptions;
 
 use txn_manager::transactions::Transaction;
@@ -33,16 +35,23 @@ pub struct Conductor {
 
 impl Conductor {
     pub fn new(managers: &'static Managers) -> Result<Self, CrustyError> {
+        let active_txn = Transaction::new();
+        managers.tm.start_transaction(active_txn.tid()?)?;
+        Ok(Self::with_transaction(managers, active_txn))
+    }
+
+    /// A conductor continuing active_txn, which was started earlier, e.g. by an earlier command
+    /// of the same client.
+    pub fn with_transaction(managers: &'static Managers, active_txn: Transaction) -> Self {
         let parser = SQLParser::new();
         let optimizer = Optimizer::new();
         let executor = Executor::new_ref(managers);
-        let conductor = Conductor {
+        Conductor {
             parser,
             optimizer,
             executor,
-            active_txn: Transaction::new(),
-        };
-        Ok(conductor)
+            active_txn,
+        }
     }
 
     pub fn run_sql_from_string(
@@ -51,8 +60,35 @@ impl Conductor {
         db_state: &'static DatabaseState,
     ) -> Result<QueryResult, CrustyError> {
         debug!("Parsing SQL: {:?}", &sql);
+        if let Some(table) = SQLParser::parse_vacuum(&sql) {
+            if self.active_txn.in_block() {
+                return Err(c_err("VACUUM cannot run inside a transaction block"));
+            }
+            return self.run_in_txn(db_state, |conductor| {
+                db_state.vacuum(table.as_deref(), true, conductor.active_txn.tid()?)
+            });
+        }
         match SQLParser::parse_sql(sql) {
-            ParserResponse::SQL(ast) => self.run_sql(ast, db_state),
+            ParserResponse::SQL(ast) => {
+                if let Some(statement) = ast.first() {
+                    if let Some(result) = self.run_txn_control(statement, db_state)? {
+                        return Ok(result);
+                    }
+                    if self.active_txn.in_block()
+                        && matches!(
+                            statement,
+                            Statement::CreateTable { .. }
+                                | Statement::CreateIndex { .. }
+                                | Statement::Drop { .. }
+                        )
+                    {
+                        return Err(c_err(
+                            "CREATE and DROP cannot run inside a transaction block",
+                        ));
+                    }
+                }
+                self.run_in_txn(db_state, |conductor| conductor.run_sql(ast, db_state))
+            }
             ParserResponse::SQLError(e) => Err(c_err(format!("SQL error: {}", e).as_str())),
             ParserResponse::SQLConstraintError(msg) => {
                 Err(c_err(format!("SQL constraint error: {}", msg).as_str()))
@@ -90,7 +126,7 @@ impl Conductor {
         logical_plan: LogicalPlan,
         db_state: &'static DatabaseState,
     ) -> Result<PhysicalPlan, CrustyError> {
-        logical_plan_to_physical_plan(logical_plan, &db_state.catalog)
+        logical_plan_to_physical_plan(logical_plan, &db_state.catalog, db_state.managers.stats)
     }
 
     pub fn run_physical_plan(
@@ -140,12 +176,18 @@ impl Conductor {
                 ..
             } => {
                 debug!("Processing CREATE table: {:?}", table_name);
-                db_state.create_table(&get_name(table_name)?, columns, constraints)
+                db_state.create_table(
+                    &get_name(table_name)?,
+                    columns,
+                    constraints,
+                    self.active_txn.tid()?,
+                )
             }
             Statement::Query(qbox) => {
                 debug!("Processing SQL Query");
                 let lp = TranslateAndValidate::from_sql(qbox, &db_state.catalog)?;
-                let pp = logical_plan_to_physical_plan(lp, &db_state.catalog)?;
+                let pp =
+                    logical_plan_to_physical_plan(lp, &db_state.catalog, db_state.managers.stats)?;
                 self.run_physical_plan(pp, db_state)
             }
             Statement::Insert {
@@ -191,6 +233,67 @@ impl Conductor {
                     }
                 }
             }
+            Statement::CreateIndex {
+                name,
+                table_name,
+                using,
+                columns,
+                unique,
+                if_not_exists,
+                ..
+            } => {
+                let name = match name {
+                    Some(name) => get_name(name)?,
+                    None => return Err(c_err("Index name required")),
+                };
+                debug!("Processing CREATE index: {} on {}", name, table_name);
+                if *if_not_exists && db_state.catalog.get_index_by_name(&name).is_some() {
+                    return Ok(QueryResult::MessageOnly(format!(
+                        "Index {} already exists, skipping",
+                        name
+                    )));
+                }
+                let columns = columns
+                    .iter()
+                    .map(|c| match &c.expr {
+                        Expr::Identifier(ident) => Ok(ident.value.clone()),
+                        Expr::CompoundIdentifier(idents) => {
+                            Ok(idents.last().unwrap().value.clone())
+                        }
+                        _ => Err(c_err("Only columns can be indexed")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let kind = match using.as_ref().map(|m| m.value.to_lowercase()).as_deref() {
+                    None | Some("btree") => IndexKind::BTree,
+                    Some("hash") => IndexKind::Hash,
+                    Some(method) => {
+                        return Err(c_err(&format!("Unknown index method {}", method)));
+                    }
+                };
+                db_state.create_index(
+                    &name,
+                    &get_name(table_name)?,
+                    &columns,
+                    *unique,
+                    kind,
+                    self.active_txn.tid()?,
+                )
+            }
+            Statement::Drop {
+                object_type: ObjectType::Index,
+                if_exists,
+                names,
+                ..
+            } => {
+                let mut result = QueryResult::MessageOnly(String::new());
+                for name in names {
+                    result = db_state.drop_index(&get_name(name)?, *if_exists)?;
+                }
+                Ok(result)
+            }
+            Statement::Drop { object_type, .. } => Err(c_err(
+                format!("DROP {} not currently supported", object_type).as_str(),
+            )),
             _ => {
                 unimplemented!()
             }
@@ -207,14 +310,143 @@ impl Conductor {
         let table_schema = db_state.catalog.get_table_schema(table_id).unwrap();
         let file = OpenOptions::new().read(true).open(file_path).unwrap();
         let mut csv_reader = CsvReader::new(file, &table_schema, b',', false).unwrap();
-        let num_inserts = self
-            .executor
-            .import_records_from_reader(&mut csv_reader, &table_id, self.active_txn.tid()?)
-            .unwrap();
-        Ok(QueryResult::new_insert_result(
-            num_inserts,
-            table_name.to_string(),
-        ))
+        self.import_records(table_name, &mut csv_reader, db_state)
+    }
+
+    /// Insert the records of reader into a table in the active transaction. Outside of a
+    /// transaction block only the ids of the inserted records are kept for a rollback, so a
+    /// large import does not hold every record in memory.
+    pub fn import_records(
+        &mut self,
+        table_name: &str,
+        reader: &mut dyn DataReader,
+        db_state: &'static DatabaseState,
+    ) -> Result<QueryResult, CrustyError> {
+        let table_id = db_state.catalog.get_table_id(table_name);
+        self.run_in_txn(db_state, |conductor| {
+            let tid = conductor.active_txn.tid()?;
+            if !conductor.active_txn.in_block() {
+                db_state.managers.tm.log_insert_ids(tid)?;
+            }
+            let inserted = conductor
+                .executor
+                .import_records_from_reader(reader, &table_id, tid)?;
+            Ok(QueryResult::new_insert_result(
+                inserted,
+                table_name.to_string(),
+            ))
+        })
+    }
+
+    /// Run f in the active transaction. Outside of a transaction block the transaction commits
+    /// if f succeeds and rolls back if it fails. Inside one only the writes of a failed f are
+    /// undone, unless the transaction itself was aborted, e.g. to break a deadlock.
+    fn run_in_txn<F>(
+        &mut self,
+        db_state: &'static DatabaseState,
+        f: F,
+    ) -> Result<QueryResult, CrustyError>
+    where
+        F: FnOnce(&mut Self) -> Result<QueryResult, CrustyError>,
+    {
+        if !self.active_txn.in_block() {
+            let result = f(self);
+            self.end_txn(result.is_ok(), db_state)?;
+            return result;
+        }
+        let tid = self.active_txn.tid()?;
+        let savepoint = db_state.managers.tm.savepoint(tid)?;
+        let result = f(self);
+        match &result {
+            Err(CrustyError::TransactionRollback(_)) => self.end_txn(false, db_state)?,
+            Err(_) => db_state.managers.rollback_to_savepoint(tid, savepoint)?,
+            Ok(_) => {}
+        }
+        result
+    }
+
+    /// Run BEGIN, COMMIT, ROLLBACK and the savepoint statements. Returns None for any other
+    /// statement.
+    fn run_txn_control(
+        &mut self,
+        statement: &Statement,
+        db_state: &'static DatabaseState,
+    ) -> Result<Option<QueryResult>, CrustyError> {
+        let managers = db_state.managers;
+        let message = match statement {
+            Statement::StartTransaction { .. } => {
+                self.active_txn.begin_block()?;
+                "BEGIN"
+            }
+            Statement::Commit { .. } => {
+                self.check_in_block()?;
+                self.end_txn(true, db_state)?;
+                "COMMIT"
+            }
+            Statement::Rollback {
+                savepoint: None, ..
+            } => {
+                self.check_in_block()?;
+                self.end_txn(false, db_state)?;
+                "ROLLBACK"
+            }
+            Statement::Rollback {
+                savepoint: Some(name),
+                ..
+            } => {
+                self.check_in_block()?;
+                let savepoint = self.active_txn.pop_savepoint(&name.value, false)?;
+                managers.rollback_to_savepoint(self.active_txn.tid()?, savepoint)?;
+                "ROLLBACK"
+            }
+            Statement::Savepoint { name } => {
+                self.check_in_block()?;
+                let savepoint = managers.tm.savepoint(self.active_txn.tid()?)?;
+                self.active_txn.add_savepoint(&name.value, savepoint);
+                "SAVEPOINT"
+            }
+            Statement::ReleaseSavepoint { name } => {
+                self.check_in_block()?;
+                self.active_txn.pop_savepoint(&name.value, true)?;
+                "RELEASE"
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(QueryResult::MessageOnly(message.to_string())))
+    }
+
+    fn check_in_block(&self) -> Result<(), CrustyError> {
+        if self.active_txn.in_block() {
+            Ok(())
+        } else {
+            Err(c_err("No transaction in progress"))
+        }
+    }
+
+    /// Commit or roll back the active transaction, undoing its writes on rollback, and start the
+    /// next one. This also closes the transaction block if one is open.
+    fn end_txn(
+        &mut self,
+        commit: bool,
+        db_state: &'static DatabaseState,
+    ) -> Result<(), CrustyError> {
+        let tid = self.active_txn.tid()?;
+        // A commit that conflicts with another transaction rolls tid back instead
+        let ended = if commit {
+            db_state.managers.commit_txn(tid)
+        } else {
+            db_state.managers.rollback_txn(tid)
+        };
+        match ended {
+            Ok(()) if commit => self.active_txn.commit()?,
+            _ => self.active_txn.abort()?,
+        }
+        self.active_txn = Transaction::new();
+        db_state
+            .managers
+            .tm
+            .start_transaction(self.active_txn.tid()?)?;
+        ended
     }
 }
 
@@ -1,6 +1,11 @@
+use crate::database_state::DatabaseState;
 use crate::server_state::ServerState;
+use common::prelude::*;
+use common::storage_trait::StorageTrait;
+use common::traits::transaction_manager_trait::TransactionManagerTrait;
+use common::QueryResult;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 // <strip milestone="silent">
 //use common::PAGE_SLOTS;
@@ -8,18 +13,60 @@ use std::time::Duration;
 // const dirty_percent: f64 = 0.5;
 // </strip>
 
-#[allow(dead_code)]
+/// Background thread of the server. It periodically breaks lock deadlocks and, if vacuum_interval
+/// is set, vacuums every table of every database without moving records, which is safe while
+/// transactions run. If checkpoint_interval is set it also checkpoints the storage of every
+/// database, so the write-ahead log does not grow until shutdown.
 pub(crate) struct Daemon {
     _server_state: &'static ServerState,
     pub(crate) _thread: Option<thread::JoinHandle<()>>,
 }
 
-#[allow(dead_code)]
 impl Daemon {
-    pub(crate) fn new(server_state: &'static ServerState, sleep_sec: u64) -> Self {
+    pub(crate) fn new(
+        server_state: &'static ServerState,
+        sleep: Duration,
+        vacuum_interval: Option<Duration>,
+        checkpoint_interval: Option<Duration>,
+    ) -> Self {
+        let mut last_vacuum = Instant::now();
+        let mut last_checkpoint = Instant::now();
         // This should be async or moved into the workers
         let thread = std::thread::spawn(move || loop {
             debug!("Daemon doing stuff");
+            for managers in server_state.all_managers() {
+                match managers.tm.detect_deadlocks() {
+                    Ok(victims) if !victims.is_empty() => {
+                        info!("Aborted deadlocked transactions {:?}", victims)
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("Deadlock detection failed: {:?}", e),
+                }
+            }
+            if vacuum_interval.is_some_and(|interval| last_vacuum.elapsed() >= interval) {
+                last_vacuum = Instant::now();
+                let dbs: Vec<_> = server_state
+                    .id_to_db
+                    .read()
+                    .unwrap()
+                    .values()
+                    .copied()
+                    .collect();
+                for db in dbs {
+                    match vacuum_db(db) {
+                        Ok(result) => debug!("Vacuumed database {}: {:?}", db.name, result),
+                        Err(e) => error!("Vacuum of database {} failed: {:?}", db.name, e),
+                    }
+                }
+            }
+            if checkpoint_interval.is_some_and(|interval| last_checkpoint.elapsed() >= interval) {
+                last_checkpoint = Instant::now();
+                for managers in server_state.all_managers() {
+                    if let Err(e) = managers.sm.checkpoint() {
+                        error!("Checkpoint failed: {:?}", e);
+                    }
+                }
+            }
             // <strip milestone="silent">
 
             // Flush the dirty pages of storage manager of server state if percentage
@@ -30,7 +77,7 @@ impl Daemon {
             // }
             // </strip>
 
-            thread::sleep(Duration::new(sleep_sec, 0));
+            thread::sleep(sleep);
         });
 
         Daemon {
@@ -39,3 +86,22 @@ impl Daemon {
         }
     }
 }
+
+/// Vacuum every table of db in a transaction of its own, so the transaction manager knows of
+/// the vacuum's writes like those of any other transaction. The transaction is rolled back if
+/// the vacuum fails.
+fn vacuum_db(db: &DatabaseState) -> Result<QueryResult, CrustyError> {
+    let managers = db.managers;
+    let tid = TransactionId::new();
+    managers.tm.start_transaction(tid)?;
+    match db.vacuum(None, false, tid) {
+        Ok(result) => {
+            managers.commit_txn(tid)?;
+            Ok(result)
+        }
+        Err(e) => {
+            managers.rollback_txn(tid)?;
+            Err(e)
+        }
+    }
+}
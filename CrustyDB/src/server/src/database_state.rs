@@ -2,23 +2,25 @@ use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fs;
 use std::hash::{Hash, Hasher};
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 use crate::{StorageManager, StorageTrait};
 use common::catalog::{Catalog, CatalogRef};
 use common::ids::{AtomicTimeStamp, StateMeta};
 use common::physical_plan::PhysicalPlan;
 use common::prelude::*;
-use common::table::TableInfo;
+use common::table::{IndexInfo, TableInfo};
 use common::traits::stat_manager_trait::StatManagerTrait;
+use common::traits::transaction_manager_trait::TransactionManagerTrait;
 use common::{Attribute, QueryResult};
 use queryexe::query::get_attr;
 use queryexe::Managers;
 use sqlparser::ast::ColumnDef;
 use sqlparser::ast::TableConstraint;
 
-use crate::query_registrar::QueryRegistrar;
+use crate::query_registrar::{QueryRegistrar, RegisteredQuery};
 use crate::sql_parser::{ParserResponse, SQLParser};
 
 use std::sync::atomic::AtomicU32;
@@ -39,8 +41,31 @@ pub struct DatabaseState {
     // Time for operations based on timing (typically inserts)
     pub atomic_time: AtomicTimeStamp,
 
-    #[serde(skip_serializing)]
     query_registrar: QueryRegistrar,
+
+    /// File the database is saved to after every catalog change, if any
+    #[serde(skip_serializing)]
+    db_path: Option<PathBuf>,
+    #[serde(skip_serializing)]
+    save_lock: Mutex<()>,
+}
+
+/// The saved form of a DatabaseState, as written by its Serialize impl
+#[derive(Deserialize)]
+struct PersistedDatabase {
+    id: u64,
+    name: String,
+    catalog: Catalog,
+    container_vec: HashMap<ContainerId, StateMeta>,
+    atomic_time: LogicalTimeStamp,
+    query_registrar: Vec<RegisteredQuery>,
+}
+
+impl PersistedDatabase {
+    fn read(file: fs::File) -> Result<Self, CrustyError> {
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| CrustyError::SerializationError(format!("Unable to read database: {}", e)))
+    }
 }
 
 #[allow(dead_code)]
@@ -60,7 +85,10 @@ impl DatabaseState {
         if db_path.exists() {
             DatabaseState::load(db_path, managers)
         } else {
-            DatabaseState::new_from_name(db_name, managers)
+            let mut db_state = DatabaseState::new_from_name(db_name, managers)?;
+            db_state.db_path = Some(db_path);
+            db_state.persist()?;
+            Ok(db_state)
         }
     }
 
@@ -79,12 +107,68 @@ impl DatabaseState {
             container_vec: Arc::new(RwLock::new(HashMap::new())),
             atomic_time: AtomicU32::new(0),
             query_registrar: QueryRegistrar::new(),
+            db_path: None,
+            save_lock: Mutex::new(()),
         };
         Ok(db_state)
     }
 
-    pub fn load(_filename: PathBuf, _managers: &'static Managers) -> Result<Self, CrustyError> {
-        unimplemented!("Load the database from sm")
+    /// Load a database saved at filename. Later changes are saved back to the same file.
+    pub fn load(filename: PathBuf, managers: &'static Managers) -> Result<Self, CrustyError> {
+        debug!("Loading DatabaseState from {:?}", filename);
+        let persisted = PersistedDatabase::read(fs::File::open(&filename)?)?;
+        for table_id in persisted.catalog.get_table_ids() {
+            let schema = persisted.catalog.get_table_schema(table_id).unwrap();
+            managers.register_row_layout(table_id, &schema);
+            managers.stats.register_container(table_id, schema)?;
+        }
+        // Indexes are written apart from their tables, so an index may not match its table
+        // after a crash and is built again from the recovered table
+        let tid = TransactionId::new();
+        managers.tm.start_transaction(tid)?;
+        for index in persisted.catalog.get_indexes() {
+            if managers.sm.recovered() {
+                managers.rebuild_index(index, tid)?;
+            } else {
+                managers.im.open_index(&index, tid)?;
+            }
+        }
+        managers.commit_txn(tid)?;
+        Ok(DatabaseState {
+            id: persisted.id,
+            name: persisted.name,
+            catalog: Arc::new(persisted.catalog),
+            managers,
+            container_vec: Arc::new(RwLock::new(persisted.container_vec)),
+            atomic_time: AtomicU32::new(persisted.atomic_time),
+            query_registrar: QueryRegistrar::from_registered(persisted.query_registrar)?,
+            db_path: Some(filename),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    /// Save the database to path, replacing any previous save atomically.
+    pub fn save(&self, path: &Path) -> Result<(), CrustyError> {
+        let _guard = self.save_lock.lock()?;
+        let bytes =
+            serde_json::to_vec(self).map_err(|e| CrustyError::SerializationError(e.to_string()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_file_name(format!(".{}.tmp", self.name));
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Save the database to the file it was loaded from or created with, if any
+    pub fn persist(&self) -> Result<(), CrustyError> {
+        match &self.db_path {
+            Some(path) => self.save(path),
+            None => Ok(()),
+        }
     }
 
     pub fn get_current_time(&self) -> LogicalTimeStamp {
@@ -100,17 +184,18 @@ impl DatabaseState {
         self.query_registrar.get_registered_query_names()
     }
 
-    /// Load in database.
+    /// Load the catalog of a saved database. The storage manager reopens the containers of the
+    /// tables from its own manifest, so only the catalog is read here.
     ///
     /// # Arguments
     ///
-    /// * `db` - Name of database to load in.
-    /// * `id` - Thread id to get the lock.
+    /// * `file` - File the database was saved to.
     pub fn load_database_from_file(
-        _file: fs::File,
+        file: fs::File,
         _storage_manager: &StorageManager,
     ) -> Result<CatalogRef, CrustyError> {
-        unimplemented!("Reconstruct the catalog from sm")
+        let persisted = PersistedDatabase::read(file)?;
+        Ok(Arc::new(persisted.catalog))
     }
 
     /// Creates a new table.
@@ -119,11 +204,13 @@ impl DatabaseState {
     ///
     /// * `name` - Name of the new table.
     /// * `cols` - Table columns.
+    /// * `tid` - Transaction creating the table.
     pub fn create_table(
         &self,
         table_name: &str,
         columns: &[ColumnDef],
         constraints: &[TableConstraint],
+        tid: TransactionId,
     ) -> Result<QueryResult, CrustyError> {
         // Constraints aren't implemented yet
 
@@ -134,6 +221,25 @@ impl DatabaseState {
             _ => unreachable!(),
         };
 
+        // The primary key is enforced by a unique index on its columns, in key order
+        let mut pk_columns = Vec::new();
+        for pk in &pks {
+            let i = columns.iter().position(|c| c.name == *pk).ok_or_else(|| {
+                CrustyError::CrustyError(format!(
+                    "Primary key column {} not in table {}",
+                    pk, table_name
+                ))
+            })?;
+            pk_columns.push(i);
+        }
+        let pk_index_name = format!("{}_pkey", table_name);
+        if self.catalog.get_index_by_name(&pk_index_name).is_some() {
+            return Err(CrustyError::CrustyError(format!(
+                "Index {} already exists",
+                pk_index_name
+            )));
+        }
+
         let mut attributes: Vec<Attribute> = Vec::new();
         for col in columns {
             let constraint = if pks.contains(&col.name) {
@@ -166,13 +272,244 @@ impl DatabaseState {
                 table_name
             )));
         }
+        self.managers.register_row_layout(table_id, &schema);
+        let pk_index = IndexInfo {
+            c_id: self.catalog.get_new_container_id(),
+            name: pk_index_name,
+            table_id,
+            attributes: pk_columns
+                .iter()
+                .map(|i| schema.get_attribute(*i).unwrap().clone())
+                .collect(),
+            key_columns: pk_columns,
+            is_unique: true,
+            is_primary: true,
+            kind: IndexKind::BTree,
+        };
         self.managers.stats.register_container(table_id, schema)?;
+        // The table is empty, so building the index reads nothing
+        self.managers.create_index(pk_index.clone(), tid)?;
+        self.catalog.add_index(pk_index);
+        self.persist()?;
 
         let qr = QueryResult::MessageOnly(format!("Table {} created", table_name));
 
         Ok(qr)
     }
 
+    /// Creates an index and fills it with the rows already in the table.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_name` - Name of the new index.
+    /// * `table_name` - Table to index.
+    /// * `columns` - Key columns, in key order.
+    /// * `is_unique` - Whether the key must be unique.
+    /// * `tid` - Transaction reading the table.
+    pub fn create_index(
+        &self,
+        index_name: &str,
+        table_name: &str,
+        columns: &[String],
+        is_unique: bool,
+        kind: IndexKind,
+        tid: TransactionId,
+    ) -> Result<QueryResult, CrustyError> {
+        if self.catalog.get_index_by_name(index_name).is_some() {
+            return Err(CrustyError::CrustyError(format!(
+                "Index {} already exists",
+                index_name
+            )));
+        }
+        let table = self.catalog.get_table_by_name(table_name).ok_or_else(|| {
+            CrustyError::CrustyError(format!("Table {} does not exist", table_name))
+        })?;
+        if columns.is_empty() {
+            return Err(CrustyError::CrustyError(String::from(
+                "Index needs at least one column",
+            )));
+        }
+        let mut key_columns = Vec::new();
+        let mut attributes = Vec::new();
+        for col in columns {
+            let qualified = format!("{}.{}", table_name, col);
+            let i = table
+                .schema
+                .get_field_index(&qualified)
+                .or_else(|| table.schema.get_field_index(col))
+                .ok_or_else(|| {
+                    CrustyError::CrustyError(format!("Column {} not in table {}", col, table_name))
+                })?;
+            key_columns.push(i);
+            attributes.push(table.schema.get_attribute(i).unwrap().clone());
+        }
+        let index_info = IndexInfo {
+            c_id: self.catalog.get_new_container_id(),
+            name: index_name.to_string(),
+            table_id: table.c_id,
+            key_columns,
+            attributes,
+            is_unique,
+            is_primary: false,
+            kind,
+        };
+        self.managers.create_index(index_info.clone(), tid)?;
+        self.catalog.add_index(index_info);
+        self.persist()?;
+        Ok(QueryResult::MessageOnly(format!(
+            "Index {} created",
+            index_name
+        )))
+    }
+
+    /// Drops an index and removes its container.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_name` - Name of the index.
+    /// * `if_exists` - Do nothing instead of failing if there is no such index.
+    pub fn drop_index(
+        &self,
+        index_name: &str,
+        if_exists: bool,
+    ) -> Result<QueryResult, CrustyError> {
+        if let Some(info) = self.catalog.get_index_by_name(index_name) {
+            if info.is_primary {
+                return Err(CrustyError::CrustyError(format!(
+                    "Index {} enforces the primary key of its table",
+                    index_name
+                )));
+            }
+        }
+        let index_info = match self.catalog.remove_index(index_name) {
+            Some(info) => info,
+            None if if_exists => {
+                return Ok(QueryResult::MessageOnly(format!(
+                    "Index {} does not exist, skipping",
+                    index_name
+                )))
+            }
+            None => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Index {} does not exist",
+                    index_name
+                )))
+            }
+        };
+        self.managers.im.drop_index(index_info.c_id)?;
+        self.persist()?;
+        Ok(QueryResult::MessageOnly(format!(
+            "Index {} dropped",
+            index_name
+        )))
+    }
+
+    /// Describe the columns and indexes of a table.
+    pub fn describe_table(&self, table_name: &str) -> Result<String, CrustyError> {
+        let table = self.catalog.get_table_by_name(table_name).ok_or_else(|| {
+            CrustyError::CrustyError(format!("Table {} does not exist", table_name))
+        })?;
+        let mut res = format!("Table {}\n", table_name);
+        for attr in table.schema.attributes() {
+            res.push_str(&format!("  {}\t{}", attr.name(), attr.dtype()));
+            if attr.constraint != common::Constraint::None {
+                res.push_str(&format!("\t{:?}", attr.constraint));
+            }
+            res.push('\n');
+        }
+        let indexes = self.catalog.get_table_indexes(table.c_id);
+        if !indexes.is_empty() {
+            res.push_str("Indexes:\n");
+        }
+        for index in indexes {
+            let columns: Vec<&str> = index.attributes.iter().map(|a| a.name()).collect();
+            res.push_str(&format!(
+                "  {}\t{}{} ({})\n",
+                index.name,
+                if index.is_primary {
+                    "primary key "
+                } else if index.is_unique {
+                    "unique "
+                } else {
+                    ""
+                },
+                index.kind,
+                columns.join(", ")
+            ));
+        }
+        Ok(res)
+    }
+
+    /// Reclaim the space of a table, or of every table if table_name is None. Records only move
+    /// to fill sparse pages if move_records is set.
+    pub fn vacuum(
+        &self,
+        table_name: Option<&str>,
+        move_records: bool,
+        tid: TransactionId,
+    ) -> Result<QueryResult, CrustyError> {
+        let table_ids = match table_name {
+            Some(name) => vec![
+                self.catalog
+                    .get_table_by_name(name)
+                    .ok_or_else(|| {
+                        CrustyError::CrustyError(format!("Table {} does not exist", name))
+                    })?
+                    .c_id,
+            ],
+            None => self.catalog.get_table_ids(),
+        };
+        let (mut removed, mut moved) = (0, 0);
+        for table_id in table_ids {
+            let (table_removed, table_moved) = self.managers.vacuum(table_id, move_records, tid)?;
+            removed += table_removed;
+            moved += table_moved;
+        }
+        Ok(QueryResult::MessageOnly(format!(
+            "VACUUM removed {} dead versions and moved {} records",
+            removed, moved
+        )))
+    }
+
+    /// Check the stored pages of a table and its indexes, or of every table if table_name is
+    /// None. Returns a summary followed by a line per problem found.
+    pub fn check_storage(&self, table_name: Option<&str>) -> Result<String, CrustyError> {
+        let mut table_ids = match table_name {
+            Some(name) => vec![
+                self.catalog
+                    .get_table_by_name(name)
+                    .ok_or_else(|| {
+                        CrustyError::CrustyError(format!("Table {} does not exist", name))
+                    })?
+                    .c_id,
+            ],
+            None => self.catalog.get_table_ids(),
+        };
+        table_ids.sort_unstable();
+        let mut containers = 0;
+        let mut problems = Vec::new();
+        for table_id in table_ids {
+            let index_ids = self
+                .catalog
+                .get_table_indexes(table_id)
+                .into_iter()
+                .map(|index| index.c_id);
+            for c_id in std::iter::once(table_id).chain(index_ids) {
+                containers += 1;
+                problems.extend(self.managers.sm.check(c_id)?);
+            }
+        }
+        let mut res = format!(
+            "Checked {} containers, found {} problems",
+            containers,
+            problems.len()
+        );
+        for problem in problems {
+            res.push_str(&format!("\n  {}", problem));
+        }
+        Ok(res)
+    }
+
     pub fn reset(&self) -> Result<(), CrustyError> {
         self.query_registrar.reset()?;
         let mut containers = self.container_vec.write().unwrap();
@@ -194,7 +531,8 @@ impl DatabaseState {
         query_plan: Arc<PhysicalPlan>,
     ) -> Result<(), CrustyError> {
         self.query_registrar
-            .register_query(query_name, json_path, query_plan)
+            .register_query(query_name, json_path, query_plan)?;
+        self.persist()
     }
 
     /// Update metadata for beginning to run a registered query.
@@ -220,6 +558,7 @@ impl DatabaseState {
     ///
     /// * `query_name` - Name of the query.
     pub fn finish_query(&self, query_name: &str) -> Result<(), CrustyError> {
-        self.query_registrar.finish_query(query_name)
+        self.query_registrar.finish_query(query_name)?;
+        self.persist()
     }
 }
@@ -27,7 +27,7 @@ pub fn handle_command(
         ),
         Command::DB(database_command) => {
             if let Ok(db) = server_state.get_connected_db(client_id) {
-                handle_database_command(db, database_command)
+                handle_database_command(db, database_command, server_state, client_id)
             } else {
                 error!("Client {} is not connected to a database", client_id);
                 (
@@ -91,8 +91,8 @@ fn run_system_command(
         SystemCommand::Test => {
             unimplemented!()
         }
-        SystemCommand::Create(db_name) => {
-            server_state.create_new_db(&db_name)?;
+        SystemCommand::Create(db_name, page_size) => {
+            server_state.create_new_db(&db_name, page_size)?;
             let response = Response::SystemMsg(format!("Created database {}", db_name));
             Ok((false, response))
         }
@@ -112,31 +112,59 @@ fn run_system_command(
 pub fn handle_database_command(
     db: &'static DatabaseState,
     database_command: DBCommand,
+    server_state: &'static ServerState,
+    client_id: u64,
 ) -> (bool, Response) {
-    match run_database_command(db, database_command.clone()) {
+    match run_database_command(db, database_command.clone(), server_state, client_id) {
         Ok(response) => response,
         Err(e) => (false, Response::QueryExecutionError(e.to_string())),
     }
 }
 
+/// The conductor for a command of a client, continuing the transaction the client has open.
+/// The transaction must be handed back with `ServerState::keep_transaction` afterwards.
+fn client_conductor(
+    db: &'static DatabaseState,
+    server_state: &'static ServerState,
+    client_id: u64,
+) -> Result<Conductor, CrustyError> {
+    match server_state.take_transaction(client_id) {
+        Some(txn) => Ok(Conductor::with_transaction(db.managers, txn)),
+        None => Conductor::new(db.managers),
+    }
+}
+
 pub fn run_database_command(
     db: &'static DatabaseState,
     database_command: DBCommand,
+    server_state: &'static ServerState,
+    client_id: u64,
 ) -> Result<(bool, Response), CrustyError> {
     match database_command {
         DBCommand::ExecuteSQL(sql) => {
-            let mut conductor = Conductor::new(db.managers)?;
-            let qr = conductor.run_sql_from_string(sql, db)?;
-            Ok((false, Response::QueryResult(qr)))
+            let mut conductor = client_conductor(db, server_state, client_id)?;
+            let qr = conductor.run_sql_from_string(sql, db);
+            server_state.keep_transaction(client_id, conductor.active_txn);
+            Ok((false, Response::QueryResult(qr?)))
         }
         DBCommand::ShowTables => {
             let tables = db.get_table_names()?;
             let result = QueryResult::MessageOnly(format!("Tables: {}", tables.join(", ")));
             Ok((false, Response::QueryResult(result)))
         }
+        DBCommand::Describe(table_name) => {
+            let result = QueryResult::MessageOnly(db.describe_table(&table_name)?);
+            Ok((false, Response::QueryResult(result)))
+        }
+        DBCommand::Check(table_name) => {
+            let result = QueryResult::MessageOnly(db.check_storage(table_name.as_deref())?);
+            Ok((false, Response::QueryResult(result)))
+        }
         DBCommand::Import(table_name, file_path) => {
-            let mut conductor = Conductor::new(db.managers)?;
-            let qr = conductor.import_csv(&table_name, file_path, db)?;
+            let mut conductor = client_conductor(db, server_state, client_id)?;
+            let qr = conductor.import_csv(&table_name, file_path, db);
+            server_state.keep_transaction(client_id, conductor.active_txn);
+            let qr = qr?;
             if let QueryResult::Insert {
                 inserted,
                 table_name,
@@ -19,4 +19,4 @@ pub use queryexe::query::Executor;
 pub use queryexe::stats::ReservoirStatManager as StatManager;
 pub use server::{QueryEngine, Server, ServerConfig};
 pub use storage::{StorageManager, STORAGE_DIR};
-pub use txn_manager::mock_tm::MockTransactionManager as TransactionManager;
+pub use txn_manager::TransactionManager;
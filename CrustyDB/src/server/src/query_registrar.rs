@@ -4,6 +4,16 @@ use std::sync::{Arc, RwLock};
 use common::ids::LogicalTimeStamp;
 use common::physical_plan::PhysicalPlan;
 use common::CrustyError;
+use serde::{Serialize, Serializer};
+
+/// A registered query as persisted with its database. Plans are stored in their json form.
+#[derive(Serialize, Deserialize)]
+pub struct RegisteredQuery {
+    name: String,
+    json_path: String,
+    plan: String,
+    watermark: LogicalTimeStamp,
+}
 
 pub struct QueryRegistrar {
     query_plans: Arc<RwLock<HashMap<String, Arc<PhysicalPlan>>>>,
@@ -22,6 +32,38 @@ impl QueryRegistrar {
         }
     }
 
+    /// Rebuild a registrar from persisted queries. Queries that were in progress when the
+    /// queries were saved are not restored.
+    pub fn from_registered(queries: Vec<RegisteredQuery>) -> Result<Self, CrustyError> {
+        let registrar = QueryRegistrar::new();
+        for query in queries {
+            let plan = Arc::new(PhysicalPlan::from_json(&query.plan)?);
+            registrar.register_query(query.name.clone(), query.json_path, plan)?;
+            registrar
+                .query_watermarks
+                .write()
+                .unwrap()
+                .insert(query.name, query.watermark);
+        }
+        Ok(registrar)
+    }
+
+    /// The registered queries with their plans and watermarks
+    pub fn registered_queries(&self) -> Vec<RegisteredQuery> {
+        let plans = self.query_plans.read().unwrap();
+        let files = self.query_filenames.read().unwrap();
+        let watermarks = self.query_watermarks.read().unwrap();
+        plans
+            .iter()
+            .map(|(name, plan)| RegisteredQuery {
+                name: name.clone(),
+                json_path: files.get(name).cloned().unwrap_or_default(),
+                plan: plan.to_json().to_string(),
+                watermark: watermarks.get(name).copied().unwrap_or(0),
+            })
+            .collect()
+    }
+
     pub fn reset(&self) -> Result<(), CrustyError> {
         let mut in_prog = self.in_progress_queries.write().unwrap();
         if !in_prog.is_empty() {
@@ -158,3 +200,13 @@ impl QueryRegistrar {
         }
     }
 }
+
+impl Serialize for QueryRegistrar {
+    /// Serialized as the list of registered queries
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.registered_queries().serialize(serializer)
+    }
+}
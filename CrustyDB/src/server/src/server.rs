@@ -1,19 +1,19 @@
 use crate::conductor::Conductor;
+use crate::daemon::Daemon;
 use crate::database_state::DatabaseState;
 use crate::handler::handle_command;
-use crate::server_state::ServerState;
+use crate::server_state::{ManagerSettings, ServerState};
 use crate::StatManager;
 use clap::Parser;
 use common::catalog::CatalogRef;
 use common::commands::Command;
 use common::commands::Response;
-use common::data_reader::{CsvReader, DataReader};
+use common::data_reader::CsvReader;
 use common::logical_plan::LogicalPlan;
 use common::physical_plan::PhysicalPlan;
-use common::prelude::TransactionId;
 use common::storage_trait::StorageTrait;
 use common::traits::stat_manager_trait::StatManagerTrait;
-use common::{CrustyError, QueryResult};
+use common::{CrustyError, QueryResult, PAGE_SIZE};
 use env_logger::Env;
 use index::IndexManager;
 use queryexe::opiterator::OpIterator;
@@ -29,14 +29,18 @@ use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
 use std::thread;
-use txn_manager::mock_tm::MockTransactionManager as TransactionManager;
+use std::time::Duration;
+use txn_manager::TransactionManager;
 
 const MAX_STAT_BUDGET_MB: usize = 100;
 
-fn create_storage_manager(storage_dir: &Path) -> &'static StorageManager {
-    let storage_manager = Box::new(StorageManager::new(storage_dir));
+fn create_storage_manager(
+    storage_dir: &Path,
+    page_size: Option<usize>,
+) -> Result<&'static StorageManager, CrustyError> {
+    let storage_manager = Box::new(StorageManager::open_with_page_size(storage_dir, page_size)?);
     let storage_manager: &'static StorageManager = Box::leak(storage_manager);
-    storage_manager
+    Ok(storage_manager)
 }
 
 fn create_transaction_manager() -> &'static TransactionManager {
@@ -45,11 +49,8 @@ fn create_transaction_manager() -> &'static TransactionManager {
     transaction_manager
 }
 
-fn create_index_manager(
-    sm: &'static StorageManager,
-    tm: &'static TransactionManager,
-) -> &'static IndexManager {
-    let index_manager = Box::new(IndexManager::new(sm, tm));
+fn create_index_manager(sm: &'static StorageManager) -> &'static IndexManager {
+    let index_manager = Box::new(IndexManager::new(sm));
     let index_manager: &'static IndexManager = Box::leak(index_manager);
     index_manager
 }
@@ -60,19 +61,23 @@ fn create_stat_manager(sm: &'static StorageManager) -> &'static StatManager {
     stat_manager
 }
 
-fn create_managers(storage_dir: &Path) -> &'static Managers {
-    let sm = create_storage_manager(storage_dir);
+/// Open the managers of a database stored in storage_dir, see
+/// StorageTrait::open_with_page_size for page_size.
+pub(crate) fn create_managers(
+    storage_dir: &Path,
+    page_size: Option<usize>,
+) -> Result<&'static Managers, CrustyError> {
+    let sm = create_storage_manager(storage_dir, page_size)?;
     let tm = create_transaction_manager();
-    let im = create_index_manager(sm, tm);
+    let im = create_index_manager(sm);
     let stm = create_stat_manager(sm);
     let managers = Box::new(Managers::new(sm, tm, im, stm));
     let managers: &'static Managers = Box::leak(managers);
-    managers
+    Ok(managers)
 }
 
-fn create_server_state(base_dir: PathBuf) -> &'static ServerState {
-    let managers = create_managers(&base_dir);
-    let server_state = Box::new(ServerState::new(&base_dir, managers).unwrap());
+fn create_server_state(base_dir: PathBuf, settings: ManagerSettings) -> &'static ServerState {
+    let server_state = Box::new(ServerState::new(&base_dir, settings).unwrap());
     let server_state: &'static ServerState = Box::leak(server_state);
     server_state
 }
@@ -98,6 +103,27 @@ pub struct ServerConfig {
     /// Log level
     #[clap(short = 'v', long = "log_level", default_value = "warning")]
     log_level: String,
+    /// Milliseconds a transaction waits for a lock before it is rolled back, 0 to wait forever
+    #[clap(long = "lock_timeout_ms", default_value = "10000")]
+    lock_timeout_ms: u64,
+    /// Milliseconds between two deadlock detection runs
+    #[clap(long = "deadlock_interval_ms", default_value = "100")]
+    deadlock_interval_ms: u64,
+    /// Roll back transactions whose scans missed records inserted by concurrent transactions
+    #[clap(long = "serializable")]
+    serializable: bool,
+    /// Milliseconds between two background vacuums of every table, 0 to never vacuum in the
+    /// background. These vacuums do not move records.
+    #[clap(long = "vacuum_interval_ms", default_value = "0")]
+    vacuum_interval_ms: u64,
+    /// Milliseconds between two checkpoints of the storage of every database, which cut the
+    /// write-ahead log short, 0 to only checkpoint at shutdown
+    #[clap(long = "checkpoint_interval_ms", default_value = "30000")]
+    checkpoint_interval_ms: u64,
+    /// Bytes in every page of a database created without a page size, a power of two from 4096
+    /// to 65536. An existing database keeps the page size it was created with.
+    #[clap(long = "page_size", default_value = "4096")]
+    page_size: usize,
 }
 
 impl Default for ServerConfig {
@@ -108,6 +134,12 @@ impl Default for ServerConfig {
             db_path: "crusty_data/persist/default/".to_owned(),
             log_file: "".to_owned(),
             log_level: "warning".to_owned(),
+            lock_timeout_ms: 10000,
+            deadlock_interval_ms: 100,
+            serializable: false,
+            vacuum_interval_ms: 0,
+            checkpoint_interval_ms: 30000,
+            page_size: PAGE_SIZE,
         }
     }
 }
@@ -132,18 +164,26 @@ pub struct Server {
     config: ServerConfig,
     server_state: &'static ServerState,
     thread_handles: Vec<thread::JoinHandle<()>>,
+    daemon: Option<Daemon>,
 }
 
 impl Server {
     pub fn new(config: ServerConfig) -> Self {
         let base_dir = Path::new(&config.db_path).to_path_buf();
-        let server_state = create_server_state(base_dir);
+        let settings = ManagerSettings {
+            page_size: config.page_size,
+            lock_timeout: (config.lock_timeout_ms > 0)
+                .then(|| Duration::from_millis(config.lock_timeout_ms)),
+            serializable: config.serializable,
+        };
+        let server_state = create_server_state(base_dir, settings);
         Server {
             cliend_id: AtomicU64::new(1), // 0 is reserved.
             shutdown_signal: Arc::new(AtomicBool::new(false)),
             config,
             server_state,
             thread_handles: vec![],
+            daemon: None,
         }
     }
 
@@ -166,6 +206,15 @@ impl Server {
             .try_init();
         }
 
+        self.daemon = Some(Daemon::new(
+            self.server_state,
+            Duration::from_millis(self.config.deadlock_interval_ms),
+            (self.config.vacuum_interval_ms > 0)
+                .then(|| Duration::from_millis(self.config.vacuum_interval_ms)),
+            (self.config.checkpoint_interval_ms > 0)
+                .then(|| Duration::from_millis(self.config.checkpoint_interval_ms)),
+        ));
+
         //Start listening to requests by spawning a handler per request.
         let mut bind_addr = self.config.host.clone();
         bind_addr.push(':');
@@ -255,6 +304,13 @@ pub fn handle_client_request(
         }
     }
 
+    // Whatever the client left uncommitted is rolled back
+    if let Err(e) = server_state.end_transaction(client_id) {
+        error!(
+            "Failed to roll back transaction of client {}: {:?}",
+            client_id, e
+        );
+    }
     info!("Closing connection with client {}", client_id);
     // finally close the stream
     stream.shutdown(Shutdown::Both).unwrap();
@@ -331,7 +387,7 @@ impl QueryEngine {
             fs::create_dir_all(base_dir).unwrap();
         }
         let storage_dir = base_dir.join(STORAGE_DIR);
-        let managers = create_managers(&storage_dir);
+        let managers = create_managers(&storage_dir, None).unwrap();
         let database_state =
             Box::new(DatabaseState::create_db(base_dir, "db_name", managers).unwrap());
         let database_state: &'static DatabaseState = Box::leak(database_state);
@@ -345,11 +401,10 @@ impl QueryEngine {
 
     pub fn persist(&mut self) {
         let file_path = self.base_dir_path_name.join("db_name");
-        serde_json::to_writer(
-            fs::File::create(file_path).expect("error creating file"),
-            &self.database_state.catalog,
-        )
-        .expect("error serializing db");
+        self.database_state
+            .save(&file_path)
+            .expect("error serializing db");
+        self.database_state.managers.sm.shutdown();
     }
 
     pub fn get_base_dir_path(&self) -> &Path {
@@ -414,11 +469,15 @@ impl QueryEngine {
             .get_table_schema(table_id)
             .unwrap();
         let mut csv_reader = CsvReader::new(reader, &table_schema, delimiter, has_header)?;
-        self.conductor.executor.import_records_from_reader(
-            &mut csv_reader as &mut dyn DataReader,
-            &table_id,
-            TransactionId::new(),
-        )
+        match self
+            .conductor
+            .import_records(table_name, &mut csv_reader, self.database_state)?
+        {
+            QueryResult::Insert { inserted, .. } => Ok(inserted),
+            _ => Err(CrustyError::CrustyError(
+                "Unexpected query result from import".to_string(),
+            )),
+        }
     }
 }
 
@@ -449,6 +508,7 @@ mod test {
 
     mod query_engine {
         use super::*;
+        use common::table::IndexKind;
 
         #[test]
         fn test_run_sql() {
@@ -494,5 +554,512 @@ mod test {
             };
             assert_eq!(t.len(), 5);
         }
+
+        #[test]
+        fn test_primary_key() {
+            let base_dir = tempfile::tempdir().unwrap().into_path();
+            let mut query_engine = QueryEngine::new(&base_dir);
+            let sql = "CREATE TABLE foo (id INT PRIMARY KEY, name VARCHAR(10));";
+            query_engine.run_sql(sql).unwrap();
+            let rows = |query_engine: &mut QueryEngine| match query_engine
+                .run_sql("SELECT * FROM foo;")
+                .unwrap()
+            {
+                QueryResult::Select { result, .. } => {
+                    let mut ids: Vec<_> = result.iter().map(|t| t.get_field(0).cloned()).collect();
+                    ids.sort();
+                    ids
+                }
+                _ => panic!("Expected select result"),
+            };
+            let ids = |ids: &[i64]| -> Vec<_> {
+                ids.iter().map(|i| Some(common::Field::Int(*i))).collect()
+            };
+
+            // Duplicates within a statement, against stored keys, and null keys
+            assert!(query_engine
+                .run_sql("INSERT INTO foo VALUES (1, 'a'), (1, 'b');")
+                .is_err());
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (1, 'a'), (2, 'b');")
+                .unwrap();
+            assert!(query_engine
+                .run_sql("INSERT INTO foo VALUES (2, 'c');")
+                .is_err());
+            assert!(query_engine
+                .run_sql("INSERT INTO foo VALUES (NULL, 'c');")
+                .is_err());
+            assert_eq!(ids(&[1, 2]), rows(&mut query_engine));
+
+            // A CSV import is checked like an insert
+            let csv = std::io::Cursor::new("4,d\n5,e\n4,f\n");
+            assert!(query_engine.import_csv(csv, b',', false, "foo").is_err());
+            let csv = std::io::Cursor::new("2,d\n");
+            assert!(query_engine.import_csv(csv, b',', false, "foo").is_err());
+            let csv = std::io::Cursor::new("4,d\n5,e\n");
+            query_engine.import_csv(csv, b',', false, "foo").unwrap();
+            assert_eq!(ids(&[1, 2, 4, 5]), rows(&mut query_engine));
+            fs::remove_dir_all(base_dir).unwrap();
+        }
+
+        #[test]
+        fn test_reload_catalog() {
+            let base_dir = tempfile::tempdir().unwrap().into_path();
+            let mut query_engine = QueryEngine::new(&base_dir);
+            let sql = "CREATE TABLE foo (id INT PRIMARY KEY, name VARCHAR(10));";
+            query_engine.run_sql(sql).unwrap();
+            let sql = "INSERT INTO foo VALUES (1, 'a'), (2, 'b'), (3, 'c');";
+            query_engine.run_sql(sql).unwrap();
+            let table_id = query_engine.get_table_id("foo");
+            query_engine.persist();
+            drop(query_engine);
+
+            let mut query_engine = QueryEngine::new(&base_dir);
+            let catalog = query_engine.get_catalog();
+            assert_eq!(vec!["foo".to_string()], catalog.get_table_names());
+            let schema = catalog.get_table_schema(table_id).unwrap();
+            assert_eq!(
+                vec![common::Constraint::PrimaryKey, common::Constraint::None],
+                schema
+                    .attributes
+                    .iter()
+                    .map(|a| a.constraint.clone())
+                    .collect::<Vec<_>>()
+            );
+            // The id generator continues after the saved tables
+            assert_eq!(table_id, query_engine.get_table_id("foo"));
+            assert_ne!(table_id, query_engine.get_table_id("bar"));
+            let result = query_engine.run_sql("SELECT * FROM foo;").unwrap();
+            let t = if let QueryResult::Select { result, .. } = result {
+                result
+            } else {
+                panic!("Expected select result");
+            };
+            assert_eq!(t.len(), 3);
+            fs::remove_dir_all(base_dir).unwrap();
+        }
+
+        #[test]
+        fn test_recover_uncommitted_block() {
+            let base_dir = tempfile::tempdir().unwrap().into_path();
+            let mut query_engine = QueryEngine::new(&base_dir);
+            let sql = "CREATE TABLE foo (id INT PRIMARY KEY, name VARCHAR(10));";
+            query_engine.run_sql(sql).unwrap();
+            let sql = "INSERT INTO foo VALUES (1, 'a'), (2, 'b');";
+            query_engine.run_sql(sql).unwrap();
+            let file_path = base_dir.join("db_name");
+            query_engine.database_state.save(&file_path).unwrap();
+            query_engine.run_sql("BEGIN;").unwrap();
+            let sql = "INSERT INTO foo VALUES (3, 'c'), (4, 'd');";
+            query_engine.run_sql(sql).unwrap();
+            // Crash without committing the block or shutting down the storage manager
+            drop(query_engine);
+
+            let mut query_engine = QueryEngine::new(&base_dir);
+            let count = |query_engine: &mut QueryEngine| match query_engine
+                .run_sql("SELECT * FROM foo;")
+                .unwrap()
+            {
+                QueryResult::Select { result, .. } => result.len(),
+                _ => panic!("Expected select result"),
+            };
+            assert_eq!(2, count(&mut query_engine));
+            // The index entries of the block were undone with its records
+            assert!(query_engine
+                .run_sql("INSERT INTO foo VALUES (2, 'x');")
+                .is_err());
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (3, 'c');")
+                .unwrap();
+            assert_eq!(3, count(&mut query_engine));
+            fs::remove_dir_all(base_dir).unwrap();
+        }
+
+        #[test]
+        fn test_transaction_block() {
+            let base_dir = tempfile::tempdir().unwrap().into_path();
+            let mut query_engine = QueryEngine::new(&base_dir);
+            let count = |query_engine: &mut QueryEngine| match query_engine
+                .run_sql("SELECT * FROM foo;")
+                .unwrap()
+            {
+                QueryResult::Select { result, .. } => result.len(),
+                _ => panic!("Expected select result"),
+            };
+            query_engine
+                .run_sql("CREATE TABLE foo (id INT PRIMARY KEY, name VARCHAR(10));")
+                .unwrap();
+            query_engine
+                .run_sql("CREATE UNIQUE INDEX foo_id ON foo (id);")
+                .unwrap();
+            assert!(query_engine.run_sql("COMMIT;").is_err());
+
+            // A rolled back block leaves neither records nor index entries
+            query_engine.run_sql("BEGIN;").unwrap();
+            assert!(query_engine.run_sql("BEGIN;").is_err());
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (1, 'a'), (2, 'b');")
+                .unwrap();
+            assert_eq!(2, count(&mut query_engine));
+            assert!(query_engine
+                .run_sql("CREATE INDEX foo_name ON foo (name);")
+                .is_err());
+            query_engine.run_sql("ROLLBACK;").unwrap();
+            assert_eq!(0, count(&mut query_engine));
+
+            query_engine.run_sql("BEGIN;").unwrap();
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (1, 'a');")
+                .unwrap();
+            query_engine.run_sql("SAVEPOINT s1;").unwrap();
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (2, 'b');")
+                .unwrap();
+            query_engine.run_sql("SAVEPOINT s2;").unwrap();
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (3, 'c');")
+                .unwrap();
+            query_engine.run_sql("ROLLBACK TO SAVEPOINT s1;").unwrap();
+            // Later savepoints are gone, the one rolled back to stays
+            assert!(query_engine.run_sql("RELEASE SAVEPOINT s2;").is_err());
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (3, 'c');")
+                .unwrap();
+            // A failed statement does not end the block
+            assert!(query_engine
+                .run_sql("INSERT INTO foo VALUES (1, 'x');")
+                .is_err());
+            query_engine.run_sql("RELEASE SAVEPOINT s1;").unwrap();
+            query_engine.run_sql("COMMIT;").unwrap();
+            assert_eq!(2, count(&mut query_engine));
+            assert!(query_engine.run_sql("ROLLBACK;").is_err());
+
+            // Outside of a block every statement commits on its own
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (2, 'b');")
+                .unwrap();
+            assert!(query_engine
+                .run_sql("INSERT INTO foo VALUES (3, 'c');")
+                .is_err());
+            assert_eq!(3, count(&mut query_engine));
+            fs::remove_dir_all(base_dir).unwrap();
+        }
+
+        #[test]
+        fn test_create_and_drop_index() {
+            let base_dir = tempfile::tempdir().unwrap().into_path();
+            let mut query_engine = QueryEngine::new(&base_dir);
+            let sql = "CREATE TABLE foo (id INT PRIMARY KEY, name VARCHAR(10));";
+            query_engine.run_sql(sql).unwrap();
+            let sql = "INSERT INTO foo VALUES (1, 'a'), (2, 'b'), (3, 'b');";
+            query_engine.run_sql(sql).unwrap();
+            // Existing rows break the unique index
+            assert!(query_engine
+                .run_sql("CREATE UNIQUE INDEX foo_name ON foo (name);")
+                .is_err());
+            query_engine
+                .run_sql("CREATE UNIQUE INDEX foo_id ON foo (id);")
+                .unwrap();
+            query_engine
+                .run_sql("CREATE INDEX foo_name ON foo (name);")
+                .unwrap();
+            assert!(query_engine
+                .run_sql("CREATE INDEX foo_name ON foo (id);")
+                .is_err());
+            query_engine
+                .run_sql("CREATE INDEX IF NOT EXISTS foo_name ON foo (id);")
+                .unwrap();
+            // The unique index rejects duplicate inserts
+            assert!(query_engine
+                .run_sql("INSERT INTO foo VALUES (3, 'c');")
+                .is_err());
+            let description = query_engine.database_state.describe_table("foo").unwrap();
+            assert!(description.contains("foo_id\tunique btree (foo.id)"));
+            assert!(description.contains("foo_name\tbtree (foo.name)"));
+            assert!(description.contains("foo_pkey\tprimary key btree (foo.id)"));
+            let table_id = query_engine.get_table_id("foo");
+            query_engine.persist();
+            drop(query_engine);
+
+            let mut query_engine = QueryEngine::new(&base_dir);
+            let indexes = query_engine.get_catalog().get_table_indexes(table_id);
+            let mut names: Vec<&str> = indexes.iter().map(|i| i.name.as_str()).collect();
+            names.sort();
+            assert_eq!(vec!["foo_id", "foo_name", "foo_pkey"], names);
+            let im = query_engine.database_state.managers.im;
+            assert_eq!(3, im.table_indexes(table_id).len());
+            assert!(query_engine
+                .run_sql("INSERT INTO foo VALUES (2, 'c');")
+                .is_err());
+            query_engine.run_sql("DROP INDEX foo_id;").unwrap();
+            assert!(query_engine.run_sql("DROP INDEX foo_id;").is_err());
+            query_engine
+                .run_sql("DROP INDEX IF EXISTS foo_id;")
+                .unwrap();
+            assert_eq!(2, im.table_indexes(table_id).len());
+            // The primary key index stays and still rejects the duplicate
+            assert!(query_engine.run_sql("DROP INDEX foo_pkey;").is_err());
+            assert!(query_engine
+                .run_sql("INSERT INTO foo VALUES (2, 'c');")
+                .is_err());
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (4, 'c');")
+                .unwrap();
+            fs::remove_dir_all(base_dir).unwrap();
+        }
+
+        #[test]
+        fn test_vacuum() {
+            let base_dir = tempfile::tempdir().unwrap().into_path();
+            let mut query_engine = QueryEngine::new(&base_dir);
+            query_engine
+                .run_sql("CREATE TABLE foo (id INT PRIMARY KEY, name VARCHAR(10));")
+                .unwrap();
+            query_engine
+                .run_sql("CREATE INDEX foo_name ON foo (name);")
+                .unwrap();
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (1, 'a'), (2, 'b'), (3, 'b');")
+                .unwrap();
+            query_engine.run_sql("vacuum foo;").unwrap();
+            query_engine.run_sql("VACUUM;").unwrap();
+            assert!(query_engine.run_sql("VACUUM bar;").is_err());
+            query_engine.run_sql("BEGIN;").unwrap();
+            assert!(query_engine.run_sql("VACUUM foo;").is_err());
+            query_engine.run_sql("COMMIT;").unwrap();
+            match query_engine
+                .run_sql("SELECT * FROM foo WHERE name = 'b';")
+                .unwrap()
+            {
+                QueryResult::Select { result, .. } => assert_eq!(2, result.len()),
+                _ => panic!("Expected select result"),
+            }
+            fs::remove_dir_all(base_dir).unwrap();
+        }
+
+        #[test]
+        fn test_check_storage() {
+            let base_dir = tempfile::tempdir().unwrap().into_path();
+            let mut query_engine = QueryEngine::new(&base_dir);
+            query_engine
+                .run_sql("CREATE TABLE foo (id INT PRIMARY KEY, name VARCHAR(10));")
+                .unwrap();
+            query_engine
+                .run_sql("CREATE INDEX foo_name ON foo (name);")
+                .unwrap();
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (1, 'a'), (2, 'b'), (3, 'b');")
+                .unwrap();
+            let db = query_engine.database_state;
+            let report = db.check_storage(Some("foo")).unwrap();
+            assert!(report.ends_with("found 0 problems"), "{}", report);
+            assert!(db
+                .check_storage(None)
+                .unwrap()
+                .ends_with("found 0 problems"));
+            assert!(db.check_storage(Some("bar")).is_err());
+            fs::remove_dir_all(base_dir).unwrap();
+        }
+
+        #[test]
+        fn test_hash_index_and_join() {
+            let base_dir = tempfile::tempdir().unwrap().into_path();
+            let mut query_engine = QueryEngine::new(&base_dir);
+            // The join columns have no index, unlike the primary keys
+            let sql = "CREATE TABLE foo (id INT PRIMARY KEY, name VARCHAR(10), code INT);";
+            query_engine.run_sql(sql).unwrap();
+            let sql = "CREATE TABLE bar (id INT PRIMARY KEY, foo_code INT);";
+            query_engine.run_sql(sql).unwrap();
+            let sql = "INSERT INTO foo VALUES (1, 'a', 1), (2, 'b', 2), (3, 'b', 3);";
+            query_engine.run_sql(sql).unwrap();
+            let sql = "INSERT INTO bar VALUES (10, 1), (11, 2), (12, 2), (13, 4);";
+            query_engine.run_sql(sql).unwrap();
+            assert!(query_engine
+                .run_sql("CREATE INDEX foo_name ON foo USING gist (name);")
+                .is_err());
+            query_engine
+                .run_sql("CREATE INDEX foo_name ON foo USING hash (name);")
+                .unwrap();
+            let description = query_engine.database_state.describe_table("foo").unwrap();
+            assert!(description.contains("foo_name\thash (foo.name)"));
+
+            let rows = |query_engine: &mut QueryEngine, sql: &str| match query_engine
+                .run_sql(sql)
+                .unwrap()
+            {
+                QueryResult::Select { result, .. } => result.len(),
+                _ => panic!("Expected select result"),
+            };
+            assert_eq!(
+                2,
+                rows(&mut query_engine, "SELECT * FROM foo WHERE name = 'b';")
+            );
+            let join = "SELECT * FROM foo JOIN bar ON foo.code = bar.foo_code;";
+            assert_eq!(3, rows(&mut query_engine, join));
+            // Joins only probe hash indexes created on their keys and never add any themselves
+            let hash_indexes = |catalog: &CatalogRef| {
+                catalog
+                    .get_indexes()
+                    .into_iter()
+                    .filter(|i| i.kind == IndexKind::Hash)
+                    .count()
+            };
+            assert_eq!(1, hash_indexes(query_engine.get_catalog()));
+            query_engine
+                .run_sql("CREATE INDEX foo_code ON foo USING hash (code);")
+                .unwrap();
+            assert_eq!(3, rows(&mut query_engine, join));
+            query_engine
+                .run_sql("INSERT INTO bar VALUES (14, 3);")
+                .unwrap();
+            query_engine
+                .run_sql("INSERT INTO foo VALUES (4, 'c', 4);")
+                .unwrap();
+            assert_eq!(5, rows(&mut query_engine, join));
+            assert_eq!(2, hash_indexes(query_engine.get_catalog()));
+            query_engine.persist();
+            drop(query_engine);
+
+            let mut query_engine = QueryEngine::new(&base_dir);
+            assert_eq!(2, hash_indexes(query_engine.get_catalog()));
+            assert_eq!(5, rows(&mut query_engine, join));
+            fs::remove_dir_all(base_dir).unwrap();
+        }
+
+        #[test]
+        fn test_order_by() {
+            let base_dir = tempfile::tempdir().unwrap().into_path();
+            let mut query_engine = QueryEngine::new(&base_dir);
+            let sql = "CREATE TABLE foo (id INT PRIMARY KEY, name VARCHAR(10), score INT);";
+            query_engine.run_sql(sql).unwrap();
+            let sql =
+                "INSERT INTO foo VALUES (1, 'b', 7), (2, 'a', NULL), (3, 'b', 5), (4, 'a', 9);";
+            query_engine.run_sql(sql).unwrap();
+            let ids = |query_engine: &mut QueryEngine, sql: &str| match query_engine
+                .run_sql(sql)
+                .unwrap()
+            {
+                QueryResult::Select { result, .. } => result
+                    .iter()
+                    .map(|t| t.get_field(0).unwrap().clone())
+                    .collect::<Vec<_>>(),
+                _ => panic!("Expected select result"),
+            };
+            let expect = |ids: &[i64]| {
+                ids.iter()
+                    .map(|i| common::Field::Int(*i))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                expect(&[4, 2, 3, 1]),
+                ids(
+                    &mut query_engine,
+                    "SELECT id FROM foo ORDER BY name, id DESC;"
+                )
+            );
+            assert_eq!(
+                expect(&[3, 1, 4, 2]),
+                ids(&mut query_engine, "SELECT id FROM foo ORDER BY score;")
+            );
+            assert_eq!(
+                expect(&[2, 4, 1, 3]),
+                ids(&mut query_engine, "SELECT id FROM foo ORDER BY score DESC;")
+            );
+            assert_eq!(
+                expect(&[2, 3, 1, 4]),
+                ids(
+                    &mut query_engine,
+                    "SELECT id FROM foo ORDER BY score ASC NULLS FIRST;"
+                )
+            );
+            fs::remove_dir_all(base_dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_server_state_reopens_databases() {
+        let base_dir = tempfile::tempdir().unwrap().into_path();
+        let server_state = ServerState::new(&base_dir, ManagerSettings::default()).unwrap();
+        server_state.create_new_db("db1", None).unwrap();
+        server_state.create_new_db("db2", None).unwrap();
+        server_state.connect_to_db("db1", 1).unwrap();
+        let db = server_state.get_connected_db(1).unwrap();
+        let mut plan = PhysicalPlan::new();
+        plan.add_node(common::physical_plan::PhysicalOp::Scan(
+            common::physical_plan::PhysicalScanNode {
+                container_id: 0,
+                filter: None,
+                projection: None,
+            },
+        ));
+        db.register_query("q".to_string(), "q.json".to_string(), Arc::new(plan))
+            .unwrap();
+        server_state.shutdown().unwrap();
+
+        let server_state = ServerState::new(&base_dir, ManagerSettings::default()).unwrap();
+        let mut names = server_state.get_db_names();
+        names.sort();
+        assert_eq!(vec!["db1".to_string(), "db2".to_string()], names);
+        server_state.connect_to_db("db1", 1).unwrap();
+        let db = server_state.get_connected_db(1).unwrap();
+        assert_eq!(
+            "q loaded from q.json",
+            db.get_registered_query_names().unwrap()
+        );
+        fs::remove_dir_all(base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_server_state_create_after_reset() {
+        let base_dir = tempfile::tempdir().unwrap().into_path();
+        let server_state = ServerState::new(&base_dir, ManagerSettings::default()).unwrap();
+        server_state.create_new_db("db1", None).unwrap();
+        server_state.reset().unwrap();
+        assert!(server_state.get_db_names().is_empty());
+
+        server_state.create_new_db("db1", None).unwrap();
+        server_state.connect_to_db("db1", 1).unwrap();
+        assert!(base_dir.join("server_state").join("db1").is_file());
+        fs::remove_dir_all(base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_server_state_page_size_per_database() {
+        let base_dir = tempfile::tempdir().unwrap().into_path();
+        let settings = ManagerSettings {
+            page_size: 8192,
+            ..ManagerSettings::default()
+        };
+        let server_state = ServerState::new(&base_dir, settings).unwrap();
+        server_state
+            .create_new_db("small", Some(PAGE_SIZE))
+            .unwrap();
+        server_state.create_new_db("wide", Some(65536)).unwrap();
+        server_state.create_new_db("default", None).unwrap();
+        assert!(server_state.create_new_db("odd", Some(5000)).is_err());
+        assert!(!server_state.get_db_names().contains(&"odd".to_string()));
+        let page_sizes = |server_state: &ServerState| {
+            let mut page_sizes = server_state
+                .id_to_db
+                .read()
+                .unwrap()
+                .values()
+                .map(|db| (db.name.clone(), db.managers.sm.page_size()))
+                .collect::<Vec<_>>();
+            page_sizes.sort();
+            page_sizes
+        };
+        let expected = vec![
+            ("default".to_string(), 8192),
+            ("small".to_string(), PAGE_SIZE),
+            ("wide".to_string(), 65536),
+        ];
+        assert_eq!(expected, page_sizes(&server_state));
+        server_state.shutdown().unwrap();
+
+        // Each database keeps its page size whatever the server's default
+        let server_state = ServerState::new(&base_dir, ManagerSettings::default()).unwrap();
+        assert_eq!(expected, page_sizes(&server_state));
+        fs::remove_dir_all(base_dir).unwrap();
     }
 }
@@ -1,31 +1,64 @@
 use std::collections::{hash_map::Entry, HashMap};
+use std::ffi::OsStr;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
 
 use crate::database_state::DatabaseState;
+use crate::server::create_managers;
 
-use common::CrustyError;
-use queryexe::Managers;
+use common::traits::transaction_manager_trait::{IsolationLevel, TransactionManagerTrait};
+use common::{CrustyError, PAGE_SIZE};
+use queryexe::{Managers, STORAGE_DIR};
+use txn_manager::transactions::Transaction;
 
 const SERVER_STATE_DIR: &str = "server_state";
 
+/// How the managers of every database are set up
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ManagerSettings {
+    /// Bytes in every page of a database created without a page size
+    pub page_size: usize,
+    /// How long a transaction waits for a lock, None to wait forever
+    pub lock_timeout: Option<Duration>,
+    /// Run transactions at the serializable isolation level
+    pub serializable: bool,
+}
+
+impl Default for ManagerSettings {
+    fn default() -> Self {
+        ManagerSettings {
+            page_size: PAGE_SIZE,
+            lock_timeout: None,
+            serializable: false,
+        }
+    }
+}
+
 /// A struct that holds information about
 /// which client is connected to which database.
+/// Every database has managers of its own, which store its containers in a directory of
+/// storage_dir named after it, so each database keeps the page size it was created with.
 pub struct ServerState {
     /// Path where database files are stored.
     pub server_state_dir: PathBuf,
+    /// Path where the storage dir of each database is
+    storage_dir: PathBuf,
+    settings: ManagerSettings,
     // maps database id to DatabaseState
     pub id_to_db: RwLock<HashMap<u64, &'static DatabaseState>>,
     /// active connections indicates what client_id is connected to what db_id
     pub active_connections: RwLock<HashMap<u64, u64>>,
-    pub managers: &'static Managers,
+    /// The transaction of each client, kept between its commands
+    client_txns: Mutex<HashMap<u64, Transaction>>,
 }
 
 impl ServerState {
-    pub(crate) fn new(base_dir: &Path, managers: &'static Managers) -> Result<Self, CrustyError> {
+    pub(crate) fn new(base_dir: &Path, settings: ManagerSettings) -> Result<Self, CrustyError> {
         // Create databases
         let server_state_dir = base_dir.join(SERVER_STATE_DIR);
+        let storage_dir = base_dir.join(STORAGE_DIR);
         debug!("Looking for databases in {:?}", server_state_dir);
 
         let mut db_map = HashMap::new();
@@ -36,7 +69,12 @@ impl ServerState {
                 for db in dbs {
                     let db = db.unwrap();
                     let db_path = db.path();
+                    // Skip partial saves
+                    if !db_path.is_file() || db.file_name().to_string_lossy().starts_with('.') {
+                        continue;
+                    }
                     debug!("Creating DatabaseState from path {:?}", db_path);
+                    let managers = open_managers(&storage_dir, &db.file_name(), None, settings)?;
                     // let db_struct: Database = Database::load(db);
                     let db_box = Box::new(DatabaseState::load(db_path, managers)?);
                     let db_state: &'static DatabaseState = Box::leak(db_box);
@@ -51,8 +89,10 @@ impl ServerState {
         let server_state = ServerState {
             id_to_db: RwLock::new(db_map),
             active_connections: RwLock::new(HashMap::new()),
+            client_txns: Mutex::new(HashMap::new()),
             server_state_dir,
-            managers,
+            storage_dir,
+            settings,
         };
 
         Ok(server_state)
@@ -83,12 +123,59 @@ impl ServerState {
         id_to_db.values().map(|db| db.name.clone()).collect()
     }
 
+    /// Take the transaction of a client to run its next command in, if it has one.
+    pub fn take_transaction(&self, client_id: u64) -> Option<Transaction> {
+        self.client_txns.lock().unwrap().remove(&client_id)
+    }
+
+    /// Keep the transaction of a client for its next command.
+    pub fn keep_transaction(&self, client_id: u64, txn: Transaction) {
+        self.client_txns.lock().unwrap().insert(client_id, txn);
+    }
+
+    /// Roll back the transaction a client left open in the database it is connected to, e.g.
+    /// when it disconnects.
+    pub fn end_transaction(&self, client_id: u64) -> Result<(), CrustyError> {
+        if let Some(mut txn) = self.take_transaction(client_id) {
+            let db = self.get_connected_db(client_id)?;
+            db.managers.rollback_txn(txn.tid()?)?;
+            txn.abort()?;
+        }
+        Ok(())
+    }
+
+    /// The managers of every database
+    pub(crate) fn all_managers(&self) -> Vec<&'static Managers> {
+        let id_to_db = self.id_to_db.read().unwrap();
+        id_to_db.values().map(|db| db.managers).collect()
+    }
+
+    fn end_all_transactions(&self) -> Result<(), CrustyError> {
+        let client_ids: Vec<u64> = self.client_txns.lock().unwrap().keys().copied().collect();
+        for client_id in client_ids {
+            self.end_transaction(client_id)?;
+        }
+        Ok(())
+    }
+
     /// Reset the server
     pub fn reset(&self) -> Result<(), CrustyError> {
+        self.end_all_transactions()?;
+
         // Clear out each DB state
         let mut id_to_db = self.id_to_db.write().unwrap();
         for db in id_to_db.values() {
             db.reset()?;
+            let db_path = self.server_state_dir.join(&db.name);
+            if db_path.exists() {
+                fs::remove_file(db_path)?;
+            }
+            // Clear the managers and remove the storage of the database
+            db.managers.reset()?;
+            let db_storage_dir = self.storage_dir.join(&db.name);
+            if db_storage_dir.exists() {
+                fs::remove_dir_all(db_storage_dir)?;
+            }
         }
         id_to_db.clear();
 
@@ -96,33 +183,30 @@ impl ServerState {
         let mut active_connections = self.active_connections.write().unwrap();
         active_connections.clear();
 
-        // Clear the storage manager
-        self.managers.reset()?;
+        fs::create_dir_all(&self.server_state_dir)?;
         Ok(())
     }
 
     pub(crate) fn shutdown(&self) -> Result<(), CrustyError> {
         info!("Shutting down");
+        self.end_all_transactions()?;
 
         // Shutdown/persist DB state
-        let _id_to_db = self.id_to_db.read().unwrap();
-
-        if self.server_state_dir.exists() {
-            debug!("Saving DB state to {:?}", self.server_state_dir);
-            info!("TODO: Save DB state to {:?}", self.server_state_dir);
-        }
-        {
-            // error!("server_state_dir should exist");
-            //TODO panic if needed
+        let id_to_db = self.id_to_db.read().unwrap();
+        fs::create_dir_all(&self.server_state_dir)?;
+        debug!("Saving DB state to {:?}", self.server_state_dir);
+        for db in id_to_db.values() {
+            db.save(&self.server_state_dir.join(&db.name))?;
+            // call shutdown on SM to ensure stateful shutdown
+            db.managers.shutdown();
         }
 
-        // call shutdown on SM to ensure stateful shutdown
-        self.managers.shutdown();
-
         Ok(())
     }
 
-    pub fn create_new_db(&self, name: &str) -> Result<(), CrustyError> {
+    /// Create a database whose pages are page_size bytes, or the default page size of the
+    /// server if None.
+    pub fn create_new_db(&self, name: &str, page_size: Option<usize>) -> Result<(), CrustyError> {
         let db_id = DatabaseState::get_database_id(name);
 
         let mut id_to_db = self.id_to_db.write().unwrap();
@@ -133,23 +217,40 @@ impl ServerState {
                 name
             ))),
             Entry::Vacant(entry) => {
-                let db_state = DatabaseState::new_from_name(name, self.managers).map_err(|e| {
-                    CrustyError::CrustyError(format!("Failed to create database state: {}", e))
-                })?;
+                let page_size = page_size.unwrap_or(self.settings.page_size);
+                let managers = open_managers(
+                    &self.storage_dir,
+                    name.as_ref(),
+                    Some(page_size),
+                    self.settings,
+                )?;
+                let db_state = DatabaseState::create_db(&self.server_state_dir, name, managers)
+                    .map_err(|e| {
+                        CrustyError::CrustyError(format!("Failed to create database state: {}", e))
+                    })?;
                 entry.insert(Box::leak(Box::new(db_state)));
                 Ok(())
             }
         }
     }
 
+    /// Connect a client to a database. A transaction the client left open in the database it
+    /// was connected to is rolled back, as it cannot continue in another database.
     pub fn connect_to_db(&self, db_name: &str, client_id: u64) -> Result<(), CrustyError> {
         let db_id = self.get_db_id_from_name(db_name)?;
+        self.end_transaction(client_id)?;
         let mut active_connections = self.active_connections.write().unwrap();
         active_connections.insert(client_id, db_id);
         Ok(())
     }
 
     pub fn close_connection(&self, client_id: u64) {
+        if let Err(e) = self.end_transaction(client_id) {
+            error!(
+                "Failed to roll back transaction of client {}: {:?}",
+                client_id, e
+            );
+        }
         let mut active_connections = self.active_connections.write().unwrap();
         active_connections.remove(&client_id);
     }
@@ -164,3 +265,21 @@ impl ServerState {
         Err(CrustyError::CrustyError(String::from("db_name not found!")))
     }
 }
+
+/// Open the managers of the database named db_name, whose storage dir is in storage_dir. A new
+/// database gets pages of page_size bytes, an existing one must have that page size if given.
+fn open_managers(
+    storage_dir: &Path,
+    db_name: &OsStr,
+    page_size: Option<usize>,
+    settings: ManagerSettings,
+) -> Result<&'static Managers, CrustyError> {
+    let managers = create_managers(&storage_dir.join(db_name), page_size)?;
+    managers.tm.set_lock_timeout(settings.lock_timeout)?;
+    if settings.serializable {
+        managers
+            .tm
+            .set_isolation_level(IsolationLevel::Serializable)?;
+    }
+    Ok(managers)
+}
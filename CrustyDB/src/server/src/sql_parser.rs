@@ -55,6 +55,20 @@ impl SQLParser {
         }
     }
 
+    /// The table of a `VACUUM [table]` statement, which the SQL parser does not support.
+    /// Returns None if sql is not a VACUUM, and Some(None) for a VACUUM of every table.
+    pub fn parse_vacuum(sql: &str) -> Option<Option<String>> {
+        let mut words = sql.trim().trim_end_matches(';').split_whitespace();
+        if !words.next()?.eq_ignore_ascii_case("vacuum") {
+            return None;
+        }
+        let table = words.next().map(str::to_string);
+        if words.next().is_some() {
+            return None;
+        }
+        Some(table)
+    }
+
     /// Returns Request::SQL if given string is valid sql, else returns Request::SQLError
     fn validate_sql(sql: String) -> ParserResponse {
         let dialect = sqlparser::dialect::GenericDialect {};
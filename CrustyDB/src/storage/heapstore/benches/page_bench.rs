@@ -1,16 +1,30 @@
-use criterion::{black_box, Criterion};
+use criterion::{black_box, BenchmarkId, Criterion};
 
 use common::testutil::get_random_vec_of_byte_vec;
-use heapstore::testutil::bench_page_insert;
+use common::PAGE_SIZE;
+use heapstore::testutil::{bench_page_insert, BENCH_PAGE_SIZES};
 
 pub fn page_benchmark(c: &mut Criterion) {
-    let to_insert = get_random_vec_of_byte_vec(40, 80, 100);
-    c.bench_function("page insert medium", |b| {
-        b.iter(|| bench_page_insert(black_box(&to_insert)))
-    });
+    let mut group = c.benchmark_group("page insert medium");
+    for page_size in BENCH_PAGE_SIZES {
+        // Fill about the same fraction of every page size
+        let to_insert = get_random_vec_of_byte_vec(40 * page_size / PAGE_SIZE, 80, 100);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(page_size),
+            &to_insert,
+            |b, v| b.iter(|| bench_page_insert(black_box(v), page_size)),
+        );
+    }
+    group.finish();
 
-    let to_insert = get_random_vec_of_byte_vec(10, 350, 400);
-    c.bench_function("page insert large recs", |b| {
-        b.iter(|| bench_page_insert(black_box(&to_insert)))
-    });
+    let mut group = c.benchmark_group("page insert large recs");
+    for page_size in BENCH_PAGE_SIZES {
+        let to_insert = get_random_vec_of_byte_vec(10 * page_size / PAGE_SIZE, 350, 400);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(page_size),
+            &to_insert,
+            |b, v| b.iter(|| bench_page_insert(black_box(v), page_size)),
+        );
+    }
+    group.finish();
 }
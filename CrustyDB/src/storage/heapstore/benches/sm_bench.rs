@@ -1,19 +1,39 @@
-use criterion::{black_box, Criterion};
+use criterion::{black_box, BenchmarkId, Criterion};
 //<strip only="pg">
 use common::storage_trait::StorageTrait;
 use common::testutil::get_random_vec_of_byte_vec;
-use heapstore::storage_manager::StorageManager;
-use heapstore::testutil::bench_sm_insert;
+use heapstore::testutil::{bench_sm, bench_sm_insert, BENCH_PAGE_SIZES};
 //</strip>
 
 pub fn sm_ins_bench(c: &mut Criterion) {
     let to_insert = get_random_vec_of_byte_vec(1000, 80, 100);
     //<strip only="pg">
-    let sm = StorageManager::new_test_sm();
-    let cid = 1;
-    sm.create_table(cid).unwrap();
-    c.bench_function("sm insert 1k", |b| {
-        b.iter(|| bench_sm_insert(&sm, black_box(&to_insert)))
-    });
+    let mut group = c.benchmark_group("sm insert 1k");
+    for page_size in BENCH_PAGE_SIZES {
+        let sm = bench_sm(page_size);
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(page_size),
+            &to_insert,
+            |b, v| b.iter(|| bench_sm_insert(&sm, black_box(v))),
+        );
+    }
+    group.finish();
+
+    // Wide rows, as stored by analytic tables
+    let to_insert = get_random_vec_of_byte_vec(20, 3000, 6000);
+    let mut group = c.benchmark_group("sm insert wide rows");
+    for page_size in BENCH_PAGE_SIZES {
+        let sm = bench_sm(page_size);
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(page_size),
+            &to_insert,
+            |b, v| b.iter(|| bench_sm_insert(&sm, black_box(v))),
+        );
+    }
+    group.finish();
     //</strip>
 }
@@ -5,6 +5,7 @@
 #[cfg(test)]
 mod test {
     use crate::heap_page::HeapPage;
+    use crate::heapfile::PageNo;
     use crate::storage_manager::StorageManager;
     use crate::testutil::*;
     use common::ids::{PageId, Permissions, TransactionId, ValueId};
@@ -31,14 +32,14 @@ mod test {
 
         let _p = sm.get_page(
             val_id.container_id,
-            val_id.page_id.unwrap(),
+            val_id.page_id.unwrap().into(),
             tid,
             Permissions::ReadOnly,
             false,
         );
         sm.get_page(
             val_id.container_id,
-            val_id.page_id.unwrap(),
+            val_id.page_id.unwrap().into(),
             tid,
             Permissions::ReadOnly,
             false,
@@ -55,7 +56,7 @@ mod test {
         sm.clear_cache();
         sm.get_page(
             val_id.container_id,
-            val_id.page_id.unwrap(),
+            val_id.page_id.unwrap().into(),
             tid,
             Permissions::ReadOnly,
             false,
@@ -81,7 +82,7 @@ mod test {
         sm.create_table(hfid).unwrap();
         let tid = TransactionId::new();
         let to_fill = PAGE_SLOTS + 1;
-        fill_hf_sm(&sm, hfid, to_fill as PageId, 10, 100, 100);
+        fill_hf_sm(&sm, hfid, to_fill as PageNo, 10, 100, 100);
         for i in 0..PAGE_SLOTS {
             let id = ValueId {
                 container_id: hfid,
@@ -91,7 +92,7 @@ mod test {
             };
             sm.get_page(
                 id.container_id,
-                id.page_id.unwrap(),
+                id.page_id.unwrap().into(),
                 tid,
                 Permissions::ReadOnly,
                 false,
@@ -113,7 +114,7 @@ mod test {
             };
             sm.get_page(
                 id.container_id,
-                id.page_id.unwrap(),
+                id.page_id.unwrap().into(),
                 tid,
                 Permissions::ReadOnly,
                 false,
@@ -134,7 +135,7 @@ mod test {
 
         sm.get_page(
             evict_id.container_id,
-            evict_id.page_id.unwrap(),
+            evict_id.page_id.unwrap().into(),
             tid,
             Permissions::ReadOnly,
             false,
@@ -148,7 +149,7 @@ mod test {
         //re read
         sm.get_page(
             evict_id.container_id,
-            evict_id.page_id.unwrap(),
+            evict_id.page_id.unwrap().into(),
             tid,
             Permissions::ReadOnly,
             false,
@@ -178,7 +179,7 @@ mod test {
         let mut p = sm
             .get_page(
                 val_id.container_id,
-                val_id.page_id.unwrap(),
+                val_id.page_id.unwrap().into(),
                 tid,
                 Permissions::ReadOnly,
                 false,
@@ -186,7 +187,7 @@ mod test {
             .unwrap();
         sm.get_page(
             val_id.container_id,
-            val_id.page_id.unwrap(),
+            val_id.page_id.unwrap().into(),
             tid,
             Permissions::ReadOnly,
             false,
@@ -212,7 +213,7 @@ mod test {
         let p2 = sm
             .get_page(
                 val_id.container_id,
-                val_id.page_id.unwrap(),
+                val_id.page_id.unwrap().into(),
                 tid,
                 Permissions::ReadOnly,
                 false,
@@ -244,7 +245,7 @@ mod test {
         let handle = thread::spawn(move || {
             s2.get_page(
                 val_id.container_id,
-                val_id.page_id.unwrap(),
+                val_id.page_id.unwrap().into(),
                 tid,
                 Permissions::ReadOnly,
                 false,
@@ -253,7 +254,7 @@ mod test {
         });
         s1.get_page(
             v2.container_id,
-            v2.page_id.unwrap(),
+            v2.page_id.unwrap().into(),
             tid,
             Permissions::ReadOnly,
             false,
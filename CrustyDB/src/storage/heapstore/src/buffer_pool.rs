@@ -1,12 +1,367 @@
-use crate::page::Page;
 use crate::heap_page::HeapPage;
+use crate::heapfile::{HeapFile, PageNo};
+use crate::page::Page;
 use crate::WRITE_THROUGH;
 use common::ids::ValueId;
-use common::CrustyError;
+use common::prelude::*;
 use common::{PAGE_SIZE, PAGE_SLOTS};
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock, RwLockWriteGuard};
 
+/// Key used to find a page in the buffer pool.
+type FrameKey = (ContainerId, PageNo);
+
+/// A single frame of the buffer pool. A frame holds at most one page and the
+/// bookkeeping needed by the replacement policy.
+struct Frame {
+    /// The container/page held by this frame. None if the frame is free.
+    key: Option<FrameKey>,
+    /// The heap file the page belongs to, used to flush the page on eviction.
+    hf: Option<Arc<HeapFile>>,
+    /// The cached page.
+    page: Page,
+    /// Number of outstanding pins. A pinned frame is never evicted.
+    pin_count: AtomicU8,
+    /// True if the page has been modified since it was last written to the heap file.
+    dirty: AtomicBool,
+    /// Reference bit for the clock replacement policy.
+    referenced: AtomicBool,
+}
+
+impl Frame {
+    fn new() -> Self {
+        Frame {
+            key: None,
+            hf: None,
+            page: Page::new(0),
+            pin_count: AtomicU8::new(0),
+            dirty: AtomicBool::new(false),
+            referenced: AtomicBool::new(false),
+        }
+    }
+
+    /// Write the page back to its heap file if dirty.
+    fn flush(&self) -> Result<(), CrustyError> {
+        if self.dirty.load(Ordering::Acquire) {
+            if let Some(hf) = &self.hf {
+                hf.write_page_to_file(&self.page)?;
+            }
+            self.dirty.store(false, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    /// Reset the frame so it can hold another page.
+    fn clear(&mut self) {
+        self.key = None;
+        self.hf = None;
+        self.pin_count.store(0, Ordering::Release);
+        self.dirty.store(false, Ordering::Release);
+        self.referenced.store(false, Ordering::Release);
+    }
+}
+
+/// A fixed size buffer pool of PAGE_SLOTS frames shared by all containers of a storage manager.
+///
+/// Lookups go through the page table. The page table lock is always taken before a frame lock,
+/// and a frame can only change its key while the page table is write locked, so holding the page
+/// table read lock guarantees a hit is not evicted underneath the reader.
+///
+/// Replacement uses the clock algorithm, skipping pinned frames.
 pub(crate) struct BufferPool {
+    frames: Vec<RwLock<Frame>>,
+    page_table: RwLock<HashMap<FrameKey, usize>>,
+    clock_hand: AtomicUsize,
+}
+
+impl BufferPool {
+    pub(crate) fn new() -> Self {
+        Self::with_capacity(PAGE_SLOTS)
+    }
+
+    pub(crate) fn with_capacity(num_frames: usize) -> Self {
+        BufferPool {
+            frames: (0..num_frames).map(|_| RwLock::new(Frame::new())).collect(),
+            page_table: RwLock::new(HashMap::new()),
+            clock_hand: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of frames in the pool.
+    pub(crate) fn capacity(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Get a copy of a page, reading it from the heap file on a miss.
+    /// If pin is true the frame stays resident until unpin_page is called.
+    pub(crate) fn get_page(
+        &self,
+        hf: &Arc<HeapFile>,
+        page_id: PageNo,
+        pin: bool,
+    ) -> Result<Page, CrustyError> {
+        let key = (hf.container_id, page_id);
+        {
+            let table = self.page_table.read()?;
+            if let Some(idx) = table.get(&key) {
+                let frame = self.frames[*idx].read()?;
+                Self::touch(&frame, pin);
+                return Ok(frame.page.clone());
+            }
+        }
+
+        let mut table = self.page_table.write()?;
+        // Another thread may have loaded the page while we waited for the lock
+        if let Some(idx) = table.get(&key) {
+            let frame = self.frames[*idx].read()?;
+            Self::touch(&frame, pin);
+            return Ok(frame.page.clone());
+        }
+        let page = hf.read_page_from_file(page_id)?;
+        match self.find_victim(&mut table)? {
+            Some((idx, mut frame)) => {
+                frame.key = Some(key);
+                frame.hf = Some(Arc::clone(hf));
+                frame.page = page.clone();
+                Self::touch(&frame, pin);
+                table.insert(key, idx);
+                Ok(page)
+            }
+            None if pin => Err(CrustyError::ExecutionError(String::from(
+                "Buffer pool full: all frames are pinned",
+            ))),
+            // Every frame is pinned, hand back an uncached copy
+            None => Ok(page),
+        }
+    }
+
+    /// Write a page into the pool. The page is flushed to the heap file right away if
+    /// WRITE_THROUGH is set, or if the page extends the file (so num_pages stays correct).
+    pub(crate) fn write_page(&self, hf: &Arc<HeapFile>, page: &Page) -> Result<(), CrustyError> {
+        let page_id = page.get_page_id();
+        let key = (hf.container_id, page_id);
+        let force = WRITE_THROUGH || page_id >= hf.num_pages();
+        {
+            let table = self.page_table.read()?;
+            if let Some(idx) = table.get(&key) {
+                let mut frame = self.frames[*idx].write()?;
+                frame.page = page.clone();
+                frame.referenced.store(true, Ordering::Release);
+                if force {
+                    hf.write_page_to_file(page)?;
+                    frame.dirty.store(false, Ordering::Release);
+                } else {
+                    frame.dirty.store(true, Ordering::Release);
+                }
+                return Ok(());
+            }
+        }
+
+        let mut table = self.page_table.write()?;
+        if let Some(idx) = table.get(&key) {
+            // Loaded by someone else in between, update in place
+            let mut frame = self.frames[*idx].write()?;
+            frame.page = page.clone();
+            frame.referenced.store(true, Ordering::Release);
+            frame.dirty.store(true, Ordering::Release);
+            if force {
+                frame.flush()?;
+            }
+            return Ok(());
+        }
+        match self.find_victim(&mut table)? {
+            Some((idx, mut frame)) => {
+                frame.key = Some(key);
+                frame.hf = Some(Arc::clone(hf));
+                frame.page = page.clone();
+                frame.referenced.store(true, Ordering::Release);
+                frame.dirty.store(true, Ordering::Release);
+                if force {
+                    frame.flush()?;
+                }
+                table.insert(key, idx);
+                Ok(())
+            }
+            None => hf.write_page_to_file(page),
+        }
+    }
+
+    /// Release a pin taken by get_page.
+    pub(crate) fn unpin_page(&self, container_id: ContainerId, page_id: PageNo) {
+        let table = self.page_table.read().unwrap();
+        if let Some(idx) = table.get(&(container_id, page_id)) {
+            let frame = self.frames[*idx].read().unwrap();
+            let _ = frame
+                .pin_count
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |c| c.checked_sub(1));
+        }
+    }
+
+    /// Returns true if the page is currently held by the pool.
+    pub(crate) fn is_cached(&self, container_id: ContainerId, page_id: PageNo) -> bool {
+        self.page_table
+            .read()
+            .unwrap()
+            .contains_key(&(container_id, page_id))
+    }
+
+    /// Write every dirty page back to its heap file.
+    pub(crate) fn flush_all(&self) -> Result<(), CrustyError> {
+        let _table = self.page_table.read()?;
+        for frame in &self.frames {
+            frame.read()?.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Flush dirty pages and drop every unpinned frame.
+    pub(crate) fn clear(&self) -> Result<(), CrustyError> {
+        let mut table = self.page_table.write()?;
+        for frame in &self.frames {
+            let mut frame = frame.write()?;
+            if frame.pin_count.load(Ordering::Acquire) > 0 {
+                continue;
+            }
+            frame.flush()?;
+            if let Some(key) = frame.key {
+                table.remove(&key);
+            }
+            frame.clear();
+        }
+        Ok(())
+    }
+
+    /// Drop all frames of a container without flushing them. Used when a container is removed.
+    pub(crate) fn discard_container(&self, container_id: ContainerId) {
+        self.discard_pages(container_id, 0);
+    }
+
+    /// Drop the frames of the pages of a container from first_page on without flushing them.
+    /// Used when the heap file is truncated.
+    pub(crate) fn discard_pages(&self, container_id: ContainerId, first_page: PageNo) {
+        let mut table = self.page_table.write().unwrap();
+        table.retain(|(cid, pid), idx| {
+            if *cid == container_id && *pid >= first_page {
+                self.frames[*idx].write().unwrap().clear();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Drop every frame without flushing.
+    pub(crate) fn discard_all(&self) {
+        let mut table = self.page_table.write().unwrap();
+        for frame in &self.frames {
+            frame.write().unwrap().clear();
+        }
+        table.clear();
+    }
+
+    /// Mark a hit frame as referenced and pin it if requested.
+    fn touch(frame: &Frame, pin: bool) {
+        frame.referenced.store(true, Ordering::Release);
+        if pin {
+            frame.pin_count.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Pick a frame to hold a new page using the clock policy. The victim is flushed if dirty
+    /// and removed from the page table. Returns None if every frame is pinned.
+    /// Requires the page table write lock.
+    fn find_victim(
+        &self,
+        table: &mut HashMap<FrameKey, usize>,
+    ) -> Result<Option<(usize, RwLockWriteGuard<'_, Frame>)>, CrustyError> {
+        let n = self.frames.len();
+        // Two sweeps are enough to clear every reference bit once
+        for _ in 0..2 * n {
+            let idx = self.clock_hand.fetch_add(1, Ordering::AcqRel) % n;
+            let mut frame = self.frames[idx].write()?;
+            if frame.key.is_none() {
+                return Ok(Some((idx, frame)));
+            }
+            if frame.pin_count.load(Ordering::Acquire) > 0 {
+                continue;
+            }
+            if frame.referenced.swap(false, Ordering::AcqRel) {
+                continue;
+            }
+            frame.flush()?;
+            if let Some(key) = frame.key {
+                table.remove(&key);
+            }
+            frame.clear();
+            return Ok(Some((idx, frame)));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::heapfile::SEGMENT_PAGES;
+    use crate::testutil::get_random_page;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    fn temp_hf(tdir: &TempDir, container_id: ContainerId) -> Arc<HeapFile> {
+        let mut f = tdir.to_path_buf();
+        f.push(gen_rand_string(4));
+        f.set_extension("hf");
+        Arc::new(HeapFile::new(f, container_id, PAGE_SIZE, SEGMENT_PAGES).unwrap())
+    }
+
+    #[test]
+    fn hs_bp_pinned_not_evicted() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let hf = temp_hf(&tdir, 0);
+        for i in 0..4 {
+            let (p, _) = get_random_page(i, 5, 10, 20);
+            hf.write_page_to_file(&p).unwrap();
+        }
+        let bp = BufferPool::with_capacity(2);
+        bp.get_page(&hf, 0, true).unwrap();
+        bp.get_page(&hf, 1, false).unwrap();
+        bp.get_page(&hf, 2, false).unwrap();
+        bp.get_page(&hf, 3, false).unwrap();
+        assert!(bp.is_cached(0, 0));
+        assert!(bp.is_cached(0, 3));
+        assert!(!bp.is_cached(0, 1));
+
+        // Fill the other frame with a pin, so nothing can be evicted
+        bp.get_page(&hf, 1, true).unwrap();
+        assert!(bp.get_page(&hf, 2, true).is_err());
+        assert!(bp.get_page(&hf, 2, false).is_ok());
+        assert!(!bp.is_cached(0, 2));
+
+        bp.unpin_page(0, 0);
+        bp.get_page(&hf, 2, false).unwrap();
+        assert!(bp.is_cached(0, 2));
+        assert!(!bp.is_cached(0, 0));
+    }
+
+    #[test]
+    fn hs_bp_clear_flushes_dirty() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let hf = temp_hf(&tdir, 0);
+        let (p, _) = get_random_page(0, 5, 10, 20);
+        hf.write_page_to_file(&p).unwrap();
+
+        let bp = BufferPool::new();
+        let mut page = bp.get_page(&hf, 0, false).unwrap();
+        page.add_value(&[1, 2, 3]);
+        bp.write_page(&hf, &page).unwrap();
+        bp.clear().unwrap();
+        assert!(!bp.is_cached(0, 0));
+        assert_eq!(
+            page.to_bytes()[..],
+            hf.read_page_from_file(0).unwrap().to_bytes()[..]
+        );
+    }
 }
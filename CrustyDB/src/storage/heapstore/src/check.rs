@@ -0,0 +1,302 @@
+use crate::heap_page::{HeapPage, PAGE_METADATA_SIZE, SLOT_ENTRY_SIZE};
+use crate::heapfile::{HeapFile, PageNo};
+use crate::overflow::{self, StoredRecord};
+use crate::page::Page;
+use crate::storage_manager::StorageManager;
+use common::prelude::*;
+use std::collections::BTreeSet;
+
+/// Integrity check for the heapstore storage manager.
+///
+/// Every page of a heap file is read back from disk, so its checksum is verified, and its header
+/// is checked against the records it describes: slot ids must match their position, live
+/// records must lie in the page body without overlapping each other or the free space, and the
+/// free-space map must never promise more room than a page has. The records of sound pages must
+/// decode, every overflow chain must lead through chunks to its full length, and every chunk
+/// must belong to exactly one chain.
+impl StorageManager {
+    pub(crate) fn check_container(
+        &self,
+        container_id: ContainerId,
+    ) -> Result<Vec<String>, CrustyError> {
+        let hf = self.open_hf(container_id)?;
+        let _latch = hf.write_latch.lock()?;
+        // The pages on disk must be current
+        if let Some(bp) = &self.buffer_pool {
+            bp.flush_all()?;
+        }
+        let num_pages = hf.num_pages();
+        let mut problems = Vec::new();
+        let mut records = OverflowRecords::default();
+        for pid in 0..num_pages {
+            match hf.read_page_from_file(pid) {
+                Ok(page) => {
+                    let found = problems.len();
+                    check_page(&hf, &page, &mut problems);
+                    // Slots of a broken header may point anywhere
+                    if problems.len() == found {
+                        records.collect(&hf, page, &mut problems);
+                    }
+                }
+                Err(e) => problems.push(e.to_string()),
+            }
+        }
+        records.check_chains(&hf, &mut problems);
+        if hf.fsm.num_pages() != num_pages as usize {
+            problems.push(format!(
+                "Free space map of container {} tracks {} pages, heap file has {}",
+                container_id,
+                hf.fsm.num_pages(),
+                num_pages
+            ));
+        }
+        debug!(
+            "Checked container {}: {} pages, {} problems",
+            container_id,
+            num_pages,
+            problems.len()
+        );
+        Ok(problems)
+    }
+}
+
+/// Check the header of a page that passed its checksum, appending a line per problem
+fn check_page(hf: &HeapFile, page: &Page, problems: &mut Vec<String>) {
+    let c_id = hf.container_id;
+    let pid = page.get_page_id();
+    let meta = page.get_metadata();
+    let page_size = page.page_size();
+    let header_size = PAGE_METADATA_SIZE + meta.num_slots as usize * SLOT_ENTRY_SIZE;
+    if header_size > page_size {
+        problems.push(format!(
+            "Page {} of container {}: {} slots do not fit in the page",
+            pid, c_id, meta.num_slots
+        ));
+        return;
+    }
+
+    let header = page.read_header();
+    let mut records = Vec::new();
+    for (idx, slot) in header.slots.iter().enumerate() {
+        if slot.slot_id as usize != idx {
+            problems.push(format!(
+                "Page {} of container {}: slot {} has id {}",
+                pid, c_id, idx, slot.slot_id
+            ));
+        }
+        if slot.size_of_record == 0 {
+            continue;
+        }
+        let start = slot.offset_of_record as usize;
+        let end = start + slot.size_of_record as usize;
+        if start < header_size || end > page_size {
+            problems.push(format!(
+                "Page {} of container {}: slot {} at {}..{} is outside the page body",
+                pid, c_id, idx, start, end
+            ));
+        }
+        records.push((start, end, idx));
+    }
+    records.sort_unstable();
+    for pair in records.windows(2) {
+        let ((_, prev_end, prev), (start, _, next)) = (pair[0], pair[1]);
+        if start < prev_end {
+            problems.push(format!(
+                "Page {} of container {}: slots {} and {} overlap",
+                pid, c_id, prev, next
+            ));
+        }
+    }
+
+    let free_start = meta.offset_of_free_space as usize;
+    let free_end = free_start + meta.size_of_free_space as usize;
+    if free_start != header_size {
+        problems.push(format!(
+            "Page {} of container {}: free space starts at {}, header ends at {}",
+            pid, c_id, free_start, header_size
+        ));
+    }
+    if free_end > page_size
+        || records
+            .iter()
+            .any(|(start, end, _)| *start < free_end && free_start < *end)
+    {
+        problems.push(format!(
+            "Page {} of container {}: free space {}..{} overlaps the records",
+            pid, c_id, free_start, free_end
+        ));
+    }
+
+    // Overlapping records could add up to more than the page, so no get_free_space
+    let used: usize = records.iter().map(|(start, end, _)| end - start).sum();
+    let free_space = page_size.saturating_sub(header_size + used);
+    if let Some(mapped) = hf.fsm.free_space(pid) {
+        if mapped > free_space {
+            problems.push(format!(
+                "Page {} of container {}: free space map has {} bytes free, page has {}",
+                pid, c_id, mapped, free_space
+            ));
+        }
+    }
+}
+
+/// Page and slot of a record
+type Location = (PageNo, SlotId);
+
+/// The overflow stubs and chunks of a heap file
+#[derive(Default)]
+struct OverflowRecords {
+    /// Location, length and first chunk of each overflow stub
+    stubs: Vec<(Location, u32, Location)>,
+    /// Chunks not reached by a chain yet
+    chunks: BTreeSet<Location>,
+}
+
+impl OverflowRecords {
+    /// Decode the records of a page with a sound header, keeping its stubs and chunks
+    fn collect(&mut self, hf: &HeapFile, page: Page, problems: &mut Vec<String>) {
+        let pid = page.get_page_id();
+        for (bytes, slot) in page {
+            match StoredRecord::from_bytes(&bytes) {
+                Ok(StoredRecord::Inline(_)) => {}
+                Ok(StoredRecord::Overflow { len, first }) => {
+                    self.stubs.push(((pid, slot), len, first))
+                }
+                Ok(StoredRecord::Chunk { .. }) => {
+                    self.chunks.insert((pid, slot));
+                }
+                Err(e) => problems.push(format!(
+                    "Page {} of container {}: slot {} cannot be decoded: {}",
+                    pid, hf.container_id, slot, e
+                )),
+            }
+        }
+    }
+
+    /// Follow the chain of every stub, then report the chunks no chain reached
+    fn check_chains(mut self, hf: &HeapFile, problems: &mut Vec<String>) {
+        let c_id = hf.container_id;
+        for ((pid, slot), len, first) in &self.stubs {
+            let mut stored = 0;
+            let mut broken = None;
+            for (chunk, data) in overflow::chain(*first, *len, |p| hf.read_page_from_file(p)) {
+                match data {
+                    Err(e) => broken = Some(e.to_string()),
+                    // A chunk reached twice is shared with another chain or part of a cycle
+                    Ok(_) if !self.chunks.remove(&chunk) => {
+                        broken = Some(format!("chunk {}:{} is reached twice", chunk.0, chunk.1))
+                    }
+                    Ok(data) => stored += data.len(),
+                }
+                if broken.is_some() {
+                    break;
+                }
+            }
+            match broken {
+                Some(problem) => problems.push(format!(
+                    "Page {} of container {}: overflow chain of slot {} is broken: {}",
+                    pid, c_id, slot, problem
+                )),
+                None if stored != *len as usize => problems.push(format!(
+                    "Page {} of container {}: overflow chain of slot {} holds {} bytes, expected {}",
+                    pid, c_id, slot, stored, len
+                )),
+                None => {}
+            }
+        }
+        for (pid, slot) in self.chunks {
+            problems.push(format!(
+                "Page {} of container {}: overflow chunk in slot {} belongs to no record",
+                pid, c_id, slot
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use common::PAGE_SIZE;
+    use std::fs;
+
+    /// A container of small records with one value stored in an overflow chain
+    fn setup() -> (StorageManager, ContainerId, TransactionId, ValueId) {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        sm.insert_values(cid, get_random_vec_of_byte_vec(20, 50, 100), tid);
+        let big = sm.insert_value(cid, get_random_byte_vec(PAGE_SIZE * 3), tid);
+        assert!(sm.check_container(cid).unwrap().is_empty());
+        (sm, cid, tid, big)
+    }
+
+    #[test]
+    fn hs_check_page_overlapping_slots() {
+        let (sm, cid, tid, _) = setup();
+        let hf = sm.open_hf(cid).unwrap();
+        let mut page = sm
+            .get_page(cid, 0, tid, Permissions::ReadOnly, false)
+            .unwrap();
+        let mut problems = Vec::new();
+        check_page(&hf, &page, &mut problems);
+        assert!(problems.is_empty());
+
+        let mut header = page.read_header();
+        header.slots[0].offset_of_record -= 1;
+        page.write_header(&header);
+        check_page(&hf, &page, &mut problems);
+        assert_eq!(1, problems.len());
+        assert!(problems[0].contains("slots 1 and 0 overlap"));
+    }
+
+    #[test]
+    fn hs_check_container_bad_checksum() {
+        let (sm, cid, _, _) = setup();
+        sm.clear_cache();
+        let hf_path = sm.hf_path(cid);
+        let mut hf_bytes = fs::read(&hf_path).unwrap();
+        hf_bytes[PAGE_SIZE - 1] ^= 0xff;
+        fs::write(&hf_path, &hf_bytes).unwrap();
+
+        // The overflow stub is on the corrupt page, so its chunks are reached by no chain
+        let problems = sm.check_container(cid).unwrap();
+        assert!(problems[0].contains("checksum"));
+        assert!(problems[1..]
+            .iter()
+            .all(|p| p.contains("belongs to no record")));
+    }
+
+    #[test]
+    fn hs_check_container_dangling_overflow_chain() {
+        let (sm, cid, tid, big) = setup();
+        let hf = sm.open_hf(cid).unwrap();
+        let stub_page = hf.page_no(big.segment_id, big.page_id.unwrap());
+        let stub = sm
+            .get_page(cid, stub_page, tid, Permissions::ReadOnly, false)
+            .unwrap()
+            .get_value(big.slot_id.unwrap())
+            .unwrap();
+        let first = match StoredRecord::from_bytes(&stub).unwrap() {
+            StoredRecord::Overflow { first, .. } => first,
+            other => panic!("Expected an overflow stub, found {:?}", other),
+        };
+
+        // Drop the first chunk, so the chain dangles and the rest of it belongs to no record
+        let mut page = sm
+            .get_page(cid, first.0, tid, Permissions::ReadOnly, false)
+            .unwrap();
+        page.delete_value(first.1).unwrap();
+        sm.write_page(cid, &page, tid).unwrap();
+        let problems = sm.check_container(cid).unwrap();
+        assert!(problems[0].contains("overflow chain of slot"));
+        assert!(problems[0].contains("Missing overflow chunk"));
+        assert!(problems.len() > 1);
+        assert!(problems[1..]
+            .iter()
+            .all(|p| p.contains("belongs to no record")));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::heap_page::HeapPage;
+use crate::heapfile::PageNo;
+use crate::page::Page;
+use common::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+/// Categories a page's free space is split into
+const CATEGORIES: usize = 256;
+
+/// A free-space map for one heap file.
+///
+/// The map holds one byte per page, the page's free space rounded down to a 256th of the page
+/// size, so a page is never reported with more space than it has. The map is kept in memory, updated on
+/// every page write, and saved next to the heap file as `<container>.fsm` when the storage manager
+/// syncs. A missing or stale file is rebuilt from the pages when the heap file is opened.
+pub(crate) struct FreeSpaceMap {
+    path: PathBuf,
+    categories: RwLock<Vec<u8>>,
+    dirty: AtomicBool,
+    /// Bytes of free space represented by one step of a page's category
+    unit: usize,
+}
+
+impl FreeSpaceMap {
+    /// Open the map saved at path for pages of page_size bytes, or an empty map if there is none.
+    pub(crate) fn open(path: PathBuf, page_size: usize) -> Self {
+        FreeSpaceMap {
+            categories: RwLock::new(fs::read(&path).unwrap_or_default()),
+            path,
+            dirty: AtomicBool::new(false),
+            unit: page_size / CATEGORIES,
+        }
+    }
+
+    /// Rebuild the map with read_page if it does not cover exactly num_pages, which happens when
+    /// the map was not saved after the heap file last changed.
+    pub(crate) fn validate<F>(&self, num_pages: PageNo, read_page: F)
+    where
+        F: Fn(PageNo) -> Result<Page, CrustyError>,
+    {
+        if self.num_pages() != num_pages as usize {
+            debug!("Rebuilding free space map {:?}", self.path);
+            self.rebuild(num_pages, read_page);
+        }
+    }
+
+    /// Path of the map for the heap file at hf_path
+    pub(crate) fn path_for(hf_path: &Path) -> PathBuf {
+        hf_path.with_extension("fsm")
+    }
+
+    fn category(&self, free_space: usize) -> u8 {
+        (free_space / self.unit).min(u8::MAX as usize) as u8
+    }
+
+    /// Recompute the category of every page. Pages that cannot be read are marked full.
+    pub(crate) fn rebuild<F>(&self, num_pages: PageNo, read_page: F)
+    where
+        F: Fn(PageNo) -> Result<Page, CrustyError>,
+    {
+        let categories = (0..num_pages)
+            .map(|pid| {
+                read_page(pid)
+                    .map(|p| self.category(p.get_free_space()))
+                    .unwrap_or(0)
+            })
+            .collect();
+        *self.categories.write().unwrap() = categories;
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// Record the free space of a page, growing the map for new pages.
+    pub(crate) fn update(&self, page_id: PageNo, free_space: usize) {
+        let mut categories = self.categories.write().unwrap();
+        let idx = page_id as usize;
+        if idx >= categories.len() {
+            categories.resize(idx + 1, 0);
+        }
+        categories[idx] = self.category(free_space);
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// A lower bound of the free space of a page
+    pub(crate) fn free_space(&self, page_id: PageNo) -> Option<usize> {
+        self.categories
+            .read()
+            .unwrap()
+            .get(page_id as usize)
+            .map(|c| *c as usize * self.unit)
+    }
+
+    /// Find the first page after `after` (or from the start) with at least `need` bytes free.
+    pub(crate) fn find_page(&self, need: usize, after: Option<PageNo>) -> Option<PageNo> {
+        let start = after.map_or(0, |p| p as usize + 1);
+        self.categories
+            .read()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .skip(start)
+            .find(|(_, c)| **c as usize * self.unit >= need)
+            .map(|(pid, _)| pid as PageNo)
+    }
+
+    /// Forget the pages from num_pages on, after the heap file was truncated.
+    pub(crate) fn truncate(&self, num_pages: PageNo) {
+        self.categories
+            .write()
+            .unwrap()
+            .truncate(num_pages as usize);
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// Number of pages tracked
+    pub(crate) fn num_pages(&self) -> usize {
+        self.categories.read().unwrap().len()
+    }
+
+    /// Write the map next to the heap file if it changed, replacing the old file atomically.
+    pub(crate) fn save(&self) -> Result<(), CrustyError> {
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let tmp = self.path.with_extension("fsm.tmp");
+        fs::write(&tmp, &*self.categories.read()?)?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Remove the saved map
+    pub(crate) fn remove(&self) -> Result<(), CrustyError> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::*;
+    use common::PAGE_SIZE;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_fsm_find_and_persist() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let path = tdir.join("1.fsm");
+        let fsm = FreeSpaceMap::open(path.clone(), PAGE_SIZE);
+        assert_eq!(None, fsm.find_page(10, None));
+        fsm.update(0, 100);
+        fsm.update(1, 15);
+        fsm.update(2, 2000);
+        assert_eq!(Some(0), fsm.find_page(90, None));
+        assert_eq!(Some(2), fsm.find_page(90, Some(0)));
+        assert_eq!(Some(2), fsm.find_page(101, None));
+        assert_eq!(None, fsm.find_page(3000, None));
+        // Rounded down, never over reports
+        assert_eq!(Some(96), fsm.free_space(0));
+        assert_eq!(Some(0), fsm.free_space(1));
+        fsm.save().unwrap();
+
+        let fsm = FreeSpaceMap::open(path.clone(), PAGE_SIZE);
+        fsm.validate(3, |_| unreachable!());
+        assert_eq!(Some(2), fsm.find_page(101, None));
+
+        // A map that does not cover the file is rebuilt
+        let fsm = FreeSpaceMap::open(path, PAGE_SIZE);
+        fsm.validate(4, |pid| Ok(Page::new(pid)));
+        assert_eq!(4, fsm.num_pages());
+        assert_eq!(Some(0), fsm.find_page(3000, None));
+    }
+}
@@ -1,11 +1,18 @@
+use crate::heapfile::PageNo;
 use crate::page;
 use crate::page::{Offset, Page};
 use common::prelude::*;
-use common::PAGE_SIZE;
 use std::fmt;
 use std::fmt::Write;
 
 // Add any other constants, type aliases, or structs, or definitions here
+/// Size of the fixed page metadata at the start of every page
+pub(crate) const PAGE_METADATA_SIZE: usize = 22;
+/// Where the checksum is within the page metadata
+pub(crate) const CHECKSUM_OFFSET: usize = 18;
+/// Size of each slot entry in the header
+pub(crate) const SLOT_ENTRY_SIZE: usize = 8;
+
 /// Page Header struct
 #[derive(Debug, Clone)]
 pub struct Header {
@@ -14,26 +21,34 @@ pub struct Header {
 }
 
 /// PageMetadata struct
-/// Every member's size is 2 bytes
+/// Every member's size is 2 bytes, except the page id and checksum which take 4 and the lsn
+/// which takes 8. The free space always starts in the header and ends before the end of the
+/// page, so u16 is enough even for MAX_PAGE_SIZE pages.
 #[derive(Debug, Clone)]
 pub struct PageMetadata {
-    /// The page id
-    pub page_id: PageId,
+    /// The number of the page in its heap file
+    pub page_id: PageNo,
     /// The number of slots in the page
     pub num_slots: u16,
     /// The pointer to the begin of the free space
     pub offset_of_free_space: u16,
     /// The rest size of free space in the page
     pub size_of_free_space: u16,
+    /// The LSN of the last log record applied to the page
+    pub lsn: Lsn,
+    /// CRC32 of the page with this field zeroed. It is set when the page is written to its heap
+    /// file, checked and zeroed again when the page is read back, so it is 0 in memory.
+    pub checksum: u32,
 }
 
 /// Slot Metadata struct
+/// The record offset takes 4 bytes, as an empty slot points at the end of the page.
 #[derive(Debug, Clone)]
 pub struct Slot {
     /// The slotid
     pub slot_id: u16,
     /// The pointer to the begin of the record
-    pub offset_of_record: u16,
+    pub offset_of_record: Offset,
     /// The size of the record
     pub size_of_record: u16,
 }
@@ -46,15 +61,19 @@ impl PageMetadata {
         bytes.extend(&self.num_slots.to_le_bytes());
         bytes.extend(&self.offset_of_free_space.to_le_bytes());
         bytes.extend(&self.size_of_free_space.to_le_bytes());
+        bytes.extend(&self.lsn.offset().to_le_bytes());
+        bytes.extend(&self.checksum.to_le_bytes());
         bytes
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Self {
         PageMetadata {
-            page_id: u16::from_le_bytes(bytes[0..2].try_into().unwrap()),
-            num_slots: u16::from_le_bytes(bytes[2..4].try_into().unwrap()),
-            offset_of_free_space: u16::from_le_bytes(bytes[4..6].try_into().unwrap()),
-            size_of_free_space: u16::from_le_bytes(bytes[6..8].try_into().unwrap()),
+            page_id: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            num_slots: u16::from_le_bytes(bytes[4..6].try_into().unwrap()),
+            offset_of_free_space: u16::from_le_bytes(bytes[6..8].try_into().unwrap()),
+            size_of_free_space: u16::from_le_bytes(bytes[8..10].try_into().unwrap()),
+            lsn: Lsn::from_offset(u64::from_le_bytes(bytes[10..18].try_into().unwrap())),
+            checksum: u32::from_le_bytes(bytes[18..22].try_into().unwrap()),
         }
     }
 }
@@ -71,8 +90,8 @@ impl Slot {
     pub fn from_bytes(bytes: &[u8]) -> Self {
         Slot {
             slot_id: u16::from_le_bytes([bytes[0], bytes[1]]),
-            offset_of_record: u16::from_le_bytes([bytes[2], bytes[3]]),
-            size_of_record: u16::from_le_bytes([bytes[4], bytes[5]]),
+            offset_of_record: Offset::from_le_bytes(bytes[2..6].try_into().unwrap()),
+            size_of_record: u16::from_le_bytes([bytes[6], bytes[7]]),
         }
     }
 }
@@ -91,6 +110,9 @@ pub trait HeapPage {
     fn write_header(&mut self, header: &Header);
     fn compact(&mut self);
     fn find_next_useable_slot(&self, slot_id: SlotId) -> Option<SlotId>;
+    fn put_value(&mut self, slot_id: SlotId, bytes: &[u8]) -> Option<()>;
+    fn get_lsn(&self) -> Lsn;
+    fn set_lsn(&mut self, lsn: Lsn);
 }
 
 impl HeapPage for Page {
@@ -123,7 +145,6 @@ impl HeapPage for Page {
     /// They must have the same size.
     /// self.data[X..y].clone_from_slice(&bytes);
     fn add_value(&mut self, bytes: &[u8]) -> Option<SlotId> {
-        const SLOT_ENTRY_SIZE: usize = 6;
         // Read header
         let mut hdr = self.read_header();
         // Total free space (scattered)
@@ -142,7 +163,7 @@ impl HeapPage for Page {
             // Now insert
             let free_start = hdr.pagemetadata.offset_of_free_space as usize;
             let free_size = hdr.pagemetadata.size_of_free_space as usize;
-            let new_off = (free_start + free_size - bytes.len()) as u16;
+            let new_off = (free_start + free_size - bytes.len()) as Offset;
             let start = new_off as usize;
             self.data[start..start + bytes.len()].copy_from_slice(bytes);
             hdr.slots[idx].offset_of_record = new_off;
@@ -163,7 +184,7 @@ impl HeapPage for Page {
         }
         let free_start = hdr.pagemetadata.offset_of_free_space as usize;
         let free_size = hdr.pagemetadata.size_of_free_space as usize;
-        let new_off = (free_start + free_size - bytes.len()) as u16;
+        let new_off = (free_start + free_size - bytes.len()) as Offset;
         let start = new_off as usize;
         self.data[start..start + bytes.len()].copy_from_slice(bytes);
         let new_id = hdr.pagemetadata.num_slots;
@@ -214,6 +235,57 @@ impl HeapPage for Page {
         None
     }
 
+    /// Store the bytes in a specific slot, replacing any value already there. Slots past the
+    /// end of the header are created as empty slots. Used to redo and undo logged changes, so
+    /// a value ends up in the same slot it was logged with.
+    /// Returns None if there is not enough space.
+    fn put_value(&mut self, slot_id: SlotId, bytes: &[u8]) -> Option<()> {
+        if self.get_value(slot_id).is_some() {
+            self.delete_value(slot_id);
+        }
+        let mut hdr = self.read_header();
+        let new_slots = (slot_id as usize + 1).saturating_sub(hdr.slots.len());
+        if self.get_free_space() < bytes.len() + new_slots * SLOT_ENTRY_SIZE {
+            return None;
+        }
+        if (hdr.pagemetadata.size_of_free_space as usize)
+            < bytes.len() + new_slots * SLOT_ENTRY_SIZE
+        {
+            self.compact();
+            hdr = self.read_header();
+        }
+        let free_start = hdr.pagemetadata.offset_of_free_space as usize;
+        let free_end = free_start + hdr.pagemetadata.size_of_free_space as usize;
+        for id in hdr.slots.len()..slot_id as usize + 1 {
+            hdr.slots.push(Slot {
+                slot_id: id as SlotId,
+                offset_of_record: free_end as Offset,
+                size_of_record: 0,
+            });
+        }
+        let new_off = free_end - bytes.len();
+        self.data[new_off..free_end].copy_from_slice(bytes);
+        hdr.slots[slot_id as usize].offset_of_record = new_off as Offset;
+        hdr.slots[slot_id as usize].size_of_record = bytes.len() as u16;
+        hdr.pagemetadata.num_slots = hdr.slots.len() as u16;
+        hdr.pagemetadata.offset_of_free_space = (free_start + new_slots * SLOT_ENTRY_SIZE) as u16;
+        hdr.pagemetadata.size_of_free_space =
+            (free_end - bytes.len() - free_start - new_slots * SLOT_ENTRY_SIZE) as u16;
+        self.write_header(&hdr);
+        Some(())
+    }
+
+    /// The LSN of the last logged change applied to this page
+    fn get_lsn(&self) -> Lsn {
+        self.get_metadata().lsn
+    }
+
+    fn set_lsn(&mut self, lsn: Lsn) {
+        let mut meta = self.get_metadata();
+        meta.lsn = lsn;
+        self.data[0..PAGE_METADATA_SIZE].copy_from_slice(&meta.to_bytes());
+    }
+
     /// get the pagemetadata
     fn get_metadata(&self) -> PageMetadata {
         self.read_header().pagemetadata
@@ -224,7 +296,7 @@ impl HeapPage for Page {
     /// Will be used by tests.
     fn get_header_size(&self) -> usize {
         let metadata = self.get_metadata();
-        metadata.num_slots as usize * 6 + 8
+        metadata.num_slots as usize * SLOT_ENTRY_SIZE + PAGE_METADATA_SIZE
     }
 
     /// A utility function to determine the total current free space in the page.
@@ -240,18 +312,18 @@ impl HeapPage for Page {
             .map(|slot| slot.size_of_record as usize)
             .sum();
 
-        PAGE_SIZE - self.get_header_size() - total_used
+        self.page_size() - self.get_header_size() - total_used
     }
 
     /// read the header from the page
     fn read_header(&self) -> Header {
-        let meta = PageMetadata::from_bytes(&self.data[0..8]);
+        let meta = PageMetadata::from_bytes(&self.data[0..PAGE_METADATA_SIZE]);
         let mut slots = Vec::with_capacity(meta.num_slots as usize);
-        let mut offset = 8;
+        let mut offset = PAGE_METADATA_SIZE;
         for _ in 0..meta.num_slots {
-            let slot = Slot::from_bytes(&self.data[offset..offset + 6]);
+            let slot = Slot::from_bytes(&self.data[offset..offset + SLOT_ENTRY_SIZE]);
             slots.push(slot);
-            offset += 6;
+            offset += SLOT_ENTRY_SIZE;
         }
         Header {
             pagemetadata: meta,
@@ -262,12 +334,12 @@ impl HeapPage for Page {
     /// write the header to the page
     fn write_header(&mut self, header: &Header) {
         let meta_bytes = header.pagemetadata.to_bytes();
-        self.data[0..8].copy_from_slice(&meta_bytes);
-        let mut offset = 8;
+        self.data[0..PAGE_METADATA_SIZE].copy_from_slice(&meta_bytes);
+        let mut offset = PAGE_METADATA_SIZE;
         for slot in &header.slots {
             let slot_bytes = slot.to_bytes();
-            self.data[offset..offset + 6].copy_from_slice(&slot_bytes);
-            offset += 6;
+            self.data[offset..offset + SLOT_ENTRY_SIZE].copy_from_slice(&slot_bytes);
+            offset += SLOT_ENTRY_SIZE;
         }
     }
 
@@ -282,23 +354,23 @@ impl HeapPage for Page {
             .collect();
         valid.sort_by_key(|s| s.offset_of_record);
         // move data to page end
-        let mut write_pos = PAGE_SIZE;
+        let mut write_pos = self.page_size();
         for slot in valid.iter_mut().rev() {
             let sz = slot.size_of_record as usize;
             write_pos -= sz;
             let old_start = slot.offset_of_record as usize;
             let tmp = self.data[old_start..old_start + sz].to_vec();
             self.data[write_pos..write_pos + sz].copy_from_slice(&tmp);
-            slot.offset_of_record = write_pos as u16;
+            slot.offset_of_record = write_pos as Offset;
         }
         // reset free space metadata
-        let header_size = 8 + (hdr.pagemetadata.num_slots as usize) * 6;
+        let header_size =
+            PAGE_METADATA_SIZE + (hdr.pagemetadata.num_slots as usize) * SLOT_ENTRY_SIZE;
         hdr.pagemetadata.offset_of_free_space = header_size as u16;
         hdr.pagemetadata.size_of_free_space = (write_pos - header_size) as u16;
         // reset deleted slots' offset
         for slot in hdr.slots.iter_mut().filter(|s| s.size_of_record == 0) {
-            slot.offset_of_record =
-                hdr.pagemetadata.offset_of_free_space + hdr.pagemetadata.size_of_free_space;
+            slot.offset_of_record = write_pos as Offset;
         }
         self.write_header(&hdr);
     }
@@ -359,14 +431,16 @@ mod tests {
     use std::collections::VecDeque;
 
     use super::*;
+    use crate::page::{MAX_PAGE_SIZE, MIN_PAGE_SIZE};
     use common::testutil::init;
     use common::testutil::*;
     use common::Tuple;
+    use common::PAGE_SIZE;
     use rand::Rng;
 
     /// Limits how on how many bytes we can use for page metadata / header
-    pub const FIXED_HEADER_SIZE: usize = 8;
-    pub const HEADER_PER_VAL_SIZE: usize = 6;
+    pub const FIXED_HEADER_SIZE: usize = PAGE_METADATA_SIZE;
+    pub const HEADER_PER_VAL_SIZE: usize = SLOT_ENTRY_SIZE;
 
     #[test]
     fn hs_page_sizes_header_free_space() {
@@ -471,7 +545,7 @@ mod tests {
     #[test]
     fn hs_page_header_size_small() {
         init();
-        // Testing that the header is no more than 8 bytes for the header, and 6 bytes per value inserted
+        // Testing that the header is no more than 22 bytes for the header, and 8 bytes per value inserted
         let mut p = Page::new(0);
         assert!(p.get_header_size() <= FIXED_HEADER_SIZE);
         let bytes = get_random_byte_vec(10);
@@ -486,17 +560,17 @@ mod tests {
     #[test]
     fn hs_page_header_size_full() {
         init();
-        // Testing that the header is no more than 8 bytes for the header, and 6 bytes per value inserted
+        // Testing that the header is no more than 22 bytes for the header, and 8 bytes per value inserted
         let mut p = Page::new(0);
         assert!(p.get_header_size() <= FIXED_HEADER_SIZE);
         let byte_size = 10;
         let bytes = get_random_byte_vec(byte_size);
-        // how many vals can we hold with 8 bytes
+        // how many vals can we hold with 22 bytes
         let num_vals: usize = (((PAGE_SIZE - FIXED_HEADER_SIZE) as f64
             / (byte_size + HEADER_PER_VAL_SIZE) as f64)
             .floor()) as usize;
-        if PAGE_SIZE == 4096 && FIXED_HEADER_SIZE == 8 && HEADER_PER_VAL_SIZE == 6 {
-            assert_eq!(255, num_vals);
+        if PAGE_SIZE == 4096 && FIXED_HEADER_SIZE == 22 && HEADER_PER_VAL_SIZE == 8 {
+            assert_eq!(226, num_vals);
         }
         for _ in 0..num_vals {
             p.add_value(&bytes);
@@ -652,6 +726,41 @@ mod tests {
         assert_eq!(PAGE_SIZE, page_bytes.len());
     }
 
+    #[test]
+    fn hs_page_sized() {
+        init();
+        for page_size in [MIN_PAGE_SIZE, 16384, MAX_PAGE_SIZE] {
+            let mut p = Page::new_sized(5, page_size);
+            assert_eq!(page_size, p.to_bytes().len());
+            assert_eq!(page_size - FIXED_HEADER_SIZE, p.get_free_space());
+
+            // The largest record fills the page
+            let big = get_random_byte_vec(page_size - FIXED_HEADER_SIZE - HEADER_PER_VAL_SIZE);
+            assert_eq!(Some(0), p.add_value(&big));
+            assert_eq!(0, p.get_free_space());
+            assert_eq!(Some(big), p.get_value(0));
+            assert_eq!(None, p.add_value(&[1]));
+
+            // Empty slots of an empty page point at the end of the page
+            assert_eq!(Some(()), p.delete_value(0));
+            p.compact();
+            assert_eq!(
+                page_size,
+                p.read_header().slots[0].offset_of_record as usize
+            );
+            let bytes = get_random_byte_vec(100);
+            assert_eq!(Some(()), p.put_value(3, &bytes));
+            assert_eq!(
+                page_size,
+                p.read_header().slots[2].offset_of_record as usize
+            );
+            assert_eq!(Some(0), p.add_value(&bytes));
+            assert_eq!(Some(bytes.clone()), p.get_value(0));
+            assert_eq!(Some(bytes), p.get_value(3));
+            assert_eq!(page_size - p.get_header_size() - 200, p.get_free_space());
+        }
+    }
+
     #[test]
     fn hs_page_simple_byte_serialize() {
         init();
@@ -701,7 +810,7 @@ mod tests {
         let p0_bytes = p0.to_bytes();
 
         // Reconstruct the page
-        let p1 = Page::from_bytes(*p0_bytes);
+        let p1 = Page::from_bytes(p0_bytes.to_vec());
         let p1_bytes = p1.to_bytes();
 
         // Enforce that the two pages serialize determinestically
@@ -734,7 +843,7 @@ mod tests {
             tuple_bytes3.clone(),
             tuple_bytes4.clone(),
         ];
-        let page_bytes = *p.to_bytes();
+        let page_bytes = p.to_bytes().to_vec();
 
         // Test iteration 1
         let mut iter = p.into_iter();
@@ -745,14 +854,14 @@ mod tests {
         assert_eq!(None, iter.next());
 
         //Check another way
-        let p = Page::from_bytes(page_bytes);
+        let p = Page::from_bytes(page_bytes.clone());
         assert_eq!(Some(tuple_bytes.clone()), p.get_value(0));
 
         for (i, x) in p.into_iter().enumerate() {
             assert_eq!(tup_vec[i], x.0);
         }
 
-        let p = Page::from_bytes(page_bytes);
+        let p = Page::from_bytes(page_bytes.clone());
         let mut count = 0;
         for _ in p {
             count += 1;
@@ -760,10 +869,10 @@ mod tests {
         assert_eq!(count, 4);
 
         //Add a value and check
-        let mut p = Page::from_bytes(page_bytes);
+        let mut p = Page::from_bytes(page_bytes.clone());
         assert_eq!(Some(4), p.add_value(&tuple_bytes));
         //get the updated bytes
-        let page_bytes = *p.to_bytes();
+        let page_bytes = p.to_bytes().to_vec();
         count = 0;
         for _ in p {
             count += 1;
@@ -771,7 +880,7 @@ mod tests {
         assert_eq!(count, 5);
 
         //Delete
-        let mut p = Page::from_bytes(page_bytes);
+        let mut p = Page::from_bytes(page_bytes.clone());
         p.delete_value(2);
         let mut iter = p.into_iter();
         assert_eq!(Some((tuple_bytes.clone(), 0)), iter.next());
@@ -894,6 +1003,39 @@ mod tests {
         assert_eq!(values[7], p4.get_value(7).unwrap());
     }
 
+    #[test]
+    pub fn hs_page_put_value_and_lsn() {
+        init();
+        let mut p = Page::new(0);
+        let bytes = get_random_byte_vec(20);
+        assert_eq!(Lsn::default(), p.get_lsn());
+
+        // Putting past the end creates the empty slots in between
+        assert_eq!(Some(()), p.put_value(3, &bytes));
+        assert_eq!(4, p.get_metadata().num_slots);
+        assert_eq!(Some(bytes.clone()), p.get_value(3));
+        assert_eq!(None, p.get_value(1));
+        assert_eq!(
+            PAGE_SIZE - p.get_header_size() - bytes.len(),
+            p.get_free_space()
+        );
+
+        // Replacing keeps the slot, new inserts use the empty slots first
+        let bytes2 = get_random_byte_vec(30);
+        assert_eq!(Some(()), p.put_value(3, &bytes2));
+        assert_eq!(Some(bytes2), p.get_value(3));
+        assert_eq!(Some(0), p.add_value(&bytes));
+
+        let lsn = Lsn::new(7, 12);
+        p.set_lsn(lsn);
+        assert_eq!(lsn, p.get_lsn());
+        assert_eq!(lsn, Page::from_bytes(p.to_bytes().to_vec()).get_lsn());
+        assert_eq!(Some(bytes), p.get_value(0));
+
+        let big = get_random_byte_vec(PAGE_SIZE);
+        assert_eq!(None, p.put_value(1, &big));
+    }
+
     #[test]
     pub fn hs_page_stress_test() {
         init();
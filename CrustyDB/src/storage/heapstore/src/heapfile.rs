@@ -1,16 +1,53 @@
+use crate::fsm::FreeSpaceMap;
 use crate::heap_page::HeapPage;
 use crate::page::Page;
+use common::ids::SegmentId;
 use common::prelude::*;
-use common::PAGE_SIZE;
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU16, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 use std::io::BufWriter;
 use std::io::{Seek, SeekFrom};
 
+/// Number of a page within a heap file. Page numbers run on across the segment files of a heap
+/// file, while a ValueId names the same page by its segment and its page id in the segment.
+pub(crate) type PageNo = u32;
+
+/// Pages in a segment file by default, the most a PageId can address
+pub(crate) const SEGMENT_PAGES: PageNo = PageId::MAX as PageNo + 1;
+
+/// Most segment files a heap file can have, the most a SegmentId can address
+const MAX_SEGMENTS: usize = SegmentId::MAX as usize + 1;
+
+/// Path of a segment file of the heap file at path. The first segment is the file at path, the
+/// others add their number to it.
+pub(crate) fn segment_path(path: &Path, segment: usize) -> PathBuf {
+    if segment == 0 {
+        return path.to_path_buf();
+    }
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{}", segment));
+    PathBuf::from(path)
+}
+
+/// Remove every segment file of the heap file at path, last first, and its free-space map
+pub(crate) fn remove_files(path: &Path) -> Result<(), CrustyError> {
+    let segments = (0..MAX_SEGMENTS)
+        .take_while(|s| segment_path(path, *s).exists())
+        .count();
+    for segment in (0..segments).rev() {
+        fs::remove_file(segment_path(path, segment))?;
+    }
+    let fsm_path = FreeSpaceMap::path_for(path);
+    if fsm_path.exists() {
+        fs::remove_file(fsm_path)?;
+    }
+    Ok(())
+}
+
 /// The struct for a heap file.  
 ///
 /// HINT: You likely will want to design for interior mutability for concurrent accesses.
@@ -22,13 +59,26 @@ use std::io::{Seek, SeekFrom};
 ///
 /// Your code should persist what information is needed to recreate the heapfile.
 ///
+/// The pages of a heap file are split into segment files of segment_pages pages, so a container
+/// is not limited to the pages a PageId can address. Every segment but the last one is full.
 pub(crate) struct HeapFile {
     // TODO milestone hs
     // Add any fields you need to maintain state for a HeapFile
-    file: Arc<RwLock<File>>,
+    segments: Arc<RwLock<Vec<File>>>,
+    // Path of the first segment file
+    path: PathBuf,
+    // Pages in every segment file
+    segment_pages: PageNo,
+    // Bytes in every page
+    pub(crate) page_size: usize,
 
     // Track this HeapFile's container Id
     pub container_id: ContainerId,
+    // Free space of every page, used to find a page for an insert
+    pub(crate) fsm: FreeSpaceMap,
+    // Held while a page is read, modified and written back, so concurrent writers of the
+    // container do not overwrite each other's changes
+    pub(crate) write_latch: Mutex<()>,
     // The following are for profiling/ correctness checks
     pub read_count: AtomicU16,
     pub write_count: AtomicU16,
@@ -38,90 +88,161 @@ pub(crate) struct HeapFile {
 impl HeapFile {
     /// Create a new heapfile for the given path. Return Result<Self> if able to create.
     /// Errors could arise from permissions, space, etc when trying to create the file used by HeapFile.
-    pub(crate) fn new(file_path: PathBuf, container_id: ContainerId) -> Result<Self, CrustyError> {
-        let file = match OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&file_path)
-        {
-            Ok(f) => f,
-            Err(error) => {
-                return Err(CrustyError::CrustyError(format!(
-                    "Cannot open or create heap file: {} {:?}",
-                    file_path.to_string_lossy(),
-                    error
-                )))
+    /// The segment files after the first one are opened if they exist.
+    pub(crate) fn new(
+        file_path: PathBuf,
+        container_id: ContainerId,
+        page_size: usize,
+        segment_pages: PageNo,
+    ) -> Result<Self, CrustyError> {
+        let mut segments = vec![Self::open_segment(&file_path)?];
+        while segments.len() < MAX_SEGMENTS {
+            let path = segment_path(&file_path, segments.len());
+            if !path.exists() {
+                break;
             }
-        };
+            segments.push(Self::open_segment(&path)?);
+        }
 
         // TODO milestone hs
         // Initialize the HeapFile
 
-        Ok(HeapFile {
+        let hf = HeapFile {
             //TODO milestone hs
             // Add your fields here
             container_id,
+            fsm: FreeSpaceMap::open(FreeSpaceMap::path_for(&file_path), page_size),
+            write_latch: Mutex::new(()),
             read_count: AtomicU16::new(0),
             write_count: AtomicU16::new(0),
-            file: Arc::new(RwLock::new(file)),
-        })
+            segments: Arc::new(RwLock::new(segments)),
+            path: file_path,
+            segment_pages,
+            page_size,
+        };
+        hf.fsm
+            .validate(hf.num_pages(), |pid| hf.read_page_from_file(pid));
+        Ok(hf)
+    }
+
+    /// Open or create a segment file
+    fn open_segment(path: &Path) -> Result<File, CrustyError> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|error| {
+                CrustyError::CrustyError(format!(
+                    "Cannot open or create heap file: {} {:?}",
+                    path.to_string_lossy(),
+                    error
+                ))
+            })
     }
 
-    /// Return the number of pages for this HeapFile.
-    /// Return type is PageId (alias for another type) as we cannot have more
-    /// pages than PageId can hold.
-    pub fn num_pages(&self) -> PageId {
+    /// Return the number of pages for this HeapFile, across its segments.
+    pub fn num_pages(&self) -> PageNo {
         // panic!("TODO milestone hs");
-        let file = self.file.read().unwrap();
-        match file.metadata() {
-            Ok(meta) => (meta.len() / PAGE_SIZE as u64) as PageId,
+        let segments = self.segments.read().unwrap();
+        let last = segments.len() - 1;
+        let last_pages = match segments[last].metadata() {
+            Ok(meta) => (meta.len() / self.page_size as u64) as PageNo,
             Err(_) => 0,
+        };
+        last as PageNo * self.segment_pages + last_pages
+    }
+
+    /// Most pages the heap file can hold
+    pub(crate) fn max_pages(&self) -> PageNo {
+        (MAX_SEGMENTS as u64 * self.segment_pages as u64).min(PageNo::MAX as u64) as PageNo
+    }
+
+    /// The segment of a page and its byte offset in the segment file
+    fn locate(&self, pid: PageNo) -> (usize, u64) {
+        (
+            (pid / self.segment_pages) as usize,
+            (pid % self.segment_pages) as u64 * self.page_size as u64,
+        )
+    }
+
+    /// The page number of page page_id of a segment, as named by a value id
+    pub(crate) fn page_no(&self, segment_id: Option<SegmentId>, page_id: PageId) -> PageNo {
+        segment_id.unwrap_or(0) as PageNo * self.segment_pages + page_id as PageNo
+    }
+
+    /// The value id naming page pid. Pages in the first segment have no segment id.
+    pub(crate) fn page_value_id(&self, pid: PageNo) -> ValueId {
+        let segment = (pid / self.segment_pages) as SegmentId;
+        ValueId {
+            container_id: self.container_id,
+            segment_id: (segment > 0).then_some(segment),
+            page_id: Some((pid % self.segment_pages) as PageId),
+            slot_id: None,
+        }
+    }
+
+    /// The value id of a slot of page pid
+    pub(crate) fn value_id(&self, pid: PageNo, slot_id: SlotId) -> ValueId {
+        ValueId {
+            slot_id: Some(slot_id),
+            ..self.page_value_id(pid)
         }
     }
 
     /// Read the page from the file.
     /// Errors could arise from the filesystem or invalid pageId
     /// Note: that std::io::{Seek, SeekFrom} require Write locks on the underlying std::fs::File
-    pub(crate) fn read_page_from_file(&self, pid: PageId) -> Result<Page, CrustyError> {
+    pub(crate) fn read_page_from_file(&self, pid: PageNo) -> Result<Page, CrustyError> {
         //If profiling count reads
         #[cfg(feature = "profile")]
         {
             self.read_count.fetch_add(1, Ordering::Relaxed);
         }
         // panic!("TODO milestone hs");
-        let mut file = self.file.write().unwrap();
-        let offset = pid as u64 * PAGE_SIZE as u64;
+        let (segment, offset) = self.locate(pid);
+        let mut segments = self.segments.write().unwrap();
+        let file = segments.get_mut(segment).ok_or_else(|| {
+            CrustyError::CrustyError(format!("Page {} is in missing segment {}", pid, segment))
+        })?;
         file.seek(SeekFrom::Start(offset))
             .map_err(|_| CrustyError::CrustyError(format!("Invalid seek for page {}", pid)))?;
 
-        let mut buffer = [0u8; PAGE_SIZE];
+        let mut buffer = vec![0u8; self.page_size];
         let bytes_read = file.read(&mut buffer).map_err(|e| {
             CrustyError::CrustyError(format!("Failed to read page {}: {:?}", pid, e))
         })?;
 
-        if bytes_read != PAGE_SIZE {
+        if bytes_read != self.page_size {
             return Err(CrustyError::CrustyError(format!(
                 "Expected {} bytes for page {}, but read {} bytes",
-                PAGE_SIZE, pid, bytes_read
+                self.page_size, pid, bytes_read
             )));
         }
 
-        let page = Page::from_bytes(buffer);
+        let mut page = Page::from_bytes(buffer);
+        if !page.verify_checksum() {
+            return Err(CrustyError::CorruptPage(
+                self.page_value_id(pid),
+                String::from("checksum mismatch"),
+            ));
+        }
         let meta = page.get_metadata();
 
         if meta.page_id != pid {
-            return Err(CrustyError::CrustyError(format!(
-                "Page ID mismatch: expected {}, but found {} in metadata",
-                pid, meta.page_id
-            )));
+            return Err(CrustyError::CorruptPage(
+                self.page_value_id(pid),
+                format!("found page id {} in metadata", meta.page_id),
+            ));
         }
 
         Ok(page)
     }
 
     /// Take a page and write it to the underlying file.
-    /// This could be an existing page or a new page
+    /// This could be an existing page or a new page. Writing a page past the last segment
+    /// creates the segment files up to the page.
     pub(crate) fn write_page_to_file(&self, page: &Page) -> Result<(), CrustyError> {
         trace!(
             "Writing page {} to file {}",
@@ -134,33 +255,61 @@ impl HeapFile {
             self.write_count.fetch_add(1, Ordering::Relaxed);
         }
         // panic!("TODO milestone hs");
-        let mut file = self.file.write().unwrap();
-        let offset = page.get_metadata().page_id as u64 * PAGE_SIZE as u64;
+        let pid = page.get_page_id();
+        if pid >= self.max_pages() {
+            return Err(CrustyError::CrustyError(format!(
+                "Page {} is past the {} pages a heap file can hold",
+                pid,
+                self.max_pages()
+            )));
+        }
+        let (segment, offset) = self.locate(pid);
+        let mut segments = self.segments.write().unwrap();
+        while segments.len() <= segment {
+            let path = segment_path(&self.path, segments.len());
+            segments.push(Self::open_segment(&path)?);
+        }
+        let file = &mut segments[segment];
 
         file.seek(SeekFrom::Start(offset)).map_err(|_| {
-            CrustyError::CrustyError(format!(
-                "Failed to seek for writing page {}",
-                page.get_metadata().page_id
-            ))
+            CrustyError::CrustyError(format!("Failed to seek for writing page {}", pid))
         })?;
 
-        let data = &page.data;
-        if data.len() != PAGE_SIZE {
+        let data = &page.checksummed_bytes();
+        if data.len() != self.page_size {
             return Err(CrustyError::CrustyError(format!(
                 "Page {} has invalid size: expected {}, got {}",
-                page.get_metadata().page_id,
-                PAGE_SIZE,
+                pid,
+                self.page_size,
                 data.len()
             )));
         }
 
-        file.write_all(data).map_err(|e| {
-            CrustyError::CrustyError(format!(
-                "Failed to write page {}: {:?}",
-                page.get_metadata().page_id,
-                e
-            ))
-        })
+        file.write_all(data)
+            .map_err(|e| CrustyError::CrustyError(format!("Failed to write page {}: {:?}", pid, e)))
+    }
+
+    /// Cut the file down to its first num_pages pages, removing the segment files it no longer
+    /// needs, last first.
+    pub(crate) fn truncate(&self, num_pages: PageNo) -> Result<(), CrustyError> {
+        let keep = (num_pages.div_ceil(self.segment_pages) as usize).max(1);
+        let mut segments = self.segments.write().unwrap();
+        while segments.len() > keep {
+            segments.pop();
+            fs::remove_file(segment_path(&self.path, segments.len()))?;
+        }
+        let last_pages = num_pages - (keep as PageNo - 1) * self.segment_pages;
+        segments[keep - 1].set_len(last_pages as u64 * self.page_size as u64)?;
+        self.fsm.truncate(num_pages);
+        Ok(())
+    }
+
+    /// Force the file contents to stable storage.
+    pub(crate) fn sync(&self) -> Result<(), CrustyError> {
+        for file in self.segments.read().unwrap().iter() {
+            file.sync_data()?;
+        }
+        Ok(())
     }
 }
 
@@ -169,6 +318,7 @@ impl HeapFile {
 mod test {
     use super::*;
     use common::testutil::*;
+    use common::PAGE_SIZE;
     use temp_testdir::TempDir;
 
     #[test]
@@ -182,7 +332,8 @@ mod test {
         f.push(gen_rand_string(4));
         f.set_extension("hf");
 
-        let mut hf = HeapFile::new(f.to_path_buf(), 0).expect("Unable to create HF for test");
+        let mut hf = HeapFile::new(f.to_path_buf(), 0, PAGE_SIZE, SEGMENT_PAGES)
+            .expect("Unable to create HF for test");
 
         // Make a page and write
         let mut p0 = Page::new(0);
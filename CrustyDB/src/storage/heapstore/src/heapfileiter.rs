@@ -1,6 +1,9 @@
+use crate::buffer_pool::BufferPool;
 use crate::heap_page::HeapPage;
 use crate::heap_page::HeapPageIntoIter;
-use crate::heapfile::HeapFile;
+use crate::heapfile::{HeapFile, PageNo};
+use crate::overflow::StoredRecord;
+use crate::page::Page;
 use common::prelude::*;
 use std::sync::Arc;
 
@@ -13,72 +16,99 @@ use std::sync::Arc;
 /// HINT: This will need an Arc<HeapFile>
 pub struct HeapFileIterator {
     hf: Arc<HeapFile>,
+    bp: Option<Arc<BufferPool>>,
     tid: TransactionId,
-    current_page_id: PageId,
+    current_page_id: PageNo,
     current_page_iter: Option<HeapPageIntoIter>,
+    /// Error reading the current page, returned by the next call to next
+    error: Option<CrustyError>,
 }
 
 /// Required HeapFileIterator functions
 impl HeapFileIterator {
     /// Create a new HeapFileIterator that stores the tid, and heapFile pointer.
     /// This should initialize the state required to iterate through the heap file.
-    pub(crate) fn new(tid: TransactionId, hf: Arc<HeapFile>) -> Self {
+    pub(crate) fn new(tid: TransactionId, hf: Arc<HeapFile>, bp: Option<Arc<BufferPool>>) -> Self {
         // panic!("TODO milestone hs");
         let mut iter = HeapFileIterator {
             hf,
+            bp,
             tid,
             current_page_id: 0,
             current_page_iter: None,
+            error: None,
         };
         iter.load_next_page_iter(); // 尝试初始化第一页的迭代器
         iter
     }
 
-    pub(crate) fn new_from(tid: TransactionId, hf: Arc<HeapFile>, value_id: ValueId) -> Self {
+    pub(crate) fn new_from(
+        tid: TransactionId,
+        hf: Arc<HeapFile>,
+        bp: Option<Arc<BufferPool>>,
+        value_id: ValueId,
+    ) -> Self {
         // panic!("TODO milestone hs");
+        // The page number counts the pages of the segments before the value's
+        let current_page_id = hf.page_no(value_id.segment_id, value_id.page_id.expect("REASON"));
         let mut iter = HeapFileIterator {
             hf,
+            bp,
             tid,
-            current_page_id: value_id.page_id.expect("REASON"),
+            current_page_id,
             current_page_iter: None,
+            error: None,
         };
         iter.load_next_page_iter(); // 定位到指定页
         iter
     }
 
+    fn read_page(&self, page_id: PageNo) -> Result<Page, CrustyError> {
+        match &self.bp {
+            Some(bp) => bp.get_page(&self.hf, page_id, false),
+            None => self.hf.read_page_from_file(page_id),
+        }
+    }
+
+    /// Load the iterator of the current page. A page that cannot be read, e.g. because it fails
+    /// its checksum, ends the scan with its error.
     fn load_next_page_iter(&mut self) {
-        while self.current_page_id < self.hf.num_pages() {
-            match self.hf.read_page_from_file(self.current_page_id) {
-                Ok(page) => {
-                    self.current_page_iter = Some(page.into_iter());
-                    return;
-                }
-                Err(_) => {
-                    // 跳过无法读取的页，尝试下一页
-                    self.current_page_id += 1;
-                }
+        self.current_page_iter = None;
+        if self.current_page_id < self.hf.num_pages() {
+            match self.read_page(self.current_page_id) {
+                Ok(page) => self.current_page_iter = Some(page.into_iter()),
+                Err(e) => self.error = Some(e),
             }
         }
-        self.current_page_iter = None;
     }
 }
 
 /// Trait implementation for heap file iterator.
 /// Note this will need to iterate through the pages and their respective iterators.
+/// Overflow chunks are skipped, and values stored in overflow pages are returned whole.
 impl Iterator for HeapFileIterator {
-    type Item = (Vec<u8>, ValueId);
+    type Item = Result<(Vec<u8>, ValueId), CrustyError>;
     fn next(&mut self) -> Option<Self::Item> {
         // panic!("TODO milestone hs");
         loop {
+            if let Some(e) = self.error.take() {
+                return Some(Err(e));
+            }
             if let Some(iter) = &mut self.current_page_iter {
-                if let Some((data, slot_id)) = iter.next() {
-                    let value_id = ValueId {
-                        container_id: self.hf.container_id,
-                        segment_id: None,
-                        page_id: Some(self.current_page_id),
-                        slot_id: Some(slot_id),
+                if let Some((record, slot_id)) = iter.next() {
+                    let data = match StoredRecord::from_bytes(&record)
+                        .and_then(|r| r.into_value(|pid| self.read_page(pid)))
+                    {
+                        Ok(Some(data)) => data,
+                        // Part of an overflow chain, returned with the record it belongs to
+                        Ok(None) => continue,
+                        Err(e) => {
+                            self.current_page_iter = None;
+                            return Some(Err(e));
+                        }
                     };
-                    return Some((data, value_id));
+                    let value_id = self.hf.value_id(self.current_page_id, slot_id);
+                    return Some(Ok((data, value_id)));
                 } else {
                     self.current_page_id += 1;
                     self.load_next_page_iter();
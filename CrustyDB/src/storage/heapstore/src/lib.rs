@@ -7,9 +7,22 @@ extern crate log;
 #[macro_use]
 extern crate serde;
 
+mod bp_tests;
+mod buffer_pool;
+mod check;
+mod fsm;
 mod heap_page;
 mod heapfile;
 mod heapfileiter;
+mod manifest;
+mod overflow;
 mod page;
+mod recovery;
 pub mod storage_manager;
 pub mod testutil;
+mod vacuum;
+mod wal;
+
+/// If true, pages written to the buffer pool are immediately written to the heap file.
+/// Otherwise dirty pages are written back on eviction, clear_cache or shutdown.
+pub(crate) const WRITE_THROUGH: bool = true;
@@ -0,0 +1,128 @@
+use crate::heapfile::{PageNo, SEGMENT_PAGES};
+use common::ids::StateType;
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Version of the manifest format written by this build. Version 2 heap files have page
+/// checksums, version 3 heap files are split into segments, version 4 pages have a configured
+/// size and wider slot entries.
+pub(crate) const MANIFEST_VERSION: u32 = 4;
+
+/// What the storage manager knows about a container beyond its heap file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ContainerEntry {
+    pub name: Option<String>,
+    pub container_type: StateType,
+    pub dependencies: Option<Vec<ContainerId>>,
+    /// Pages in the heap file when the manifest was written
+    pub num_pages: PageNo,
+}
+
+impl ContainerEntry {
+    pub(crate) fn new(
+        name: Option<String>,
+        container_type: StateType,
+        dependencies: Option<Vec<ContainerId>>,
+    ) -> Self {
+        ContainerEntry {
+            name,
+            container_type,
+            dependencies,
+            num_pages: 0,
+        }
+    }
+}
+
+/// The manifest lists every container of a heapstore storage manager. It is saved as JSON in
+/// the storage dir, written to a temporary file and renamed over the old one so a crash leaves
+/// either the old or the new manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    pub version: u32,
+    /// Bytes in every page of the heap files, fixed when the storage dir is created
+    pub page_size: usize,
+    /// Pages in every segment file of the heap files. It is fixed when the storage dir is
+    /// created, as value ids depend on it.
+    pub segment_pages: PageNo,
+    pub containers: BTreeMap<ContainerId, ContainerEntry>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest {
+            version: MANIFEST_VERSION,
+            page_size: PAGE_SIZE,
+            segment_pages: SEGMENT_PAGES,
+            containers: BTreeMap::new(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Read the manifest at path. Returns None if there is no manifest and an error if it cannot
+    /// be parsed or was written by an unsupported version.
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>, CrustyError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let manifest: Manifest = serde_json::from_slice(&fs::read(path)?).map_err(|e| {
+            CrustyError::ValidationError(format!("Unreadable manifest {:?}: {}", path, e))
+        })?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(CrustyError::ValidationError(format!(
+                "Manifest {:?} has version {}, expected {}",
+                path, manifest.version, MANIFEST_VERSION
+            )));
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Atomically replace the manifest at path
+    pub(crate) fn save(&self, path: &Path) -> Result<(), CrustyError> {
+        let tmp = path.with_extension("tmp");
+        let bytes = serde_json::to_vec_pretty(self)
+            .map_err(|e| CrustyError::SerializationError(e.to_string()))?;
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_manifest_roundtrip_and_version() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let path = tdir.join("manifest.json");
+        assert!(Manifest::load(&path).unwrap().is_none());
+
+        let mut manifest = Manifest::default();
+        manifest.containers.insert(
+            3,
+            ContainerEntry::new(Some("t".to_string()), StateType::MatView, Some(vec![1, 2])),
+        );
+        manifest.save(&path).unwrap();
+        let loaded = Manifest::load(&path).unwrap().unwrap();
+        let entry = &loaded.containers[&3];
+        assert_eq!(Some("t".to_string()), entry.name);
+        assert_eq!(StateType::MatView, entry.container_type);
+        assert_eq!(Some(vec![1, 2]), entry.dependencies);
+
+        manifest.version = MANIFEST_VERSION + 1;
+        manifest.save(&path).unwrap();
+        assert!(Manifest::load(&path).is_err());
+        fs::write(&path, b"not a manifest").unwrap();
+        assert!(Manifest::load(&path).is_err());
+    }
+}
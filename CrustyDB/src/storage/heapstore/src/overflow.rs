@@ -0,0 +1,291 @@
+use crate::heap_page::{HeapPage, PAGE_METADATA_SIZE, SLOT_ENTRY_SIZE};
+use crate::heapfile::PageNo;
+use crate::page::Page;
+use common::prelude::*;
+
+/// Tag byte stored in front of every record in a heap page, telling how to read the value.
+const INLINE_TAG: u8 = 0;
+const OVERFLOW_TAG: u8 = 1;
+const CHUNK_TAG: u8 = 2;
+
+/// Tag, next flag and next page and slot
+const CHUNK_HEADER_SIZE: usize = 8;
+/// Tag, length and first page and slot
+const OVERFLOW_SIZE: usize = 11;
+
+/// Largest record that fits in an empty page of page_size bytes
+pub(crate) fn max_record_size(page_size: usize) -> usize {
+    page_size - PAGE_METADATA_SIZE - SLOT_ENTRY_SIZE
+}
+
+/// Largest piece of a value stored in one overflow chunk in pages of page_size bytes
+pub(crate) fn max_chunk_size(page_size: usize) -> usize {
+    max_record_size(page_size) - CHUNK_HEADER_SIZE
+}
+
+/// A record as stored in a heap page slot.
+///
+/// Values that fit in a page are stored inline. Larger values are split into a chain of chunk
+/// records, filling overflow pages, and the slot the value id points to holds a stub with the
+/// total length and the first chunk. Chunks are never returned by scans.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StoredRecord {
+    Inline(Vec<u8>),
+    Overflow {
+        len: u32,
+        first: (PageNo, SlotId),
+    },
+    Chunk {
+        next: Option<(PageNo, SlotId)>,
+        data: Vec<u8>,
+    },
+}
+
+impl StoredRecord {
+    /// Returns the inline record for value, if it fits in a page of page_size bytes.
+    pub(crate) fn inline(value: &[u8], page_size: usize) -> Option<Self> {
+        if value.len() < max_record_size(page_size) {
+            Some(StoredRecord::Inline(value.to_vec()))
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            StoredRecord::Inline(value) => {
+                bytes.push(INLINE_TAG);
+                bytes.extend(value);
+            }
+            StoredRecord::Overflow { len, first } => {
+                bytes.push(OVERFLOW_TAG);
+                bytes.extend(len.to_le_bytes());
+                bytes.extend(first.0.to_le_bytes());
+                bytes.extend(first.1.to_le_bytes());
+            }
+            StoredRecord::Chunk { next, data } => {
+                bytes.push(CHUNK_TAG);
+                let (flag, (pid, slot)) = match next {
+                    Some(n) => (1u8, *n),
+                    None => (0u8, (0, 0)),
+                };
+                bytes.push(flag);
+                bytes.extend(pid.to_le_bytes());
+                bytes.extend(slot.to_le_bytes());
+                bytes.extend(data);
+            }
+        }
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, CrustyError> {
+        let corrupt = || CrustyError::CrustyError(String::from("Corrupt heap record"));
+        match bytes.first() {
+            Some(&INLINE_TAG) => Ok(StoredRecord::Inline(bytes[1..].to_vec())),
+            Some(&OVERFLOW_TAG) if bytes.len() == OVERFLOW_SIZE => Ok(StoredRecord::Overflow {
+                len: u32::from_le_bytes(bytes[1..5].try_into().unwrap()),
+                first: (
+                    u32::from_le_bytes(bytes[5..9].try_into().unwrap()),
+                    u16::from_le_bytes(bytes[9..11].try_into().unwrap()),
+                ),
+            }),
+            Some(&CHUNK_TAG) if bytes.len() >= CHUNK_HEADER_SIZE => Ok(StoredRecord::Chunk {
+                next: match bytes[1] {
+                    0 => None,
+                    _ => Some((
+                        u32::from_le_bytes(bytes[2..6].try_into().unwrap()),
+                        u16::from_le_bytes(bytes[6..8].try_into().unwrap()),
+                    )),
+                },
+                data: bytes[CHUNK_HEADER_SIZE..].to_vec(),
+            }),
+            _ => Err(corrupt()),
+        }
+    }
+
+    /// Resolve the record into its value, following the overflow chain with read_page.
+    /// Returns None for chunk records.
+    pub(crate) fn into_value<F>(self, read_page: F) -> Result<Option<Vec<u8>>, CrustyError>
+    where
+        F: Fn(PageNo) -> Result<Page, CrustyError>,
+    {
+        match self {
+            StoredRecord::Inline(value) => Ok(Some(value)),
+            StoredRecord::Chunk { .. } => Ok(None),
+            StoredRecord::Overflow { len, first } => {
+                let mut value = Vec::with_capacity(len as usize);
+                for (_, data) in chain(first, len, read_page) {
+                    value.extend(data?);
+                }
+                if value.len() != len as usize {
+                    return Err(CrustyError::CrustyError(format!(
+                        "Overflow chain holds {} bytes, expected {}",
+                        value.len(),
+                        len
+                    )));
+                }
+                Ok(Some(value))
+            }
+        }
+    }
+}
+
+/// Iterate the chunks of an overflow chain of len bytes, returning the location and data of each
+/// chunk. A chain holding more than len bytes, or an empty chunk, is corrupt, e.g. loops back on
+/// itself: the chunk that shows it is returned as an error and the iteration stops.
+pub(crate) fn chain<F>(
+    first: (PageNo, SlotId),
+    len: u32,
+    read_page: F,
+) -> impl Iterator<Item = ((PageNo, SlotId), Result<Vec<u8>, CrustyError>)>
+where
+    F: Fn(PageNo) -> Result<Page, CrustyError>,
+{
+    let mut next = Some(first);
+    let mut read = 0;
+    std::iter::from_fn(move || {
+        let (pid, slot) = next.take()?;
+        let chunk = read_page(pid).and_then(|page| {
+            let bytes = page.get_value(slot).ok_or_else(|| {
+                CrustyError::CrustyError(format!("Missing overflow chunk {}:{}", pid, slot))
+            })?;
+            match StoredRecord::from_bytes(&bytes)? {
+                StoredRecord::Chunk { data, .. } if data.is_empty() => Err(
+                    CrustyError::CrustyError(format!("Empty overflow chunk at {}:{}", pid, slot)),
+                ),
+                StoredRecord::Chunk { next, data } => {
+                    read += data.len();
+                    if read > len as usize {
+                        return Err(CrustyError::CrustyError(format!(
+                            "Overflow chain holds more than {} bytes at {}:{}",
+                            len, pid, slot
+                        )));
+                    }
+                    Ok((next, data))
+                }
+                _ => Err(CrustyError::CrustyError(format!(
+                    "Expected an overflow chunk at {}:{}",
+                    pid, slot
+                ))),
+            }
+        });
+        Some((
+            (pid, slot),
+            chunk.map(|(n, data)| {
+                next = n;
+                data
+            }),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::page::MAX_PAGE_SIZE;
+    use common::testutil::*;
+    use common::PAGE_SIZE;
+
+    #[test]
+    fn hs_overflow_record_roundtrip() {
+        init();
+        let records = vec![
+            StoredRecord::Inline(get_random_byte_vec(100)),
+            StoredRecord::Inline(vec![]),
+            StoredRecord::Overflow {
+                len: 100_000,
+                first: (3, 1),
+            },
+            StoredRecord::Chunk {
+                next: Some((4, 0)),
+                data: get_random_byte_vec(max_chunk_size(PAGE_SIZE)),
+            },
+            StoredRecord::Chunk {
+                next: None,
+                data: get_random_byte_vec(10),
+            },
+        ];
+        for r in records {
+            assert_eq!(r, StoredRecord::from_bytes(&r.to_bytes()).unwrap());
+        }
+        assert!(StoredRecord::from_bytes(&[]).is_err());
+        assert!(StoredRecord::from_bytes(&[9, 1, 2]).is_err());
+        for page_size in [PAGE_SIZE, MAX_PAGE_SIZE] {
+            assert_eq!(
+                max_record_size(page_size),
+                StoredRecord::Chunk {
+                    next: None,
+                    data: vec![0; max_chunk_size(page_size)]
+                }
+                .to_bytes()
+                .len()
+            );
+        }
+        let value = get_random_byte_vec(PAGE_SIZE);
+        assert!(StoredRecord::inline(&value, PAGE_SIZE).is_none());
+        assert!(StoredRecord::inline(&value, 2 * PAGE_SIZE).is_some());
+    }
+
+    #[test]
+    fn hs_overflow_read_chain() {
+        init();
+        let v1 = get_random_byte_vec(max_chunk_size(PAGE_SIZE));
+        let v2 = get_random_byte_vec(50);
+        let mut p0 = Page::new(0);
+        let mut p1 = Page::new(1);
+        let last = p1
+            .add_value(
+                &StoredRecord::Chunk {
+                    next: None,
+                    data: v2.clone(),
+                }
+                .to_bytes(),
+            )
+            .unwrap();
+        let first = p0
+            .add_value(
+                &StoredRecord::Chunk {
+                    next: Some((1, last)),
+                    data: v1.clone(),
+                }
+                .to_bytes(),
+            )
+            .unwrap();
+        let pages = [p0, p1];
+        let read = |pid: PageNo| Ok(pages[pid as usize].clone());
+        let stub = StoredRecord::Overflow {
+            len: (v1.len() + v2.len()) as u32,
+            first: (0, first),
+        };
+        let mut expected = v1;
+        expected.extend(v2);
+        assert_eq!(Some(expected), stub.into_value(read).unwrap());
+
+        let bad = StoredRecord::Overflow {
+            len: 10,
+            first: (0, first),
+        };
+        assert!(bad.into_value(read).is_err());
+
+        // A chain looping back on itself ends with an error
+        let mut looped = Page::new(0);
+        let slot = looped
+            .add_value(
+                &StoredRecord::Chunk {
+                    next: Some((0, 0)),
+                    data: vec![1; 10],
+                }
+                .to_bytes(),
+            )
+            .unwrap();
+        assert_eq!(0, slot);
+        let read = |_| Ok(looped.clone());
+        let stub = StoredRecord::Overflow {
+            len: 100,
+            first: (0, slot),
+        };
+        assert!(stub.into_value(read).is_err());
+        assert_eq!(11, chain((0, slot), 100, read).count());
+    }
+}
@@ -1,92 +1,144 @@
 pub use crate::heap_page::{HeapPage, PageMetadata};
+use crate::heap_page::{CHECKSUM_OFFSET, PAGE_METADATA_SIZE};
+use crate::heapfile::PageNo;
 use common::prelude::*;
 use common::PAGE_SIZE;
 use std::fmt;
 use std::fmt::Write;
 
-// Type to hold any value smaller than the size of a page.
-// We choose u16 because it is sufficient to represent any slot that fits in a 4096-byte-sized page.
+// Type to hold any offset within a page.
+// We choose u32 because an offset may be the end of the page, which is past u16 for 64K pages.
 // Note that you will need to cast Offset to usize if you want to use it to index an array.
-pub type Offset = u16;
+pub type Offset = u32;
+/// Smallest page size a storage manager can be created with
+pub const MIN_PAGE_SIZE: usize = 4096;
+/// Largest page size a storage manager can be created with. Record sizes are stored as u16, so
+/// a page must not hold a record of 64K bytes.
+pub const MAX_PAGE_SIZE: usize = 65536;
 // For debug
 const BYTES_PER_LINE: usize = 40;
 
-/// Page struct. This must occupy not more than PAGE_SIZE when serialized.
-/// In the header, you are allowed to allocate 8 bytes for general page metadata and
-/// 6 bytes per value/entry/slot stored. For example a page that has stored 3 values, can use
-/// up to 8+3*6=26 bytes, leaving the rest (PAGE_SIZE-26 for data) when serialized.
-/// If you delete a value, you do not need reclaim header space the way you must reclaim page
-/// body space. E.g., if you insert 3 values then delete 2 of them, your header can remain 26
-/// bytes & subsequent inserts can simply add 6 more bytes to the header as normal.
+/// Page struct. This must occupy not more than its page size when serialized. The page size is
+/// PAGE_SIZE unless the storage manager was created with another one, see page_size_error.
+/// In the header, you are allowed to allocate 22 bytes for general page metadata (including the
+/// page LSN and checksum) and 8 bytes per value/entry/slot stored. For example a page that has
+/// stored 3 values, can use up to 22+3*8=46 bytes, leaving the rest (PAGE_SIZE-46 for data) when
+/// serialized. If you delete a value, you do not need reclaim header space the way you must
+/// reclaim page body space. E.g., if you insert 3 values then delete 2 of them, your header can
+/// remain 46 bytes & subsequent inserts can simply add 8 more bytes to the header as normal.
 /// The rest must filled as much as possible to hold values.
+#[derive(Clone)]
 pub struct Page {
-    /// The data for data
-    pub(crate) data: [u8; PAGE_SIZE],
+    /// The data for data, as many bytes as the page size
+    pub(crate) data: Vec<u8>,
+}
+
+/// Returns an error unless page_size is a power of two from MIN_PAGE_SIZE to MAX_PAGE_SIZE
+pub fn page_size_error(page_size: usize) -> Option<CrustyError> {
+    if page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size) {
+        None
+    } else {
+        Some(CrustyError::ValidationError(format!(
+            "Page size {} is not a power of two from {} to {}",
+            page_size, MIN_PAGE_SIZE, MAX_PAGE_SIZE
+        )))
+    }
 }
 
 /// The functions required for page
 impl Page {
     /// Add two helper functions to set and get the metadata
-    /// The metadata is the first 8 bytes of the page
+    /// The metadata is the first PAGE_METADATA_SIZE bytes of the page
     /// set the metadata
     pub fn set_metadata(&mut self, metadata: &PageMetadata) {
-        // Set the page id in the first 2 bytes
-        self.data[0..2].copy_from_slice(&metadata.page_id.to_le_bytes());
-        // Set the number of slots in the next 2 bytes
-        self.data[2..4].copy_from_slice(&metadata.num_slots.to_le_bytes());
-        // Set the offset of free space in the next 2 bytes
-        self.data[4..6].copy_from_slice(&metadata.offset_of_free_space.to_le_bytes());
-        // Set the size of free space in the next 2 bytes
-        self.data[6..8].copy_from_slice(&(metadata.size_of_free_space).to_le_bytes());
+        self.data[0..PAGE_METADATA_SIZE].copy_from_slice(&metadata.to_bytes());
     }
 
-    fn new_metadata(page_id: PageId) -> PageMetadata {
+    fn new_metadata(page_id: PageNo, page_size: usize) -> PageMetadata {
         PageMetadata {
             page_id,
             num_slots: 0,
-            offset_of_free_space: 8_u16,
-            size_of_free_space: (PAGE_SIZE - (8_usize)) as u16,
+            offset_of_free_space: PAGE_METADATA_SIZE as u16,
+            size_of_free_space: (page_size - PAGE_METADATA_SIZE) as u16,
+            lsn: Lsn::default(),
+            checksum: 0,
         }
     }
 
     /// Create a new page
     /// HINT: To convert a variable x to bytes using little endian, use
     /// x.to_le_bytes()
-    pub fn new(page_id: PageId) -> Self {
+    pub fn new(page_id: PageNo) -> Self {
+        Page::new_sized(page_id, PAGE_SIZE)
+    }
+
+    /// Create a new page of page_size bytes
+    pub fn new_sized(page_id: PageNo, page_size: usize) -> Self {
         let mut page = Page {
-            data: [0u8; PAGE_SIZE],
+            data: vec![0u8; page_size],
         };
-        let metadata = Self::new_metadata(page_id);
+        let metadata = Self::new_metadata(page_id, page_size);
         page.set_metadata(&metadata);
         page
     }
 
+    /// The size of the page in bytes
+    pub fn page_size(&self) -> usize {
+        self.data.len()
+    }
+
     /// Return the page id for a page
     ///
     /// HINT to create a primitive data type from a slice you can use the following
     /// (the example is for a u16 type and the data store in little endian)
     /// u16::from_le_bytes(data[X..Y].try_into().unwrap());
-    pub fn get_page_id(&self) -> PageId {
-        // Get the page id from the first 2 bytes
-        u16::from_le_bytes(self.data[0..2].try_into().unwrap())
+    pub fn get_page_id(&self) -> PageNo {
+        // Get the page id from the first 4 bytes
+        u32::from_le_bytes(self.data[0..4].try_into().unwrap())
     }
 
-    /// Create a page from a byte array
+    /// Create a page from its bytes. The page size is the length of data.
     #[allow(dead_code)]
-    pub fn from_bytes(data: [u8; PAGE_SIZE]) -> Self {
-        // Create a new page from the byte array
-        let mut page = Page::new(0);
-        page.data.copy_from_slice(&data);
-        page
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Page { data }
     }
 
     /// Get a reference to the bytes of the page
     ///
-    pub fn to_bytes(&self) -> &[u8; PAGE_SIZE] {
+    pub fn to_bytes(&self) -> &[u8] {
         // Get a reference to the bytes of the page
         &self.data
     }
 
+    /// CRC32 of the page bytes, taking the checksum field as 0
+    fn compute_checksum(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.data[..CHECKSUM_OFFSET]);
+        hasher.update(&[0; 4]);
+        hasher.update(&self.data[CHECKSUM_OFFSET + 4..]);
+        hasher.finalize()
+    }
+
+    /// The bytes to write to disk: the page with its checksum set
+    pub(crate) fn checksummed_bytes(&self) -> Vec<u8> {
+        let mut data = self.data.clone();
+        data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4]
+            .copy_from_slice(&self.compute_checksum().to_le_bytes());
+        data
+    }
+
+    /// Check the checksum of a page read from disk and zero it. Returns false if it does not
+    /// match the page bytes.
+    pub(crate) fn verify_checksum(&mut self) -> bool {
+        let stored = u32::from_le_bytes(
+            self.data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        );
+        self.data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].fill(0);
+        stored == self.compute_checksum()
+    }
+
     /// Utility function for comparing the bytes of another page.
     /// Returns a vec  of Offset and byte diff
     #[allow(dead_code)]
@@ -115,12 +167,6 @@ impl Page {
     }
 }
 
-impl Clone for Page {
-    fn clone(&self) -> Self {
-        Page { data: self.data }
-    }
-}
-
 impl fmt::Debug for Page {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         //let bytes: &[u8] = unsafe { any_as_u8_slice(&self) };
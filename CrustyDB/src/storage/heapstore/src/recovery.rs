@@ -0,0 +1,491 @@
+use crate::fsm::FreeSpaceMap;
+use crate::heap_page::HeapPage;
+use crate::heapfile::{self, PageNo};
+use crate::page::Page;
+use crate::storage_manager::StorageManager;
+use crate::wal::{LogKind, LogRecord};
+use common::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/// Where undone deletes restored the records they could not put back in their slot, by the
+/// container, page and slot the record was deleted from
+type Relocated = HashMap<(ContainerId, PageNo, SlotId), (PageNo, SlotId)>;
+
+/// ARIES style recovery for the heapstore storage manager.
+///
+/// Analysis finds the transactions whose log chain was not ended (the losers), which are the
+/// transactions that neither committed nor finished rolling back. Redo repeats history: every
+/// change is reapplied to pages whose LSN is older than the record. Undo then rolls back the
+/// losers from their newest record backwards, logging a compensation record for every undone
+/// change so a crash during recovery does not undo the same change twice.
+///
+/// Records are only locked by their transaction, so the slot a loser deleted from may hold the
+/// record of another transaction by the time the delete is undone. The deleted record is then
+/// restored elsewhere, and the compensation record names the slot it was moved from.
+impl StorageManager {
+    pub(crate) fn recover(&self) -> Result<(), CrustyError> {
+        let records = self.log.read_all()?;
+        info!("Recovering from {} log records", records.len());
+
+        // Analysis
+        let mut losers: HashMap<TransactionId, Lsn> = HashMap::new();
+        let mut incarnation: HashMap<ContainerId, Lsn> = HashMap::new();
+        for (lsn, (record, _)) in &records {
+            match &record.kind {
+                LogKind::End => {
+                    losers.remove(&record.tid);
+                }
+                LogKind::CreateContainer { container_id }
+                | LogKind::RemoveContainer { container_id } => {
+                    incarnation.insert(*container_id, *lsn);
+                }
+                _ => {
+                    losers.insert(record.tid, *lsn);
+                }
+            }
+        }
+
+        // Redo, learning where undone deletes restored their records
+        let mut relocated = HashMap::new();
+        for (lsn, (record, _)) in &records {
+            match &record.kind {
+                LogKind::CreateContainer { container_id } => {
+                    if incarnation.get(container_id) == Some(lsn) {
+                        self.open_or_create_hf(*container_id)?;
+                    }
+                }
+                LogKind::RemoveContainer { container_id } => {
+                    if incarnation.get(container_id) == Some(lsn) {
+                        heapfile::remove_files(&self.hf_path(*container_id))?;
+                    }
+                }
+                kind => {
+                    if let LogKind::Compensation {
+                        container_id,
+                        page_id,
+                        slot_id,
+                        moved_from: Some(from),
+                        ..
+                    } = kind
+                    {
+                        relocated.insert((*container_id, from.0, from.1), (*page_id, *slot_id));
+                    }
+                    if let Some((container_id, _)) = kind.page() {
+                        let stale = incarnation.get(&container_id).is_some_and(|c| c > lsn);
+                        if !stale {
+                            self.redo_record(*lsn, kind)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Undo
+        let mut to_undo: BTreeMap<Lsn, TransactionId> =
+            losers.into_iter().map(|(tid, lsn)| (lsn, tid)).collect();
+        while let Some((lsn, tid)) = to_undo.pop_last() {
+            let record = &records[&lsn].0;
+            let next = match &record.kind {
+                LogKind::Compensation { undo_next, .. } => *undo_next,
+                _ => {
+                    self.undo_record(record, &mut relocated)?;
+                    record.prev_lsn
+                }
+            };
+            match next {
+                Some(next) => {
+                    to_undo.insert(next, tid);
+                }
+                None => {
+                    self.log.end(tid)?;
+                }
+            }
+        }
+
+        // Everything is applied, checkpoint by flushing the pages and dropping the log.
+        // Saved free-space maps predate the crash, so they are rebuilt from the pages.
+        if let Some(bp) = &self.buffer_pool {
+            bp.flush_all()?;
+        }
+        self.rebuild_fsms()?;
+        self.sync_containers()?;
+        self.log.truncate()
+    }
+
+    /// Checkpoint while transactions run: put every page on disk, then drop the log records
+    /// before the first record of the oldest transaction that has not ended. Records of an ended
+    /// transaction are only dropped once its pages are written, as the pages are flushed after
+    /// the point to keep is chosen. Returns the number of log bytes dropped.
+    pub(crate) fn checkpoint_log(&self) -> Result<u64, CrustyError> {
+        if !self.log.has_records() {
+            return Ok(0);
+        }
+        let keep = self.log.checkpoint_lsn();
+        self.log.sync()?;
+        if let Some(bp) = &self.buffer_pool {
+            bp.flush_all()?;
+        }
+        self.sync_containers()?;
+        self.save_manifest()?;
+        self.log.truncate_before(keep)
+    }
+
+    /// Open the heap file of a container, creating an empty one if the file is missing
+    fn open_or_create_hf(&self, container_id: ContainerId) -> Result<(), CrustyError> {
+        let path = self.hf_path(container_id);
+        if !path.exists() {
+            fs::File::create(path)?;
+        }
+        self.open_hf(container_id).map(|_| ())
+    }
+
+    /// Read a page for recovery, creating it (and any missing pages before it) if the heap file
+    /// is too short. Returns None if the container no longer exists.
+    fn recovery_page(
+        &self,
+        container_id: ContainerId,
+        page_id: PageNo,
+    ) -> Result<Option<Page>, CrustyError> {
+        let hf = match self.open_hf(container_id) {
+            Ok(hf) => hf,
+            Err(_) => return Ok(None),
+        };
+        let pages = hf.num_pages();
+        if page_id < pages {
+            return self.read_page(&hf, page_id, false).map(Some);
+        }
+        for pid in pages..page_id {
+            self.flush_page(&hf, &Page::new_sized(pid, hf.page_size))?;
+        }
+        Ok(Some(Page::new_sized(page_id, hf.page_size)))
+    }
+
+    /// Apply the change of a record if the page does not already reflect it
+    fn redo_record(&self, lsn: Lsn, kind: &LogKind) -> Result<(), CrustyError> {
+        let (container_id, page_id) = match kind.page() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let mut page = match self.recovery_page(container_id, page_id)? {
+            Some(page) => page,
+            None => return Ok(()),
+        };
+        if page.get_lsn() >= lsn {
+            return Ok(());
+        }
+        let applied = match kind {
+            LogKind::Insert { slot_id, value, .. }
+            | LogKind::Compensation {
+                slot_id,
+                value: Some(value),
+                ..
+            } => page.put_value(*slot_id, value),
+            LogKind::Delete { slot_id, .. }
+            | LogKind::Compensation {
+                slot_id,
+                value: None,
+                ..
+            } => {
+                page.delete_value(*slot_id);
+                Some(())
+            }
+            _ => Some(()),
+        };
+        if applied.is_none() {
+            return Err(CrustyError::CrustyError(format!(
+                "Unable to redo {:?} on page {} of container {}",
+                lsn, page_id, container_id
+            )));
+        }
+        page.set_lsn(lsn);
+        self.flush_page(&self.open_hf(container_id)?, &page)
+    }
+
+    /// Revert the change of an insert or delete record, logging a compensation record. An
+    /// inserted record is only removed if its slot still holds it. A deleted record whose slot
+    /// was taken, or whose page has no room left, is restored in a page with room.
+    fn undo_record(
+        &self,
+        record: &LogRecord,
+        relocated: &mut Relocated,
+    ) -> Result<(), CrustyError> {
+        let (container_id, page_id, slot_id, value, inserted) = match &record.kind {
+            LogKind::Insert {
+                container_id,
+                page_id,
+                slot_id,
+                value,
+            } => (*container_id, *page_id, *slot_id, value, true),
+            LogKind::Delete {
+                container_id,
+                page_id,
+                slot_id,
+                value,
+            } => (*container_id, *page_id, *slot_id, value, false),
+            _ => return Ok(()),
+        };
+        let hf = match self.open_hf(container_id) {
+            Ok(hf) => hf,
+            Err(_) => return Ok(()),
+        };
+        let _latch = hf.write_latch.lock()?;
+        let (page_id, slot_id) = match inserted {
+            true => relocated
+                .remove(&(container_id, page_id, slot_id))
+                .unwrap_or((page_id, slot_id)),
+            false => (page_id, slot_id),
+        };
+        let mut page = match self.recovery_page(container_id, page_id)? {
+            Some(page) => page,
+            None => return Ok(()),
+        };
+        let (slot_id, restore, moved_from) = if inserted {
+            if page.get_value(slot_id).as_ref() != Some(value) {
+                return Ok(());
+            }
+            page.delete_value(slot_id);
+            (slot_id, None, None)
+        } else if page.get_value(slot_id).is_none() && page.put_value(slot_id, value).is_some() {
+            (slot_id, Some(value.clone()), None)
+        } else {
+            page = self.page_for_insert(&hf, value.len())?;
+            let new_slot = page.add_value(value).ok_or_else(|| {
+                CrustyError::CrustyError(format!(
+                    "No space to restore slot {} on page {} of container {}",
+                    slot_id, page_id, container_id
+                ))
+            })?;
+            relocated.insert(
+                (container_id, page_id, slot_id),
+                (page.get_page_id(), new_slot),
+            );
+            (new_slot, Some(value.clone()), Some((page_id, slot_id)))
+        };
+        let lsn = self.log.append(
+            record.tid,
+            LogKind::Compensation {
+                container_id,
+                page_id: page.get_page_id(),
+                slot_id,
+                value: restore,
+                undo_next: record.prev_lsn,
+                moved_from,
+            },
+        )?;
+        page.set_lsn(lsn);
+        self.flush_page(&hf, &page)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage_manager::StorageManager;
+    use crate::wal::LogKind;
+    use common::prelude::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use std::fs;
+
+    const RO: Permissions = Permissions::ReadOnly;
+
+    fn values(sm: &StorageManager, cid: ContainerId) -> Vec<Vec<u8>> {
+        let t = TransactionId::new();
+        sm.get_iterator(cid, t, RO).map(|r| r.unwrap().0).collect()
+    }
+
+    #[test]
+    fn hs_recovery_undo_partial_update() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let t = TransactionId::new();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let vals = get_random_vec_of_byte_vec(30, 50, 100);
+        let ids = sm.insert_values(cid, vals.clone(), t);
+        sm.commit(t).unwrap();
+
+        // Crash between the delete and the insert of an update
+        let t2 = TransactionId::new();
+        sm.delete_logged(ids[3], t2).unwrap();
+        assert!(!compare_unordered_byte_vecs(&vals, values(&sm, cid)));
+        drop(sm);
+
+        let sm = StorageManager::new(&path);
+        assert!(compare_unordered_byte_vecs(&vals, values(&sm, cid)));
+        assert_eq!(vals[3], sm.get_value(ids[3], t, RO).unwrap());
+        assert!(!sm.log.has_records());
+        sm.reset().unwrap();
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_recovery_redo_lost_page_write() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let t = TransactionId::new();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let vals = get_random_vec_of_byte_vec(60, 50, 100);
+        sm.insert_values(cid, vals.clone(), t);
+        sm.commit(t).unwrap();
+        drop(sm);
+
+        // Lose every page write, only the log survives
+        let hf_path = path.join(crate::storage_manager::STORAGE_DIR).join("1.hf");
+        fs::File::create(&hf_path).unwrap();
+
+        let sm = StorageManager::new(&path);
+        assert!(compare_unordered_byte_vecs(&vals, values(&sm, cid)));
+        sm.reset().unwrap();
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_recovery_ignores_removed_container() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let t = TransactionId::new();
+        sm.create_table(1).unwrap();
+        sm.insert_values(1, get_random_vec_of_byte_vec(10, 50, 100), t);
+        sm.remove_container(1).unwrap();
+        sm.create_table(1).unwrap();
+        let vals = get_random_vec_of_byte_vec(5, 50, 100);
+        sm.insert_values(1, vals.clone(), t);
+        sm.commit(t).unwrap();
+        drop(sm);
+
+        let sm = StorageManager::new(&path);
+        assert!(compare_unordered_byte_vecs(&vals, values(&sm, 1)));
+        sm.reset().unwrap();
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_recovery_checkpoint_keeps_open_transactions() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let t = TransactionId::new();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let vals = get_random_vec_of_byte_vec(30, 50, 100);
+        let ids = sm.insert_values(cid, vals.clone(), t);
+        sm.commit(t).unwrap();
+        let log_path = path
+            .join(crate::storage_manager::STORAGE_DIR)
+            .join(crate::storage_manager::LOG_FILE);
+        let log_len = || fs::metadata(&log_path).unwrap().len();
+        let full = log_len();
+
+        // t2 has not ended, so the checkpoint keeps its delete for undo
+        let t2 = TransactionId::new();
+        sm.delete_logged(ids[3], t2).unwrap();
+        sm.checkpoint().unwrap();
+        assert!(log_len() < full);
+        assert!(sm.log.has_records());
+        drop(sm);
+
+        let sm = StorageManager::new(&path);
+        assert!(compare_unordered_byte_vecs(&vals, values(&sm, cid)));
+        sm.checkpoint().unwrap();
+        assert!(!sm.log.has_records());
+        sm.reset().unwrap();
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_recovery_undo_uncommitted() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let t = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(20, 50, 100);
+        sm.insert_values(cid, vals.clone(), t);
+        sm.commit(t).unwrap();
+
+        // t2 made several storage calls but never committed
+        let t2 = TransactionId::new();
+        sm.insert_values(cid, get_random_vec_of_byte_vec(20, 50, 100), t2);
+        sm.insert_value(cid, get_random_byte_vec(3000), t2);
+        drop(sm);
+
+        let sm = StorageManager::new(&path);
+        assert!(sm.recovered());
+        assert!(compare_unordered_byte_vecs(&vals, values(&sm, cid)));
+        sm.reset().unwrap();
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_recovery_restores_taken_slot() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let t = TransactionId::new();
+        let mut vals = get_random_vec_of_byte_vec(10, 50, 100);
+        let ids = sm.insert_values(cid, vals.clone(), t);
+        sm.commit(t).unwrap();
+
+        // t3 commits an insert into the slot the uncommitted delete of t2 freed
+        let t2 = TransactionId::new();
+        sm.delete_value(ids[3], t2).unwrap();
+        let t3 = TransactionId::new();
+        let taken = get_random_byte_vec(60);
+        assert_eq!(ids[3], sm.insert_value(cid, taken.clone(), t3));
+        sm.commit(t3).unwrap();
+        drop(sm);
+
+        let sm = StorageManager::new(&path);
+        assert_eq!(taken, sm.get_value(ids[3], t, RO).unwrap());
+        vals.push(taken);
+        assert!(compare_unordered_byte_vecs(&vals, values(&sm, cid)));
+        assert!(sm
+            .log
+            .read_all()
+            .unwrap()
+            .values()
+            .all(|(record, _)| record.kind != LogKind::End));
+        sm.reset().unwrap();
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_recovery_undo_uncommitted_vacuum() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let t = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(200, 50, 100);
+        let ids = sm.insert_values(cid, vals.clone(), t);
+        // The few records kept leave the last page sparse, so they move
+        for id in &ids[..195] {
+            sm.delete_value(*id, t).unwrap();
+        }
+        sm.commit(t).unwrap();
+        let kept = vals[195..].to_vec();
+
+        // The moves of t2 and the pages it cut off the file are undone
+        let t2 = TransactionId::new();
+        let moved = sm.vacuum(cid, true, t2).unwrap();
+        assert!(!moved.is_empty());
+        drop(sm);
+
+        let sm = StorageManager::new(&path);
+        assert!(compare_unordered_byte_vecs(&kept, values(&sm, cid)));
+        for (id, val) in ids[195..].iter().zip(&kept) {
+            assert_eq!(*val, sm.get_value(*id, t, RO).unwrap());
+        }
+        sm.reset().unwrap();
+        fs::remove_dir_all(path).unwrap();
+    }
+}
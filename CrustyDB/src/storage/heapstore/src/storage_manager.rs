@@ -1,45 +1,255 @@
-use crate::heap_page::HeapPage;
-use crate::heapfile::HeapFile;
+use crate::buffer_pool::BufferPool;
+use crate::fsm::FreeSpaceMap;
+use crate::heap_page::{HeapPage, SLOT_ENTRY_SIZE};
+use crate::heapfile::{self, HeapFile, PageNo, SEGMENT_PAGES};
 use crate::heapfileiter::HeapFileIterator;
-use crate::page::Page;
+use crate::manifest::{ContainerEntry, Manifest};
+use crate::overflow::{chain, max_chunk_size, StoredRecord};
+use crate::page::{page_size_error, Page};
+use crate::wal::{LogKind, LogManager};
 use common::prelude::*;
 use common::storage_trait::StorageTrait;
 use common::testutil::gen_random_test_sm_dir;
 use common::PAGE_SIZE;
-use std::collections::HashMap;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
 use std::{fs, num};
 
 pub const STORAGE_DIR: &str = "heapstore";
+/// File name of the write-ahead log within STORAGE_DIR
+pub(crate) const LOG_FILE: &str = "wal.log";
+/// File name of the container manifest within STORAGE_DIR
+pub(crate) const MANIFEST_FILE: &str = "manifest.json";
+/// Directory within STORAGE_DIR of the heap files of temporary containers, emptied on startup
+pub(crate) const TEMP_DIR: &str = "temp";
 
 /// The StorageManager struct
-#[derive(Serialize, Deserialize)]
 pub struct StorageManager {
     /// Path to database metadata files.
     pub storage_dir: PathBuf,
+    /// Open heap files, one per container
+    containers: RwLock<HashMap<ContainerId, Arc<HeapFile>>>,
+    /// Metadata of every container, saved on shutdown and when containers are created or removed
+    pub(crate) manifest: RwLock<Manifest>,
+    /// Temporary containers. Their heap files are in TEMP_DIR, and they are neither logged nor
+    /// listed in the manifest, as they do not outlive the process.
+    temp_containers: RwLock<HashSet<ContainerId>>,
+    /// Buffer pool shared by all containers
+    pub(crate) buffer_pool: Option<Arc<BufferPool>>,
+    /// Write-ahead log for all changes made through this storage manager
+    pub(crate) log: LogManager,
+    /// Pages in every segment file of the heap files
+    segment_pages: PageNo,
+    /// Bytes in every page of the heap files
+    page_size: usize,
     /// Indicates if this is a temp StorageManager (for testing)
     is_temp: bool,
+    /// Whether opening the storage manager ran recovery after an unclean shutdown
+    recovered: bool,
 }
 
 /// The required functions in HeapStore's StorageManager that are specific for HeapFiles
 impl StorageManager {
+    /// Helper: build a storage manager over storage_dir, creating the dirs and log if needed
+    pub(crate) fn build(
+        storage_dir: PathBuf,
+        is_temp: bool,
+        page_size: usize,
+        segment_pages: PageNo,
+    ) -> Result<Self, CrustyError> {
+        if let Some(e) = page_size_error(page_size) {
+            return Err(e);
+        }
+        // Temporary containers left by an earlier process are never reopened
+        let temp_dir = storage_dir.join(STORAGE_DIR).join(TEMP_DIR);
+        if temp_dir.exists() {
+            fs::remove_dir_all(&temp_dir)?;
+        }
+        fs::create_dir_all(&temp_dir)?;
+        let log = LogManager::open(&storage_dir.join(STORAGE_DIR).join(LOG_FILE))?;
+        Ok(StorageManager {
+            storage_dir,
+            containers: RwLock::new(HashMap::new()),
+            manifest: RwLock::new(Manifest {
+                page_size,
+                segment_pages,
+                ..Manifest::default()
+            }),
+            temp_containers: RwLock::new(HashSet::new()),
+            buffer_pool: Some(Arc::new(BufferPool::new())),
+            log,
+            segment_pages,
+            page_size,
+            is_temp,
+            recovered: false,
+        })
+    }
+
+    /// Open the storage manager persisted in storage_dir: recover from the log if the last
+    /// shutdown was unclean, then check the manifest against the heap files on disk.
+    pub(crate) fn open(storage_dir: &Path) -> Result<Self, CrustyError> {
+        StorageManager::open_with(storage_dir, None, SEGMENT_PAGES)
+    }
+
+    /// Open the storage manager persisted in storage_dir. A new dir gets pages of page_size
+    /// bytes, PAGE_SIZE if None, and heap files split into segments of segment_pages pages. An
+    /// existing dir keeps the page and segment sizes in its manifest, and a page_size other than
+    /// the one in its manifest is an error.
+    pub(crate) fn open_with(
+        storage_dir: &Path,
+        page_size: Option<usize>,
+        segment_pages: PageNo,
+    ) -> Result<Self, CrustyError> {
+        // Refuse a manifest from another version before changing anything
+        let manifest = Manifest::load(&storage_dir.join(STORAGE_DIR).join(MANIFEST_FILE))?;
+        let (page_size, segment_pages) = match &manifest {
+            Some(m) => match page_size {
+                Some(page_size) if page_size != m.page_size => {
+                    return Err(CrustyError::ValidationError(format!(
+                        "Storage in {:?} has pages of {} bytes, not {}",
+                        storage_dir, m.page_size, page_size
+                    )));
+                }
+                _ => (m.page_size, m.segment_pages),
+            },
+            None => (page_size.unwrap_or(PAGE_SIZE), segment_pages),
+        };
+        let mut sm =
+            StorageManager::build(storage_dir.to_path_buf(), false, page_size, segment_pages)?;
+        let recovered = sm.log.has_records();
+        if recovered {
+            info!("Unclean shutdown of {:?}, running recovery", storage_dir);
+            sm.recover()?;
+        }
+        sm.recovered = recovered;
+        let manifest = match manifest {
+            Some(manifest) => manifest,
+            None => sm.manifest.read()?.clone(),
+        };
+        sm.validate_manifest(manifest, recovered)?;
+        Ok(sm)
+    }
+
+    pub(crate) fn manifest_path(&self) -> PathBuf {
+        self.storage_dir.join(STORAGE_DIR).join(MANIFEST_FILE)
+    }
+
+    /// Check that every container in the manifest has a heap file at least as long as when the
+    /// manifest was written, and open it. After recovery the manifest may be behind the log, so
+    /// containers whose file was removed are dropped instead of failing. Heap files missing from
+    /// the manifest (from a crash during create, or a dir written before there was a manifest)
+    /// are added as base tables.
+    fn validate_manifest(
+        &self,
+        mut manifest: Manifest,
+        recovered: bool,
+    ) -> Result<(), CrustyError> {
+        let mut on_disk = BTreeSet::new();
+        for entry in fs::read_dir(self.storage_dir.join(STORAGE_DIR))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "hf") {
+                if let Some(cid) = path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .and_then(|s| s.parse::<ContainerId>().ok())
+                {
+                    on_disk.insert(cid);
+                }
+            }
+        }
+        let listed: Vec<ContainerId> = manifest.containers.keys().cloned().collect();
+        for container_id in listed {
+            if !on_disk.contains(&container_id) {
+                if !recovered {
+                    return Err(CrustyError::ValidationError(format!(
+                        "Container {} is in the manifest but has no heap file",
+                        container_id
+                    )));
+                }
+                warn!("Dropping removed container {} from manifest", container_id);
+                manifest.containers.remove(&container_id);
+                continue;
+            }
+            let pages = self.open_hf(container_id)?.num_pages();
+            let expected = manifest.containers[&container_id].num_pages;
+            if pages < expected && !recovered {
+                return Err(CrustyError::ValidationError(format!(
+                    "Heap file of container {} has {} pages, manifest expects {}",
+                    container_id, pages, expected
+                )));
+            }
+        }
+        for container_id in on_disk {
+            if let Entry::Vacant(e) = manifest.containers.entry(container_id) {
+                warn!("Adding container {} missing from manifest", container_id);
+                e.insert(ContainerEntry::new(
+                    None,
+                    common::ids::StateType::BaseTable,
+                    None,
+                ));
+                self.open_hf(container_id)?;
+            }
+        }
+        *self.manifest.write()? = manifest;
+        self.save_manifest()
+    }
+
+    /// Atomically write the manifest with the current page count of every open container
+    pub(crate) fn save_manifest(&self) -> Result<(), CrustyError> {
+        let mut manifest = self.manifest.write()?;
+        let containers = self.containers.read()?;
+        for (container_id, entry) in manifest.containers.iter_mut() {
+            if let Some(hf) = containers.get(container_id) {
+                entry.num_pages = hf.num_pages();
+            }
+        }
+        manifest.save(&self.manifest_path())
+    }
+
     /// Helper: build path to heap file for a container
-    fn hf_path(&self, container_id: ContainerId) -> PathBuf {
-        let dir = self.storage_dir.join(STORAGE_DIR);
+    pub(crate) fn hf_path(&self, container_id: ContainerId) -> PathBuf {
+        let mut dir = self.storage_dir.join(STORAGE_DIR);
+        if self.is_temporary(container_id) {
+            dir = dir.join(TEMP_DIR);
+        }
         dir.join(format!("{}.hf", container_id))
     }
 
     /// Ensure base dirs exist
     fn ensure_dirs(&self) -> Result<(), CrustyError> {
         fs::create_dir_all(&self.storage_dir)?;
-        fs::create_dir_all(self.storage_dir.join(STORAGE_DIR))?;
+        fs::create_dir_all(self.storage_dir.join(STORAGE_DIR).join(TEMP_DIR))?;
         Ok(())
     }
 
-    /// Open a HeapFile for container, error if container not exists
-    fn open_hf(&self, container_id: ContainerId) -> Result<HeapFile, CrustyError> {
+    /// Whether container_id was created as a temporary container
+    pub(crate) fn is_temporary(&self, container_id: ContainerId) -> bool {
+        self.temp_containers.read().unwrap().contains(&container_id)
+    }
+
+    /// End the log chain of tid if it logged changes since the chain was last ended. Returns
+    /// whether there was a chain to end.
+    fn end_logged(&self, tid: TransactionId) -> Result<bool, CrustyError> {
+        if self.log.last_lsn(tid).is_none() {
+            return Ok(false);
+        }
+        self.log.end(tid)?;
+        Ok(true)
+    }
+
+    /// Get the HeapFile for container, error if container not exists.
+    /// Heap files are opened once and kept open for the life of the storage manager.
+    pub(crate) fn open_hf(&self, container_id: ContainerId) -> Result<Arc<HeapFile>, CrustyError> {
+        if let Some(hf) = self.containers.read()?.get(&container_id) {
+            return Ok(Arc::clone(hf));
+        }
+        let mut containers = self.containers.write()?;
+        if let Some(hf) = containers.get(&container_id) {
+            return Ok(Arc::clone(hf));
+        }
         let path = self.hf_path(container_id);
         // Only open existing container files
         if !path.exists() {
@@ -48,22 +258,57 @@ impl StorageManager {
                 container_id
             )));
         }
-        HeapFile::new(path, container_id)
+        let hf = Arc::new(HeapFile::new(
+            path,
+            container_id,
+            self.page_size,
+            self.segment_pages,
+        )?);
+        containers.insert(container_id, Arc::clone(&hf));
+        Ok(hf)
+    }
+
+    /// Read a page through the buffer pool if there is one
+    pub(crate) fn read_page(
+        &self,
+        hf: &Arc<HeapFile>,
+        page_id: PageNo,
+        pin: bool,
+    ) -> Result<Page, CrustyError> {
+        match &self.buffer_pool {
+            Some(bp) => bp.get_page(hf, page_id, pin),
+            None => hf.read_page_from_file(page_id),
+        }
+    }
+
+    /// Write a page through the buffer pool if there is one, and record its free space
+    pub(crate) fn flush_page(&self, hf: &Arc<HeapFile>, page: &Page) -> Result<(), CrustyError> {
+        match &self.buffer_pool {
+            Some(bp) => bp.write_page(hf, page)?,
+            None => hf.write_page_to_file(page)?,
+        }
+        hf.fsm.update(page.get_page_id(), page.get_free_space());
+        Ok(())
     }
 
     /// Get a page if exists for a given container.
+    /// If pin is set the page stays in the buffer pool until unpin_page is called.
     pub(crate) fn get_page(
         &self,
         container_id: ContainerId,
-        page_id: PageId,
+        page_id: PageNo,
         _tid: TransactionId,
         _perm: Permissions,
-        _pin: bool,
+        pin: bool,
     ) -> Option<Page> {
-        if let Ok(hf) = self.open_hf(container_id) {
-            hf.read_page_from_file(page_id).ok()
-        } else {
-            None
+        let hf = self.open_hf(container_id).ok()?;
+        self.read_page(&hf, page_id, pin).ok()
+    }
+
+    /// Release a pin taken with get_page
+    pub(crate) fn unpin_page(&self, container_id: ContainerId, page_id: PageNo) {
+        if let Some(bp) = &self.buffer_pool {
+            bp.unpin_page(container_id, page_id);
         }
     }
 
@@ -75,11 +320,254 @@ impl StorageManager {
         _tid: TransactionId,
     ) -> Result<(), CrustyError> {
         let hf = self.open_hf(container_id)?;
-        hf.write_page_to_file(page)
+        self.flush_page(&hf, page)
+    }
+
+    /// Store a value, logging the change. Values that do not fit in a page are split into a
+    /// chain of overflow chunks, and the returned id points to the stub for the chain.
+    /// The change joins the log chain of tid, which ends when tid commits or aborts.
+    pub(crate) fn insert_logged(
+        &self,
+        container_id: ContainerId,
+        value: &[u8],
+        tid: TransactionId,
+    ) -> Result<ValueId, CrustyError> {
+        if let Some(record) = StoredRecord::inline(value, self.page_size) {
+            return self.insert_record_logged(container_id, &record.to_bytes(), tid);
+        }
+        // Write the chain back to front so every chunk knows the next one
+        let hf = self.open_hf(container_id)?;
+        let mut next = None;
+        for data in value.chunks(max_chunk_size(self.page_size)).rev() {
+            let chunk = StoredRecord::Chunk {
+                next,
+                data: data.to_vec(),
+            };
+            let id = self.insert_record_logged(container_id, &chunk.to_bytes(), tid)?;
+            next = id
+                .page_id
+                .zip(id.slot_id)
+                .map(|(page_id, slot)| (hf.page_no(id.segment_id, page_id), slot));
+        }
+        let stub = StoredRecord::Overflow {
+            len: value.len() as u32,
+            first: next.unwrap(),
+        };
+        self.insert_record_logged(container_id, &stub.to_bytes(), tid)
+    }
+
+    /// Remove a value and its overflow chain if it has one, logging the change.
+    /// The change joins the log chain of tid, which ends when tid commits or aborts.
+    pub(crate) fn delete_logged(&self, id: ValueId, tid: TransactionId) -> Result<(), CrustyError> {
+        let record = match self.delete_record_logged(id, tid)? {
+            Some(record) => StoredRecord::from_bytes(&record)?,
+            None => return Ok(()),
+        };
+        if let StoredRecord::Overflow { len, first } = record {
+            let hf = self.open_hf(id.container_id)?;
+            let chunks = chain(first, len, |pid| self.read_page(&hf, pid, false))
+                .map(|(loc, data)| data.map(|_| loc))
+                .collect::<Result<Vec<_>, _>>()?;
+            for (pid, slot) in chunks {
+                self.delete_record_logged(hf.value_id(pid, slot), tid)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Store many values, logging each one. Runs of values that fit in a page are added to the
+    /// same page until it is full, so the page is written once for the run.
+    /// The change joins the log chain of tid, which ends when tid commits or aborts.
+    pub(crate) fn insert_batch_logged(
+        &self,
+        container_id: ContainerId,
+        values: Vec<Vec<u8>>,
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        let hf = self.open_hf(container_id)?;
+        let mut ids = Vec::with_capacity(values.len());
+        let mut values = values.into_iter().peekable();
+        while let Some(value) = values.peek() {
+            let mut record = match StoredRecord::inline(value, self.page_size) {
+                Some(record) => record.to_bytes(),
+                None => {
+                    let value = values.next().unwrap();
+                    ids.push(self.insert_logged(container_id, &value, tid)?);
+                    continue;
+                }
+            };
+            let _latch = hf.write_latch.lock()?;
+            let mut page = self.page_for_insert(&hf, record.len())?;
+            while let Some(slot) = self.add_logged(container_id, &mut page, &record, tid)? {
+                ids.push(hf.value_id(page.get_page_id(), slot));
+                values.next();
+                match values
+                    .peek()
+                    .and_then(|v| StoredRecord::inline(v, self.page_size))
+                {
+                    Some(next) => record = next.to_bytes(),
+                    None => break,
+                }
+            }
+            self.flush_page(&hf, &page)?;
+        }
+        Ok(ids)
+    }
+
+    /// Store a record in the first page with room, logging the change.
+    fn insert_record_logged(
+        &self,
+        container_id: ContainerId,
+        value: &[u8],
+        tid: TransactionId,
+    ) -> Result<ValueId, CrustyError> {
+        let hf = self.open_hf(container_id)?;
+        let _latch = hf.write_latch.lock()?;
+        let mut page = self.page_for_insert(&hf, value.len())?;
+        let slot = self
+            .add_logged(container_id, &mut page, value, tid)?
+            .ok_or_else(|| {
+                CrustyError::CrustyError(format!(
+                    "No space for {} bytes on page {}",
+                    value.len(),
+                    page.get_page_id()
+                ))
+            })?;
+        self.flush_page(&hf, &page)?;
+        Ok(hf.value_id(page.get_page_id(), slot))
+    }
+
+    /// Find the first page with room for a record of len bytes using the free-space map, or a
+    /// new page at the end of the file. The caller must hold the write latch of hf.
+    pub(crate) fn page_for_insert(
+        &self,
+        hf: &Arc<HeapFile>,
+        len: usize,
+    ) -> Result<Page, CrustyError> {
+        if let Some(page) = self.page_with_room(hf, len, hf.num_pages())? {
+            return Ok(page);
+        }
+        if hf.num_pages() >= hf.max_pages() {
+            return Err(CrustyError::CrustyError(format!(
+                "Container {} is full at {} pages",
+                hf.container_id,
+                hf.max_pages()
+            )));
+        }
+        let page = Page::new_sized(hf.num_pages(), self.page_size);
+        if page.get_free_space() < len + SLOT_ENTRY_SIZE {
+            return Err(CrustyError::CrustyError(format!(
+                "Value of {} bytes does not fit in an empty page",
+                len
+            )));
+        }
+        Ok(page)
+    }
+
+    /// Find the first page before `before` with room for a record of len bytes using the
+    /// free-space map. The caller must hold the write latch of hf.
+    pub(crate) fn page_with_room(
+        &self,
+        hf: &Arc<HeapFile>,
+        len: usize,
+        before: PageNo,
+    ) -> Result<Option<Page>, CrustyError> {
+        let need = len + SLOT_ENTRY_SIZE;
+        let mut after = None;
+        while let Some(pid) = hf.fsm.find_page(need, after).filter(|pid| *pid < before) {
+            let page = self.read_page(hf, pid, false)?;
+            if page.get_free_space() >= need {
+                return Ok(Some(page));
+            }
+            // The map was out of date
+            hf.fsm.update(pid, page.get_free_space());
+            after = Some(pid);
+        }
+        Ok(None)
+    }
+
+    /// Add a record to a page and log the insert, without writing the page.
+    /// Returns None if the page is full.
+    pub(crate) fn add_logged(
+        &self,
+        container_id: ContainerId,
+        page: &mut Page,
+        value: &[u8],
+        tid: TransactionId,
+    ) -> Result<Option<SlotId>, CrustyError> {
+        let slot = match page.add_value(value) {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+        if self.is_temporary(container_id) {
+            return Ok(Some(slot));
+        }
+        let lsn = self.log.append(
+            tid,
+            LogKind::Insert {
+                container_id,
+                page_id: page.get_page_id(),
+                slot_id: slot,
+                value: value.to_vec(),
+            },
+        )?;
+        page.set_lsn(lsn);
+        Ok(Some(slot))
+    }
+
+    /// Remove a record, logging its bytes so the delete can be undone.
+    /// Returns the removed record if the slot held one.
+    fn delete_record_logged(
+        &self,
+        id: ValueId,
+        tid: TransactionId,
+    ) -> Result<Option<Vec<u8>>, CrustyError> {
+        if let (Some(page_id), Some(slot)) = (id.page_id, id.slot_id) {
+            let hf = self.open_hf(id.container_id)?;
+            let pid = hf.page_no(id.segment_id, page_id);
+            let _latch = hf.write_latch.lock()?;
+            let mut page = self.read_page(&hf, pid, false)?;
+            if let Some(old) = page.get_value(slot) {
+                page.delete_value(slot);
+                if !self.is_temporary(id.container_id) {
+                    let lsn = self.log.append(
+                        tid,
+                        LogKind::Delete {
+                            container_id: id.container_id,
+                            page_id: pid,
+                            slot_id: slot,
+                            value: old.clone(),
+                        },
+                    )?;
+                    page.set_lsn(lsn);
+                }
+                self.flush_page(&hf, &page)?;
+                return Ok(Some(old));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Force every open heap file to stable storage and save its free-space map
+    pub(crate) fn sync_containers(&self) -> Result<(), CrustyError> {
+        for hf in self.containers.read()?.values() {
+            hf.sync()?;
+            hf.fsm.save()?;
+        }
+        Ok(())
+    }
+
+    /// Recompute the free-space map of every open heap file from its pages
+    pub(crate) fn rebuild_fsms(&self) -> Result<(), CrustyError> {
+        for hf in self.containers.read()?.values() {
+            hf.fsm
+                .rebuild(hf.num_pages(), |pid| self.read_page(hf, pid, false));
+        }
+        Ok(())
     }
 
     /// Get the number of pages for a container
-    fn get_num_pages(&self, container_id: ContainerId) -> PageId {
+    fn get_num_pages(&self, container_id: ContainerId) -> PageNo {
         if let Ok(hf) = self.open_hf(container_id) {
             hf.num_pages()
         } else {
@@ -101,7 +589,7 @@ impl StorageManager {
     }
 
     /// For testing
-    pub fn get_page_debug(&self, container_id: ContainerId, page_id: PageId) -> String {
+    pub fn get_page_debug(&self, container_id: ContainerId, page_id: PageNo) -> String {
         match self.get_page(
             container_id,
             page_id,
@@ -126,12 +614,16 @@ impl StorageTrait for StorageManager {
     /// For startup/shutdown: check the storage_dir for data persisted in shutdown() that you can
     /// use to populate this instance of the SM. Otherwise create a new one.
     fn new(storage_dir: &Path) -> Self {
-        let sm = StorageManager {
-            storage_dir: storage_dir.to_path_buf(),
-            is_temp: false,
-        };
-        sm.ensure_dirs().expect("Failed to create storage dirs");
-        sm
+        StorageManager::open(storage_dir).expect("Failed to open heapstore storage manager")
+    }
+
+    /// Open the storage manager in storage_dir, creating it with pages of page_size bytes if it
+    /// is new. The page size is saved in the manifest, and an existing dir keeps its own.
+    fn open_with_page_size(
+        storage_dir: &Path,
+        page_size: Option<usize>,
+    ) -> Result<Self, CrustyError> {
+        StorageManager::open_with(storage_dir, page_size, SEGMENT_PAGES)
     }
 
     /// Create a new storage manager for testing. There is no startup/shutdown logic here: it
@@ -139,12 +631,7 @@ impl StorageTrait for StorageManager {
     fn new_test_sm() -> Self {
         let storage_dir = gen_random_test_sm_dir();
         debug!("Making new temp storage_manager {:?}", storage_dir);
-        let sm = StorageManager {
-            storage_dir: storage_dir.clone(),
-            is_temp: true,
-        };
-        sm.ensure_dirs().unwrap();
-        sm
+        StorageManager::build(storage_dir, true, PAGE_SIZE, SEGMENT_PAGES).unwrap()
     }
 
     /// Insert some bytes into a container for a particular value (e.g. record).
@@ -152,80 +639,153 @@ impl StorageTrait for StorageManager {
     /// Returns the value id associated with the stored value.
     /// Function will need to find the first page that can hold the value.
     /// A new page may need to be created if no space on existing pages can be found.
+    /// Values larger than a page are stored in a chain of overflow pages.
     fn insert_value(
         &self,
         container_id: ContainerId,
         value: Vec<u8>,
         tid: TransactionId,
     ) -> ValueId {
-        if value.len() > PAGE_SIZE {
-            panic!("Cannot handle inserting a value larger than the page size");
-        }
-        let hf = self.open_hf(container_id).expect("Container not found");
-        let pages = hf.num_pages();
-        for pid in 0..pages {
-            let mut page = hf.read_page_from_file(pid).unwrap();
-            if let Some(slot) = page.add_value(&value) {
-                hf.write_page_to_file(&page).unwrap();
-                return ValueId {
-                    container_id,
-                    segment_id: None,
-                    page_id: Some(pid),
-                    slot_id: Some(slot),
-                };
-            }
-        }
-        // no fit, new page
-        let pid = pages;
-        let mut page = Page::new(pid);
-        let slot = page.add_value(&value).unwrap();
-        hf.write_page_to_file(&page).unwrap();
-        ValueId {
-            container_id,
-            segment_id: None,
-            page_id: Some(pid),
-            slot_id: Some(slot),
-        }
+        self.insert_logged(container_id, &value, tid)
+            .expect("Container not found")
     }
 
     /// Insert some bytes into a container for vector of values (e.g. record).
     /// Any validation will be assumed to happen before.
     /// Returns a vector of value ids associated with the stored values.
+    /// Values are packed into pages so each page is written once for the batch.
     fn insert_values(
         &self,
         container_id: ContainerId,
         values: Vec<Vec<u8>>,
         tid: TransactionId,
     ) -> Vec<ValueId> {
-        let mut ret = Vec::new();
-        for v in values {
-            ret.push(self.insert_value(container_id, v, tid));
-        }
-        ret
+        self.insert_batch_logged(container_id, values, tid)
+            .expect("Container not found")
     }
 
     /// Delete the data for a value. If the valueID is not found it returns Ok() still.
     fn delete_value(&self, id: ValueId, tid: TransactionId) -> Result<(), CrustyError> {
-        if let (Some(pid), Some(slot)) = (id.page_id, id.slot_id) {
-            let hf = self.open_hf(id.container_id)?;
-            let mut page = hf.read_page_from_file(pid)?;
-            page.delete_value(slot);
-            hf.write_page_to_file(&page)?;
-        }
-        Ok(())
+        self.delete_logged(id, tid)
     }
 
     /// Updates a value. Returns valueID on update (which may have changed). Error on failure
     /// Any process that needs to determine if a value changed will need to compare the return valueId against
     /// the sent value.
+    /// The delete and insert are logged in the chain of tid, so a crash before tid commits
+    /// restores the old value.
     fn update_value(
         &self,
         value: Vec<u8>,
         id: ValueId,
-        _tid: TransactionId,
+        tid: TransactionId,
     ) -> Result<ValueId, CrustyError> {
-        self.delete_value(id, _tid)?;
-        Ok(self.insert_value(id.container_id, value, _tid))
+        self.delete_logged(id, tid)?;
+        self.insert_logged(id.container_id, &value, tid)
+    }
+
+    /// Overwrite a value in its slot so its ValueId does not change. The delete of the old
+    /// record and the insert of the new one are logged in the chain of tid.
+    fn replace_value(
+        &self,
+        value: Vec<u8>,
+        id: ValueId,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let not_found = || CrustyError::CrustyError(format!("ValueId not found: {:?}", id));
+        let (page_id, slot) = id.page_id.zip(id.slot_id).ok_or_else(not_found)?;
+        let record = StoredRecord::inline(&value, self.page_size)
+            .ok_or_else(|| {
+                CrustyError::CrustyError(format!(
+                    "Value of {} bytes is too large to replace in place",
+                    value.len()
+                ))
+            })?
+            .to_bytes();
+        let hf = self.open_hf(id.container_id)?;
+        let pid = hf.page_no(id.segment_id, page_id);
+        let _latch = hf.write_latch.lock()?;
+        let mut page = self.read_page(&hf, pid, false)?;
+        let old = page.get_value(slot).ok_or_else(not_found)?;
+        if !matches!(StoredRecord::from_bytes(&old)?, StoredRecord::Inline(_)) {
+            return Err(CrustyError::CrustyError(format!(
+                "Cannot replace {:?} in place",
+                id
+            )));
+        }
+        if page.put_value(slot, &record).is_none() {
+            return Err(CrustyError::CrustyError(format!(
+                "No space to replace {:?} in place",
+                id
+            )));
+        }
+        let container_id = id.container_id;
+        if !self.is_temporary(container_id) {
+            self.log.append(
+                tid,
+                LogKind::Delete {
+                    container_id,
+                    page_id: pid,
+                    slot_id: slot,
+                    value: old,
+                },
+            )?;
+            let lsn = self.log.append(
+                tid,
+                LogKind::Insert {
+                    container_id,
+                    page_id: pid,
+                    slot_id: slot,
+                    value: record,
+                },
+            )?;
+            page.set_lsn(lsn);
+        }
+        self.flush_page(&hf, &page)
+    }
+
+    /// Compact the pages of a container, move the values off nearly empty pages to earlier pages
+    /// if move_values is set, and cut the empty pages off the end of the heap file.
+    /// The moves are logged in the chain of tid.
+    fn vacuum(
+        &self,
+        container_id: ContainerId,
+        move_values: bool,
+        tid: TransactionId,
+    ) -> Result<Vec<(ValueId, ValueId)>, CrustyError> {
+        self.vacuum_container(container_id, move_values, tid)
+    }
+
+    /// Read every page of a container from disk, verifying its checksum, and check its header
+    /// and free-space map entry.
+    fn check(&self, container_id: ContainerId) -> Result<Vec<String>, CrustyError> {
+        self.check_container(container_id)
+    }
+
+    /// End the log chain of tid with its commit record and force the log to disk.
+    fn commit(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        if self.end_logged(tid)? {
+            self.log.sync()?;
+        }
+        Ok(())
+    }
+
+    /// End the log chain of tid once the caller undid its changes. The log is not forced, as
+    /// recovery undoing the chain again leaves the same values.
+    fn abort(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.end_logged(tid).map(|_| ())
+    }
+
+    fn recovered(&self) -> bool {
+        self.recovered
+    }
+
+    /// Write every page to disk and drop the log records no recovery needs anymore, keeping
+    /// those of the transactions that have not ended.
+    fn checkpoint(&self) -> Result<(), CrustyError> {
+        let dropped = self.checkpoint_log()?;
+        debug!("Checkpoint dropped {} bytes of the log", dropped);
+        Ok(())
     }
 
     /// Create a new container to be stored.
@@ -238,18 +798,40 @@ impl StorageTrait for StorageManager {
     /// # Arguments
     ///
     /// * `container_id` - Id of container to add delta to.
+    ///
+    /// A Temporary container is not logged or saved in the manifest, and its heap file is
+    /// removed when the storage manager next starts.
     fn create_container(
         &self,
         container_id: ContainerId,
-        _name: Option<String>,
-        _container_type: common::ids::StateType,
-        _dependencies: Option<Vec<ContainerId>>,
+        name: Option<String>,
+        container_type: common::ids::StateType,
+        dependencies: Option<Vec<ContainerId>>,
     ) -> Result<(), CrustyError> {
         self.ensure_dirs()?;
+        if container_type == common::ids::StateType::Temporary {
+            self.temp_containers.write()?.insert(container_id);
+            let path = self.hf_path(container_id);
+            heapfile::remove_files(&path)?;
+            let hf = HeapFile::new(path, container_id, self.page_size, self.segment_pages)?;
+            self.containers.write()?.insert(container_id, Arc::new(hf));
+            return Ok(());
+        }
         let path = self.hf_path(container_id);
+        if !path.exists() {
+            self.log.append(
+                TransactionId::new(),
+                LogKind::CreateContainer { container_id },
+            )?;
+        }
         // new file created by HeapFile
-        HeapFile::new(path, container_id)?;
-        Ok(())
+        let hf = HeapFile::new(path, container_id, self.page_size, self.segment_pages)?;
+        self.containers.write()?.insert(container_id, Arc::new(hf));
+        self.manifest.write()?.containers.insert(
+            container_id,
+            ContainerEntry::new(name, container_type, dependencies),
+        );
+        self.save_manifest()
     }
 
     /// A wrapper function to call create container
@@ -260,11 +842,26 @@ impl StorageTrait for StorageManager {
     /// Remove the container and all stored values in the container.
     /// If the container is persisted remove the underlying files
     fn remove_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
-        let path = self.hf_path(container_id);
-        if path.exists() {
-            fs::remove_file(path)?;
+        if self.is_temporary(container_id) {
+            if let Some(bp) = &self.buffer_pool {
+                bp.discard_container(container_id);
+            }
+            self.containers.write()?.remove(&container_id);
+            let path = self.hf_path(container_id);
+            self.temp_containers.write()?.remove(&container_id);
+            return heapfile::remove_files(&path);
         }
-        Ok(())
+        self.log.append(
+            TransactionId::new(),
+            LogKind::RemoveContainer { container_id },
+        )?;
+        if let Some(bp) = &self.buffer_pool {
+            bp.discard_container(container_id);
+        }
+        self.containers.write()?.remove(&container_id);
+        self.manifest.write()?.containers.remove(&container_id);
+        self.save_manifest()?;
+        heapfile::remove_files(&self.hf_path(container_id))
     }
 
     /// Get an iterator that returns all valid records
@@ -274,8 +871,8 @@ impl StorageTrait for StorageManager {
         tid: TransactionId,
         _perm: Permissions,
     ) -> Self::ValIterator {
-        let hf = Arc::new(self.open_hf(container_id).expect("Container not found"));
-        HeapFileIterator::new(tid, hf)
+        let hf = self.open_hf(container_id).expect("Container not found");
+        HeapFileIterator::new(tid, hf, self.buffer_pool.clone())
     }
 
     fn get_iterator_from(
@@ -285,24 +882,32 @@ impl StorageTrait for StorageManager {
         _perm: Permissions,
         start: ValueId,
     ) -> Self::ValIterator {
-        let hf = Arc::new(self.open_hf(container_id).expect("Container not found"));
-        HeapFileIterator::new_from(tid, hf, start)
+        let hf = self.open_hf(container_id).expect("Container not found");
+        HeapFileIterator::new_from(tid, hf, self.buffer_pool.clone(), start)
     }
 
     /// Get the data for a particular ValueId. Error if does not exists
+    /// Values stored in overflow pages are reassembled from their chain.
     fn get_value(
         &self,
         id: ValueId,
         tid: TransactionId,
         perm: Permissions,
     ) -> Result<Vec<u8>, CrustyError> {
-        if let (Some(pid), Some(slot)) = (id.page_id, id.slot_id) {
-            if let Some(page) =
-                self.get_page(id.container_id, pid, TransactionId::new(), perm, false)
-            {
-                return page
+        if let (Some(page_id), Some(slot)) = (id.page_id, id.slot_id) {
+            let hf = self.open_hf(id.container_id)?;
+            let pid = hf.page_no(id.segment_id, page_id);
+            // A corrupt page is an error of its own, a page past the end is just not found
+            if pid < hf.num_pages() {
+                let page = self.read_page(&hf, pid, false)?;
+                let record = page
                     .get_value(slot)
-                    .ok_or(CrustyError::CrustyError(format!("Slot {} empty", slot)));
+                    .ok_or(CrustyError::CrustyError(format!("Slot {} empty", slot)))?;
+                if let Some(value) = StoredRecord::from_bytes(&record)?
+                    .into_value(|pid| self.read_page(&hf, pid, false))?
+                {
+                    return Ok(value);
+                }
             }
         }
         Err(CrustyError::CrustyError(format!(
@@ -315,30 +920,58 @@ impl StorageTrait for StorageManager {
         &self.storage_dir
     }
 
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
     /// Testing utility to reset all state associated the storage manager. Deletes all data in
     /// storage path (keeping storage path as a directory). Doesn't need to serialize any data to
     /// disk as its just meant to clear state.
     ///
     /// Clear any data structures in the SM you add
     fn reset(&self) -> Result<(), CrustyError> {
+        if let Some(bp) = &self.buffer_pool {
+            bp.discard_all();
+        }
+        self.containers.write()?.clear();
+        self.temp_containers.write()?.clear();
+        *self.manifest.write()? = Manifest {
+            page_size: self.page_size,
+            segment_pages: self.segment_pages,
+            ..Manifest::default()
+        };
         fs::remove_dir_all(self.storage_dir.clone())?;
         fs::create_dir_all(self.storage_dir.clone()).unwrap();
         self.ensure_dirs()?;
+        self.log.reset()?;
         Ok(())
     }
 
     /// If there is a buffer pool or cache it should be cleared/reset.
     /// Otherwise do nothing.
-    fn clear_cache(&self) {}
+    fn clear_cache(&self) {
+        if let Some(bp) = &self.buffer_pool {
+            bp.clear().expect("Failed to flush buffer pool");
+        }
+    }
 
     /// Shutdown the storage manager. Should be safe to call multiple times. You can assume this
     /// function will never be called on a temp SM.
-    /// This should serialize the mapping between containerID and Heapfile to disk in a way that
-    /// can be read by StorageManager::new.
-    /// HINT: Heapfile won't be serializable/deserializable. You'll want to serialize information
-    /// that can be used to create a HeapFile object pointing to the same data. You don't need to
-    /// worry about recreating read_count or write_count.
-    fn shutdown(&self) {}
+    /// Flushes every page, then writes the manifest of containers that StorageManager::new
+    /// reads back to reopen the heap files. The log records of transactions that have not
+    /// committed are kept, so the next start undoes them.
+    fn shutdown(&self) {
+        let keep = self.log.checkpoint_lsn();
+        self.log.sync().expect("Failed to sync log");
+        if let Some(bp) = &self.buffer_pool {
+            bp.flush_all().expect("Failed to flush buffer pool");
+        }
+        self.sync_containers().expect("Failed to sync heap files");
+        self.save_manifest().expect("Failed to save manifest");
+        self.log
+            .truncate_before(keep)
+            .expect("Failed to truncate log");
+    }
 }
 
 /// Trait Impl for Drop
@@ -359,6 +992,8 @@ impl Drop for StorageManager {
 #[allow(unused_must_use)]
 mod test {
     use super::*;
+    use crate::manifest::MANIFEST_VERSION;
+    use crate::page::MAX_PAGE_SIZE;
     use crate::storage_manager::StorageManager;
     use common::storage_trait::StorageTrait;
     use common::testutil::*;
@@ -412,7 +1047,7 @@ mod test {
         }
         let iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
         for (i, x) in iter.enumerate() {
-            assert_eq!(byte_vec[i], x.0);
+            assert_eq!(byte_vec[i], x.unwrap().0);
         }
 
         // Should be on two pages
@@ -430,7 +1065,7 @@ mod test {
 
         let iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
         for (i, x) in iter.enumerate() {
-            assert_eq!(byte_vec[i], x.0);
+            assert_eq!(byte_vec[i], x.unwrap().0);
         }
 
         // Should be on 3 pages
@@ -447,10 +1082,494 @@ mod test {
 
         let iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
         for (i, x) in iter.enumerate() {
-            assert_eq!(byte_vec[i], x.0);
+            assert_eq!(byte_vec[i], x.unwrap().0);
         }
     }
 
+    #[test]
+    fn hs_sm_c_overflow() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+
+        let small = get_random_byte_vec(100);
+        let page_sized = get_random_byte_vec(PAGE_SIZE);
+        let large = get_random_byte_vec(PAGE_SIZE * 5 + 123);
+        let id_small = sm.insert_value(cid, small.clone(), tid);
+        let id_page = sm.insert_value(cid, page_sized.clone(), tid);
+        let id_large = sm.insert_value(cid, large.clone(), tid);
+        assert_eq!(
+            page_sized,
+            sm.get_value(id_page, tid, Permissions::ReadOnly).unwrap()
+        );
+        assert_eq!(
+            large,
+            sm.get_value(id_large, tid, Permissions::ReadOnly).unwrap()
+        );
+
+        // Scans skip the overflow chunks
+        let vals: Vec<(Vec<u8>, ValueId)> = sm
+            .get_iterator(cid, tid, Permissions::ReadOnly)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(3, vals.len());
+        assert!(vals.contains(&(large.clone(), id_large)));
+        assert!(vals.contains(&(page_sized.clone(), id_page)));
+
+        // Update between inline and overflow values
+        let id_small = sm.update_value(large.clone(), id_small, tid).unwrap();
+        assert_eq!(
+            large,
+            sm.get_value(id_small, tid, Permissions::ReadOnly).unwrap()
+        );
+        let id_large = sm.update_value(small.clone(), id_large, tid).unwrap();
+        assert_eq!(
+            small,
+            sm.get_value(id_large, tid, Permissions::ReadOnly).unwrap()
+        );
+
+        // Deleting frees the chain
+        sm.delete_value(id_small, tid).unwrap();
+        sm.delete_value(id_page, tid).unwrap();
+        assert!(sm.get_value(id_small, tid, Permissions::ReadOnly).is_err());
+        let vals: Vec<Vec<u8>> = sm
+            .get_iterator(cid, tid, Permissions::ReadOnly)
+            .map(|r| r.unwrap().0)
+            .collect();
+        assert_eq!(vec![small], vals);
+        let pages = sm.get_num_pages(cid);
+        let used: usize = (0..pages)
+            .map(|pid| {
+                let p = sm
+                    .get_page(cid, pid, tid, Permissions::ReadOnly, false)
+                    .unwrap();
+                PAGE_SIZE - p.get_free_space()
+            })
+            .sum();
+        assert!(used < PAGE_SIZE);
+
+        // A record that cannot be decoded ends the scan with an error
+        let mut page = sm
+            .get_page(cid, 0, tid, Permissions::ReadOnly, false)
+            .unwrap();
+        page.add_value(&[0xff]).unwrap();
+        sm.write_page(cid, &page, tid).unwrap();
+        let mut iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
+        assert!(iter.any(|r| r.is_err()));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn hs_sm_d_free_space_map() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+
+        // A batch is packed into as few pages as a page at a time would use
+        let vals = get_random_vec_of_byte_vec(200, 100, 100);
+        let ids = sm.insert_values(cid, vals.clone(), tid);
+        let per_page = ids.iter().filter(|id| id.page_id == Some(0)).count();
+        assert!(per_page > 30);
+        assert_eq!(
+            vals.len().div_ceil(per_page),
+            sm.get_num_pages(cid) as usize
+        );
+        assert!(compare_unordered_byte_vecs(
+            &vals,
+            sm.get_iterator(cid, tid, Permissions::ReadOnly)
+                .map(|r| r.unwrap().0)
+                .collect()
+        ));
+
+        // Space freed on a middle page is found again
+        for id in ids.iter().filter(|id| id.page_id == Some(2)) {
+            sm.delete_value(*id, tid).unwrap();
+        }
+        let id = sm.insert_value(cid, get_random_byte_vec(500), tid);
+        assert_eq!(Some(2), id.page_id);
+
+        // The map is saved on shutdown and used when the container is reopened
+        sm.commit(tid).unwrap();
+        sm.shutdown();
+        drop(sm);
+        let fsm_path = path.join(STORAGE_DIR).join("1.fsm");
+        assert!(fsm_path.exists());
+        let sm = StorageManager::new(&path);
+        let id = sm.insert_value(cid, get_random_byte_vec(500), tid);
+        assert_eq!(Some(2), id.page_id);
+        sm.remove_container(cid).unwrap();
+        assert!(!fsm_path.exists());
+        sm.reset().unwrap();
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_e_manifest() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let tid = TransactionId::new();
+        sm.create_container(
+            1,
+            Some("t1".to_string()),
+            common::ids::StateType::BaseTable,
+            None,
+        )
+        .unwrap();
+        sm.create_container(
+            2,
+            Some("t1_hash".to_string()),
+            common::ids::StateType::HashTable,
+            Some(vec![1]),
+        )
+        .unwrap();
+        sm.create_table(3).unwrap();
+        sm.remove_container(3).unwrap();
+        let vals = get_random_vec_of_byte_vec(100, 50, 100);
+        sm.insert_values(1, vals.clone(), tid);
+        let pages = sm.get_num_pages(1);
+        sm.commit(tid).unwrap();
+        sm.shutdown();
+        drop(sm);
+
+        let sm = StorageManager::new(&path);
+        {
+            let manifest = sm.manifest.read().unwrap();
+            assert_eq!(vec![&1, &2], manifest.containers.keys().collect::<Vec<_>>());
+            assert_eq!(pages, manifest.containers[&1].num_pages);
+            let hash = &manifest.containers[&2];
+            assert_eq!(Some("t1_hash".to_string()), hash.name);
+            assert_eq!(common::ids::StateType::HashTable, hash.container_type);
+            assert_eq!(Some(vec![1]), hash.dependencies);
+        }
+        assert!(compare_unordered_byte_vecs(
+            &vals,
+            sm.get_iterator(1, tid, Permissions::ReadOnly)
+                .map(|r| r.unwrap().0)
+                .collect()
+        ));
+        drop(sm);
+
+        // A heap file that lost pages fails validation
+        let hf_path = path.join(STORAGE_DIR).join("1.hf");
+        let hf_bytes = fs::read(&hf_path).unwrap();
+        fs::write(&hf_path, &hf_bytes[..PAGE_SIZE]).unwrap();
+        assert!(StorageManager::open(&path).is_err());
+        fs::write(&hf_path, &hf_bytes).unwrap();
+        assert!(StorageManager::open(&path).is_ok());
+
+        // So does a manifest from another version
+        let manifest_path = path.join(STORAGE_DIR).join(MANIFEST_FILE);
+        let text = fs::read_to_string(&manifest_path).unwrap();
+        fs::write(
+            &manifest_path,
+            text.replace(
+                &format!("\"version\": {}", MANIFEST_VERSION),
+                "\"version\": 99",
+            ),
+        )
+        .unwrap();
+        assert!(StorageManager::open(&path).is_err());
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_f_replace_value() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        let ids = sm.insert_values(cid, get_random_vec_of_byte_vec(10, 100, 200), tid);
+        let new_val = get_random_byte_vec(300);
+        sm.replace_value(new_val.clone(), ids[3], tid).unwrap();
+        assert_eq!(
+            new_val,
+            sm.get_value(ids[3], tid, Permissions::ReadOnly).unwrap()
+        );
+        // Values too large for their page cannot be replaced in place
+        assert!(sm
+            .replace_value(get_random_byte_vec(PAGE_SIZE), ids[3], tid)
+            .is_err());
+        sm.commit(tid).unwrap();
+        sm.shutdown();
+        drop(sm);
+
+        let sm = StorageManager::new(&path);
+        assert_eq!(
+            new_val,
+            sm.get_value(ids[3], tid, Permissions::ReadOnly).unwrap()
+        );
+        assert_eq!(10, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+        drop(sm);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_g_vacuum() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(200, 100, 100);
+        let ids = sm.insert_values(cid, vals.clone(), tid);
+        let pages = sm.get_num_pages(cid);
+        assert!(pages > 4);
+
+        // Keep every tenth value, which leaves every page sparse
+        let mut kept = HashMap::new();
+        for (i, (id, val)) in ids.iter().zip(vals).enumerate() {
+            if i % 10 == 0 {
+                kept.insert(*id, val);
+            } else {
+                sm.delete_value(*id, tid).unwrap();
+            }
+        }
+        // Without moving values only the empty slots are reclaimed
+        assert!(sm.vacuum(cid, false, tid).unwrap().is_empty());
+        assert_eq!(pages, sm.get_num_pages(cid));
+
+        // The values move to the first page and the rest of the file is cut off
+        let moved = sm.vacuum(cid, true, tid).unwrap();
+        assert!(!moved.is_empty());
+        assert_eq!(1, sm.get_num_pages(cid));
+        for (old_id, new_id) in moved {
+            let val = kept.remove(&old_id).unwrap();
+            kept.insert(new_id, val);
+        }
+        let check = |sm: &StorageManager| {
+            for (id, val) in &kept {
+                assert_eq!(val, &sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+            }
+            assert_eq!(
+                kept.len(),
+                sm.get_iterator(cid, tid, Permissions::ReadOnly).count()
+            );
+        };
+        check(&sm);
+        let hf_path = path.join(STORAGE_DIR).join("1.hf");
+        assert_eq!(PAGE_SIZE as u64, fs::metadata(&hf_path).unwrap().len());
+
+        // The shorter file passes validation when reopened
+        sm.commit(tid).unwrap();
+        sm.shutdown();
+        drop(sm);
+        let sm = StorageManager::new(&path);
+        check(&sm);
+        drop(sm);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_h_check() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        let ids = sm.insert_values(cid, get_random_vec_of_byte_vec(100, 100, 100), tid);
+        for id in ids.iter().step_by(3) {
+            sm.delete_value(*id, tid).unwrap();
+        }
+        assert!(sm.check(cid).unwrap().is_empty());
+        sm.commit(tid).unwrap();
+        sm.shutdown();
+        drop(sm);
+
+        // Flip a byte in the body of the second page
+        let hf_path = path.join(STORAGE_DIR).join("1.hf");
+        let mut hf_bytes = fs::read(&hf_path).unwrap();
+        hf_bytes[2 * PAGE_SIZE - 1] ^= 0xff;
+        fs::write(&hf_path, &hf_bytes).unwrap();
+
+        let sm = StorageManager::new(&path);
+        sm.clear_cache();
+        let id = ids.iter().find(|id| id.page_id == Some(1)).unwrap();
+        assert!(matches!(
+            sm.get_value(*id, tid, Permissions::ReadOnly),
+            Err(CrustyError::CorruptPage(page, _)) if page == ValueId::new_page(1, 1)
+        ));
+        // A scan returns the records of the first page, then stops at the corrupt one
+        let scanned: Vec<_> = sm.get_iterator(cid, tid, Permissions::ReadOnly).collect();
+        assert!(scanned[..scanned.len() - 1]
+            .iter()
+            .all(|r| matches!(r, Ok((_, id)) if id.page_id == Some(0))));
+        assert!(matches!(
+            scanned.last(),
+            Some(Err(CrustyError::CorruptPage(page, _))) if *page == ValueId::new_page(1, 1)
+        ));
+        let problems = sm.check(cid).unwrap();
+        assert_eq!(1, problems.len());
+        assert!(problems[0].contains("checksum"));
+        drop(sm);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_i_segments() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::open_with(&path, Some(PAGE_SIZE), 4).unwrap();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(400, 100, 100);
+        let ids = sm.insert_values(cid, vals.clone(), tid);
+        let big = get_random_byte_vec(PAGE_SIZE * 5);
+        let big_id = sm.insert_value(cid, big.clone(), tid);
+        assert!(sm.get_num_pages(cid) > 12);
+        assert!(ids.iter().any(|id| id.segment_id == Some(2)));
+        let hf_path = path.join(STORAGE_DIR).join("1.hf");
+        assert!(heapfile::segment_path(&hf_path, 3).exists());
+
+        for (id, val) in ids.iter().zip(&vals) {
+            assert_eq!(val, &sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        assert_eq!(
+            big,
+            sm.get_value(big_id, tid, Permissions::ReadOnly).unwrap()
+        );
+        let mut scanned: Vec<ValueId> = sm
+            .get_iterator(cid, tid, Permissions::ReadOnly)
+            .map(|r| r.unwrap().1)
+            .collect();
+        assert_eq!(ids.len() + 1, scanned.len());
+        // The stub of the big value may fit in a page of small values
+        scanned.retain(|id| *id != big_id);
+        assert_eq!(ids, scanned);
+
+        // A scan from a page of the second segment skips the first segment
+        let start = *ids.iter().find(|id| id.segment_id == Some(1)).unwrap();
+        let from: Vec<ValueId> = sm
+            .get_iterator_from(cid, tid, Permissions::ReadOnly, start)
+            .map(|r| r.unwrap().1)
+            .collect();
+        assert_eq!(from[0], start);
+        assert!(from.iter().all(|id| id.segment_id.is_some()));
+
+        // Emptying the later segments lets vacuum remove their files
+        for id in ids.iter().filter(|id| id.segment_id.is_some()) {
+            sm.delete_value(*id, tid).unwrap();
+        }
+        sm.delete_value(big_id, tid).unwrap();
+        sm.vacuum(cid, false, tid).unwrap();
+        assert!(sm.get_num_pages(cid) <= 4);
+        assert!(!heapfile::segment_path(&hf_path, 1).exists());
+
+        // The segment size is kept in the manifest
+        sm.commit(tid).unwrap();
+        sm.shutdown();
+        drop(sm);
+        let sm = StorageManager::new(&path);
+        let kept: Vec<&ValueId> = ids.iter().filter(|id| id.segment_id.is_none()).collect();
+        assert_eq!(
+            kept.len(),
+            sm.get_iterator(cid, tid, Permissions::ReadOnly).count()
+        );
+        let id = sm.insert_value(cid, big.clone(), tid);
+        assert_eq!(big, sm.get_value(id, tid, Permissions::ReadOnly).unwrap());
+        assert!(heapfile::segment_path(&hf_path, 1).exists());
+        sm.remove_container(cid).unwrap();
+        assert!(!hf_path.exists());
+        assert!(!heapfile::segment_path(&hf_path, 1).exists());
+        drop(sm);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_j_page_size() {
+        init();
+        for page_size in [1024, 5000, 2 * MAX_PAGE_SIZE] {
+            assert!(
+                StorageManager::open_with(&gen_random_test_sm_dir(), Some(page_size), 4).is_err()
+            );
+        }
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::open_with_page_size(&path, Some(MAX_PAGE_SIZE)).unwrap();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        // Rows too wide for the default page size are stored inline
+        let vals = get_random_vec_of_byte_vec(10, 15000, 20000);
+        let ids = sm.insert_values(cid, vals.clone(), tid);
+        assert!(ids.iter().all(|id| id.page_id.unwrap() < 4));
+        let big = get_random_byte_vec(MAX_PAGE_SIZE * 2);
+        let big_id = sm.insert_value(cid, big.clone(), tid);
+        let num_pages = sm.get_num_pages(cid);
+        assert!(num_pages <= 7);
+        for (id, val) in ids.iter().zip(&vals) {
+            assert_eq!(val, &sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        assert_eq!(
+            ids.len() + 1,
+            sm.get_iterator(cid, tid, Permissions::ReadOnly).count()
+        );
+        assert!(sm.check(cid).unwrap().is_empty());
+        let hf_path = path.join(STORAGE_DIR).join("1.hf");
+        sm.clear_cache();
+        assert_eq!(
+            num_pages as u64 * MAX_PAGE_SIZE as u64,
+            fs::metadata(&hf_path).unwrap().len()
+        );
+
+        // The page size is kept in the manifest, and another one is refused
+        sm.commit(tid).unwrap();
+        sm.shutdown();
+        drop(sm);
+        assert!(StorageManager::open_with_page_size(&path, Some(PAGE_SIZE)).is_err());
+        let sm = StorageManager::open_with_page_size(&path, None).unwrap();
+        assert_eq!(MAX_PAGE_SIZE, sm.page_size);
+        assert_eq!(
+            big,
+            sm.get_value(big_id, tid, Permissions::ReadOnly).unwrap()
+        );
+        for (id, val) in ids.iter().zip(&vals) {
+            assert_eq!(val, &sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        drop(sm);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_k_temporary_container() {
+        init();
+        let path = gen_random_test_sm_dir();
+        let sm = StorageManager::new(&path);
+        let cid = ContainerId::MAX;
+        sm.create_container(cid, None, common::ids::StateType::Temporary, None)
+            .unwrap();
+        let tid = TransactionId::new();
+        let id = sm.insert_value(cid, get_random_byte_vec(100), tid);
+        sm.insert_values(cid, get_random_vec_of_byte_vec(50, 50, 100), tid);
+        sm.delete_value(id, tid).unwrap();
+        assert_eq!(50, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+
+        // Nothing about the container is logged or kept in the manifest
+        assert!(!sm.log.has_records());
+        assert!(!sm.manifest.read().unwrap().containers.contains_key(&cid));
+        let hf_path = sm.hf_path(cid);
+        assert!(hf_path.starts_with(path.join(STORAGE_DIR).join(TEMP_DIR)));
+        sm.clear_cache();
+        assert!(hf_path.exists());
+
+        // The heap file left by a process that did not remove it is gone after a restart
+        drop(sm);
+        let sm = StorageManager::new(&path);
+        assert!(!hf_path.exists());
+        assert!(sm.get_value(id, tid, Permissions::ReadOnly).is_err());
+        drop(sm);
+        fs::remove_dir_all(path).unwrap();
+    }
+
     #[test]
     #[ignore]
     fn hs_sm_b_iter_large() {
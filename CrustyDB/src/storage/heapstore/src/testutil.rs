@@ -1,19 +1,23 @@
 use crate::heap_page::HeapPage;
 use crate::heapfile::HeapFile;
+use crate::heapfile::{PageNo, SEGMENT_PAGES};
 use crate::page::Page;
 use crate::storage_manager::StorageManager;
 use common::ids::TransactionId;
-use common::ids::{ContainerId, PageId, SlotId};
+use common::ids::{ContainerId, SlotId};
 use common::storage_trait::StorageTrait;
 use common::testutil::*;
 use std::sync::Arc;
 
+/// Page sizes the benchmarks run with
+pub const BENCH_PAGE_SIZES: [usize; 4] = [4096, 8192, 16384, 65536];
+
 #[allow(dead_code)]
 pub(crate) fn fill_hf_sm(
     sm: &StorageManager,
     container_id: ContainerId,
-    num_pages: PageId,
-    vals_per_page: PageId,
+    num_pages: PageNo,
+    vals_per_page: PageNo,
     min_size: usize,
     max_size: usize,
 ) {
@@ -28,8 +32,8 @@ pub(crate) fn fill_hf_sm(
 #[allow(dead_code)]
 pub(crate) fn fill_hf(
     hf: Arc<HeapFile>,
-    num_pages: PageId,
-    vals_per_page: PageId,
+    num_pages: PageNo,
+    vals_per_page: PageNo,
     min_size: usize,
     max_size: usize,
 ) {
@@ -40,8 +44,8 @@ pub(crate) fn fill_hf(
 }
 
 pub(crate) fn get_random_page(
-    id: PageId,
-    vals_per_page: PageId,
+    id: PageNo,
+    vals_per_page: PageNo,
     min_size: usize,
     max_size: usize,
 ) -> (Page, Vec<SlotId>) {
@@ -54,13 +58,18 @@ pub(crate) fn get_random_page(
     (page, res)
 }
 
-pub fn bench_page_insert(vals: &[Vec<u8>]) {
-    let mut p = Page::new(0);
+pub fn bench_page_insert(vals: &[Vec<u8>], page_size: usize) {
+    let mut p = Page::new_sized(0, page_size);
     for i in vals {
         p.add_value(i).unwrap();
     }
 }
 
+/// A temp storage manager with pages of page_size bytes, removed when dropped
+pub fn bench_sm(page_size: usize) -> StorageManager {
+    StorageManager::build(gen_random_test_sm_dir(), true, page_size, SEGMENT_PAGES).unwrap()
+}
+
 pub fn bench_sm_insert(sm: &StorageManager, to_insert: &[Vec<u8>]) {
     let cid = 1;
     let tid = TransactionId::new();
@@ -0,0 +1,307 @@
+use crate::heap_page::HeapPage;
+use crate::heapfile::{HeapFile, PageNo};
+use crate::overflow::StoredRecord;
+use crate::page::Page;
+use crate::storage_manager::StorageManager;
+use crate::wal::LogKind;
+use common::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Values are moved off pages whose records take less than this fraction of the page
+const SPARSE_PAGE_DIVISOR: usize = 4;
+
+/// Vacuum for the heapstore storage manager.
+///
+/// A delete only marks its slot empty and a page is only compacted when an insert needs the
+/// space, so a heap file never shrinks on its own. Vacuum compacts every page, dropping the empty
+/// slots at the end of its header, optionally moves the values of sparse pages to earlier pages
+/// with room, starting from the end of the file, and then truncates the empty pages the file
+/// ends with. Overflow chunks never move, as the record before them in the chain links to them
+/// by page and slot.
+impl StorageManager {
+    pub(crate) fn vacuum_container(
+        &self,
+        container_id: ContainerId,
+        move_values: bool,
+        tid: TransactionId,
+    ) -> Result<Vec<(ValueId, ValueId)>, CrustyError> {
+        let hf = self.open_hf(container_id)?;
+        let _latch = hf.write_latch.lock()?;
+        let num_pages = hf.num_pages();
+        for pid in 0..num_pages {
+            let mut page = self.read_page(&hf, pid, false)?;
+            if compact_page(&mut page) {
+                self.flush_page(&hf, &page)?;
+            }
+        }
+
+        // The original id of each value that moved, by its current id
+        let mut moved = HashMap::new();
+        if move_values {
+            for pid in (0..num_pages).rev() {
+                if !self.move_values_off(&hf, pid, &mut moved, tid)? {
+                    break;
+                }
+            }
+        }
+
+        let mut keep = num_pages;
+        while keep > 0 && is_empty(&self.read_page(&hf, keep - 1, false)?) {
+            keep -= 1;
+        }
+        if keep < num_pages {
+            self.truncate_hf(&hf, keep)?;
+        }
+        debug!(
+            "Vacuumed container {}: moved {} values, {} of {} pages left",
+            container_id,
+            moved.len(),
+            keep,
+            num_pages
+        );
+        Ok(moved.into_iter().map(|(new, old)| (old, new)).collect())
+    }
+
+    /// Move the values of page pid to pages before it if the page is sparse, logging every move.
+    /// Returns false if a value did not fit in any earlier page, as no earlier page will find
+    /// more room. The caller must hold the write latch of hf.
+    fn move_values_off(
+        &self,
+        hf: &Arc<HeapFile>,
+        pid: PageNo,
+        moved: &mut HashMap<ValueId, ValueId>,
+        tid: TransactionId,
+    ) -> Result<bool, CrustyError> {
+        let container_id = hf.container_id;
+        let mut page = self.read_page(hf, pid, false)?;
+        let records: Vec<(Vec<u8>, SlotId)> = page.clone().into_iter().collect();
+        let used: usize = records.iter().map(|(record, _)| record.len()).sum();
+        if records.is_empty() || used >= hf.page_size / SPARSE_PAGE_DIVISOR {
+            return Ok(true);
+        }
+        for (record, _) in &records {
+            if let StoredRecord::Chunk { .. } = StoredRecord::from_bytes(record)? {
+                return Ok(true);
+            }
+        }
+
+        let mut fits = true;
+        let mut changed = false;
+        for (record, slot) in records {
+            let mut target = match self.page_with_room(hf, record.len(), pid)? {
+                Some(target) => target,
+                None => {
+                    fits = false;
+                    break;
+                }
+            };
+            let new_slot = self
+                .add_logged(container_id, &mut target, &record, tid)?
+                .ok_or_else(|| {
+                    CrustyError::CrustyError(format!(
+                        "No space for {} bytes on page {}",
+                        record.len(),
+                        target.get_page_id()
+                    ))
+                })?;
+            self.flush_page(hf, &target)?;
+            page.delete_value(slot);
+            let lsn = self.log.append(
+                tid,
+                LogKind::Delete {
+                    container_id,
+                    page_id: pid,
+                    slot_id: slot,
+                    value: record,
+                },
+            )?;
+            page.set_lsn(lsn);
+            changed = true;
+
+            // A value moved onto a page that is emptied later moves again
+            let old_id = hf.value_id(pid, slot);
+            let new_id = hf.value_id(target.get_page_id(), new_slot);
+            let original = moved.remove(&old_id).unwrap_or(old_id);
+            moved.insert(new_id, original);
+        }
+        if changed {
+            self.flush_page(hf, &page)?;
+        }
+        Ok(fits)
+    }
+
+    /// Cut a heap file down to its first num_pages pages, which must be followed by empty pages
+    /// only. The manifest is saved first, so it never expects more pages than the file has.
+    fn truncate_hf(&self, hf: &Arc<HeapFile>, num_pages: PageNo) -> Result<(), CrustyError> {
+        // Moves off the dropped pages must survive a crash
+        self.log.sync()?;
+        {
+            let mut manifest = self.manifest.write()?;
+            if let Some(entry) = manifest.containers.get_mut(&hf.container_id) {
+                entry.num_pages = num_pages;
+            }
+            manifest.save(&self.manifest_path())?;
+        }
+        if let Some(bp) = &self.buffer_pool {
+            bp.discard_pages(hf.container_id, num_pages);
+        }
+        hf.truncate(num_pages)
+    }
+}
+
+/// Drop the empty slots at the end of the header of a page and compact the page if that frees
+/// space. Returns true if the page changed.
+fn compact_page(page: &mut Page) -> bool {
+    let mut header = page.read_header();
+    let live = header
+        .slots
+        .iter()
+        .rposition(|s| s.size_of_record > 0)
+        .map_or(0, |last| last + 1);
+    let fragmented = (header.pagemetadata.size_of_free_space as usize) < page.get_free_space();
+    if live == header.slots.len() && !fragmented {
+        return false;
+    }
+    header.slots.truncate(live);
+    header.pagemetadata.num_slots = live as u16;
+    page.write_header(&header);
+    page.compact();
+    true
+}
+
+/// True if no slot of the page holds a record
+fn is_empty(page: &Page) -> bool {
+    page.read_header()
+        .slots
+        .iter()
+        .all(|s| s.size_of_record == 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+
+    /// A container of equally sized values spread over several pages
+    fn setup() -> (StorageManager, ContainerId, TransactionId, Arc<HeapFile>) {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        sm.insert_values(cid, get_random_vec_of_byte_vec(200, 100, 100), tid);
+        let hf = sm.open_hf(cid).unwrap();
+        assert!(hf.num_pages() > 4);
+        (sm, cid, tid, hf)
+    }
+
+    /// Delete every value of page pid but the first keep ones
+    fn thin_page(sm: &StorageManager, hf: &Arc<HeapFile>, pid: PageNo, keep: usize) {
+        let mut page = sm.read_page(hf, pid, false).unwrap();
+        let slots: Vec<SlotId> = page.clone().into_iter().map(|(_, slot)| slot).collect();
+        for slot in slots.into_iter().skip(keep) {
+            page.delete_value(slot).unwrap();
+        }
+        sm.flush_page(hf, &page).unwrap();
+    }
+
+    #[test]
+    fn hs_vacuum_compact_page() {
+        let mut page = Page::new(0);
+        let vals = get_random_vec_of_byte_vec(3, 50, 50);
+        for val in &vals {
+            page.add_value(val).unwrap();
+        }
+        assert!(!compact_page(&mut page));
+
+        // The empty slot at the end of the header is dropped
+        let free = page.get_free_space();
+        page.delete_value(2).unwrap();
+        assert!(compact_page(&mut page));
+        assert_eq!(2, page.read_header().slots.len());
+        assert!(page.get_free_space() > free);
+        assert!(!compact_page(&mut page));
+
+        // An empty slot before a live one stays, so the live slot keeps its id
+        page.delete_value(0).unwrap();
+        compact_page(&mut page);
+        assert_eq!(2, page.read_header().slots.len());
+        assert_eq!(None, page.get_value(0));
+        assert_eq!(Some(vals[1].clone()), page.get_value(1));
+        assert!(!compact_page(&mut page));
+        assert!(!is_empty(&page));
+
+        page.delete_value(1).unwrap();
+        assert!(compact_page(&mut page));
+        assert!(page.read_header().slots.is_empty());
+        assert!(is_empty(&page));
+    }
+
+    #[test]
+    fn hs_vacuum_move_values_off() {
+        let (sm, cid, tid, hf) = setup();
+        let last = hf.num_pages() - 1;
+        let _latch = hf.write_latch.lock().unwrap();
+        let mut moved = HashMap::new();
+
+        // A page that is not sparse keeps its values
+        assert!(sm.move_values_off(&hf, 0, &mut moved, tid).unwrap());
+        assert!(moved.is_empty());
+
+        // Free room on the first page and leave a single value on the last one
+        thin_page(&sm, &hf, 0, 5);
+        thin_page(&sm, &hf, last, 1);
+        let (val, slot) = sm
+            .read_page(&hf, last, false)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        assert!(sm.move_values_off(&hf, last, &mut moved, tid).unwrap());
+        assert!(is_empty(&sm.read_page(&hf, last, false).unwrap()));
+        assert_eq!(1, moved.len());
+        let (new_id, old_id) = moved.iter().next().unwrap();
+        assert_eq!(hf.value_id(last, slot), *old_id);
+        assert_eq!(cid, new_id.container_id);
+        let stored = sm.get_value(*new_id, tid, Permissions::ReadOnly).unwrap();
+        assert!(
+            matches!(StoredRecord::from_bytes(&val).unwrap(), StoredRecord::Inline(v) if v == stored)
+        );
+        // The value went to the first page with room
+        let first = sm.read_page(&hf, 0, false).unwrap();
+        assert!(first
+            .into_iter()
+            .any(|(v, s)| v == val && hf.value_id(0, s) == *new_id));
+    }
+
+    #[test]
+    fn hs_vacuum_truncate_hf() {
+        let (sm, cid, tid, hf) = setup();
+        let pages = hf.num_pages();
+        for pid in 2..pages {
+            thin_page(&sm, &hf, pid, 0);
+        }
+        let kept = sm.get_iterator(cid, tid, Permissions::ReadOnly).count();
+        {
+            let _latch = hf.write_latch.lock().unwrap();
+            sm.truncate_hf(&hf, 2).unwrap();
+        }
+        assert_eq!(2, hf.num_pages());
+        assert_eq!(2, sm.manifest.read().unwrap().containers[&cid].num_pages);
+        assert_eq!(
+            kept,
+            sm.get_iterator(cid, tid, Permissions::ReadOnly).count()
+        );
+
+        // The file grows again from where it was cut
+        sm.insert_values(cid, get_random_vec_of_byte_vec(100, 100, 100), tid);
+        assert!(hf.num_pages() > 2);
+        assert_eq!(
+            kept + 100,
+            sm.get_iterator(cid, tid, Permissions::ReadOnly).count()
+        );
+        assert!(sm.check_container(cid).unwrap().is_empty());
+    }
+}
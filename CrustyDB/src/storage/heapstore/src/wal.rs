@@ -0,0 +1,455 @@
+use crate::heapfile::PageNo;
+use common::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Magic bytes at the start of the log file
+const LOG_MAGIC: &[u8; 4] = b"CWAL";
+/// Magic, version and the base offset of the first record
+const LOG_HEADER_SIZE: u64 = 16;
+const LOG_VERSION: u32 = 1;
+/// Each record is framed by its length and a CRC32 of the body
+const RECORD_FRAME_SIZE: usize = 8;
+
+/// The change a log record describes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum LogKind {
+    /// A value was stored in a slot. Undone by deleting the slot.
+    Insert {
+        container_id: ContainerId,
+        page_id: PageNo,
+        slot_id: SlotId,
+        value: Vec<u8>,
+    },
+    /// A value was removed from a slot. Undone by putting the value back.
+    Delete {
+        container_id: ContainerId,
+        page_id: PageNo,
+        slot_id: SlotId,
+        value: Vec<u8>,
+    },
+    /// Compensation record written while undoing a change. Redo only: stores `value` in the slot,
+    /// or clears the slot if None. `undo_next` is the next record of the transaction to undo.
+    /// `moved_from` is the page and slot of an undone delete whose record could not go back there.
+    Compensation {
+        container_id: ContainerId,
+        page_id: PageNo,
+        slot_id: SlotId,
+        value: Option<Vec<u8>>,
+        undo_next: Option<Lsn>,
+        #[serde(default)]
+        moved_from: Option<(PageNo, SlotId)>,
+    },
+    /// The transaction committed, or finished rolling back. Its changes since its last End must
+    /// not be undone.
+    End,
+    /// A container was created. Earlier records for the id belong to a removed container.
+    CreateContainer { container_id: ContainerId },
+    /// A container was removed.
+    RemoveContainer { container_id: ContainerId },
+}
+
+impl LogKind {
+    /// True if the record is part of a transaction's undo chain
+    fn is_chained(&self) -> bool {
+        matches!(
+            self,
+            LogKind::Insert { .. } | LogKind::Delete { .. } | LogKind::Compensation { .. }
+        )
+    }
+
+    /// The page changed by the record, if any
+    pub(crate) fn page(&self) -> Option<(ContainerId, PageNo)> {
+        match self {
+            LogKind::Insert {
+                container_id,
+                page_id,
+                ..
+            }
+            | LogKind::Delete {
+                container_id,
+                page_id,
+                ..
+            }
+            | LogKind::Compensation {
+                container_id,
+                page_id,
+                ..
+            } => Some((*container_id, *page_id)),
+            _ => None,
+        }
+    }
+}
+
+/// A record in the write-ahead log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct LogRecord {
+    /// The transaction that made the change
+    pub tid: TransactionId,
+    /// The previous record of the same transaction, for undo
+    pub prev_lsn: Option<Lsn>,
+    pub kind: LogKind,
+}
+
+struct LogState {
+    file: File,
+    /// Offset of the first record of the file in the LSN space
+    base: u64,
+    /// LSN the next record will get
+    next: u64,
+    /// Last record of every transaction that has not ended
+    last_lsn: HashMap<TransactionId, Lsn>,
+    /// First record of every transaction that has not ended
+    first_lsn: HashMap<TransactionId, Lsn>,
+}
+
+/// The write-ahead log for a heapstore storage manager.
+///
+/// The log is a single append-only file. An LSN is the position of a record in the log, counted
+/// from when the storage directory was created, so LSNs keep growing when the log is truncated.
+/// Records are written to the file before the pages they describe, so a killed process never
+/// leaves a page change without its log record.
+pub(crate) struct LogManager {
+    path: PathBuf,
+    state: Mutex<LogState>,
+}
+
+impl LogManager {
+    /// Open the log at path, creating an empty log if there is none.
+    pub(crate) fn open(path: &Path) -> Result<Self, CrustyError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        let base = if len < LOG_HEADER_SIZE {
+            Self::write_header(&mut file, 0)?;
+            0
+        } else {
+            let mut header = [0u8; LOG_HEADER_SIZE as usize];
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut header)?;
+            if &header[0..4] != LOG_MAGIC
+                || u32::from_le_bytes(header[4..8].try_into().unwrap()) != LOG_VERSION
+            {
+                return Err(CrustyError::CrustyError(format!(
+                    "Invalid write-ahead log {:?}",
+                    path
+                )));
+            }
+            u64::from_le_bytes(header[8..16].try_into().unwrap())
+        };
+        let log = LogManager {
+            path: path.to_path_buf(),
+            state: Mutex::new(LogState {
+                file,
+                base,
+                next: base,
+                last_lsn: HashMap::new(),
+                first_lsn: HashMap::new(),
+            }),
+        };
+        // Position after the last complete record, dropping a torn tail
+        let end = log
+            .read_all()?
+            .iter()
+            .next_back()
+            .map(|(lsn, (_, size))| lsn.offset() + *size as u64)
+            .unwrap_or(base);
+        let mut state = log.state.lock()?;
+        state.file.set_len(end - base + LOG_HEADER_SIZE)?;
+        state.next = end;
+        drop(state);
+        Ok(log)
+    }
+
+    fn write_header(file: &mut File, base: u64) -> Result<(), CrustyError> {
+        let mut header = Vec::with_capacity(LOG_HEADER_SIZE as usize);
+        header.extend(LOG_MAGIC);
+        header.extend(LOG_VERSION.to_le_bytes());
+        header.extend(base.to_le_bytes());
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+        Ok(())
+    }
+
+    /// Append a record for tid and return its LSN. The record is linked to the previous record
+    /// of the transaction; an End record closes the chain.
+    pub(crate) fn append(&self, tid: TransactionId, kind: LogKind) -> Result<Lsn, CrustyError> {
+        let mut state = self.state.lock()?;
+        let lsn = Lsn::from_offset(state.next);
+        let prev_lsn = if kind.is_chained() || kind == LogKind::End {
+            state.last_lsn.get(&tid).copied()
+        } else {
+            None
+        };
+        if kind.is_chained() {
+            state.last_lsn.insert(tid, lsn);
+            state.first_lsn.entry(tid).or_insert(lsn);
+        } else if kind == LogKind::End {
+            state.last_lsn.remove(&tid);
+            state.first_lsn.remove(&tid);
+        }
+        let body = serde_cbor::to_vec(&LogRecord {
+            tid,
+            prev_lsn,
+            kind,
+        })
+        .map_err(|e| CrustyError::SerializationError(e.to_string()))?;
+        let mut buf = Vec::with_capacity(body.len() + RECORD_FRAME_SIZE);
+        buf.extend((body.len() as u32).to_le_bytes());
+        buf.extend(crc32fast::hash(&body).to_le_bytes());
+        buf.extend(body);
+        let pos = state.next - state.base + LOG_HEADER_SIZE;
+        state.file.seek(SeekFrom::Start(pos))?;
+        state.file.write_all(&buf)?;
+        state.next += buf.len() as u64;
+        Ok(lsn)
+    }
+
+    /// Close the transaction's chain. Its changes will not be undone by recovery.
+    pub(crate) fn end(&self, tid: TransactionId) -> Result<Lsn, CrustyError> {
+        self.append(tid, LogKind::End)
+    }
+
+    /// The last record of tid if it has not ended.
+    pub(crate) fn last_lsn(&self, tid: TransactionId) -> Option<Lsn> {
+        self.state.lock().unwrap().last_lsn.get(&tid).copied()
+    }
+
+    /// Force the log to stable storage.
+    pub(crate) fn sync(&self) -> Result<(), CrustyError> {
+        self.state.lock()?.file.sync_data()?;
+        Ok(())
+    }
+
+    /// True if the log holds records, ie the storage manager did not shut down cleanly.
+    pub(crate) fn has_records(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.next > state.base
+    }
+
+    /// The LSN a checkpoint can drop the records before: the first record of the oldest
+    /// transaction that has not ended, or the end of the log if every transaction has ended.
+    pub(crate) fn checkpoint_lsn(&self) -> Lsn {
+        let state = self.state.lock().unwrap();
+        state
+            .first_lsn
+            .values()
+            .min()
+            .copied()
+            .unwrap_or(Lsn::from_offset(state.next))
+    }
+
+    /// Read every complete record in the log, with the size of its frame.
+    /// Reading stops at the first torn or corrupt record.
+    pub(crate) fn read_all(&self) -> Result<BTreeMap<Lsn, (LogRecord, usize)>, CrustyError> {
+        let mut state = self.state.lock()?;
+        let base = state.base;
+        let mut bytes = Vec::new();
+        state.file.seek(SeekFrom::Start(LOG_HEADER_SIZE))?;
+        state.file.read_to_end(&mut bytes)?;
+        let mut records = BTreeMap::new();
+        let mut pos = 0;
+        while pos + RECORD_FRAME_SIZE <= bytes.len() {
+            let len = u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+            let crc = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap());
+            let start = pos + RECORD_FRAME_SIZE;
+            if start + len > bytes.len() || crc32fast::hash(&bytes[start..start + len]) != crc {
+                debug!("Write-ahead log ends with a torn record at {}", pos);
+                break;
+            }
+            let record: LogRecord = match serde_cbor::from_slice(&bytes[start..start + len]) {
+                Ok(r) => r,
+                Err(_) => break,
+            };
+            records.insert(
+                Lsn::from_offset(base + pos as u64),
+                (record, len + RECORD_FRAME_SIZE),
+            );
+            pos = start + len;
+        }
+        Ok(records)
+    }
+
+    /// Drop every record. Must only be called once all pages are on disk and no transaction
+    /// needs undo. LSNs continue from the current end of the log.
+    pub(crate) fn truncate(&self) -> Result<(), CrustyError> {
+        let mut state = self.state.lock()?;
+        let next = state.next;
+        state.file.set_len(LOG_HEADER_SIZE)?;
+        Self::write_header(&mut state.file, next)?;
+        state.file.sync_data()?;
+        state.base = next;
+        state.last_lsn.clear();
+        state.first_lsn.clear();
+        Ok(())
+    }
+
+    /// Drop the records before lsn, keeping the ones after it, and return the number of bytes
+    /// dropped. Must only be called once the pages of the dropped records are on disk and no
+    /// transaction with a record before lsn needs undo. The rest of the log is copied to a new
+    /// file that replaces the log, so a crash leaves either the old or the new log.
+    pub(crate) fn truncate_before(&self, lsn: Lsn) -> Result<u64, CrustyError> {
+        let mut state = self.state.lock()?;
+        let keep = lsn.offset().clamp(state.base, state.next);
+        if keep == state.base {
+            return Ok(0);
+        }
+        let len = state.next - keep;
+        let pos = keep - state.base + LOG_HEADER_SIZE;
+        let mut rest = Vec::with_capacity(len as usize);
+        state.file.seek(SeekFrom::Start(pos))?;
+        (&mut state.file).take(len).read_to_end(&mut rest)?;
+
+        let tmp = self.path.with_extension("tmp");
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+        Self::write_header(&mut file, keep)?;
+        file.write_all(&rest)?;
+        file.sync_all()?;
+        std::fs::rename(tmp, &self.path)?;
+        let dropped = keep - state.base;
+        state.file = file;
+        state.base = keep;
+        Ok(dropped)
+    }
+
+    /// Recreate an empty log file, used when the storage directory is wiped.
+    pub(crate) fn reset(&self) -> Result<(), CrustyError> {
+        let mut state = self.state.lock()?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path)?;
+        Self::write_header(&mut file, 0)?;
+        *state = LogState {
+            file,
+            base: 0,
+            next: 0,
+            last_lsn: HashMap::new(),
+            first_lsn: HashMap::new(),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    fn insert(value: Vec<u8>) -> LogKind {
+        LogKind::Insert {
+            container_id: 1,
+            page_id: 0,
+            slot_id: 0,
+            value,
+        }
+    }
+
+    #[test]
+    fn hs_wal_append_read() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let path = tdir.join("wal.log");
+        let log = LogManager::open(&path).unwrap();
+        let t1 = TransactionId::new();
+        let t2 = TransactionId::new();
+        let l1 = log.append(t1, insert(get_random_byte_vec(10))).unwrap();
+        let l2 = log.append(t2, insert(get_random_byte_vec(10))).unwrap();
+        let l3 = log.append(t1, insert(get_random_byte_vec(3000))).unwrap();
+        assert!(l1 < l2 && l2 < l3);
+        assert_eq!(Some(l3), log.last_lsn(t1));
+        log.end(t1).unwrap();
+        assert_eq!(None, log.last_lsn(t1));
+
+        let log = LogManager::open(&path).unwrap();
+        let records = log.read_all().unwrap();
+        assert_eq!(4, records.len());
+        assert_eq!(Some(l1), records[&l3].0.prev_lsn);
+        assert_eq!(None, records[&l2].0.prev_lsn);
+        let end = records.iter().next_back().unwrap().1;
+        assert_eq!(LogKind::End, end.0.kind);
+        assert_eq!(Some(l3), end.0.prev_lsn);
+    }
+
+    #[test]
+    fn hs_wal_torn_tail() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let path = tdir.join("wal.log");
+        let log = LogManager::open(&path).unwrap();
+        let t = TransactionId::new();
+        log.append(t, insert(get_random_byte_vec(100))).unwrap();
+        log.append(t, insert(get_random_byte_vec(100))).unwrap();
+        drop(log);
+        // Cut the last record in half
+        let len = std::fs::metadata(&path).unwrap().len();
+        let f = OpenOptions::new().write(true).open(&path).unwrap();
+        f.set_len(len - 50).unwrap();
+
+        let log = LogManager::open(&path).unwrap();
+        assert_eq!(1, log.read_all().unwrap().len());
+        let l = log.append(t, insert(get_random_byte_vec(10))).unwrap();
+        assert_eq!(2, log.read_all().unwrap().len());
+        assert!(log.read_all().unwrap().contains_key(&l));
+    }
+
+    #[test]
+    fn hs_wal_truncate_keeps_lsns_growing() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let path = tdir.join("wal.log");
+        let log = LogManager::open(&path).unwrap();
+        let t = TransactionId::new();
+        let l1 = log.append(t, insert(get_random_byte_vec(100))).unwrap();
+        log.truncate().unwrap();
+        assert!(!log.has_records());
+        drop(log);
+
+        let log = LogManager::open(&path).unwrap();
+        assert!(!log.has_records());
+        let l2 = log.append(t, insert(get_random_byte_vec(100))).unwrap();
+        assert!(l1 < l2);
+        assert!(log.has_records());
+    }
+
+    #[test]
+    fn hs_wal_truncate_before_keeps_open_chains() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let path = tdir.join("wal.log");
+        let log = LogManager::open(&path).unwrap();
+        let t1 = TransactionId::new();
+        let t2 = TransactionId::new();
+        log.append(t1, insert(get_random_byte_vec(100))).unwrap();
+        let l2 = log.append(t2, insert(get_random_byte_vec(100))).unwrap();
+        log.end(t1).unwrap();
+        let l4 = log.append(t2, insert(get_random_byte_vec(100))).unwrap();
+        assert_eq!(l2, log.checkpoint_lsn());
+
+        assert!(log.truncate_before(log.checkpoint_lsn()).unwrap() > 0);
+        drop(log);
+
+        let log = LogManager::open(&path).unwrap();
+        let records = log.read_all().unwrap();
+        assert_eq!(3, records.len());
+        assert_eq!(Some(&l2), records.keys().next());
+        assert_eq!(Some(l2), records[&l4].0.prev_lsn);
+        assert_eq!(LogKind::End, records.values().nth(1).unwrap().0.kind);
+    }
+}
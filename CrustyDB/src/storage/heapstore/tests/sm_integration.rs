@@ -21,7 +21,7 @@ fn sm_inserts() {
         let cid = i as ContainerId;
         sm.create_table(cid).unwrap();
         sm.insert_values(cid, vals1.clone(), t);
-        let check_vals: Vec<Vec<u8>> = sm.get_iterator(cid, t, RO).map(|(a, _)| a).collect();
+        let check_vals: Vec<Vec<u8>> = sm.get_iterator(cid, t, RO).map(|r| r.unwrap().0).collect();
         assert!(
             compare_unordered_byte_vecs(&vals1, check_vals),
             "Insert of size {} should be equal",
@@ -42,7 +42,7 @@ fn sm_insert_delete() {
     for _ in 0..10 {
         let idx_to_del = rng.gen_range(0..vals1.len());
         sm.delete_value(val_ids[idx_to_del], t).unwrap();
-        let check_vals: Vec<Vec<u8>> = sm.get_iterator(cid, t, RO).map(|(a, _)| a).collect();
+        let check_vals: Vec<Vec<u8>> = sm.get_iterator(cid, t, RO).map(|r| r.unwrap().0).collect();
         assert!(!compare_unordered_byte_vecs(&vals1, check_vals.clone()));
         vals1.swap_remove(idx_to_del);
         val_ids.swap_remove(idx_to_del);
@@ -69,10 +69,11 @@ fn sm_test_shutdown() {
     let cid = 1;
     sm.create_table(cid).unwrap();
     let _val_ids = sm.insert_values(cid, vals1.clone(), t);
+    sm.commit(t).unwrap();
     sm.shutdown();
 
     let sm2 = StorageManager::new(&path.clone());
-    let check_vals: Vec<Vec<u8>> = sm2.get_iterator(cid, t, RO).map(|(a, _)| a).collect();
+    let check_vals: Vec<Vec<u8>> = sm2.get_iterator(cid, t, RO).map(|r| r.unwrap().0).collect();
     assert!(compare_unordered_byte_vecs(&vals1, check_vals));
     sm2.reset().unwrap();
     fs::remove_dir_all(path).unwrap();
@@ -0,0 +1,169 @@
+extern crate common;
+extern crate heapstore as sm;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use common::prelude::*;
+use common::storage_trait::StorageTrait;
+use common::testutil::*;
+use rand::{thread_rng, Rng};
+use sm::storage_manager::StorageManager;
+
+const RO: Permissions = Permissions::ReadOnly;
+/// Set for the child process, holds the storage dir to work on
+const CRASH_DIR_VAR: &str = "CRUSTY_CRASH_DIR";
+const CID: ContainerId = 1;
+const NUM_ROWS: u32 = 20;
+
+/// Rows are stored as "key:version:" followed by padding, so updates move rows between pages
+fn encode(key: u32, version: u32) -> Vec<u8> {
+    let mut bytes = format!("{}:{}:", key, version).into_bytes();
+    bytes.resize(thread_rng().gen_range(40..400), b'x');
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> (u32, u32) {
+    let s = String::from_utf8_lossy(bytes);
+    let mut parts = s.split(':');
+    let key = parts.next().unwrap().parse().unwrap();
+    let version = parts.next().unwrap().parse().unwrap();
+    (key, version)
+}
+
+fn scan(sm: &StorageManager) -> Vec<((u32, u32), ValueId)> {
+    let t = TransactionId::new();
+    sm.get_iterator(CID, t, RO)
+        .map(|r| r.unwrap())
+        .map(|(bytes, id)| (decode(&bytes), id))
+        .collect()
+}
+
+/// Runs in the child process: update and insert rows until killed, reporting every committed
+/// operation on stdout. Every operation is a transaction of its own.
+#[test]
+fn sm_recovery_child() {
+    let dir = match std::env::var(CRASH_DIR_VAR) {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    let sm = StorageManager::new(Path::new(&dir));
+    let mut rows: HashMap<u32, (u32, ValueId)> = HashMap::new();
+    for ((key, version), id) in scan(&sm) {
+        rows.insert(key, (version, id));
+    }
+    let mut next_key = rows.keys().max().map_or(0, |k| k + 1);
+    let mut rng = thread_rng();
+    loop {
+        let t = TransactionId::new();
+        if rng.gen_bool(0.8) {
+            let key = rng.gen_range(0..NUM_ROWS);
+            let (version, id) = rows[&key];
+            let new_id = sm.update_value(encode(key, version + 1), id, t).unwrap();
+            sm.commit(t).unwrap();
+            rows.insert(key, (version + 1, new_id));
+            println!("done {} {}", key, version + 1);
+        } else {
+            let id = sm.insert_value(CID, encode(next_key, 0), t);
+            sm.commit(t).unwrap();
+            rows.insert(next_key, (0, id));
+            println!("done {} 0", next_key);
+            next_key += 1;
+        }
+    }
+}
+
+/// Check every row is stored exactly once, at the last reported version or the one after it
+fn check_recovered(sm: &StorageManager, done: &HashMap<u32, u32>) {
+    let mut seen: HashMap<u32, u32> = HashMap::new();
+    for ((key, version), _) in scan(sm) {
+        assert!(
+            seen.insert(key, version).is_none(),
+            "Row {} recovered twice",
+            key
+        );
+    }
+    for key in 0..NUM_ROWS {
+        assert!(seen.contains_key(&key), "Row {} lost", key);
+    }
+    for (key, version) in done {
+        let found = *seen
+            .get(key)
+            .unwrap_or_else(|| panic!("Inserted row {} lost", key));
+        assert!(
+            found == *version || found == version + 1,
+            "Row {} at version {}, expected {}",
+            key,
+            found,
+            version
+        );
+    }
+}
+
+#[test]
+fn sm_recovery_random_kill() {
+    if std::env::var(CRASH_DIR_VAR).is_ok() {
+        return;
+    }
+    let path = gen_random_test_sm_dir();
+    let t = TransactionId::new();
+    let sm = StorageManager::new(&path);
+    sm.create_table(CID).unwrap();
+    let rows: Vec<Vec<u8>> = (0..NUM_ROWS).map(|k| encode(k, 0)).collect();
+    sm.insert_values(CID, rows, t);
+    sm.commit(t).unwrap();
+    sm.shutdown();
+    drop(sm);
+
+    let mut done: HashMap<u32, u32> = (0..NUM_ROWS).map(|k| (k, 0)).collect();
+    let mut rng = thread_rng();
+    for _ in 0..4 {
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "sm_recovery_child",
+                "--exact",
+                "--nocapture",
+                "--test-threads=1",
+            ])
+            .env(CRASH_DIR_VAR, &path)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let reader = thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if let Some(rest) = line.strip_prefix("done ") {
+                    let mut parts = rest.split(' ');
+                    let key: u32 = parts.next().unwrap().parse().unwrap();
+                    let version: u32 = parts.next().unwrap().parse().unwrap();
+                    tx.send((key, version)).unwrap();
+                }
+            }
+        });
+        // Wait for the child to make progress, then kill it at a random point
+        let first = rx.recv_timeout(Duration::from_secs(30)).unwrap();
+        thread::sleep(Duration::from_millis(rng.gen_range(10..200)));
+        child.kill().unwrap();
+        child.wait().unwrap();
+        reader.join().unwrap();
+        done.insert(first.0, first.1);
+        for (key, version) in rx.try_iter() {
+            done.insert(key, version);
+        }
+
+        let sm = StorageManager::new(&path);
+        check_recovered(&sm, &done);
+        // A row updated when the child died may have made it, use what was recovered
+        for ((key, version), _) in scan(&sm) {
+            done.insert(key, version);
+        }
+        drop(sm);
+    }
+    fs::remove_dir_all(path).unwrap();
+}
@@ -147,6 +147,32 @@ impl StorageTrait for StorageManager {
         Ok(self.insert_value(id.container_id, value, _tid))
     }
 
+    /// Overwrite a value in place
+    fn replace_value(
+        &self,
+        value: Vec<u8>,
+        id: ValueId,
+        _tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let containers = self.containers.read().unwrap();
+        match containers.get(&id.container_id) {
+            Some(table) => match table.write().unwrap().get_mut(&id) {
+                Some(old) => {
+                    *old = value;
+                    Ok(())
+                }
+                None => Err(CrustyError::ExecutionError(format!(
+                    "Record ID not found {:?}",
+                    id
+                ))),
+            },
+            None => Err(CrustyError::ExecutionError(format!(
+                "File ID not found {:?}",
+                id
+            ))),
+        }
+    }
+
     /// Add a new container
     fn create_container(
         &self,
@@ -390,14 +416,14 @@ impl ValueIterator {
 }
 
 impl Iterator for ValueIterator {
-    type Item = (Vec<u8>, ValueId);
+    type Item = Result<(Vec<u8>, ValueId), CrustyError>;
     fn next(&mut self) -> Option<Self::Item> {
         while self.current <= self.max {
             match self.table_map.read().unwrap().get(&self.tracker) {
                 Some(res) => {
                     self.tracker.slot_id = Some(self.tracker.slot_id.unwrap() + 1);
                     self.current += 1;
-                    return Some((res.clone(), self.tracker));
+                    return Some(Ok((res.clone(), self.tracker)));
                 }
                 None => {
                     self.tracker.slot_id = Some(self.tracker.slot_id.unwrap() + 1);
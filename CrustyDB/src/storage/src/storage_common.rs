@@ -36,7 +36,10 @@ mod tests {
             let cid = i as ContainerId;
             instance.create_table(cid).unwrap();
             instance.insert_values(cid, expected.clone(), t);
-            let result: Vec<Vec<u8>> = instance.get_iterator(cid, t, RO).map(|(a, _)| a).collect();
+            let result: Vec<Vec<u8>> = instance
+                .get_iterator(cid, t, RO)
+                .map(|r| r.unwrap().0)
+                .collect();
             assert!(compare_unordered(&expected, &result));
         }
     }
@@ -55,7 +58,10 @@ mod tests {
         for _ in 0..10 {
             let idx_to_del = gen_random_int(0, expected.len() - 1);
             instance.delete_value(val_ids[idx_to_del], t).unwrap();
-            let result: Vec<Vec<u8>> = instance.get_iterator(cid, t, RO).map(|(a, _)| a).collect();
+            let result: Vec<Vec<u8>> = instance
+                .get_iterator(cid, t, RO)
+                .map(|r| r.unwrap().0)
+                .collect();
             assert!(!compare_unordered(&expected, &result));
             expected.swap_remove(idx_to_del);
             val_ids.swap_remove(idx_to_del);
@@ -80,7 +86,10 @@ mod tests {
             let new_val_id = instance
                 .update_value(new_bytes.clone(), val_ids[idx_to_upd], t)
                 .unwrap();
-            let result: Vec<Vec<u8>> = instance.get_iterator(cid, t, RO).map(|(a, _)| a).collect();
+            let result: Vec<Vec<u8>> = instance
+                .get_iterator(cid, t, RO)
+                .map(|r| r.unwrap().0)
+                .collect();
             assert!(!compare_unordered(&expected, &result));
             expected[idx_to_upd] = new_bytes;
             val_ids[idx_to_upd] = new_val_id;
@@ -138,11 +147,15 @@ mod tests {
         let cid = 1;
         instance1.create_table(cid).unwrap();
         let _val_ids = instance1.insert_values(cid, expected.clone(), t);
+        instance1.commit(t).unwrap();
         instance1.shutdown();
         drop(instance1);
 
         let instance2 = get_sm(path);
-        let result: Vec<Vec<u8>> = instance2.get_iterator(cid, t, RO).map(|(a, _)| a).collect();
+        let result: Vec<Vec<u8>> = instance2
+            .get_iterator(cid, t, RO)
+            .map(|r| r.unwrap().0)
+            .collect();
         assert!(compare_unordered(&expected, &result));
         instance2.reset().unwrap();
 
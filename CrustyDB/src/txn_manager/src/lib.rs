@@ -1,5 +1,21 @@
 #[macro_use]
 extern crate log;
+pub mod locking_tm;
+pub mod lockmanager;
+pub mod predicate_log;
 pub mod transactions;
+pub mod write_log;
 
 pub mod mock_tm;
+#[cfg(feature = "mvcc")]
+pub mod mvcc_tm;
+
+// The transaction manager used by the other crates. Build with the `locking` feature for strict
+// two-phase locking or the `mvcc` feature for snapshot isolation. Features add up across a build,
+// so `mvcc` wins when both are enabled.
+#[cfg(all(feature = "locking", not(feature = "mvcc")))]
+pub use locking_tm::LockingTransactionManager as TransactionManager;
+#[cfg(not(any(feature = "locking", feature = "mvcc")))]
+pub use mock_tm::MockTransactionManager as TransactionManager;
+#[cfg(feature = "mvcc")]
+pub use mvcc_tm::MvccTransactionManager as TransactionManager;
@@ -0,0 +1,401 @@
+use std::path::Path;
+use std::sync::{Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::lockmanager::LockManager;
+use crate::predicate_log::PredicateLog;
+use crate::write_log::WriteLog;
+use common::ast_expr::AstExpr;
+use common::ids::TupleAssignments;
+use common::prelude::*;
+use common::traits::transaction_manager_trait::{
+    IsolationLevel, TransactionManagerTrait, TxnWrite,
+};
+
+/// Transaction manager doing strict two-phase locking through the lock manager.
+///
+/// Records are locked shared when read and exclusive when inserted or updated. A transaction
+/// waits for a lock held by another transaction and keeps every lock it gets until it commits
+/// or rolls back. A waiting transaction is rolled back when it waits longer than the lock
+/// timeout or when `detect_deadlocks` picks it as the victim of a deadlock.
+///
+/// Record locks do not stop another transaction from inserting a record a scan would have read.
+/// At the serializable level the predicates of scans are checked for such phantoms at commit.
+///
+/// Before a record is locked, its page is locked with the matching intention lock: IS for a read
+/// and IX for a write. A shared or exclusive lock taken on a whole page with `lock` therefore
+/// waits for the writers or the readers of its records, and keeps them out while it is held.
+pub struct LockingTransactionManager {
+    lock_manager: Mutex<LockManager>,
+    /// Signalled whenever a transaction releases its locks or is picked as a deadlock victim
+    released: Condvar,
+    lock_timeout: RwLock<Option<Duration>>,
+    isolation: RwLock<IsolationLevel>,
+    writes: WriteLog,
+    predicates: PredicateLog,
+}
+
+impl Default for LockingTransactionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LockingTransactionManager {
+    pub fn new() -> Self {
+        Self {
+            lock_manager: Mutex::new(LockManager::new()),
+            released: Condvar::new(),
+            lock_timeout: RwLock::new(None),
+            isolation: RwLock::new(IsolationLevel::ReadCommitted),
+            writes: WriteLog::new(),
+            predicates: PredicateLog::new(),
+        }
+    }
+
+    fn serializable(&self) -> Result<bool, CrustyError> {
+        Ok(*self.isolation.read()? == IsolationLevel::Serializable)
+    }
+
+    /// Block until tid holds a lock of kind perm on id. The id names a record, or a whole page
+    /// when it has no slot. Fails with `TransactionRollback` when tid times out or is picked as a
+    /// deadlock victim while waiting; the caller must then roll tid back.
+    pub fn lock(
+        &self,
+        tid: TransactionId,
+        id: ValueId,
+        perm: Permissions,
+    ) -> Result<(), CrustyError> {
+        let timeout = *self.lock_timeout.read()?;
+        let start = Instant::now();
+        let mut lock_manager = self.lock_manager.lock()?;
+        loop {
+            if lock_manager.take_victim(tid) {
+                debug!("{:?} aborted to break a deadlock", tid);
+                return Err(CrustyError::TransactionRollback(tid));
+            }
+            if lock_manager.acquire_lock(tid, id, perm) {
+                return Ok(());
+            }
+            debug!("{:?} waiting for {:?} lock on {:?}", tid, perm, id);
+            lock_manager = match timeout {
+                None => self.released.wait(lock_manager)?,
+                Some(timeout) => {
+                    let waited = start.elapsed();
+                    if waited >= timeout {
+                        debug!("{:?} timed out waiting for {:?}", tid, id);
+                        lock_manager.stop_waiting(tid);
+                        return Err(CrustyError::TransactionRollback(tid));
+                    }
+                    self.released
+                        .wait_timeout(lock_manager, timeout - waited)?
+                        .0
+                }
+            };
+        }
+    }
+
+    /// The ids locked by tid
+    pub fn locked_ids(&self, tid: TransactionId) -> Vec<ValueId> {
+        self.lock_manager.lock().unwrap().page_ids(tid)
+    }
+
+    /// Release every lock of tid and wake the transactions waiting for one.
+    fn release(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        let mut lock_manager = self.lock_manager.lock()?;
+        if !lock_manager.page_ids(tid).is_empty() {
+            lock_manager.release_locks(tid)?;
+            self.released.notify_all();
+        }
+        Ok(())
+    }
+}
+
+impl TransactionManagerTrait for LockingTransactionManager {
+    const LOCKS_RECORDS: bool = true;
+
+    fn new(_storage_path: &Path) -> Self {
+        Self::new()
+    }
+
+    fn shutdown(&mut self) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
+    fn set_isolation_level(&self, lvl: IsolationLevel) -> Result<(), CrustyError> {
+        match lvl {
+            IsolationLevel::ReadCommitted | IsolationLevel::Serializable => {
+                *self.isolation.write()? = lvl;
+                Ok(())
+            }
+            IsolationLevel::SnapshotIsolation => Err(CrustyError::CrustyError(
+                "Snapshot isolation needs the mvcc transaction manager".to_string(),
+            )),
+        }
+    }
+
+    fn set_lock_timeout(&self, timeout: Option<Duration>) -> Result<(), CrustyError> {
+        *self.lock_timeout.write()? = timeout;
+        Ok(())
+    }
+
+    fn detect_deadlocks(&self) -> Result<Vec<TransactionId>, CrustyError> {
+        let victims = self.lock_manager.lock()?.find_deadlock_victims();
+        if !victims.is_empty() {
+            self.released.notify_all();
+        }
+        Ok(victims)
+    }
+
+    fn start_transaction(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.start(tid)?;
+        if self.serializable()? {
+            self.predicates.start(tid)?;
+        }
+        Ok(())
+    }
+
+    fn read_record(&self, value_id: &ValueId, tid: &TransactionId) -> Result<(), CrustyError> {
+        self.lock(*tid, *value_id, Permissions::ReadOnly)
+    }
+
+    fn pre_update_record(
+        &self,
+        tuple: &mut Tuple,
+        value_id: &ValueId,
+        tid: &TransactionId,
+        _changes: &TupleAssignments,
+    ) -> Result<(), CrustyError> {
+        self.lock(*tid, *value_id, Permissions::ReadWrite)?;
+        self.writes.updating(*tid, *value_id, tuple)
+    }
+
+    fn post_update_record(
+        &self,
+        tuple: &mut Tuple,
+        value_id: &ValueId,
+        old_value_id: &ValueId,
+        tid: &TransactionId,
+        _changes: &TupleAssignments,
+    ) -> Result<(), CrustyError> {
+        // The updated record may have moved
+        if value_id != old_value_id {
+            self.lock(*tid, *value_id, Permissions::ReadWrite)?;
+        }
+        self.writes.updated(*tid, *old_value_id, *value_id, tuple)
+    }
+
+    fn pre_insert_record(
+        &self,
+        _tuple: &mut Tuple,
+        _tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
+    fn post_insert_record(
+        &self,
+        tuple: &mut Tuple,
+        value_id: ValueId,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        self.lock(tid, value_id, Permissions::ReadWrite)?;
+        self.writes.inserted(tid, value_id, tuple)
+    }
+
+    fn read_predicate(
+        &self,
+        container_id: ContainerId,
+        predicate: AstExpr,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        if self.serializable()? {
+            self.predicates.read(container_id, &predicate, tid)?;
+        }
+        Ok(())
+    }
+
+    fn validate_txn(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        if self.serializable()? {
+            self.predicates.validate(tid, &self.writes.writes(tid)?)?;
+        }
+        Ok(())
+    }
+
+    fn others_running(&self, tid: TransactionId) -> Result<bool, CrustyError> {
+        self.writes.others_running(tid)
+    }
+
+    fn log_insert_ids(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.log_insert_ids(tid)
+    }
+
+    fn savepoint(&self, tid: TransactionId) -> Result<usize, CrustyError> {
+        self.writes.savepoint(tid)
+    }
+
+    fn take_writes(
+        &self,
+        tid: TransactionId,
+        savepoint: usize,
+    ) -> Result<Vec<TxnWrite>, CrustyError> {
+        self.writes.take(tid, savepoint)
+    }
+
+    fn rollback_txn(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.forget(tid)?;
+        self.predicates.end(tid)?;
+        self.release(tid)
+    }
+
+    fn commit_txn(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.forget(tid)?;
+        self.predicates.end(tid)?;
+        self.release(tid)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_shared_and_upgrade() {
+        let tm = LockingTransactionManager::new();
+        let tuple = Tuple::new(vec![Field::Int(1)]);
+        let id = ValueId::new_slot(1, 0, 0);
+        let (t1, t2) = (TransactionId::new(), TransactionId::new());
+        tm.read_record(&id, &t1).unwrap();
+        tm.read_record(&id, &t2).unwrap();
+        tm.commit_txn(t2).unwrap();
+        // t1 is the only reader left so it may write
+        tm.pre_update_record(&mut tuple.clone(), &id, &t1, &Vec::new())
+            .unwrap();
+        let moved = ValueId::new_slot(1, 0, 1);
+        tm.post_update_record(&mut tuple.clone(), &moved, &id, &t1, &Vec::new())
+            .unwrap();
+        assert_eq!(vec![ValueId::new_page(1, 0), id, moved], tm.locked_ids(t1));
+        tm.rollback_txn(t1).unwrap();
+        assert!(tm.locked_ids(t1).is_empty());
+        // Ending a transaction without locks is fine
+        tm.commit_txn(t2).unwrap();
+    }
+
+    #[test]
+    fn test_reader_waits_for_writer() {
+        let tm = Arc::new(LockingTransactionManager::new());
+        let tuple = Tuple::new(vec![Field::Int(1)]);
+        let id = ValueId::new_slot(1, 0, 0);
+        let writer = TransactionId::new();
+        tm.post_insert_record(&mut tuple.clone(), id, writer)
+            .unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let reader_tm = Arc::clone(&tm);
+        let reader = thread::spawn(move || {
+            let tid = TransactionId::new();
+            reader_tm.read_record(&id, &tid).unwrap();
+            sender.send(()).unwrap();
+            reader_tm.commit_txn(tid).unwrap();
+        });
+        // The reader blocks until the writer commits
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        tm.commit_txn(writer).unwrap();
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        reader.join().unwrap();
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let tm = LockingTransactionManager::new();
+        tm.set_lock_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let tuple = Tuple::new(vec![Field::Int(1)]);
+        let id = ValueId::new_slot(1, 0, 0);
+        let (writer, reader) = (TransactionId::new(), TransactionId::new());
+        tm.post_insert_record(&mut tuple.clone(), id, writer)
+            .unwrap();
+        assert_eq!(
+            Err(CrustyError::TransactionRollback(reader)),
+            tm.read_record(&id, &reader)
+        );
+        tm.rollback_txn(reader).unwrap();
+        // A timed out transaction is not waiting anymore
+        assert!(tm.detect_deadlocks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_serializable_phantom() {
+        let tm = LockingTransactionManager::new();
+        tm.set_isolation_level(IsolationLevel::Serializable)
+            .unwrap();
+        let (reader, writer) = (TransactionId::new(), TransactionId::new());
+        tm.start_transaction(reader).unwrap();
+        tm.start_transaction(writer).unwrap();
+        // reader scans all of table 1 while writer inserts into it
+        tm.read_predicate(1, AstExpr::Literal(Field::Bool(true)), reader)
+            .unwrap();
+        let mut tuple = Tuple::new(vec![Field::Int(1)]);
+        tm.pre_insert_record(&mut tuple, writer).unwrap();
+        tm.post_insert_record(&mut tuple, ValueId::new_slot(1, 0, 0), writer)
+            .unwrap();
+        tm.validate_txn(writer).unwrap();
+        tm.commit_txn(writer).unwrap();
+        assert_eq!(
+            Err(CrustyError::TransactionRollback(reader)),
+            tm.validate_txn(reader)
+        );
+        tm.rollback_txn(reader).unwrap();
+    }
+
+    #[test]
+    fn test_deadlock_aborts_youngest() {
+        let tm = Arc::new(LockingTransactionManager::new());
+        let (a, b) = (ValueId::new_slot(1, 0, 0), ValueId::new_slot(1, 0, 1));
+        let (older, younger) = (TransactionId::new(), TransactionId::new());
+        tm.lock(older, a, Permissions::ReadWrite).unwrap();
+        tm.lock(younger, b, Permissions::ReadWrite).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let threads: Vec<_> = [(older, b), (younger, a)]
+            .into_iter()
+            .map(|(tid, id)| {
+                let tm = Arc::clone(&tm);
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    let res = tm.lock(tid, id, Permissions::ReadWrite);
+                    sender.send((tid, res.clone())).unwrap();
+                    // Either way the transaction ends
+                    tm.rollback_txn(tid).unwrap();
+                    res
+                })
+            })
+            .collect();
+
+        let mut victims = Vec::new();
+        for _ in 0..100 {
+            victims = tm.detect_deadlocks().unwrap();
+            if !victims.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(vec![younger], victims);
+        assert_eq!(
+            (younger, Err(CrustyError::TransactionRollback(younger))),
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap()
+        );
+        // Rolling back the victim lets the older transaction through
+        assert_eq!(
+            (older, Ok(())),
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap()
+        );
+        for t in threads {
+            t.join().unwrap().ok();
+        }
+    }
+}
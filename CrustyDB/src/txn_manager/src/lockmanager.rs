@@ -2,16 +2,80 @@ use common::ids::Permissions;
 use common::ids::TransactionId;
 use common::ids::ValueId;
 use common::CrustyError;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+/// Kind of lock a transaction holds on a page or record. A transaction takes an intention lock on
+/// a page before it locks one of the page's records, so a shared or exclusive lock on the whole
+/// page excludes the writers or readers of its records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockMode {
+    /// Intends to read records of the page.
+    IntentionShared,
+    /// Intends to write records of the page.
+    IntentionExclusive,
+    /// Reads the page or record.
+    Shared,
+    /// Reads the whole page and intends to write records of it.
+    SharedIntentionExclusive,
+    /// Writes the page or record.
+    Exclusive,
+}
+
+impl LockMode {
+    /// The mode of a lock on a record taken with perm.
+    pub fn of(perm: Permissions) -> Self {
+        match perm {
+            Permissions::ReadOnly => LockMode::Shared,
+            Permissions::ReadWrite => LockMode::Exclusive,
+        }
+    }
+
+    /// The mode of the lock on a page whose record is locked with perm.
+    pub fn intention(perm: Permissions) -> Self {
+        match perm {
+            Permissions::ReadOnly => LockMode::IntentionShared,
+            Permissions::ReadWrite => LockMode::IntentionExclusive,
+        }
+    }
+
+    /// Whether two transactions may hold self and other on the same id at once.
+    fn compatible(self, other: LockMode) -> bool {
+        use LockMode::*;
+        match (self, other) {
+            (Exclusive, _) | (_, Exclusive) => false,
+            (IntentionShared, _) | (_, IntentionShared) => true,
+            (IntentionExclusive, IntentionExclusive) | (Shared, Shared) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether holding self allows everything holding other allows.
+    fn covers(self, other: LockMode) -> bool {
+        use LockMode::*;
+        self == other
+            || self == Exclusive
+            || other == IntentionShared
+            || (self == SharedIntentionExclusive && matches!(other, IntentionExclusive | Shared))
+    }
+
+    /// The weakest mode covering both self and other.
+    fn join(self, other: LockMode) -> LockMode {
+        if self.covers(other) {
+            self
+        } else if other.covers(self) {
+            other
+        } else {
+            LockMode::SharedIntentionExclusive
+        }
+    }
+}
 
 /// Implementation of a lock.
 pub struct Lock {
-    /// Page that the lock is for.
+    /// Page or record that the lock is for.
     _vid: ValueId,
-    /// Transactions that hold the lock.
-    txn: Vec<TransactionId>,
-    /// Type of lock.
-    lock_type: Permissions,
+    /// Transactions that hold the lock, with the mode each holds it in.
+    txn: HashMap<TransactionId, LockMode>,
 }
 
 /// Implementation of the lock manager.
@@ -20,6 +84,11 @@ pub struct LockManager {
     pid_lock: HashMap<ValueId, Lock>,
     /// Mapping from transaction to vector of pages locked by the transaction.
     txn_locks: HashMap<TransactionId, Vec<ValueId>>,
+    /// Waits-for graph: mapping from a transaction that failed to get a lock to the
+    /// transactions holding it.
+    waits_for: HashMap<TransactionId, HashSet<TransactionId>>,
+    /// Transactions aborted to break a deadlock that have not been told yet.
+    victims: HashSet<TransactionId>,
 }
 
 impl Default for LockManager {
@@ -34,6 +103,8 @@ impl LockManager {
         Self {
             pid_lock: HashMap::new(),
             txn_locks: HashMap::new(),
+            waits_for: HashMap::new(),
+            victims: HashSet::new(),
         }
     }
 
@@ -43,60 +114,128 @@ impl LockManager {
     pub fn clear(&mut self) {
         self.pid_lock = HashMap::new();
         self.txn_locks = HashMap::new();
+        self.waits_for = HashMap::new();
+        self.victims = HashSet::new();
     }
 
-    /// Returns true if lock acquired, else false.
+    /// Returns true if lock acquired, else false. On false tid is recorded as waiting for the
+    /// current holders of the lock until it gets a lock or releases its locks.
+    ///
+    /// A lock on a record first takes the matching intention lock on the record's page, which tid
+    /// keeps even if it does not get the record.
     ///
     /// # Arguments
     ///
     /// * `tid` - Id of the transaction that wants to lock the page.
-    /// * `pid` - Id of the page to lock.
+    /// * `pid` - Id of the page or record to lock.
     /// * `perm` - Type of lock to get.
     pub fn acquire_lock(&mut self, tid: TransactionId, pid: ValueId, perm: Permissions) -> bool {
-        // First time seeing txn, initial tracking for ValueIds
-        self.txn_locks.entry(tid).or_default();
-
-        #[allow(clippy::map_entry)]
-        if !(self.pid_lock.contains_key(&pid)) {
-            // No lock exists on this page.
-            let lock = Lock {
-                _vid: pid,
-                txn: vec![tid],
-                lock_type: perm,
+        if pid.slot_id.is_some() {
+            let page = ValueId {
+                slot_id: None,
+                ..pid
             };
-            self.pid_lock.insert(pid, lock);
-            self.txn_locks.get_mut(&tid).unwrap().push(pid);
+            if !self.acquire_mode(tid, page, LockMode::intention(perm)) {
+                return false;
+            }
+        }
+        self.acquire_mode(tid, pid, LockMode::of(perm))
+    }
+
+    /// Returns true if tid now holds a lock on pid covering mode, else false and tid waits for
+    /// the holders of the incompatible modes.
+    pub fn acquire_mode(&mut self, tid: TransactionId, pid: ValueId, mode: LockMode) -> bool {
+        // First time seeing txn, initial tracking for ValueIds
+        let locked = self.txn_locks.entry(tid).or_default();
+        let lock = self.pid_lock.entry(pid).or_insert_with(|| Lock {
+            _vid: pid,
+            txn: HashMap::new(),
+        });
+        let wanted = match lock.txn.get(&tid) {
+            Some(held) => held.join(mode),
+            None => mode,
+        };
+        let blockers: HashSet<TransactionId> = lock
+            .txn
+            .iter()
+            .filter(|(&holder, &held)| holder != tid && !wanted.compatible(held))
+            .map(|(&holder, _)| holder)
+            .collect();
+
+        if blockers.is_empty() {
+            if lock.txn.insert(tid, wanted).is_none() {
+                locked.push(pid);
+            }
+            self.waits_for.remove(&tid);
             true
         } else {
-            // Lock exists.
-            let lock = self.pid_lock.get_mut(&pid).unwrap();
-            match perm {
-                Permissions::ReadOnly => match lock.lock_type {
-                    Permissions::ReadWrite => lock.txn.contains(&tid),
-                    Permissions::ReadOnly => {
-                        if lock.txn.contains(&tid) {
-                            true
-                        } else {
-                            lock.txn.push(tid);
-                            self.txn_locks.get_mut(&tid).unwrap().push(pid);
-                            true
-                        }
-                    }
-                },
-                Permissions::ReadWrite => {
-                    if lock.txn.len() == 1 && lock.txn.contains(&tid) {
-                        //We already have the lock.
-                        if let Permissions::ReadOnly = lock.lock_type {
-                            lock.lock_type = Permissions::ReadWrite
-                        }
-                        true
-                    } else {
-                        // Theoretically, could starve writer.
-                        false
-                    }
+            // Theoretically, could starve writer.
+            self.waits_for.insert(tid, blockers);
+            false
+        }
+    }
+
+    /// Stops tracking tid as waiting for a lock, e.g. when it gives up after a timeout.
+    pub fn stop_waiting(&mut self, tid: TransactionId) {
+        self.waits_for.remove(&tid);
+    }
+
+    /// Breaks every cycle in the waits-for graph by picking the youngest transaction of the
+    /// cycle as a victim. Returns the victims picked by this call.
+    pub fn find_deadlock_victims(&mut self) -> Vec<TransactionId> {
+        let mut victims = Vec::new();
+        while let Some(cycle) = self.find_cycle() {
+            let victim = *cycle.iter().max_by_key(|tid| tid.id()).unwrap();
+            debug!("Deadlock among {:?}, aborting {:?}", cycle, victim);
+            self.waits_for.remove(&victim);
+            self.victims.insert(victim);
+            victims.push(victim);
+        }
+        victims
+    }
+
+    /// Returns true, once, if tid was picked as a deadlock victim.
+    pub fn take_victim(&mut self, tid: TransactionId) -> bool {
+        self.victims.remove(&tid)
+    }
+
+    /// Returns the transactions of some cycle in the waits-for graph.
+    fn find_cycle(&self) -> Option<Vec<TransactionId>> {
+        let mut done = HashSet::new();
+        for start in self.waits_for.keys() {
+            let mut path = Vec::new();
+            if let Some(cycle) = self.cycle_from(*start, &mut path, &mut done) {
+                return Some(cycle);
+            }
+        }
+        None
+    }
+
+    /// Depth first search from tid. path holds the transactions on the way to tid and done the
+    /// ones already known not to reach a cycle.
+    fn cycle_from(
+        &self,
+        tid: TransactionId,
+        path: &mut Vec<TransactionId>,
+        done: &mut HashSet<TransactionId>,
+    ) -> Option<Vec<TransactionId>> {
+        if let Some(pos) = path.iter().position(|&t| t == tid) {
+            return Some(path[pos..].to_vec());
+        }
+        if done.contains(&tid) {
+            return None;
+        }
+        path.push(tid);
+        if let Some(holders) = self.waits_for.get(&tid) {
+            for holder in holders {
+                if let Some(cycle) = self.cycle_from(*holder, path, done) {
+                    return Some(cycle);
                 }
             }
         }
+        path.pop();
+        done.insert(tid);
+        None
     }
 
     /// Returns all the pages locked by the transaction.
@@ -117,6 +256,12 @@ impl LockManager {
     ///
     /// * `tid` - Id of the transaction to release all the locks for.
     pub fn release_locks(&mut self, tid: TransactionId) -> Result<(), CrustyError> {
+        // A transaction that ends waits for nothing and nobody waits for it
+        self.victims.remove(&tid);
+        self.waits_for.remove(&tid);
+        for holders in self.waits_for.values_mut() {
+            holders.remove(&tid);
+        }
         let mut pages_to_release = Vec::new();
 
         if let Some(pages) = self.txn_locks.get(&tid) {
@@ -148,12 +293,10 @@ impl LockManager {
         match self.txn_locks.get_mut(&tid) {
             Some(pages) => {
                 if let Some(lock) = self.pid_lock.get_mut(&pid) {
-                    if lock.txn.contains(&tid) {
-                        if lock.txn.len() == 1 {
+                    if lock.txn.remove(&tid).is_some() {
+                        if lock.txn.is_empty() {
                             // Only one holding txn.
                             self.pid_lock.remove(&pid);
-                        } else {
-                            lock.txn.retain(|&x| x != tid);
                         }
                     } else {
                         return Err(CrustyError::CrustyError(format!(
@@ -332,6 +475,90 @@ mod test {
         assert!(lm.acquire_lock(txn1, pid, Permissions::ReadWrite));
     }
 
+    #[test]
+    fn test_intention_locks() {
+        let page = ValueId::new_page(1, 1);
+        let rec1 = ValueId::new_slot(1, 1, 0);
+        let rec2 = ValueId::new_slot(1, 1, 1);
+        let txn1 = TransactionId::new();
+        let txn2 = TransactionId::new();
+        let txn3 = TransactionId::new();
+        let mut lm = LockManager::new();
+
+        // Writers of different records of a page share it
+        assert!(lm.acquire_lock(txn1, rec1, Permissions::ReadWrite));
+        assert!(lm.acquire_lock(txn2, rec2, Permissions::ReadWrite));
+        assert_eq!(vec![page, rec1], lm.page_ids(txn1));
+        // A reader of the whole page waits for them
+        assert!(!lm.acquire_lock(txn3, page, Permissions::ReadOnly));
+        lm.release_locks(txn1).unwrap();
+        lm.release_locks(txn2).unwrap();
+        assert!(lm.acquire_lock(txn3, page, Permissions::ReadOnly));
+
+        // Records of a page read as a whole can be read but not written
+        assert!(lm.acquire_lock(txn1, rec1, Permissions::ReadOnly));
+        assert!(!lm.acquire_lock(txn2, rec2, Permissions::ReadWrite));
+        // The reader of the page may still write one of its records
+        assert!(lm.acquire_lock(txn3, rec2, Permissions::ReadWrite));
+        assert!(!lm.acquire_lock(txn1, page, Permissions::ReadOnly));
+    }
+
+    #[test]
+    fn test_lock_modes() {
+        use LockMode::*;
+        let modes = [
+            IntentionShared,
+            IntentionExclusive,
+            Shared,
+            SharedIntentionExclusive,
+            Exclusive,
+        ];
+        let compatible = [
+            [true, true, true, true, false],
+            [true, true, false, false, false],
+            [true, false, true, false, false],
+            [true, false, false, false, false],
+            [false, false, false, false, false],
+        ];
+        for (i, a) in modes.iter().enumerate() {
+            for (j, b) in modes.iter().enumerate() {
+                assert_eq!(compatible[i][j], a.compatible(*b), "{:?} {:?}", a, b);
+            }
+        }
+        assert_eq!(SharedIntentionExclusive, Shared.join(IntentionExclusive));
+        assert_eq!(Exclusive, IntentionExclusive.join(Exclusive));
+        assert_eq!(Shared, IntentionShared.join(Shared));
+    }
+
+    #[test]
+    fn test_deadlock_victims() {
+        let pid1 = ValueId::new_page(1, 1);
+        let pid2 = ValueId::new_page(1, 2);
+        let pid3 = ValueId::new_page(1, 3);
+        let txn1 = TransactionId::new();
+        let txn2 = TransactionId::new();
+        let txn3 = TransactionId::new();
+        let mut lm = LockManager::new();
+
+        assert!(lm.acquire_lock(txn1, pid1, Permissions::ReadWrite));
+        assert!(lm.acquire_lock(txn2, pid2, Permissions::ReadWrite));
+        assert!(lm.acquire_lock(txn3, pid3, Permissions::ReadOnly));
+        // txn3 waits on txn1 without closing a cycle
+        assert!(!lm.acquire_lock(txn3, pid1, Permissions::ReadOnly));
+        assert!(!lm.acquire_lock(txn1, pid2, Permissions::ReadOnly));
+        assert!(lm.find_deadlock_victims().is_empty());
+
+        // The youngest transaction of the cycle is the victim
+        assert!(!lm.acquire_lock(txn2, pid1, Permissions::ReadOnly));
+        assert_eq!(vec![txn2], lm.find_deadlock_victims());
+        assert!(lm.find_deadlock_victims().is_empty());
+        assert!(lm.take_victim(txn2));
+        assert!(!lm.take_victim(txn2));
+
+        lm.release_locks(txn2).unwrap();
+        assert!(lm.acquire_lock(txn1, pid2, Permissions::ReadOnly));
+    }
+
     #[test]
     fn test_lock_manager() -> Result<(), CrustyError> {
         let pid1 = ValueId::new_page(1, 1);
@@ -3,20 +3,30 @@ use std::path::Path;
 use common::ast_expr::AstExpr;
 use common::ids::TupleAssignments;
 use common::prelude::*;
-use common::traits::transaction_manager_trait::{IsolationLevel, TransactionManagerTrait};
+use common::traits::transaction_manager_trait::{
+    IsolationLevel, TransactionManagerTrait, TxnWrite,
+};
 
+use crate::write_log::WriteLog;
+
+/// Transaction manager without concurrency control. It only keeps the writes of each
+/// transaction so they can be undone.
 #[derive(Default)]
-pub struct MockTransactionManager {}
+pub struct MockTransactionManager {
+    writes: WriteLog,
+}
 
 impl MockTransactionManager {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            writes: WriteLog::new(),
+        }
     }
 }
 
 impl TransactionManagerTrait for MockTransactionManager {
     fn new(_storage_path: &Path) -> Self {
-        Self {}
+        Self::new()
     }
 
     fn shutdown(&mut self) -> Result<(), CrustyError> {
@@ -27,38 +37,33 @@ impl TransactionManagerTrait for MockTransactionManager {
         Ok(())
     }
 
-    fn start_transaction(&self, _tid: TransactionId) -> Result<(), CrustyError> {
-        Ok(())
+    fn start_transaction(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.start(tid)
     }
 
-    fn read_record(
-        &self,
-        _tuple: &Tuple,
-        _value_id: &ValueId,
-        _tid: &TransactionId,
-    ) -> Result<(), CrustyError> {
+    fn read_record(&self, _value_id: &ValueId, _tid: &TransactionId) -> Result<(), CrustyError> {
         Ok(())
     }
 
     fn pre_update_record(
         &self,
-        _tuple: &mut Tuple,
-        _value_id: &ValueId,
-        _tid: &TransactionId,
+        tuple: &mut Tuple,
+        value_id: &ValueId,
+        tid: &TransactionId,
         _changes: &TupleAssignments,
     ) -> Result<(), CrustyError> {
-        Ok(())
+        self.writes.updating(*tid, *value_id, tuple)
     }
 
     fn post_update_record(
         &self,
-        _tuple: &mut Tuple,
-        _value_id: &ValueId,
-        _old_value_id: &ValueId,
-        _tid: &TransactionId,
+        tuple: &mut Tuple,
+        value_id: &ValueId,
+        old_value_id: &ValueId,
+        tid: &TransactionId,
         _changes: &TupleAssignments,
     ) -> Result<(), CrustyError> {
-        Ok(())
+        self.writes.updated(*tid, *old_value_id, *value_id, tuple)
     }
 
     fn pre_insert_record(
@@ -71,14 +76,19 @@ impl TransactionManagerTrait for MockTransactionManager {
 
     fn post_insert_record(
         &self,
-        _tuple: &mut Tuple,
-        _value_id: ValueId,
-        _tid: TransactionId,
+        tuple: &mut Tuple,
+        value_id: ValueId,
+        tid: TransactionId,
     ) -> Result<(), CrustyError> {
-        Ok(())
+        self.writes.inserted(tid, value_id, tuple)
     }
 
-    fn read_predicate(&self, _predicate: AstExpr, _tid: TransactionId) -> Result<(), CrustyError> {
+    fn read_predicate(
+        &self,
+        _container_id: ContainerId,
+        _predicate: AstExpr,
+        _tid: TransactionId,
+    ) -> Result<(), CrustyError> {
         Ok(())
     }
 
@@ -86,11 +96,31 @@ impl TransactionManagerTrait for MockTransactionManager {
         Ok(())
     }
 
-    fn rollback_txn(&self, _tid: TransactionId) -> Result<(), CrustyError> {
-        Ok(())
+    fn others_running(&self, tid: TransactionId) -> Result<bool, CrustyError> {
+        self.writes.others_running(tid)
     }
 
-    fn commit_txn(&self, _tid: TransactionId) -> Result<(), CrustyError> {
-        Ok(())
+    fn log_insert_ids(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.log_insert_ids(tid)
+    }
+
+    fn savepoint(&self, tid: TransactionId) -> Result<usize, CrustyError> {
+        self.writes.savepoint(tid)
+    }
+
+    fn take_writes(
+        &self,
+        tid: TransactionId,
+        savepoint: usize,
+    ) -> Result<Vec<TxnWrite>, CrustyError> {
+        self.writes.take(tid, savepoint)
+    }
+
+    fn rollback_txn(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.forget(tid)
+    }
+
+    fn commit_txn(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.forget(tid)
     }
 }
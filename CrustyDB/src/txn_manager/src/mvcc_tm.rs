@@ -0,0 +1,479 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::predicate_log::PredicateLog;
+use crate::write_log::WriteLog;
+use common::ast_expr::AstExpr;
+use common::ids::{TidType, TupleAssignments};
+use common::prelude::*;
+use common::traits::transaction_manager_trait::{
+    IsolationLevel, TransactionManagerTrait, TxnWrite,
+};
+use common::LIVE_TS;
+
+/// Transaction manager keeping several versions of each record for snapshot isolation.
+///
+/// A version stores the transaction that created it in `begin_ts`. When the transaction that
+/// replaced it commits, the version stores that transaction in `end_ts` and the id of the version
+/// that replaced it in `tuple_pointer`, so the versions of a record form a chain from the oldest
+/// to the newest. A transaction reads the versions committed before it started, so readers take
+/// no locks and never wait for writers.
+/// When two transactions replace the same version the one that commits last finds the version
+/// already ended and is rolled back.
+/// At the serializable level the predicates of scans are also checked against the records
+/// written by transactions that committed after the snapshot was taken.
+///
+/// Transaction ids double as timestamps. They start past the current time in microseconds so
+/// they stay larger than the ids stored in records before a restart.
+pub struct MvccTransactionManager {
+    state: Mutex<MvccState>,
+    isolation: RwLock<IsolationLevel>,
+    writes: WriteLog,
+    predicates: PredicateLog,
+}
+
+#[derive(Default)]
+struct MvccState {
+    /// The start timestamp of each running transaction
+    snapshots: HashMap<TidType, TidType>,
+    /// Commit timestamps a running snapshot still needs. A transaction missing here that is not
+    /// running committed before every running snapshot started. A transaction that rolled back
+    /// leaves no versions, as its writes are undone first.
+    committed: HashMap<TidType, TidType>,
+    /// The versions each running transaction replaced, which it no longer sees although they
+    /// are only ended when it commits
+    replaced: HashMap<TidType, HashSet<ValueId>>,
+}
+
+impl MvccState {
+    /// The start timestamp of tid, which starts now if it has not started yet.
+    fn snapshot(&mut self, tid: TransactionId) -> TidType {
+        *self
+            .snapshots
+            .entry(tid.id())
+            .or_insert_with(|| TransactionId::new().id())
+    }
+
+    /// Whether txn committed before the snapshot taken at start
+    fn committed_before(&self, txn: TidType, start: TidType) -> bool {
+        match self.committed.get(&txn) {
+            Some(commit_ts) => *commit_ts < start,
+            None => txn < start && !self.snapshots.contains_key(&txn),
+        }
+    }
+
+    /// Give tid its commit timestamp, if it does not have one yet.
+    fn commit(&mut self, tid: TransactionId) {
+        self.snapshot(tid);
+        self.committed
+            .entry(tid.id())
+            .or_insert_with(|| TransactionId::new().id());
+    }
+
+    /// Forget the snapshot of tid and the commits every remaining snapshot sees.
+    fn end(&mut self, tid: TransactionId) {
+        self.snapshots.remove(&tid.id());
+        self.replaced.remove(&tid.id());
+        match self.snapshots.values().min().copied() {
+            Some(oldest) => self.committed.retain(|_, commit_ts| *commit_ts >= oldest),
+            None => self.committed.clear(),
+        }
+    }
+}
+
+impl Default for MvccTransactionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MvccTransactionManager {
+    pub fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as TidType);
+        TransactionId::skip_past(now);
+        Self {
+            state: Mutex::new(MvccState::default()),
+            isolation: RwLock::new(IsolationLevel::SnapshotIsolation),
+            writes: WriteLog::new(),
+            predicates: PredicateLog::new(),
+        }
+    }
+
+    fn serializable(&self) -> Result<bool, CrustyError> {
+        Ok(*self.isolation.read()? == IsolationLevel::Serializable)
+    }
+}
+
+impl TransactionManagerTrait for MvccTransactionManager {
+    const MULTI_VERSION: bool = true;
+
+    fn new(_storage_path: &Path) -> Self {
+        Self::new()
+    }
+
+    fn shutdown(&mut self) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
+    fn set_isolation_level(&self, lvl: IsolationLevel) -> Result<(), CrustyError> {
+        // Snapshots never show uncommitted versions, which covers read committed
+        let lvl = match lvl {
+            IsolationLevel::ReadCommitted => IsolationLevel::SnapshotIsolation,
+            lvl => lvl,
+        };
+        *self.isolation.write()? = lvl;
+        Ok(())
+    }
+
+    fn start_transaction(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.start(tid)?;
+        self.state.lock()?.snapshot(tid);
+        if self.serializable()? {
+            self.predicates.start(tid)?;
+        }
+        Ok(())
+    }
+
+    fn read_record(&self, _value_id: &ValueId, _tid: &TransactionId) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
+    fn is_visible(
+        &self,
+        tuple: &Tuple,
+        value_id: &ValueId,
+        tid: &TransactionId,
+    ) -> Result<bool, CrustyError> {
+        let mut state = self.state.lock()?;
+        let start = state.snapshot(*tid);
+        if state
+            .replaced
+            .get(&tid.id())
+            .is_some_and(|replaced| replaced.contains(value_id))
+        {
+            return Ok(false);
+        }
+        let created = tuple.begin_ts == tid.id() || state.committed_before(tuple.begin_ts, start);
+        let ended = tuple.end_ts != LIVE_TS && state.committed_before(tuple.end_ts, start);
+        Ok(created && !ended)
+    }
+
+    fn pre_update_record(
+        &self,
+        tuple: &mut Tuple,
+        value_id: &ValueId,
+        tid: &TransactionId,
+        _changes: &TupleAssignments,
+    ) -> Result<(), CrustyError> {
+        self.state.lock()?.snapshot(*tid);
+        self.writes.updating(*tid, *value_id, tuple)?;
+        tuple.begin_ts = tid.id();
+        tuple.end_ts = LIVE_TS;
+        Ok(())
+    }
+
+    fn post_update_record(
+        &self,
+        tuple: &mut Tuple,
+        value_id: &ValueId,
+        old_value_id: &ValueId,
+        tid: &TransactionId,
+        _changes: &TupleAssignments,
+    ) -> Result<(), CrustyError> {
+        self.writes
+            .versioned(*tid, *old_value_id, *value_id, tuple)?;
+        self.state
+            .lock()?
+            .replaced
+            .entry(tid.id())
+            .or_default()
+            .insert(*old_value_id);
+        Ok(())
+    }
+
+    fn pre_insert_record(&self, tuple: &mut Tuple, tid: TransactionId) -> Result<(), CrustyError> {
+        self.state.lock()?.snapshot(tid);
+        tuple.begin_ts = tid.id();
+        tuple.end_ts = LIVE_TS;
+        Ok(())
+    }
+
+    fn post_insert_record(
+        &self,
+        tuple: &mut Tuple,
+        value_id: ValueId,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        self.writes.inserted(tid, value_id, tuple)
+    }
+
+    fn read_predicate(
+        &self,
+        container_id: ContainerId,
+        predicate: AstExpr,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        if self.serializable()? {
+            self.predicates.read(container_id, &predicate, tid)?;
+        }
+        Ok(())
+    }
+
+    fn validate_txn(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.state.lock()?.snapshot(tid);
+        if self.serializable()? {
+            self.predicates.validate(tid, &self.writes.writes(tid)?)?;
+        }
+        Ok(())
+    }
+
+    fn end_version(
+        &self,
+        old: &mut Tuple,
+        new_id: ValueId,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        if old.end_ts != LIVE_TS && old.end_ts != tid.id() {
+            return Err(CrustyError::TransactionRollback(tid));
+        }
+        old.end_ts = tid.id();
+        old.tuple_pointer = Some(new_id);
+        Ok(())
+    }
+
+    fn is_dead(&self, tuple: &Tuple, _value_id: &ValueId) -> Result<bool, CrustyError> {
+        if tuple.end_ts == LIVE_TS {
+            return Ok(false);
+        }
+        let state = self.state.lock()?;
+        // Transactions that start later take their snapshot after every commit so far
+        let oldest = state
+            .snapshots
+            .values()
+            .min()
+            .copied()
+            .unwrap_or(TidType::MAX);
+        Ok(state.committed_before(tuple.end_ts, oldest))
+    }
+
+    fn version_moved(&self, tuple: &mut Tuple, moved: &HashMap<ValueId, ValueId>) -> bool {
+        match tuple.tuple_pointer.and_then(|id| moved.get(&id)) {
+            Some(new_id) => {
+                tuple.tuple_pointer = Some(*new_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn holds_key(
+        &self,
+        tuple: &Tuple,
+        value_id: &ValueId,
+        tid: &TransactionId,
+    ) -> Result<bool, CrustyError> {
+        let state = self.state.lock()?;
+        let replaced = state
+            .replaced
+            .get(&tid.id())
+            .is_some_and(|replaced| replaced.contains(value_id));
+        // Versions are only stamped as ended once the transaction replacing them commits
+        Ok(!replaced && tuple.end_ts == LIVE_TS)
+    }
+
+    fn others_running(&self, tid: TransactionId) -> Result<bool, CrustyError> {
+        self.writes.others_running(tid)
+    }
+
+    fn log_insert_ids(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.log_insert_ids(tid)
+    }
+
+    fn savepoint(&self, tid: TransactionId) -> Result<usize, CrustyError> {
+        self.writes.savepoint(tid)
+    }
+
+    fn take_writes(
+        &self,
+        tid: TransactionId,
+        savepoint: usize,
+    ) -> Result<Vec<TxnWrite>, CrustyError> {
+        let taken = self.writes.take(tid, savepoint)?;
+        let mut state = self.state.lock()?;
+        if let Some(replaced) = state.replaced.get_mut(&tid.id()) {
+            for write in &taken {
+                if let TxnWrite::Version { old_id, .. } = write {
+                    replaced.remove(old_id);
+                }
+            }
+        }
+        Ok(taken)
+    }
+
+    fn rollback_txn(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        let mut state = self.state.lock()?;
+        state.committed.remove(&tid.id());
+        state.end(tid);
+        self.predicates.end(tid)?;
+        self.writes.forget(tid)
+    }
+
+    fn commit_txn(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        let mut state = self.state.lock()?;
+        state.commit(tid);
+        state.end(tid);
+        self.predicates.end(tid)?;
+        self.writes.forget(tid)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn version(tm: &MvccTransactionManager, tid: TransactionId, id: ValueId) -> Tuple {
+        let mut tuple = Tuple::new(vec![Field::Int(id.slot_id.unwrap() as i64)]);
+        tm.pre_insert_record(&mut tuple, tid).unwrap();
+        tm.post_insert_record(&mut tuple, id, tid).unwrap();
+        tuple
+    }
+
+    /// Replace the version at old_id with a new one at new_id
+    fn replace(
+        tm: &MvccTransactionManager,
+        tid: TransactionId,
+        old_id: ValueId,
+        new_id: ValueId,
+    ) -> Tuple {
+        let mut tuple = Tuple::new(vec![Field::Int(0)]);
+        tm.pre_update_record(&mut tuple, &old_id, &tid, &Vec::new())
+            .unwrap();
+        tm.post_update_record(&mut tuple, &new_id, &old_id, &tid, &Vec::new())
+            .unwrap();
+        tuple
+    }
+
+    #[test]
+    fn test_snapshot_visibility() {
+        let tm = MvccTransactionManager::new();
+        let (id, new_id) = (ValueId::new_slot(1, 0, 0), ValueId::new_slot(1, 0, 1));
+        let loader = TransactionId::new();
+        tm.start_transaction(loader).unwrap();
+        let v0 = version(&tm, loader, id);
+        let early = TransactionId::new();
+        tm.start_transaction(early).unwrap();
+        assert!(tm.is_visible(&v0, &id, &loader).unwrap());
+        assert!(!tm.is_visible(&v0, &id, &early).unwrap());
+        tm.validate_txn(loader).unwrap();
+        tm.commit_txn(loader).unwrap();
+        // early started before loader committed
+        assert!(!tm.is_visible(&v0, &id, &early).unwrap());
+
+        let reader = TransactionId::new();
+        tm.start_transaction(reader).unwrap();
+        assert!(tm.is_visible(&v0, &id, &reader).unwrap());
+        let writer = TransactionId::new();
+        tm.start_transaction(writer).unwrap();
+        let mut old = v0.clone();
+        let v1 = replace(&tm, writer, id, new_id);
+        assert!(!tm.is_visible(&v0, &id, &writer).unwrap());
+        assert!(tm.is_visible(&v1, &new_id, &writer).unwrap());
+        // Only the writer may give the replaced version's key to another record
+        assert!(!tm.holds_key(&v0, &id, &writer).unwrap());
+        assert!(tm.holds_key(&v0, &id, &reader).unwrap());
+        tm.validate_txn(writer).unwrap();
+        tm.end_version(&mut old, new_id, writer).unwrap();
+        assert_eq!(Some(new_id), old.tuple_pointer);
+        // Until the writer commits its stamp does not end the version
+        assert!(tm.is_visible(&old, &id, &reader).unwrap());
+        tm.commit_txn(writer).unwrap();
+
+        // reader keeps its snapshot, later transactions see the new version
+        assert!(tm.is_visible(&old, &id, &reader).unwrap());
+        assert!(!tm.is_visible(&v1, &new_id, &reader).unwrap());
+        let later = TransactionId::new();
+        assert!(!tm.is_visible(&old, &id, &later).unwrap());
+        assert!(tm.is_visible(&v1, &new_id, &later).unwrap());
+        assert!(!tm.is_dead(&old, &id).unwrap());
+        assert!(!tm.holds_key(&old, &id, &later).unwrap());
+        assert!(tm.holds_key(&v1, &new_id, &later).unwrap());
+        tm.commit_txn(reader).unwrap();
+        tm.commit_txn(early).unwrap();
+        // Once no snapshot needs the commit, the stamped versions alone tell
+        assert!(!tm.is_visible(&old, &id, &later).unwrap());
+        assert!(tm.is_visible(&v1, &new_id, &later).unwrap());
+        assert!(tm.is_dead(&old, &id).unwrap());
+        assert!(!tm.is_dead(&v1, &new_id).unwrap());
+    }
+
+    #[test]
+    fn test_write_write_conflict() {
+        let tm = MvccTransactionManager::new();
+        let id = ValueId::new_slot(1, 0, 0);
+        let loader = TransactionId::new();
+        let mut v0 = version(&tm, loader, id);
+        tm.commit_txn(loader).unwrap();
+
+        let (first, second) = (TransactionId::new(), TransactionId::new());
+        tm.start_transaction(first).unwrap();
+        tm.start_transaction(second).unwrap();
+        let (first_id, second_id) = (ValueId::new_slot(1, 0, 1), ValueId::new_slot(1, 0, 2));
+        replace(&tm, first, id, first_id);
+        replace(&tm, second, id, second_id);
+        tm.validate_txn(first).unwrap();
+        tm.end_version(&mut v0, first_id, first).unwrap();
+        tm.commit_txn(first).unwrap();
+        // The version first ended stays linked to the version of first
+        tm.validate_txn(second).unwrap();
+        assert_eq!(
+            CrustyError::TransactionRollback(second),
+            tm.end_version(&mut v0, second_id, second).unwrap_err()
+        );
+        assert_eq!(Some(first_id), v0.tuple_pointer);
+        tm.take_writes(second, 0).unwrap();
+        tm.rollback_txn(second).unwrap();
+
+        // The rolled back transaction leaves nothing behind once its versions are removed
+        assert!(!tm.is_visible(&v0, &id, &TransactionId::new()).unwrap());
+        let state = tm.state.lock().unwrap();
+        assert!(state.committed.is_empty() && state.replaced.is_empty());
+    }
+
+    #[test]
+    fn test_rollback_logs_loaded_ids() {
+        let tm = MvccTransactionManager::new();
+        let id = ValueId::new_slot(1, 0, 0);
+        let loader = TransactionId::new();
+        tm.start_transaction(loader).unwrap();
+        tm.log_insert_ids(loader).unwrap();
+        version(&tm, loader, id);
+        // The caller removes the loaded records before the loader rolls back
+        assert_eq!(
+            vec![TxnWrite::Load { ids: vec![id] }],
+            tm.take_writes(loader, 0).unwrap()
+        );
+        tm.rollback_txn(loader).unwrap();
+        assert!(tm.state.lock().unwrap().committed.is_empty());
+    }
+
+    #[test]
+    fn test_version_moved() {
+        let tm = MvccTransactionManager::new();
+        let (id, new_id, moved_id) = (
+            ValueId::new_slot(1, 0, 0),
+            ValueId::new_slot(1, 0, 1),
+            ValueId::new_slot(1, 0, 2),
+        );
+        let mut v0 = Tuple::new(vec![Field::Int(0)]);
+        assert!(!tm.version_moved(&mut v0, &HashMap::from([(id, moved_id)])));
+        tm.end_version(&mut v0, new_id, TransactionId::new())
+            .unwrap();
+        assert!(!tm.version_moved(&mut v0, &HashMap::from([(id, moved_id)])));
+        assert!(tm.version_moved(&mut v0, &HashMap::from([(new_id, moved_id)])));
+        assert_eq!(Some(moved_id), v0.tuple_pointer);
+    }
+}
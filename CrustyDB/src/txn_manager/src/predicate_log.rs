@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use common::ast_expr::AstExpr;
+use common::bytecode_expr::ByteCodeExpr;
+use common::prelude::*;
+use common::traits::transaction_manager_trait::TxnWrite;
+
+/// The predicates serializable transactions read, validated when they commit against the records
+/// written by the transactions that committed while they ran. This catches phantoms, records a
+/// concurrent transaction inserted into or changed into the range a predicate covers.
+#[derive(Default)]
+pub struct PredicateLog {
+    inner: Mutex<Predicates>,
+}
+
+#[derive(Default)]
+struct Predicates {
+    /// Counts validated transactions
+    clock: u64,
+    /// The clock when each running transaction started and the predicates it read
+    running: HashMap<TransactionId, (u64, Vec<(ContainerId, ByteCodeExpr)>)>,
+    /// The records written by the transaction validated at each clock value, while a running
+    /// transaction started before it
+    committed: Vec<(u64, Vec<(ContainerId, Tuple)>)>,
+}
+
+impl Predicates {
+    fn running(&mut self, tid: TransactionId) -> &mut Vec<(ContainerId, ByteCodeExpr)> {
+        let clock = self.clock;
+        &mut self
+            .running
+            .entry(tid)
+            .or_insert_with(|| (clock, Vec::new()))
+            .1
+    }
+
+    /// Forget tid and the writes no running transaction needs to check.
+    fn end(&mut self, tid: TransactionId) {
+        self.running.remove(&tid);
+        match self.running.values().map(|(start, _)| *start).min() {
+            Some(oldest) => self.committed.retain(|(clock, _)| *clock > oldest),
+            None => self.committed.clear(),
+        }
+    }
+}
+
+/// The records a write changed, with the values before and after it. The values of a bulk
+/// load are not kept, so predicates are not checked against them.
+fn written_records(write: &TxnWrite) -> Vec<(ContainerId, Tuple)> {
+    match write {
+        TxnWrite::Load { .. } => Vec::new(),
+        TxnWrite::Insert { id, tuple } => vec![(id.container_id, tuple.clone())],
+        TxnWrite::Update {
+            old_id,
+            old,
+            new_id,
+            new,
+        }
+        | TxnWrite::Version {
+            old_id,
+            old,
+            new_id,
+            new,
+        } => vec![
+            (old_id.container_id, old.clone()),
+            (new_id.container_id, new.clone()),
+        ],
+    }
+}
+
+impl PredicateLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking tid if it is not tracked yet. Writes validated from now on are checked
+    /// against its predicates.
+    pub fn start(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.inner.lock()?.running(tid);
+        Ok(())
+    }
+
+    /// Record that tid read the records of container_id matching predicate, an expression bound
+    /// to the table's schema.
+    pub fn read(
+        &self,
+        container_id: ContainerId,
+        predicate: &AstExpr,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let predicate = ByteCodeExpr::from_ast(predicate)?;
+        self.inner
+            .lock()?
+            .running(tid)
+            .push((container_id, predicate));
+        Ok(())
+    }
+
+    /// Roll tid back if a transaction validated since tid started wrote a record matching one of
+    /// its predicates. Otherwise keep writes, the writes of tid, for the running transactions
+    /// to check and stop tracking tid.
+    pub fn validate(&self, tid: TransactionId, writes: &[TxnWrite]) -> Result<(), CrustyError> {
+        let mut inner = self.inner.lock()?;
+        if let Some((start, predicates)) = inner.running.get(&tid) {
+            let conflict = inner
+                .committed
+                .iter()
+                .filter(|(clock, _)| clock > start)
+                .flat_map(|(_, records)| records)
+                .any(|(container_id, tuple)| {
+                    predicates.iter().any(|(predicate_container, predicate)| {
+                        predicate_container == container_id
+                            && predicate.eval(tuple) == Field::Bool(true)
+                    })
+                });
+            if conflict {
+                return Err(CrustyError::TransactionRollback(tid));
+            }
+        }
+        inner.clock += 1;
+        if !writes.is_empty() && !inner.running.is_empty() {
+            let clock = inner.clock;
+            let records = writes.iter().flat_map(written_records).collect();
+            inner.committed.push((clock, records));
+        }
+        inner.end(tid);
+        Ok(())
+    }
+
+    /// Stop tracking tid once it ended.
+    pub fn end(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.inner.lock()?.end(tid);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::BooleanOp;
+
+    #[test]
+    fn test_phantom_insert() {
+        let log = PredicateLog::new();
+        let (reader, writer) = (TransactionId::new(), TransactionId::new());
+        log.start(reader).unwrap();
+        // reader reads the records of table 1 with a first column above 10
+        let above_ten = AstExpr::Boolean(
+            BooleanOp::Gt,
+            Box::new(AstExpr::ColIdx(0)),
+            Box::new(AstExpr::Literal(Field::Int(10))),
+        );
+        log.read(1, &above_ten, reader).unwrap();
+
+        let insert = |container_id, value| TxnWrite::Insert {
+            id: ValueId::new_slot(container_id, 0, 0),
+            tuple: Tuple::new(vec![Field::Int(value)]),
+        };
+        log.validate(writer, &[insert(1, 5), insert(2, 20)])
+            .unwrap();
+        log.validate(reader, &[]).unwrap();
+
+        let (reader, writer) = (TransactionId::new(), TransactionId::new());
+        log.start(reader).unwrap();
+        log.read(1, &above_ten, reader).unwrap();
+        log.validate(writer, &[insert(1, 20)]).unwrap();
+        assert_eq!(
+            CrustyError::TransactionRollback(reader),
+            log.validate(reader, &[]).unwrap_err()
+        );
+        log.end(reader).unwrap();
+
+        // A transaction that starts after the insert does not conflict with it
+        let reader = TransactionId::new();
+        log.read(1, &above_ten, reader).unwrap();
+        log.validate(reader, &[]).unwrap();
+    }
+}
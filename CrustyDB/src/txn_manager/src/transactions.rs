@@ -15,6 +15,10 @@ pub enum TxnState {
 pub struct Transaction {
     tid: TransactionId,
     state: TxnState,
+    /// Set by BEGIN: the transaction spans statements until COMMIT or ROLLBACK
+    in_block: bool,
+    /// Named savepoints of the block, oldest first, with the write savepoint each one marks
+    savepoints: Vec<(String, usize)>,
 }
 
 impl Default for Transaction {
@@ -29,6 +33,8 @@ impl Transaction {
         Self {
             tid: TransactionId::new(),
             state: TxnState::Active,
+            in_block: false,
+            savepoints: Vec::new(),
         }
     }
 
@@ -41,6 +47,44 @@ impl Transaction {
         }
     }
 
+    /// Open a transaction block, so statements no longer commit on their own.
+    pub fn begin_block(&mut self) -> Result<(), CrustyError> {
+        if self.in_block {
+            return Err(CrustyError::CrustyError(
+                "A transaction is already in progress".to_string(),
+            ));
+        }
+        self.in_block = true;
+        Ok(())
+    }
+
+    /// Whether a transaction block is open
+    pub fn in_block(&self) -> bool {
+        self.in_block
+    }
+
+    /// Name a point of the block, replacing an older savepoint with the same name.
+    pub fn add_savepoint(&mut self, name: &str, savepoint: usize) {
+        self.savepoints.retain(|(n, _)| n != name);
+        self.savepoints.push((name.to_string(), savepoint));
+    }
+
+    /// The write savepoint of the newest savepoint called name. Savepoints made after it are
+    /// forgotten, and it is removed too if release is set.
+    pub fn pop_savepoint(&mut self, name: &str, release: bool) -> Result<usize, CrustyError> {
+        let pos = self
+            .savepoints
+            .iter()
+            .rposition(|(n, _)| n == name)
+            .ok_or_else(|| {
+                CrustyError::CrustyError(format!("Savepoint {} does not exist", name))
+            })?;
+        let savepoint = self.savepoints[pos].1;
+        self.savepoints
+            .truncate(if release { pos } else { pos + 1 });
+        Ok(savepoint)
+    }
+
     /// Commits the transaction.
     pub fn commit(&mut self) -> Result<(), CrustyError> {
         self.state = TxnState::PartiallyCommitted;
@@ -53,20 +97,17 @@ impl Transaction {
         self.complete()
     }
 
-    /// Completes the transaction.
-    ///
-    /// # Arguments
-    ///
-    /// * `commit` - True if the transaction should commit.
+    /// Completes the transaction. The transaction manager must already have been told to commit
+    /// or roll it back.
     pub fn complete(&mut self) -> Result<(), CrustyError> {
-        if self.state != TxnState::PartiallyCommitted || self.state != TxnState::Failed {
-            error!(
-                "Error: FIXME, need to notify on txn complete {:?}",
-                self.state
-            );
-            //FIXME DBSERVER.transaction_complete(self.tid, commit)?;
-            self.state = TxnState::Terminated;
-        }
+        self.state = match self.state {
+            TxnState::PartiallyCommitted => TxnState::Committed,
+            TxnState::Failed => TxnState::Aborted,
+            _ => {
+                error!("Completing a transaction in state {:?}", self.state);
+                TxnState::Terminated
+            }
+        };
         Ok(())
     }
 }
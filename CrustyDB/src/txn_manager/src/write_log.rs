@@ -0,0 +1,282 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use common::prelude::*;
+use common::traits::transaction_manager_trait::TxnWrite;
+
+/// The writes of each active transaction, oldest first, kept so a rollback can undo them.
+#[derive(Default)]
+pub struct WriteLog {
+    writes: Mutex<HashMap<TransactionId, Vec<TxnWrite>>>,
+    /// Records between pre_update_record and post_update_record with their old value
+    updating: Mutex<HashMap<(TransactionId, ValueId), Tuple>>,
+    /// Transactions that log the ids of their inserts only, see `log_insert_ids`, with the
+    /// position of the Load write new ids are added to, if any
+    loading: Mutex<HashMap<TransactionId, Option<usize>>>,
+    /// Transactions started and not ended yet, whether they wrote or not
+    running: Mutex<HashSet<TransactionId>>,
+}
+
+impl WriteLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that tid started.
+    pub fn start(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.running.lock()?.insert(tid);
+        Ok(())
+    }
+
+    fn push(&self, tid: TransactionId, write: TxnWrite) -> Result<(), CrustyError> {
+        self.writes.lock()?.entry(tid).or_default().push(write);
+        Ok(())
+    }
+
+    /// Keep only the ids of the records tid inserts from now on, e.g. for a bulk load that would
+    /// otherwise hold every record it inserts until it ends. Consecutive inserts share one Load
+    /// write.
+    pub fn log_insert_ids(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.loading.lock()?.entry(tid).or_insert(None);
+        Ok(())
+    }
+
+    /// Record that tid inserted tuple at id.
+    pub fn inserted(
+        &self,
+        tid: TransactionId,
+        id: ValueId,
+        tuple: &Tuple,
+    ) -> Result<(), CrustyError> {
+        if let Some(open) = self.loading.lock()?.get_mut(&tid) {
+            let mut writes = self.writes.lock()?;
+            let tid_writes = writes.entry(tid).or_default();
+            match open.and_then(|i| tid_writes.get_mut(i)) {
+                Some(TxnWrite::Load { ids }) => ids.push(id),
+                _ => {
+                    *open = Some(tid_writes.len());
+                    tid_writes.push(TxnWrite::Load { ids: vec![id] });
+                }
+            }
+            return Ok(());
+        }
+        self.push(
+            tid,
+            TxnWrite::Insert {
+                id,
+                tuple: tuple.clone(),
+            },
+        )
+    }
+
+    /// Remember the value of the record at id before tid updates it.
+    pub fn updating(
+        &self,
+        tid: TransactionId,
+        id: ValueId,
+        old: &Tuple,
+    ) -> Result<(), CrustyError> {
+        self.updating.lock()?.insert((tid, id), old.clone());
+        Ok(())
+    }
+
+    /// The value of the record at old_id announced with `updating`
+    fn announced(&self, tid: TransactionId, old_id: ValueId) -> Result<Tuple, CrustyError> {
+        self.updating.lock()?.remove(&(tid, old_id)).ok_or_else(|| {
+            CrustyError::CrustyError(format!(
+                "Update of {:?} by {:?} was not announced",
+                old_id, tid
+            ))
+        })
+    }
+
+    /// The writes of tid so far, oldest first
+    pub fn writes(&self, tid: TransactionId) -> Result<Vec<TxnWrite>, CrustyError> {
+        Ok(self.writes.lock()?.get(&tid).cloned().unwrap_or_default())
+    }
+
+    /// Record that tid changed the record at old_id, announced with `updating`, to new at new_id.
+    pub fn updated(
+        &self,
+        tid: TransactionId,
+        old_id: ValueId,
+        new_id: ValueId,
+        new: &Tuple,
+    ) -> Result<(), CrustyError> {
+        let old = self.announced(tid, old_id)?;
+        self.push(
+            tid,
+            TxnWrite::Update {
+                old_id,
+                old,
+                new_id,
+                new: new.clone(),
+            },
+        )
+    }
+
+    /// Record that tid inserted new at new_id as the next version of the record at old_id,
+    /// announced with `updating`.
+    pub fn versioned(
+        &self,
+        tid: TransactionId,
+        old_id: ValueId,
+        new_id: ValueId,
+        new: &Tuple,
+    ) -> Result<(), CrustyError> {
+        let old = self.announced(tid, old_id)?;
+        self.push(
+            tid,
+            TxnWrite::Version {
+                old_id,
+                old,
+                new_id,
+                new: new.clone(),
+            },
+        )
+    }
+
+    /// Whether a transaction other than tid started and has not ended, or has writes
+    pub fn others_running(&self, tid: TransactionId) -> Result<bool, CrustyError> {
+        if self.running.lock()?.iter().any(|t| *t != tid) {
+            return Ok(true);
+        }
+        Ok(self
+            .writes
+            .lock()?
+            .iter()
+            .any(|(t, writes)| *t != tid && !writes.is_empty()))
+    }
+
+    /// The number of writes of tid so far. Later inserts start a new Load write, so undoing
+    /// the writes after the savepoint leaves the earlier ones.
+    pub fn savepoint(&self, tid: TransactionId) -> Result<usize, CrustyError> {
+        if let Some(open) = self.loading.lock()?.get_mut(&tid) {
+            *open = None;
+        }
+        Ok(self.writes.lock()?.get(&tid).map_or(0, Vec::len))
+    }
+
+    /// Remove the writes of tid after savepoint and return them newest first.
+    pub fn take(&self, tid: TransactionId, savepoint: usize) -> Result<Vec<TxnWrite>, CrustyError> {
+        let mut writes = self.writes.lock()?;
+        let mut taken = match writes.get_mut(&tid) {
+            Some(tid_writes) if savepoint < tid_writes.len() => tid_writes.split_off(savepoint),
+            _ => Vec::new(),
+        };
+        taken.reverse();
+        Ok(taken)
+    }
+
+    /// Forget everything about tid once it has ended.
+    pub fn forget(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.writes.lock()?.remove(&tid);
+        self.updating.lock()?.retain(|(t, _), _| *t != tid);
+        self.loading.lock()?.remove(&tid);
+        self.running.lock()?.remove(&tid);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_take_writes() {
+        let log = WriteLog::new();
+        let tid = TransactionId::new();
+        let (a, b) = (
+            Tuple::new(vec![Field::Int(1)]),
+            Tuple::new(vec![Field::Int(2)]),
+        );
+        let (id, moved) = (ValueId::new_slot(1, 0, 0), ValueId::new_slot(1, 0, 1));
+        log.inserted(tid, id, &a).unwrap();
+        let savepoint = log.savepoint(tid).unwrap();
+        assert!(log.updated(tid, id, moved, &b).is_err());
+        log.updating(tid, id, &a).unwrap();
+        log.updated(tid, id, moved, &b).unwrap();
+
+        let update = TxnWrite::Update {
+            old_id: id,
+            old: a.clone(),
+            new_id: moved,
+            new: b,
+        };
+        assert_eq!(vec![update], log.take(tid, savepoint).unwrap());
+        assert!(log.take(tid, savepoint).unwrap().is_empty());
+        assert_eq!(
+            vec![TxnWrite::Insert { id, tuple: a }],
+            log.take(tid, 0).unwrap()
+        );
+        log.forget(tid).unwrap();
+        assert_eq!(0, log.savepoint(tid).unwrap());
+    }
+
+    #[test]
+    fn test_log_insert_ids() {
+        let log = WriteLog::new();
+        let tid = TransactionId::new();
+        let tuple = Tuple::new(vec![Field::Int(1)]);
+        let id = |slot| ValueId::new_slot(1, 0, slot);
+        log.log_insert_ids(tid).unwrap();
+        log.inserted(tid, id(0), &tuple).unwrap();
+        log.inserted(tid, id(1), &tuple).unwrap();
+        let savepoint = log.savepoint(tid).unwrap();
+        assert_eq!(1, savepoint);
+        log.inserted(tid, id(2), &tuple).unwrap();
+        assert_eq!(
+            vec![TxnWrite::Load { ids: vec![id(2)] }],
+            log.take(tid, savepoint).unwrap()
+        );
+        assert_eq!(
+            vec![TxnWrite::Load {
+                ids: vec![id(0), id(1)]
+            }],
+            log.take(tid, 0).unwrap()
+        );
+
+        // Only the loading transaction logs ids, and only until it ends
+        let other = TransactionId::new();
+        log.inserted(other, id(3), &tuple).unwrap();
+        assert_eq!(
+            vec![TxnWrite::Insert {
+                id: id(3),
+                tuple: tuple.clone()
+            }],
+            log.writes(other).unwrap()
+        );
+        log.forget(tid).unwrap();
+        log.inserted(tid, id(4), &tuple).unwrap();
+        assert_eq!(
+            vec![TxnWrite::Insert { id: id(4), tuple }],
+            log.writes(tid).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_others_running() {
+        let log = WriteLog::new();
+        let (tid, reader, writer) = (
+            TransactionId::new(),
+            TransactionId::new(),
+            TransactionId::new(),
+        );
+        log.start(tid).unwrap();
+        assert!(!log.others_running(tid).unwrap());
+
+        // A transaction that only reads counts until it ends
+        log.start(reader).unwrap();
+        assert!(log.others_running(tid).unwrap());
+        log.forget(reader).unwrap();
+        assert!(!log.others_running(tid).unwrap());
+
+        // So does one that was never started but wrote
+        let tuple = Tuple::new(vec![Field::Int(1)]);
+        log.inserted(writer, ValueId::new_slot(1, 0, 0), &tuple)
+            .unwrap();
+        assert!(log.others_running(tid).unwrap());
+        log.forget(writer).unwrap();
+        assert!(!log.others_running(tid).unwrap());
+    }
+}